@@ -4,6 +4,13 @@ let midi_sources_div = null;
 
 document.addEventListener('DOMContentLoaded', async () => {
 	document.addEventListener('keyup', keyup);
+	document.body.addEventListener('htmx:responseError', on_response_error);
+	document.body.addEventListener('htmx:afterSwap', (event) => {
+		if (event.detail.target.id === "blocks") {
+			apply_block_filter();
+		}
+	});
+	document.body.addEventListener('htmx:configRequest', upload_to_filtered_group);
 
 	// Sometimes when we update the page, browser preserve the state of inputs
 	// which allows us to keep keybindings from previous state of page
@@ -43,6 +50,16 @@ async function init_websocket() {
 			});
 
 			socket.addEventListener("message", (event) => {
+				if (event.data === "blocks-changed") {
+					reload_blocks();
+					return;
+				}
+
+				if (event.data === "ports-changed") {
+					reload_midi_ports();
+					return;
+				}
+
 				let incoming = document.createElement('div');
 				incoming.innerHTML = event.data;
 				status.replaceChildren(incoming.childNodes[0]);
@@ -64,6 +81,83 @@ async function init_websocket() {
 	}
 }
 
+/** Set when blocks changed while user was editing one of them */
+let blocks_reload_pending = false;
+
+/**
+	* Reload list of blocks after it was changed, possibly in another tab
+	*
+	* Reload is postponed while user edits input of the block, so typed value is not lost.
+	*/
+function reload_blocks() {
+	const blocks = document.getElementById("blocks");
+	const active = document.activeElement;
+	if (active && blocks.contains(active) && (active.nodeName == "INPUT" || active.nodeName == "SELECT")) {
+		if (!blocks_reload_pending) {
+			blocks_reload_pending = true;
+			active.addEventListener("blur", () => {
+				blocks_reload_pending = false;
+				reload_blocks();
+			}, { once: true });
+		}
+		return;
+	}
+
+	// Blocks found by the search stay found after reload
+	const query = document.getElementById("block-search").value;
+	htmx.ajax("GET", "/blocks", { target: "#blocks", swap: "innerHTML", values: { query } }).then(() => {
+		for (const input of blocks.querySelectorAll('input[name=keybind]')) {
+			update_key_binding(input);
+		}
+	});
+}
+
+/**
+	* Reload list of MIDI ports, unless user is editing settings of one of them
+	*
+	* Ports are reloaded on the next change if skipped, which is good enough for rarely changing
+	* list of devices.
+	*/
+function reload_midi_ports() {
+	const ports = document.getElementById("midi-ports");
+	if (ports.contains(document.activeElement)) {
+		return;
+	}
+	htmx.ajax("GET", "/midi/ports", { target: "#midi-ports", swap: "innerHTML" });
+}
+
+/**
+	* Ask for access token when Harmonia rejected change made from another computer
+	* @param {CustomEvent} ev
+	*/
+async function on_response_error(ev) {
+	if (ev.detail.xhr.status == 401) {
+		await ask_for_access_token();
+	}
+}
+
+/**
+	* Ask for access token and log in with it
+	*
+	* Valid token is remembered by the browser in a cookie, so it's asked only once.
+	*/
+async function ask_for_access_token() {
+	const token = prompt("This Harmonia instance requires access token for changes. Access token:");
+	if (!token) {
+		return;
+	}
+
+	const response = await fetch('/login', {
+		method: 'POST',
+		body: new URLSearchParams({ token }),
+	});
+	if (response.ok) {
+		location.reload();
+	} else {
+		alert("Invalid access token");
+	}
+}
+
 // TODO: When refreshing page previous value of keybind cell may stay,
 //       but we only notice when page changes. Also it should be preserved
 //       across the calls, so send this keybindings to server.
@@ -80,8 +174,13 @@ async function keyup(ev) {
 	if (ev.metaKey || ev.altKey || ev.ctrlKey)
 		return;
 
+	// Viewers cannot start or stop playback
+	if (document.body.classList.contains("viewer"))
+		return;
+
 	if (ev.key == ' ') {
-		await fetch('/interrupt', { method: 'POST' });
+		// Shift+Space lets the music finish the bar, whatever mode is chosen
+		await fetch(ev.shiftKey ? '/interrupt?at=bar-end' : '/interrupt', { method: 'POST' });
 		ev.preventDefault();
 		return;
 	}
@@ -111,6 +210,174 @@ function update_key_binding(input_element) {
 }
 
 
+/** @type {?HTMLAudioElement} */
+let preview_audio = null;
+
+/**
+ * Play block rendered through SoundFont, or stop it if it's already playing
+ * @param {string} uuid
+ */
+function preview(uuid) {
+	const src = '/blocks/preview/' + uuid;
+	const was_playing = preview_audio !== null && preview_audio.dataset.uuid === uuid;
+	if (preview_audio !== null) {
+		preview_audio.pause();
+		preview_audio = null;
+	}
+	if (was_playing) {
+		return;
+	}
+
+	preview_audio = new Audio(src);
+	preview_audio.dataset.uuid = uuid;
+	preview_audio.addEventListener('ended', () => { preview_audio = null; });
+	preview_audio.play().catch(err => console.error('failed to preview', uuid, err));
+}
+
 function toggle_delete(self) {
 	document.body.classList.toggle('delete-mode-active');
 }
+
+/** Tag chosen in the filter bar, kept while blocks are reloaded */
+let block_tag_filter = "";
+
+/** Group chosen in the filter bar, kept while blocks are reloaded */
+let block_group_filter = "";
+
+/** Remember tag and group chosen in the filter bar and show only blocks with them */
+function filter_blocks() {
+	const tag_select = document.getElementById("tag-filter");
+	const group_select = document.getElementById("group-filter");
+	block_tag_filter = tag_select ? tag_select.value : "";
+	block_group_filter = group_select ? group_select.value : "";
+	apply_block_filter();
+}
+
+/**
+	* Restore value chosen in the filter select, or empty one when it's no longer offered
+	*
+	* @param {string} id
+	* @param {string} value
+	* @returns {string}
+	*/
+function restore_filter(id, value) {
+	const select = document.getElementById(id);
+	if (!select || ![...select.options].some(option => option.value === value)) {
+		return "";
+	}
+	select.value = value;
+	return value;
+}
+
+/**
+	* Hide blocks without the chosen tag or outside of the chosen group
+	*
+	* Filter bar is rendered again with every reload of blocks, so chosen tag and group are
+	* restored here, unless no block has them anymore.
+	*/
+function apply_block_filter() {
+	block_tag_filter = restore_filter("tag-filter", block_tag_filter);
+	block_group_filter = restore_filter("group-filter", block_group_filter);
+
+	for (const block of document.querySelectorAll("#blocks section.block")) {
+		const tags = block.dataset.tags ? block.dataset.tags.split(",") : [];
+		block.classList.toggle("filtered-out",
+			(block_tag_filter !== "" && !tags.includes(block_tag_filter))
+			|| (block_group_filter !== "" && block.dataset.group !== block_group_filter));
+	}
+}
+
+/** Routes creating blocks from uploaded files, which take group as query parameter */
+const upload_paths = ["/blocks/midi", "/blocks/sample", "/blocks/audio"];
+
+/**
+	* Put uploaded files into group chosen in the filter bar, when enabled in defaults
+	*
+	* Saved state of the checkbox is used, not the one that may be toggled without saving.
+	*
+	* @param {CustomEvent} event
+	*/
+function upload_to_filtered_group(event) {
+	const toggle = document.querySelector("#defaults input[name=upload_to_filtered_group]");
+	if (!toggle || !toggle.defaultChecked || block_group_filter === "") {
+		return;
+	}
+
+	const detail = event.detail;
+	if (detail.verb === "put" && upload_paths.includes(detail.path)) {
+		detail.path += "?group=" + encodeURIComponent(block_group_filter);
+	} else if (detail.verb === "post" && detail.path === "/blocks/midi/url") {
+		detail.parameters.group = block_group_filter;
+	}
+}
+
+/** Block being dragged by it's handle, see `drag-handle` in blocks */
+let dragged_block = null;
+
+document.addEventListener('dragstart', (ev) => {
+	const handle = ev.target.closest && ev.target.closest("#blocks .drag-handle");
+	if (!handle) {
+		return;
+	}
+	dragged_block = handle.closest("section.block");
+	dragged_block.classList.add("dragged");
+	ev.dataTransfer.effectAllowed = "move";
+	ev.dataTransfer.setData("text/plain", dragged_block.dataset.uuid);
+});
+
+document.addEventListener('dragover', (ev) => {
+	if (!dragged_block) {
+		return;
+	}
+	const target = ev.target.closest && ev.target.closest("#blocks section.block");
+	if (!target) {
+		return;
+	}
+	ev.preventDefault();
+	if (target === dragged_block) {
+		return;
+	}
+	const rect = target.getBoundingClientRect();
+	const after = ev.clientY > rect.top + rect.height / 2;
+	target.parentNode.insertBefore(dragged_block, after ? target.nextSibling : target);
+});
+
+document.addEventListener('drop', (ev) => {
+	if (dragged_block) {
+		ev.preventDefault();
+	}
+});
+
+/** Store order of blocks as it is shown after dragging */
+document.addEventListener('dragend', async () => {
+	if (!dragged_block) {
+		return;
+	}
+	dragged_block.classList.remove("dragged");
+	dragged_block = null;
+
+	const order = [...document.querySelectorAll("#blocks section.block")].map(block => block.dataset.uuid);
+	const response = await fetch("/blocks/reorder", {
+		method: "POST",
+		headers: { "Content-Type": "application/json" },
+		body: JSON.stringify(order),
+	});
+	if (!response.ok) {
+		console.error(`failed to reorder blocks: ${await response.text()}`);
+		reload_blocks();
+	}
+});
+
+/**
+	* Ask for new name of the block and rename it
+	*
+	* @param {HTMLButtonElement} button
+	*/
+function rename_block(button) {
+	const name = prompt("New name of the block:", button.dataset.name);
+	if (name === null || name.trim() === "" || name === button.dataset.name) {
+		return;
+	}
+	// List of blocks is reloaded after the change is announced over WebSocket
+	htmx.ajax("POST", `/blocks/rename/${button.dataset.uuid}`, { values: { name }, swap: "none" });
+}
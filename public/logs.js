@@ -0,0 +1,57 @@
+/** Number of characters kept in the viewer, older lines are dropped */
+const MAX_LOG_LENGTH = 2_000_000;
+
+document.addEventListener('DOMContentLoaded', async () => {
+	const logs = document.getElementById("logs");
+	if (!logs) {
+		return;
+	}
+
+	document.getElementById("copy-logs").addEventListener("click", () => {
+		navigator.clipboard.writeText(logs.textContent);
+	});
+
+	await follow_logs(logs);
+});
+
+function delay(miliseconds) {
+	return new Promise(resolve => setTimeout(resolve, miliseconds));
+}
+
+/**
+	* Append lines streamed by Harmonia, reconnecting when connection is lost
+	*
+	* @param {HTMLPreElement} logs
+	*/
+async function follow_logs(logs) {
+	const follow = document.getElementById("follow-logs");
+	const scheme = location.protocol === "https:" ? "wss" : "ws";
+
+	for (;;) {
+		let socket = null;
+		try {
+			socket = new WebSocket(`${scheme}://${location.host}/logs/websocket`);
+
+			// Each connection starts with the end of the current log
+			socket.addEventListener("open", () => { logs.textContent = ""; });
+
+			socket.addEventListener("message", (event) => {
+				logs.textContent += event.data;
+				if (logs.textContent.length > MAX_LOG_LENGTH) {
+					const excess = logs.textContent.length - MAX_LOG_LENGTH;
+					const line_start = logs.textContent.indexOf("\n", excess) + 1;
+					logs.textContent = logs.textContent.slice(line_start);
+				}
+				if (follow.checked) {
+					logs.scrollTop = logs.scrollHeight;
+				}
+			});
+
+			await new Promise(resolve => socket.addEventListener("close", resolve));
+		} catch (err) {
+			console.error(err);
+		}
+		console.error("Log viewer connection was closed, trying to reconnect after 1s");
+		await delay(1000);
+	}
+}
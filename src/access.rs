@@ -0,0 +1,166 @@
+//! Access control protecting Harmonia from changes made by others in the network
+//!
+//! Harmonia listens on all interfaces by default, so anyone in the same network could remove
+//! blocks or start playback. Two modes limit what other computers can do:
+//!
+//! - with `--token` (or `HARMONIA_TOKEN` environment variable) other computers must carry the
+//!   token to make changes,
+//! - with `--read-only-remote` and no token other computers are only viewers: they see blocks
+//!   and playback status, but UI is rendered without controls.
+//!
+//! [restrict_changes] middleware rejects mutating requests (anything other then `GET` and `HEAD`)
+//! from clients that [cannot change][can_change] anything. Requests from the same computer are
+//! always allowed. [OSC messages][crate::osc] cannot carry the token, so in both modes they are
+//! accepted only from the same computer.
+//!
+//! Token is accepted from:
+//!
+//! - `Authorization: Bearer <token>` header, for scripts,
+//! - [TOKEN_COOKIE] cookie, set for the browser by `POST /login`.
+
+use crate::AppState;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE, SET_COOKIE},
+        HeaderMap, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Form,
+};
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use tracing::{info, warn};
+
+/// Name of the cookie in which browser keeps the token
+pub const TOKEN_COOKIE: &str = "harmonia_token";
+
+/// Path of the route that sets [TOKEN_COOKIE], it doesn't require the token itself
+pub const LOGIN_PATH: &str = "/login";
+
+/// Validate token given on command line, it must fit in the cookie
+pub fn parse_token(token: &str) -> Result<String, String> {
+    if token.is_empty() {
+        return Err("access token cannot be empty".to_string());
+    }
+    if !token
+        .bytes()
+        .all(|byte| byte.is_ascii_graphic() && !matches!(byte, b';' | b',' | b'"' | b'\\'))
+    {
+        return Err(
+            "access token may contain only printable ASCII characters without spaces, `;`, `,`, `\"` and `\\`"
+                .to_string(),
+        );
+    }
+    Ok(token.to_string())
+}
+
+/// Compare tokens in time independent of the position of the first difference
+fn tokens_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (lhs, rhs)| difference | (lhs ^ rhs))
+            == 0
+}
+
+/// Token carried by the request, see [module documentation][self]
+fn request_token(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    bearer.or_else(|| {
+        headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == TOKEN_COOKIE)
+            .map(|(_, value)| value)
+    })
+}
+
+/// Whether client with given address and request headers can change anything
+pub fn can_change(app_state: &AppState, address: &SocketAddr, headers: &HeaderMap) -> bool {
+    if address.ip().is_loopback() {
+        return true;
+    }
+    match &app_state.token {
+        Some(token) => request_token(headers).map_or(false, |given| tokens_match(token, given)),
+        None => !app_state.read_only_remote,
+    }
+}
+
+/// Middleware rejecting mutating requests from clients that [cannot change][can_change] anything
+pub async fn restrict_changes(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if matches!(*request.method(), Method::GET | Method::HEAD)
+        || request.uri().path() == LOGIN_PATH
+        || can_change(&app_state, &address, request.headers())
+    {
+        return next.run(request).await;
+    }
+
+    warn!(
+        "rejected {} {} from {address}, it cannot change anything",
+        request.method(),
+        request.uri().path()
+    );
+    if app_state.token.is_some() {
+        (StatusCode::UNAUTHORIZED, "missing or invalid access token").into_response()
+    } else {
+        (StatusCode::FORBIDDEN, "remote clients can only view").into_response()
+    }
+}
+
+/// Payload of login request
+#[derive(Deserialize)]
+pub struct Login {
+    /// Token given by the user
+    token: String,
+}
+
+/// Remembers valid token in the browser with [TOKEN_COOKIE]
+pub async fn login(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    Form(Login { token: given }): Form<Login>,
+) -> Response {
+    let Some(token) = &app_state.token else {
+        return StatusCode::OK.into_response();
+    };
+
+    if !tokens_match(token, given.trim()) {
+        warn!("invalid access token given by {address}");
+        return (StatusCode::UNAUTHORIZED, "invalid access token").into_response();
+    }
+
+    info!("{address} logged in with access token");
+    (
+        [(
+            SET_COOKIE,
+            format!(
+                "{TOKEN_COOKIE}={}; Path=/; SameSite=Strict; HttpOnly{}",
+                given.trim(),
+                // Over HTTPS cookie shouldn't leak through plain HTTP
+                if app_state.scheme == "https" {
+                    "; Secure"
+                } else {
+                    ""
+                }
+            ),
+        )],
+        StatusCode::OK,
+    )
+        .into_response()
+}
@@ -0,0 +1,874 @@
+//! Routes meant for scripts and other programs rather than the browser UI.
+//!
+//! While [handlers] render HTML for [HTMX], handlers in this module accept and return plain data,
+//! so Harmonia can be controlled with tools like `curl` without constructing HTML forms.
+//!
+//! [handlers]: crate::handlers
+//! [HTMX]: https://htmx.org
+
+use crate::{audio_engine, block, handlers, AppState};
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, FromRequestParts, Path, Query, State},
+    http::{header::ACCEPT, request::Parts, HeaderMap, StatusCode},
+    Json,
+};
+use rusty_link::SessionState;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use tracing::{error, info};
+
+/// Representation of the response requested by the client
+///
+/// Routes shared between UI and programmatic clients use it to decide whether to render HTML
+/// fragment for HTMX or to respond with JSON. JSON is chosen when the `Accept` header asks for
+/// `application/json` (and not for HTML) or when `?format=json` query parameter is present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// HTML fragment, default for browsers and HTMX
+    Html,
+
+    /// JSON document for scripts and other programs
+    Json,
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for Format
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query_requests_json = parts
+            .uri
+            .query()
+            .map(|query| query.split('&').any(|pair| pair == "format=json"))
+            .unwrap_or(false);
+
+        let accept_requests_json = parts
+            .headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map(|accept| accept.contains("application/json") && !accept.contains("text/html"))
+            .unwrap_or(false);
+
+        Ok(if query_requests_json || accept_requests_json {
+            Self::Json
+        } else {
+            Self::Html
+        })
+    }
+}
+
+/// JSON representation of the block, without it's content
+#[derive(Serialize)]
+pub struct BlockSummary {
+    /// Unique identifier of the block
+    pub uuid: String,
+
+    /// Human readable name of the block
+    pub name: String,
+
+    /// Kind of the block content, see [block::Content::kind]
+    pub kind: &'static str,
+
+    /// Group in which block is played
+    pub group: String,
+
+    /// Associated keybind
+    pub keybind: String,
+
+    /// Free-text notes of the block, see [block::Block::description]
+    pub description: String,
+
+    /// Labels of the block, see [block::Block::tags]
+    pub tags: Vec<String>,
+
+    /// Color label of the block, see [block::Block::color]
+    pub color: Option<block::Color>,
+
+    /// MIDI message starting and stopping the block, see [crate::midi_input]
+    pub midi_trigger: Option<crate::midi_input::Trigger>,
+
+    /// Custom order from the user
+    pub order: Option<usize>,
+
+    /// MIDI port for blocks played through MIDI, see [block::Content::port]
+    pub port: Option<usize>,
+
+    /// Reason why block failed integrity verification, see [crate::integrity]
+    pub integrity_error: Option<String>,
+
+    /// Whether block starts again when it ends, see [block::Block::loop_playback]
+    pub loop_playback: bool,
+
+    /// Launch mode override of the block, [None] when session default is used
+    pub launch: Option<block::Launch>,
+
+    /// Number of beats counted in before block starts
+    pub count_in: u32,
+
+    /// Tick from which MIDI block starts, see [block::MidiSource::start_tick]
+    pub start_tick: Option<u64>,
+
+    /// Tick on which MIDI block ends, see [block::MidiSource::end_tick]
+    pub end_tick: Option<u64>,
+
+    /// Velocity scaling of MIDI block, see [block::MidiSource::velocity]
+    pub velocity: Option<block::Velocity>,
+
+    /// Format, track count and length of MIDI file, see [block::MidiMetadata]
+    pub metadata: Option<block::MidiMetadata>,
+}
+
+impl BlockSummary {
+    /// Describe given block
+    pub fn new(uuid: &str, block: &block::Block) -> Self {
+        let (start_tick, end_tick, velocity, metadata) = match &block.content {
+            block::Content::Midi(source) => (
+                source.start_tick,
+                source.end_tick,
+                Some(source.velocity),
+                source.metadata().map(std::borrow::Cow::into_owned),
+            ),
+            _ => (None, None, None, None),
+        };
+        Self {
+            uuid: uuid.to_owned(),
+            name: block.content.name(),
+            kind: block.content.kind(),
+            group: block.group.clone(),
+            keybind: block.keybind.clone(),
+            description: block.description.clone(),
+            tags: block.tags.clone(),
+            color: block.color,
+            midi_trigger: block.midi_trigger,
+            order: block.order,
+            port: block.content.port(),
+            integrity_error: block.integrity_error.clone(),
+            loop_playback: block.loop_playback,
+            launch: block.launch,
+            count_in: block.count_in,
+            start_tick,
+            end_tick,
+            velocity,
+            metadata,
+        }
+    }
+}
+
+/// Describe all blocks in the same order as they are presented in UI
+pub fn block_summaries(app_state: &AppState) -> Vec<BlockSummary> {
+    let blocks = app_state.blocks.read().unwrap();
+    handlers::ordered_blocks(&blocks)
+        .into_iter()
+        .map(|(uuid, block)| BlockSummary::new(uuid, block))
+        .collect()
+}
+
+/// Responds with all blocks in the same order as they are presented in UI
+pub async fn blocks(State(app_state): State<Arc<AppState>>) -> Json<Vec<BlockSummary>> {
+    Json(block_summaries(&app_state))
+}
+
+/// Responds with given block
+pub async fn block(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<BlockSummary>, (StatusCode, String)> {
+    let blocks = app_state.blocks.read().unwrap();
+    let block = blocks
+        .get(&uuid)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("block#{uuid} not found")))?;
+    Ok(Json(BlockSummary::new(&uuid, block)))
+}
+
+/// Changes of the block settings, missing fields stay unchanged
+#[derive(Deserialize)]
+pub struct BlockChanges {
+    /// Group in which block is played
+    group: Option<String>,
+
+    /// MIDI port, only for blocks played through MIDI
+    port: Option<usize>,
+
+    /// Associated keybind
+    keybind: Option<String>,
+
+    /// Free-text notes of the block
+    description: Option<String>,
+
+    /// Labels of the block, replacing the current ones
+    tags: Option<Vec<String>>,
+
+    /// Name of file based or shared memory block, see [block::Content::rename]
+    name: Option<String>,
+}
+
+/// Changes name, group, port, keybind, description or tags of given block
+///
+/// Changes are applied only when all of them are valid. Responds with updated block.
+pub async fn update_block(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Json(changes): Json<BlockChanges>,
+) -> Result<Json<BlockSummary>, (StatusCode, String)> {
+    let max_port = app_state.midi_port_count();
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+        let block = blocks
+            .get_mut(&uuid)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("block#{uuid} not found")))?;
+
+        if let Some(name) = &changes.name {
+            if !block.content.can_rename() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("{} blocks cannot be renamed", block.content.kind()),
+                ));
+            }
+            block::validate_name(name).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        }
+        if let Some(port) = changes.port {
+            #[allow(clippy::absurd_extreme_comparisons)]
+            if port < handlers::MIN_PORT_NUMBER || port > max_port {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "port number should be between {} and {max_port}",
+                        handlers::MIN_PORT_NUMBER
+                    ),
+                ));
+            }
+            let associated_port = block.content.port_mut().ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("block#{uuid} is not played through MIDI"),
+                )
+            })?;
+            info!("setting port {port} for block#{uuid}");
+            *associated_port = port;
+        }
+        if let Some(name) = changes.name {
+            block
+                .content
+                .rename(&name)
+                .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+            info!("renamed block#{uuid} to {:?}", block.content.name());
+        }
+        if let Some(group) = changes.group {
+            block.group = handlers::limit_group_length(&group);
+            info!("switched block#{uuid} to group {:?}", block.group);
+        }
+        if let Some(keybind) = changes.keybind {
+            info!("changing keybind for block#{uuid} to {keybind}");
+            block.keybind = keybind;
+        }
+        if let Some(description) = changes.description {
+            block.description = handlers::limit_description_length(&description);
+            info!(
+                "changing description of block#{uuid} to {:?}",
+                block.description
+            );
+        }
+        if let Some(tags) = changes.tags {
+            block.tags = handlers::normalize_tags(tags.iter().map(String::as_str));
+            info!("changing tags of block#{uuid} to {:?}", block.tags);
+        }
+        Json(BlockSummary::new(&uuid, block))
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::update_block failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Moves given block to [trash][crate::trash], responds with removed block
+pub async fn remove_block(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<BlockSummary>, (StatusCode, String)> {
+    let block = app_state
+        .blocks
+        .write()
+        .unwrap()
+        .remove(&uuid)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("block#{uuid} not found")))?;
+    let summary = BlockSummary::new(&uuid, &block);
+
+    info!("moving block#{uuid} to trash");
+    app_state.trash.lock().unwrap().put(uuid, block);
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::remove_block failed to remember current sources: {err:#}")
+    }
+
+    Ok(Json(summary))
+}
+
+/// JSON representation of the main page
+#[derive(Serialize)]
+pub struct Overview {
+    /// Current state of synchronization and playback
+    pub status: Status,
+
+    /// All blocks in the UI order
+    pub blocks: Vec<BlockSummary>,
+}
+
+/// Taps further apart than this start new tempo measurement
+const TAP_RESET: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Number of the most recent taps from which tempo is computed
+const MAX_TAPS: usize = 8;
+
+/// Result of the tap
+#[derive(Serialize)]
+pub struct Tap {
+    /// Number of taps in current measurement
+    pub taps: usize,
+
+    /// Tempo set from the taps, [None] until there are at least two taps
+    pub bpm: Option<f64>,
+}
+
+/// Registers tap and sets Link tempo from the average interval between recent taps
+///
+/// Taps more than [TAP_RESET] apart start new measurement, so the previous tempo is not mixed in.
+pub async fn tap(State(app_state): State<Arc<AppState>>) -> Json<Tap> {
+    let now = std::time::Instant::now();
+    let mut taps = app_state.taps.lock().unwrap();
+
+    if taps
+        .last()
+        .map_or(false, |last| now.duration_since(*last) > TAP_RESET)
+    {
+        taps.clear();
+    }
+    taps.push(now);
+    if taps.len() > MAX_TAPS {
+        taps.remove(0);
+    }
+
+    let bpm = match (taps.first(), taps.last()) {
+        (Some(first), Some(last)) if taps.len() >= 2 => {
+            let interval = last.duration_since(*first).as_secs_f64() / (taps.len() - 1) as f64;
+            Some((60.0 / interval).clamp(handlers::MIN_TEMPO, handlers::MAX_TEMPO))
+        }
+        _ => None,
+    };
+
+    if let Some(bpm) = bpm {
+        info!("setting tempo to {bpm:.1} BPM from {} taps", taps.len());
+        let mut session_state = SessionState::default();
+        app_state.link.capture_app_session_state(&mut session_state);
+        session_state.set_tempo(bpm, app_state.link.clock_micros());
+        app_state.link.commit_app_session_state(&session_state);
+        crate::timeline::record(
+            &app_state,
+            crate::timeline::Kind::TempoChanged,
+            None,
+            format!("{bpm:.1} BPM from {} taps", taps.len()),
+        );
+    }
+
+    Json(Tap {
+        taps: taps.len(),
+        bpm,
+    })
+}
+
+/// Header that may carry the file name of the uploaded MIDI file
+pub const FILE_NAME_HEADER: &str = "X-File-Name";
+
+/// Query parameters for raw MIDI upload
+#[derive(Deserialize)]
+pub struct UploadMidi {
+    /// Original file name of the uploaded MIDI file
+    name: Option<String>,
+
+    /// Group for created blocks, overriding the default one
+    group: Option<String>,
+}
+
+/// Adds new MIDI block with the request body being the MIDI file itself
+///
+/// File name is taken from `name` query parameter or [FILE_NAME_HEADER] header, which allows
+/// uploading with `curl --data-binary @piece.mid`. Responds with the identifier of created block.
+///
+/// Zip archives (recognized by `.zip` extension) create block for each contained MIDI file, see
+/// [handlers::insert_zip_archive]. Identifiers of created blocks are returned one per line.
+pub async fn add_new_midi_source_block(
+    State(app_state): State<Arc<AppState>>,
+    Query(UploadMidi { name, group }): Query<UploadMidi>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<String, (StatusCode, String)> {
+    let file_name = name
+        .or_else(|| {
+            headers
+                .get(FILE_NAME_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        })
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("missing file name, provide it with ?name= or {FILE_NAME_HEADER} header"),
+            )
+        })?;
+
+    if handlers::is_zip_archive(&file_name) {
+        let import =
+            handlers::import_zip_archive(app_state.clone(), std::io::Cursor::new(body), group)
+                .await
+                .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))?;
+        info!(
+            "added {} blocks from {file_name:?}, skipped {} files",
+            import.uuids.len(),
+            import.skipped.len()
+        );
+
+        if let Err(err) = app_state.remember_current_blocks() {
+            error!("api::add_new_midi_source_block failed to remember current sources: {err:#}")
+        }
+
+        return Ok(import.uuids.join("\n"));
+    }
+
+    if let Err(err) = midly::Smf::parse(&body) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{file_name:?} is not a valid MIDI file: {err}"),
+        ));
+    }
+
+    let uuid = handlers::insert_midi_block(&app_state, file_name, body.to_vec(), group.as_deref());
+    info!("added block#{uuid} from raw upload");
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::add_new_midi_source_block failed to remember current sources: {err:#}")
+    }
+
+    Ok(uuid)
+}
+
+/// Adds new scene block described by JSON [block::Scene]
+///
+/// Identifier is derived from the name of the scene, so adding scene with the same name replaces
+/// previous one. Responds with the identifier of created block.
+pub async fn add_new_scene_block(
+    State(app_state): State<Arc<AppState>>,
+    Json(scene): Json<block::Scene>,
+) -> Result<String, (StatusCode, String)> {
+    if scene.blocks.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "scene must start at least one block".to_string(),
+        ));
+    }
+
+    {
+        let blocks = app_state.blocks.read().unwrap();
+        for member in &scene.blocks {
+            let Some(block) = blocks.get(member) else {
+                return Err((StatusCode::NOT_FOUND, format!("block#{member} not found")));
+            };
+            if !block::Scene::can_include(&block.content) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "block#{member} of kind {} cannot be part of scene",
+                        block.content.kind()
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(scene.name.as_bytes());
+    let uuid = format!("scene-{}", hex::encode(hasher.finalize()));
+
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Scene(scene));
+    app_state
+        .blocks
+        .write()
+        .unwrap()
+        .insert(uuid.clone(), block);
+    info!("added scene block#{uuid}");
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::add_new_scene_block failed to remember current sources: {err:#}")
+    }
+
+    Ok(uuid)
+}
+
+/// Adds new pattern block described by JSON [block::Pattern]
+///
+/// Identifier is derived from the name of the pattern, so adding pattern with the same name
+/// replaces previous one. Responds with the identifier of created block.
+pub async fn add_new_pattern_block(
+    State(app_state): State<Arc<AppState>>,
+    Json(pattern): Json<block::Pattern>,
+) -> Result<String, (StatusCode, String)> {
+    pattern
+        .validate()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let mut hasher = Sha1::new();
+    hasher.update(pattern.name.as_bytes());
+    let uuid = format!("pattern-{}", hex::encode(hasher.finalize()));
+
+    // Port is part of the pattern description, so only the default group is applied
+    let mut block = block::Block::new(block::Content::Pattern(pattern));
+    block.group = app_state.defaults.read().unwrap().group.clone();
+    app_state
+        .blocks
+        .write()
+        .unwrap()
+        .insert(uuid.clone(), block);
+    info!("added pattern block#{uuid}");
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::add_new_pattern_block failed to remember current sources: {err:#}")
+    }
+
+    Ok(uuid)
+}
+
+/// Run `edit` on the pattern of given block, validating the result before it's stored
+fn edit_pattern(
+    app_state: &AppState,
+    uuid: &str,
+    edit: impl FnOnce(&mut block::Pattern) -> Result<(), String>,
+) -> Result<Json<block::Pattern>, (StatusCode, String)> {
+    let pattern = {
+        let mut blocks = app_state.blocks.write().unwrap();
+        let Some(block) = blocks.get_mut(uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+        let block::Content::Pattern(ref mut current) = block.content else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("block#{uuid} is not a pattern"),
+            ));
+        };
+
+        let mut pattern = current.clone();
+        edit(&mut pattern).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        pattern
+            .validate()
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        *current = pattern.clone();
+        pattern
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::edit_pattern failed to remember current sources: {err:#}")
+    }
+
+    Ok(Json(pattern))
+}
+
+/// Replaces the pattern of given block, keeping it's output port
+pub async fn set_pattern(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Json(new_pattern): Json<block::Pattern>,
+) -> Result<Json<block::Pattern>, (StatusCode, String)> {
+    edit_pattern(&app_state, &uuid, |pattern| {
+        *pattern = block::Pattern {
+            associated_port: pattern.associated_port,
+            ..new_pattern
+        };
+        Ok(())
+    })
+}
+
+/// Request to change a single step of the pattern
+#[derive(Deserialize)]
+pub struct SetPatternStep {
+    /// Index of the row of the pattern
+    row: usize,
+
+    /// Index of the step within the row
+    step: usize,
+
+    /// Whether note should be played on this step
+    on: bool,
+}
+
+/// Turns single step of the pattern on or off, responding with the updated pattern
+pub async fn set_pattern_step(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Json(SetPatternStep { row, step, on }): Json<SetPatternStep>,
+) -> Result<Json<block::Pattern>, (StatusCode, String)> {
+    edit_pattern(&app_state, &uuid, |pattern| {
+        let cell = pattern
+            .rows
+            .get_mut(row)
+            .and_then(|row| row.steps.get_mut(step))
+            .ok_or_else(|| format!("step {step} of row {row} is outside of the pattern"))?;
+        *cell = on;
+        Ok(())
+    })
+}
+
+/// Responds with sections of given MIDI block, see [block::MidiSource::sections]
+pub async fn sections(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Json<Vec<block::Section>>, (StatusCode, String)> {
+    let blocks = app_state.blocks.read().unwrap();
+    match blocks.get(&uuid).map(|block| &block.content) {
+        Some(block::Content::Midi(midi_source)) => Ok(Json(midi_source.sections())),
+        Some(_) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("block#{uuid} is not a MIDI block"),
+        )),
+        None => Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found"))),
+    }
+}
+
+/// Replaces sections defined for given MIDI block
+///
+/// Empty list brings back sections derived from MIDI markers.
+pub async fn set_sections(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Json(sections): Json<Vec<block::Section>>,
+) -> Result<Json<Vec<block::Section>>, (StatusCode, String)> {
+    if let Some(section) = sections.iter().find(|section| {
+        !section.start.is_finite()
+            || section.start < 0.0
+            || section
+                .end
+                .map_or(false, |end| !end.is_finite() || end <= section.start)
+    }) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid range of section {:?}", section.name),
+        ));
+    }
+
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+        match blocks.get_mut(&uuid).map(|block| &mut block.content) {
+            Some(block::Content::Midi(midi_source)) => {
+                info!("setting {} sections for block#{uuid}", sections.len());
+                midi_source.sections = sections;
+                Json(midi_source.sections())
+            }
+            Some(_) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("block#{uuid} is not a MIDI block"),
+                ))
+            }
+            None => return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found"))),
+        }
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("api::set_sections failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Synchronization and playback state of Harmonia instance
+#[derive(Serialize)]
+pub struct Status {
+    /// Is Link session enabled
+    pub synchronized: bool,
+
+    /// Tempo of Link session in beats per minute
+    pub tempo: f64,
+
+    /// Current beat of Link session
+    pub beat: f64,
+
+    /// Quantum used for synchronization
+    pub quantum: f64,
+
+    /// Number of peers in Link session
+    pub peers: u64,
+
+    /// Currently played blocks, ordered by identifier
+    pub playing: Vec<Playing>,
+
+    /// Timing accuracy of MIDI blocks by their identifiers, each since it was last started
+    pub timing: BTreeMap<String, audio_engine::TimingStats>,
+
+    /// Failures that audio engine recovered from
+    pub engine: audio_engine::Health,
+}
+
+/// Description of currently played block
+#[derive(Serialize)]
+pub struct Playing {
+    /// Unique identifier of the block
+    pub uuid: String,
+
+    /// Human readable name of the block
+    pub name: String,
+
+    /// Beats played since the start of the block
+    pub progress_beats: f64,
+
+    /// Seconds played since the start of the block, based on current tempo
+    pub progress_seconds: f64,
+
+    /// Length of the block in beats, [None] for infinite blocks
+    pub length_beats: Option<f64>,
+
+    /// Length of the block in seconds based on current tempo, [None] for infinite blocks
+    pub length_seconds: Option<f64>,
+
+    /// Seconds until the end of the block based on current tempo, [None] for infinite blocks
+    pub remaining_seconds: Option<f64>,
+
+    /// Bar (counted from 1) that is currently played
+    pub bar: usize,
+
+    /// Number of bars in the block, [None] for infinite blocks
+    pub bar_count: Option<usize>,
+
+    /// Beats left until the block starts, [None] when it already started
+    pub countdown_beats: Option<f64>,
+}
+
+/// Collect current [Status] of the Harmonia instance
+pub fn collect_status(app_state: &AppState) -> Status {
+    let mut session_state = SessionState::default();
+    app_state.link.capture_app_session_state(&mut session_state);
+    let time = app_state.link.clock_micros();
+    let quantum = app_state.quantum;
+    let tempo = session_state.tempo();
+    let beat = session_state.beat_at_time(time, quantum);
+    let to_seconds = |beats: f64| beats * 60.0 / tempo;
+
+    let blocks = app_state.blocks.read().unwrap();
+    let playing = app_state
+        .currently_playing
+        .read()
+        .unwrap()
+        .iter()
+        .filter_map(|(uuid, progress)| {
+            let block = blocks.get(uuid)?;
+            let beat = session_state.beat_at_time(time, progress.quantum);
+            let progress_beats = progress.beat(beat);
+            Some(Playing {
+                name: block.content.name(),
+                uuid: uuid.clone(),
+                progress_beats,
+                progress_seconds: to_seconds(progress_beats),
+                length_beats: progress.length,
+                length_seconds: progress.length.map(to_seconds),
+                remaining_seconds: progress.remaining_seconds(beat, tempo),
+                bar: progress.bar(beat),
+                bar_count: progress.bar_count(),
+                countdown_beats: progress.countdown(beat),
+            })
+        })
+        .collect();
+
+    Status {
+        synchronized: app_state.link.is_enabled(),
+        tempo,
+        beat,
+        quantum,
+        peers: app_state.link.num_peers(),
+        playing,
+        timing: app_state
+            .timing
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(uuid, _)| blocks.contains_key(*uuid))
+            .map(|(uuid, stats)| (uuid.clone(), stats.clone()))
+            .collect(),
+        engine: app_state.audio_engine.read().unwrap().health(),
+    }
+}
+
+/// Responds with current [Status] of Harmonia instance
+pub async fn status(State(app_state): State<Arc<AppState>>) -> Json<Status> {
+    Json(collect_status(&app_state))
+}
+
+/// Plays given block and responds only after it finished or was interrupted
+///
+/// Response body is either `finished` or `interrupted`, allowing shell scripts to sequence blocks
+/// without polling [status].
+pub async fn play_and_wait(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    if !app_state.blocks.read().unwrap().contains_key(&uuid) {
+        return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+    }
+    handlers::ensure_local_programs(&app_state, addr, [uuid.as_str()])?;
+
+    audio_engine::play_and_wait(app_state, &uuid)
+        .await
+        .map(|ending| ending.to_string())
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))
+}
+
+/// Starts playing given blocks as a timed sequence
+///
+/// Accepts JSON list of [audio_engine::SequenceEntry]. Responds immediately, the sequence is
+/// executed by the [audio_engine] until finished or interrupted.
+pub async fn play_sequence(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Json(entries): Json<Vec<audio_engine::SequenceEntry>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    {
+        let blocks = app_state.blocks.read().unwrap();
+        if let Some(missing) = entries
+            .iter()
+            .find(|entry| !blocks.contains_key(&entry.uuid))
+        {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("block#{uuid} not found", uuid = missing.uuid),
+            ));
+        }
+    }
+    handlers::ensure_local_programs(
+        &app_state,
+        addr,
+        entries.iter().map(|entry| entry.uuid.as_str()),
+    )?;
+
+    if let Some(entry) = entries
+        .iter()
+        .find(|entry| !entry.start_at_beat.is_finite() || entry.start_at_beat < 0.0)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "invalid start beat {beat} for block#{uuid}",
+                beat = entry.start_at_beat,
+                uuid = entry.uuid
+            ),
+        ));
+    }
+
+    info!("starting sequence of {count} blocks", count = entries.len());
+    audio_engine::play_sequence(app_state, entries);
+    Ok(StatusCode::ACCEPTED)
+}
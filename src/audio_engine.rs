@@ -2,20 +2,26 @@
 //!
 //! Audio engine is a module that has all of the runtime machinery for Harmonia blocks, including
 //! the execution of them, creating audio output (currently only in form of MIDI messages),
-//! providing API for other applications (currently shared memory only), executing start procedure
-//! and more.
+//! providing API for other applications (currently shared memory only), launching external
+//! programs, executing start procedure and more.
 //!
 //! The [AudioEngine] holds the state that is held in [crate::AppState] and stays for the whole
 //! runtime of Harmonia instance. Functions [play], [quit] and [interrupt] interact with
 //! [AudioEngine] via [AppState] to send commands to running worker which allows for
 //! asynchronous communication between audio thread in this module and UI threads outside of it.
 //!
-//! [audio_engine_main] consumes incoming [tasks][Request] and executes them in a way that allows
-//! for automatic cleanup of previous task. Currently supported types of tasks are:
+//! [audio_engine_main] consumes incoming [tasks][Request] and executes each played block in it's
+//! own [Player], so several blocks (for example drums on one port and strings on another) can play
+//! at once. Currently supported types of tasks are:
 //!
-//! * [play] ([Request::Play]) - ask worker to interrupt any ongoing task and start playing
-//! new one, possibly starting new synchronization group and session or joining existing ones.
-//! * [interrupt] ([Request::Interrupt]) - stop playing currently played block if any
+//! * [play] ([Request::Play]) - ask worker to start playing new block next to the already played
+//! ones, possibly starting new synchronization group and session or joining existing ones.
+//! Block that is already playing is started again.
+//! * [play_and_wait] ([Request::Play]) - same as [play], but waits until block stops playing
+//! * [interrupt] ([Request::Interrupt]) - stop playing all currently played blocks if any
+//! * [interrupt_block] ([Request::Interrupt]) - stop playing given block only
+//! * [play_sequence] - play several blocks, each at the given beat from the start of sequence
+//! * [play_setlist] - play several blocks, each starting on the bar after the previous one ended
 //! * [quit] ([Request::Quit]) - request stop from worker and gracefull quit
 //!
 //! [quit] request should only be issued when the application is in gracefull shutdown procedure.
@@ -27,17 +33,22 @@
 // next integration.
 
 use std::{
-    sync::{Arc, Weak},
+    collections::{BTreeSet, HashMap},
+    sync::{atomic, Arc, Weak},
     time::Duration,
 };
 
-use anyhow::anyhow;
-use midir::{MidiOutput, MidiOutputConnection};
+use anyhow::{anyhow, Context};
+use futures::{future::BoxFuture, FutureExt};
+use midir::MidiOutput;
 use midly::live::LiveEvent;
 use rusty_link::SessionState;
 use tracing::{info, warn};
 
-use crate::{block, AppState};
+use crate::{
+    block, channels, clock::Clock, latency, mix, priority, timeline, virtual_port::MidiSink,
+    AppState,
+};
 
 /// State of the AudioEngine, including it's worker thread and channel to send requests
 pub struct AudioEngine {
@@ -51,6 +62,161 @@ pub struct AudioEngine {
 
     /// Incoming work channel used to send request to [audio_engine_main]
     work_in: tokio::sync::mpsc::Sender<Request>,
+
+    /// Task executing currently running [play_sequence] if any
+    sequence: Option<tokio::task::JoinHandle<()>>,
+
+    /// Incremented on each interrupt, to stop one-shot samples started before it
+    one_shot_generation: Arc<atomic::AtomicUsize>,
+
+    /// Blocks interrupted by the user that fade out before they stop, see [fade_out]
+    ///
+    /// Blocks restarted or stopped by panic are not listed here, so they stop right away.
+    fading: BTreeSet<String>,
+
+    /// Failures of the engine that [watchdog] recovered from
+    health: Health,
+}
+
+/// Failures of the [AudioEngine] that were recovered from, reported in UI and status
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct Health {
+    /// How many times engine failed since Harmonia started
+    pub failures: usize,
+
+    /// Description of the most recent failure
+    pub last_failure: Option<String>,
+
+    /// Local time of the most recent failure in RFC 3339 format
+    pub last_failure_time: Option<String>,
+}
+
+impl AudioEngine {
+    /// Failures that engine recovered from so far
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+}
+
+/// Single step of the sequence started with [play_sequence]
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SequenceEntry {
+    /// Block to be played
+    pub uuid: String,
+
+    /// Beat, counted from the start of the sequence, on which block should start
+    pub start_at_beat: f64,
+}
+
+/// Musical position of the currently played block
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    /// Link beat at which the block started
+    pub origin: f64,
+
+    /// Length of the block in beats, [None] for infinite blocks
+    pub length: Option<f64>,
+
+    /// Time signatures of the block
+    pub meter: block::Meter,
+
+    /// Quantum with which the block was started
+    pub quantum: f64,
+}
+
+impl Progress {
+    /// Beats played since the start of the block at given Link beat
+    pub fn beat(&self, link_beat: f64) -> f64 {
+        (link_beat - self.origin).max(0.0)
+    }
+
+    /// Beats left until the block starts at given Link beat, [None] when it already started
+    ///
+    /// Quantized start lands in the future, so there is a moment between request to play the
+    /// block and its first beat.
+    pub fn countdown(&self, link_beat: f64) -> Option<f64> {
+        let beats = self.origin - link_beat;
+        (beats > 0.0).then_some(beats)
+    }
+
+    /// Bar (counted from 1) played at given Link beat
+    pub fn bar(&self, link_beat: f64) -> usize {
+        let bar = self.meter.bar_at(self.beat(link_beat));
+        self.bar_count().map_or(bar, |count| bar.min(count))
+    }
+
+    /// Number of bars in the block, [None] for infinite blocks
+    pub fn bar_count(&self) -> Option<usize> {
+        self.length
+            .map(|length| (self.meter.bars(length).ceil() as usize).max(1))
+    }
+
+    /// Seconds until the end of the block at given Link beat and tempo, [None] for infinite blocks
+    ///
+    /// Blocks are played in Link beats, so tempo events of MIDI files don't change the estimate.
+    pub fn remaining_seconds(&self, link_beat: f64, tempo: f64) -> Option<f64> {
+        self.length
+            .map(|length| (length - self.beat(link_beat)).max(0.0) * 60.0 / tempo)
+    }
+
+    /// Fraction of the block that was played at given Link beat, [None] for infinite blocks
+    pub fn fraction(&self, link_beat: f64) -> Option<f64> {
+        self.length
+            .filter(|length| *length > 0.0)
+            .map(|length| (self.beat(link_beat) / length).min(1.0))
+    }
+}
+
+/// Lateness (in seconds) above which event is counted as late
+const LATE_EVENT_THRESHOLD: f64 = 0.005;
+
+/// Lateness (in seconds) above which playback is considered to fall behind Link clock
+const FALLING_BEHIND_THRESHOLD: f64 = 0.050;
+
+/// Accuracy of sending MIDI events of a played block
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct TimingStats {
+    /// Number of sent events
+    pub events: u64,
+
+    /// Number of events sent later than [LATE_EVENT_THRESHOLD]
+    pub late_events: u64,
+
+    /// Highest lateness of sent event in milliseconds
+    pub max_lateness_ms: f64,
+
+    /// Number of times playback fell behind Link clock more than [FALLING_BEHIND_THRESHOLD]
+    ///
+    /// Counted once for each run of such late events. Following events are still scheduled on
+    /// their own Link beats, so playback catches up by sending late events right away.
+    pub fell_behind: u64,
+
+    /// Whether the last event was later than [FALLING_BEHIND_THRESHOLD]
+    #[serde(skip)]
+    behind: bool,
+}
+
+impl TimingStats {
+    /// Record event sent `lateness` seconds after the Link beat on which it was scheduled
+    pub fn record(&mut self, lateness: f64) {
+        self.events += 1;
+        self.max_lateness_ms = self.max_lateness_ms.max(lateness * 1000.0);
+        if lateness > LATE_EVENT_THRESHOLD {
+            self.late_events += 1;
+        }
+
+        let behind = lateness > FALLING_BEHIND_THRESHOLD;
+        if behind && !self.behind {
+            self.fell_behind += 1;
+            warn!(
+                "playback fell behind Link clock by {:.1}ms, sending late events right away to catch up",
+                lateness * 1000.0
+            );
+        } else if !behind && self.behind {
+            info!("playback caught up with Link clock");
+        }
+        self.behind = behind;
+    }
 }
 
 /// Requests that [audio_engine_main] can receive
@@ -59,13 +225,54 @@ enum Request {
     /// Stop playing and quit the [audio_engine_main]
     Quit,
 
-    /// Stop playing and wait for more requests
-    Interrupt,
+    /// Stop playing given block, or all blocks when none is given, and wait for more requests
+    Interrupt(Option<String>),
 
-    /// Start playing given block and stop playing previous one
+    /// Start playing given block next to the already played ones, restarting it if it's already
+    /// played
     Play(RequestPlay),
 }
 
+/// Block played by the [AudioEngine] worker
+struct Player {
+    /// Interrupt flag and condition variable waking the player when it's interrupted
+    interrupt: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+
+    /// Task playing the block
+    task: tokio::task::JoinHandle<()>,
+
+    /// Blocks played as a part of the [scene][block::Scene], they are marked as playing under
+    /// their own identifiers, but stop only together with the whole scene
+    members: Vec<String>,
+}
+
+impl Player {
+    /// Ask player to stop, without waiting for it
+    fn interrupt(&self) {
+        *self.interrupt.0.lock().unwrap() = true;
+        self.interrupt.1.notify_all();
+    }
+
+    /// Wait until player stops
+    async fn join(self) {
+        // Panics of played blocks are handled by the task itself
+        if let Err(err) = self.task.await {
+            tracing::error!("block player failed: {err}");
+        }
+    }
+
+    /// Interrupt all players and wait until they stop
+    async fn stop_all(players: &mut HashMap<String, Player>) {
+        // All players are interrupted first, so they stop at the same time
+        for player in players.values() {
+            player.interrupt();
+        }
+        for (_, player) in players.drain() {
+            player.join().await;
+        }
+    }
+}
+
 /// Metadata needed to handle play request in [audio_engine_main]
 struct RequestPlay {
     /// Unique block identifier
@@ -73,6 +280,31 @@ struct RequestPlay {
 
     /// Application state from main thread
     app_state: Arc<AppState>,
+
+    /// Section of the MIDI block to play instead of the whole block
+    section: Option<usize>,
+
+    /// Channel to notify about the end of playing, used by [play_and_wait]
+    done: Option<tokio::sync::oneshot::Sender<Result<Ending, String>>>,
+}
+
+/// Reason why block stopped playing
+#[derive(Debug, Clone, Copy)]
+pub enum Ending {
+    /// Block played until it's end
+    Finished,
+
+    /// Block was interrupted by user, by the sequence or by starting it again
+    Interrupted,
+}
+
+impl std::fmt::Display for Ending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Finished => write!(f, "finished"),
+            Self::Interrupted => write!(f, "interrupted"),
+        }
+    }
 }
 
 impl std::fmt::Debug for RequestPlay {
@@ -90,7 +322,12 @@ async fn audio_engine_main(
     request_play: RequestPlay,
     interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
 ) -> anyhow::Result<()> {
-    let RequestPlay { uuid, app_state } = request_play;
+    let RequestPlay {
+        uuid,
+        app_state,
+        section,
+        ..
+    } = request_play;
 
     let block = {
         let blocks = app_state.blocks.read().unwrap();
@@ -102,271 +339,1473 @@ async fn audio_engine_main(
         block.clone()
     };
 
-    match block.content {
-        block::Content::Midi(midi) => {
-            audio_engine_main_midi(uuid, block.group, app_state, midi, interrupts).await
-        }
+    let quantum = block.quantum.unwrap_or(app_state.quantum);
 
-        block::Content::SharedMemory { path } => {
-            audio_engine_shered_memory_main(uuid, path, block.group, app_state, interrupts)
-                .await
-                .map_err(anyhow::Error::msg)
+    if let Some(section) = section {
+        let launch = block.launch.unwrap_or(app_state.launch);
+        let block::Content::Midi(midi) = &block.content else {
+            return Err(anyhow!(
+                "block#{uuid} is not a MIDI block, it has no sections"
+            ));
+        };
+        let midi = midi.section(section).map_err(anyhow::Error::msg)?;
+        if !count_in(&uuid, &block, quantum, launch, &app_state, &interrupts).await? {
+            return Ok(());
         }
+        let start = Start::Synchronize {
+            group: block.group,
+            launch,
+        };
+        let result = audio_engine_main_midi(
+            uuid.clone(),
+            start,
+            quantum,
+            app_state.clone(),
+            midi,
+            interrupts,
+        )
+        .await;
+        mark_stopped(&app_state, &uuid);
+        return result;
     }
+
+    play_block(uuid, block, quantum, None, app_state, interrupts).await
 }
 
-/// Play shared memory block
+/// Play content of given block with given quantum
 ///
-/// Starts synchronously and then updates time stored in shared memory. Creates and deletes shared
-/// memory.
-async fn audio_engine_shered_memory_main(
+/// Block starts on given `origin` beat when it's already known, as for blocks of the scene, and
+/// synchronizes it's start otherwise. Returns boxed future since [scenes][block::Scene] play their
+/// blocks recursively.
+fn play_block(
     uuid: String,
-    path: String,
-    group: String,
+    block: block::Block,
+    quantum: f64,
+    origin: Option<f64>,
     app_state: Arc<AppState>,
     interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
-) -> Result<(), String> {
+) -> BoxFuture<'static, anyhow::Result<()>> {
+    async move {
+        // Executors mark block as stopped themselves, unless they failed before
+        let stopped = (uuid.clone(), app_state.clone());
+        let launch = block.launch.unwrap_or(app_state.launch);
+        if !count_in(&uuid, &block, quantum, launch, &app_state, &interrupts).await? {
+            return Ok(());
+        }
+        let start = match origin {
+            Some(origin) => Start::At(origin),
+            None => Start::Synchronize {
+                group: block.group.clone(),
+                launch,
+            },
+        };
+        let result = match block.content {
+            block::Content::Midi(midi) => {
+                audio_engine_main_midi(uuid, start, quantum, app_state, midi, interrupts).await
+            }
+
+            block::Content::SharedMemory { path, .. } => {
+                audio_engine_shered_memory_main(uuid, path, start, quantum, app_state, interrupts)
+                    .await
+                    .map_err(anyhow::Error::msg)
+            }
+
+            block::Content::Sample(sample) => {
+                let quantum = block.quantum;
+                tokio::task::spawn_blocking(move || {
+                    sample_worker(&app_state, &sample, quantum, || {
+                        interrupts.0.try_lock().map(|x| *x).unwrap_or(false)
+                    })
+                })
+                .await?
+            }
+
+            block::Content::Audio(source) => {
+                audio_engine_audio_main(uuid, source, start, quantum, app_state, interrupts).await
+            }
+
+            block::Content::Command { program, args } => {
+                let mut command = std::process::Command::new(program);
+                command.args(args);
+                audio_engine_command_main(uuid, command, start, quantum, app_state, interrupts)
+                    .await
+            }
+
+            block::Content::Chord(chord) => {
+                let midi = chord.to_midi_source().map_err(anyhow::Error::msg)?;
+                audio_engine_main_midi(uuid, start, quantum, app_state, midi, interrupts).await
+            }
+
+            block::Content::Alias(alias) => {
+                let target = app_state
+                    .blocks
+                    .read()
+                    .unwrap()
+                    .get(&alias.target)
+                    .map(|target| target.content.clone())
+                    .ok_or_else(|| {
+                        anyhow!("block#{} referenced by alias not found", alias.target)
+                    })?;
+                let midi = alias.resolve(&target).map_err(anyhow::Error::msg)?;
+                audio_engine_main_midi(uuid, start, quantum, app_state, midi, interrupts).await
+            }
+
+            block::Content::Pattern(pattern) => {
+                audio_engine_pattern_main(
+                    uuid,
+                    pattern.associated_port,
+                    start,
+                    quantum,
+                    app_state,
+                    interrupts,
+                )
+                .await
+            }
+
+            block::Content::Metronome(metronome) => {
+                audio_engine_metronome_main(uuid, metronome, start, quantum, app_state, interrupts)
+                    .await
+            }
+
+            block::Content::Silence { beats } => {
+                audio_engine_silence_main(uuid, beats, start, quantum, app_state, interrupts).await
+            }
+
+            block::Content::Tempo { .. } => Err(anyhow!(
+                "block#{uuid} changes tempo, which is done next to the played blocks, not by them"
+            )),
+
+            block::Content::Scene(scene) => {
+                // Scene synchronizes once and all it's blocks start on the same beat. Starting
+                // each of them on it's own would see the ones already started as other playing
+                // blocks and delay the rest to the next quantum boundary.
+                let (_, origin) = start.resolve(&app_state, quantum).await;
+                let members: Vec<_> = {
+                    let blocks = app_state.blocks.read().unwrap();
+                    scene
+                        .blocks
+                        .iter()
+                        .map(|member_uuid| {
+                            let mut member = blocks.get(member_uuid).cloned().ok_or_else(|| {
+                                anyhow!("block#{member_uuid} referenced by scene not found")
+                            })?;
+                            // Scene was already counted in as a whole
+                            member.count_in = 0;
+                            Ok((member_uuid.clone(), member))
+                        })
+                        .collect::<anyhow::Result<_>>()?
+                };
+
+                let results =
+                    futures::future::join_all(members.into_iter().map(|(member_uuid, member)| {
+                        // Tempo changes like with [play], but stop together with the scene
+                        if let block::Content::Tempo { bpm, ramp_beats } = member.content {
+                            let app_state = app_state.clone();
+                            let interrupts = interrupts.clone();
+                            return async move {
+                                change_tempo(&app_state, bpm, ramp_beats, || {
+                                    interrupts.0.try_lock().map(|x| *x).unwrap_or(false)
+                                })
+                                .await;
+                                Ok(())
+                            }
+                            .boxed();
+                        }
+                        play_block(
+                            member_uuid,
+                            member,
+                            quantum,
+                            Some(origin),
+                            app_state.clone(),
+                            interrupts.clone(),
+                        )
+                    }))
+                    .await;
+                results.into_iter().collect()
+            }
+        };
+        mark_stopped(&stopped.1, &stopped.0);
+        result
+    }
+    .boxed()
+}
+
+/// How executor of the block starts playing
+enum Start {
+    /// Synchronize the start with the session, see [start_synchronized]
+    Synchronize {
+        /// Group joined when nothing else is playing
+        group: String,
+
+        /// How the block is launched
+        launch: block::Launch,
+    },
+
+    /// Start on already synchronized beat, shared by all blocks of the scene
+    At(f64),
+}
+
+impl Start {
+    /// Session state and the beat on which the block starts, like [start_synchronized]
+    async fn resolve(self, app_state: &AppState, quantum: f64) -> (SessionState, f64) {
+        match self {
+            Self::Synchronize { group, launch } => {
+                start_synchronized(app_state, &group, quantum, launch).await
+            }
+            Self::At(origin) => {
+                let mut session_state = SessionState::new();
+                app_state.link.capture_app_session_state(&mut session_state);
+                (session_state, origin)
+            }
+        }
+    }
+}
+
+/// Start playing synchronously, alone or within the group
+///
+/// When other blocks are already playing or [quantized launch][block::Launch::Quantized] is
+/// requested, timeline of the session is kept, so the block starts on it's next quantum boundary
+/// without joining the group. Otherwise the timeline is realigned to start at beat 0.
+///
+/// Returns session state captured during the start and the beat on which the block starts, to be
+/// used by the executor.
+async fn start_synchronized(
+    app_state: &AppState,
+    group: &str,
+    quantum: f64,
+    launch: block::Launch,
+) -> (SessionState, f64) {
     let mut session_state = SessionState::new();
-    let quantum = 1.0;
+
+    let others_playing = !app_state.currently_playing.read().unwrap().is_empty();
+    if others_playing || launch == block::Launch::Quantized {
+        app_state.link.capture_app_session_state(&mut session_state);
+        let beat = session_state.beat_at_time(app_state.clock.micros(), quantum);
+        let origin = (beat / quantum).ceil() * quantum;
+        if others_playing {
+            tracing::info!("Other blocks are playing, starting on beat {origin}");
+        } else {
+            tracing::info!("Quantized launch, starting on beat {origin}");
+        }
+        return (session_state, origin);
+    }
 
     if group.is_empty() {
         tracing::info!("Empty group, starting using request_beat_at_time");
         app_state.link.capture_app_session_state(&mut session_state);
-        session_state.request_beat_at_time(0.0, app_state.link.clock_micros(), quantum);
+        session_state.request_beat_at_time(0.0, app_state.clock.micros(), quantum);
         app_state.link.commit_app_session_state(&session_state);
     } else {
         tracing::info!("Starting with group: {group:?}");
+        timeline::record(app_state, timeline::Kind::GroupJoined, None, group);
         app_state
             .groups
             .as_ref()
             .unwrap()
-            .start(&group)
+            .start_with_quantum(group, quantum)
             .await
             .unwrap();
     }
 
-    tokio::task::spawn_blocking(move || {
-        let shm = match shared_memory::ShmemConf::new()
-            .size(std::mem::size_of::<f64>())
-            .os_id(&path)
-            .create()
-        {
-            Ok(shm) => shm,
-            Err(error) => return Err(error.to_string()),
-        };
-        let p = shm.as_ptr() as *mut f64;
-        tracing::info!("creating shared_memory instance {path}");
-
-        *app_state.currently_playing_uuid.write().unwrap() = Some(uuid.clone());
-        *app_state.current_playing_progress.write().unwrap() = (0_usize, 0_usize);
-        info!("commiting start state");
-
-        loop {
-            let (interrupt, interruptable_sleep) = &*interrupts;
-            let interrupted = interrupt.try_lock().map(|x| *x).unwrap_or(false);
-            if interrupted {
-                break;
-            }
+    (session_state, 0.0)
+}
 
-            app_state.link.capture_app_session_state(&mut session_state);
+/// Channel of count-in clicks, General MIDI percussion channel 10
+const COUNT_IN_CHANNEL: u8 = 9;
 
-            let time = session_state.beat_at_time(app_state.link.clock_micros(), quantum);
-            unsafe { *p = time };
+/// Key of count-in click on the first beat of the bar, General MIDI Hi Wood Block
+const COUNT_IN_ACCENT_KEY: u8 = 76;
 
-            let guard = interrupt.lock().unwrap();
-            let (interrupted, _) = interruptable_sleep
-                .wait_timeout(guard, Duration::from_secs_f64(0.0001))
-                .unwrap();
+/// Key of other count-in clicks, General MIDI Low Wood Block
+const COUNT_IN_KEY: u8 = 77;
 
-            if *interrupted {
-                break;
-            }
-        }
+/// How long count-in click is held
+const COUNT_IN_CLICK_DURATION: Duration = Duration::from_millis(50);
 
-        let task = {
-            let app_state = app_state.clone();
-            tokio::spawn(async move { app_state.groups.as_ref().unwrap().stop().await })
-        };
+/// Count in [Block::count_in][block::Block::count_in] beats before the block starts
+///
+/// Count-in starts synchronously like the block itself and ends on the quantum boundary, on which
+/// the block starts. During the count-in block is reported as playing with the countdown to it's
+/// start, and beats are clicked on the MIDI port of the block, if it has one.
+///
+/// Returns false when interrupted during the count-in, so the block should not be played.
+async fn count_in(
+    uuid: &str,
+    block: &block::Block,
+    quantum: f64,
+    launch: block::Launch,
+    app_state: &Arc<AppState>,
+    interrupts: &Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<bool> {
+    let beats = block.count_in;
+    if beats == 0 || !block.content.is_synchronized() {
+        return Ok(true);
+    }
 
-        *app_state.currently_playing_uuid.write().unwrap() = None;
-        Ok(task)
-    })
-    .await
-    .unwrap()?
-    .await
-    .unwrap();
+    let (session_state, start) = start_synchronized(app_state, &block.group, quantum, launch).await;
+    let origin = start + (beats as f64 / quantum).ceil() * quantum;
+    info!("counting in {beats} beats, block#{uuid} starts on beat {origin}");
+    mark_playing(
+        app_state,
+        uuid,
+        Progress {
+            quantum,
+            origin,
+            ..Default::default()
+        },
+    );
+
+    let port = block.content.port();
+    let counted = {
+        let app_state = app_state.clone();
+        let interrupts = interrupts.clone();
+        tokio::task::spawn_blocking(move || match port {
+            Some(port) => with_midi_output(&app_state, port, |output| {
+                count_in_worker(
+                    &app_state,
+                    &interrupts,
+                    session_state,
+                    quantum,
+                    origin,
+                    beats,
+                    Some(output),
+                )
+            }),
+            None => count_in_worker(
+                &app_state,
+                &interrupts,
+                session_state,
+                quantum,
+                origin,
+                beats,
+                None,
+            ),
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result)
+    };
 
-    Ok(())
+    if !matches!(counted, Ok(true)) {
+        mark_stopped(app_state, uuid);
+        stop_synchronized(app_state).await;
+    }
+    counted
 }
 
-/// Worker that actually plays the MIDI source
-fn midi_worker(
-    app_state: Arc<AppState>,
-    uuid: String,
-    midi_source: block::MidiSource,
-    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+/// Click count-in beats that end on `origin` and wait until the block should be started
+///
+/// Returns false when interrupted.
+fn count_in_worker(
+    app_state: &AppState,
+    interrupts: &(std::sync::Mutex<bool>, std::sync::Condvar),
     mut session_state: SessionState,
     quantum: f64,
-) -> anyhow::Result<()> {
-    // TODO: Find better solution then checking two times if we have this source
-    let midi = midi_source
-        .midi()
-        .map_err(|err| format!("failed to parse midi: {err}"))
-        .unwrap();
+    origin: f64,
+    beats: u32,
+    mut output: Option<&mut Output>,
+) -> anyhow::Result<bool> {
+    let mut wait_until = |beat: f64, latency: i64| loop {
+        app_state.link.capture_app_session_state(&mut session_state);
+        let current_time = session_state.beat_at_time(app_state.clock.micros() + latency, quantum);
+        if current_time >= beat {
+            return (true, session_state.tempo());
+        }
 
-    let ticks_per_quater_note = match midi.header.timing {
-        midly::Timing::Metrical(tpqn) => tpqn.as_int(),
-        _ => unimplemented!("Timecode timing format is not supported"),
+        let sleep_time = (beat - current_time) * 60.0 / session_state.tempo();
+        let sleep_time = Duration::from_secs_f64(sleep_time).min(SILENCE_POLL_INTERVAL);
+        if app_state.clock.sleep(interrupts, sleep_time) {
+            return (false, session_state.tempo());
+        }
     };
 
-    #[cfg(unix)]
-    let conn = app_state.connection.read().unwrap();
-
-    #[cfg(unix)]
-    let mut virtual_output = { conn.virtual_port.lock().unwrap() };
-
-    #[allow(unused_assignments)]
-    let mut conn_storage = None;
-
-    {
-        let output: &mut MidiOutputConnection = {
-            let port_number = midi_source
-                .associated_port
-                .max(crate::handlers::MIN_PORT_NUMBER);
-            if port_number == 0 {
-                #[cfg(windows)]
-                unreachable!();
-
-                #[cfg(unix)]
-                &mut virtual_output
-            } else {
-                let out = MidiOutput::new("harmonia")?;
-                let ports = out.ports();
-                let Some(midi_port) = ports.get(port_number - 1) else {
-                    return Err(anyhow!(
-                        "failed to connect to unknown midi port number {} (max {})",
-                        port_number,
-                        ports.len()
-                    ));
-                };
-                info!(
-                    "outputing to output port #{} named: {}",
-                    midi_source.associated_port,
-                    out.port_name(&midi_port).unwrap(),
-                );
+    let mut buf = Vec::new();
+    let latency = output.as_ref().map_or(0, |output| output.latency);
+    for beat in (0..beats).map(|beat| origin - (beats - beat) as f64) {
+        let (counting, tempo) = wait_until(beat, latency);
+        if !counting {
+            return Ok(false);
+        }
 
-                conn_storage = Some(
-                    out.connect(&midi_port, /* TODO: Better name */ "harmonia-play")
-                        .map_err(|err| {
-                            anyhow::Error::msg(format!("failed to connect to midi port: {err}"))
-                        })?,
-                );
-                conn_storage.as_mut().unwrap()
-            }
+        let Some(output) = output.as_deref_mut() else {
+            continue;
         };
+        let key = if beat.rem_euclid(quantum) == 0.0 {
+            COUNT_IN_ACCENT_KEY
+        } else {
+            COUNT_IN_KEY
+        };
+        for message in [
+            midly::MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+            midly::MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            },
+        ] {
+            if matches!(message, midly::MidiMessage::NoteOff { .. }) {
+                std::thread::sleep(COUNT_IN_CLICK_DURATION);
+            }
+            buf.clear();
+            LiveEvent::Midi {
+                channel: COUNT_IN_CHANNEL.into(),
+                message,
+            }
+            .write(&mut buf)
+            .expect("count-in messages are valid so they must serialize");
+            send_midi(app_state, output, tempo, &buf)
+                .map_err(|err| anyhow!("failed to send count-in click: {err}"))?;
+        }
+    }
 
-        *app_state.currently_playing_uuid.write().unwrap() = Some(uuid.clone());
-        *app_state.current_playing_progress.write().unwrap() =
-            (0_usize, midi.tracks.last().unwrap().len());
-        info!("commiting start state");
+    // Block starts synchronizing a moment before it's origin, while other blocks are playing, so
+    // it lands on the origin as the next quantum boundary
+    Ok(wait_until(origin - (quantum / 2.0).min(0.5), 0).0)
+}
 
-        let mut time_passed = 0.0;
-        let mut track = midi.tracks.last().unwrap().iter().enumerate();
+/// Leave the group after the block stopped playing, unless other blocks are still playing
+async fn stop_synchronized(app_state: &AppState) {
+    let others_playing = !app_state.currently_playing.read().unwrap().is_empty();
+    if !others_playing {
+        app_state.groups.as_ref().unwrap().stop().await;
+    }
+}
 
-        let mut notes_played_per_channel = [[false; 128]; 16];
+/// Mark block as playing with given musical progress
+fn mark_playing(app_state: &AppState, uuid: &str, progress: Progress) {
+    let previous = app_state
+        .currently_playing
+        .write()
+        .unwrap()
+        .insert(uuid.to_string(), progress);
+    if previous.is_none() {
+        app_state.notify(crate::Change::Playback);
+    }
+}
 
-        'audio_loop: loop {
-            let Some((nth, (bytes, event))) = track.next() else {
-                break;
-            };
+/// Mark block as no longer playing
+fn mark_stopped(app_state: &AppState, uuid: &str) {
+    app_state.audio_engine.write().unwrap().fading.remove(uuid);
+    let removed = app_state.currently_playing.write().unwrap().remove(uuid);
+    if removed.is_some() {
+        app_state.notify(crate::Change::Playback);
+    }
+}
 
-            *app_state.current_playing_progress.write().unwrap() =
-                (nth, midi.tracks.last().unwrap().len());
+/// How often sample playback checks if it should stop
+const SAMPLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
-            let (interrupt, interruptable_sleep) = &*interrupts;
-            let interrupted = interrupt.try_lock().map(|x| *x).unwrap_or(false);
-            if interrupted {
+/// Play audio sample through default system audio output
+///
+/// With `quantum` playback starts on the next quantum boundary of current Link session, otherwise
+/// immediately. Playback ends with the sample or when `should_stop` returns true.
+fn sample_worker(
+    app_state: &AppState,
+    sample: &block::AudioSource,
+    quantum: Option<f64>,
+    should_stop: impl Fn() -> bool,
+) -> anyhow::Result<()> {
+    let (_stream, handle) =
+        rodio::OutputStream::try_default().context("failed to open audio output")?;
+    let sink = rodio::Sink::try_new(&handle).context("failed to create audio sink")?;
+    let source = rodio::Decoder::new(std::io::Cursor::new(sample.bytes.clone()))
+        .with_context(|| format!("failed to decode {:?}", sample.file_name))?;
+    sink.pause();
+    sink.append(source);
+
+    if let Some(quantum) = quantum {
+        let mut session_state = SessionState::new();
+        app_state.link.capture_app_session_state(&mut session_state);
+        let beat = session_state.beat_at_time(app_state.clock.micros(), quantum);
+        let next_boundary = (beat / quantum).floor() * quantum + quantum;
+        let start = session_state.time_at_beat(next_boundary, quantum);
+
+        loop {
+            let now = app_state.clock.micros();
+            if now >= start {
                 break;
             }
+            if should_stop() {
+                return Ok(());
+            }
+            let until_start = Duration::from_micros((start - now) as u64);
+            std::thread::sleep(until_start.min(SAMPLE_POLL_INTERVAL));
+        }
+    }
 
-            let time_to_wait = event.delta.as_int() as f64 / ticks_per_quater_note as f64;
-            time_passed += time_to_wait;
+    info!("playing sample {:?}", sample.file_name);
+    sink.play();
+    while !sink.empty() {
+        if should_stop() {
+            sink.stop();
+            break;
+        }
+        std::thread::sleep(SAMPLE_POLL_INTERVAL);
+    }
 
-            // TODO: Rust makes a note that condvar shouldn't be use in time critical applications?
-            loop {
-                app_state.link.capture_app_session_state(&mut session_state);
-                let current_time =
-                    session_state.beat_at_time(app_state.link.clock_micros(), quantum);
+    Ok(())
+}
 
-                info!("[passed={time_passed}, current={current_time}] {event:?}");
-                if current_time >= time_passed {
-                    break;
-                }
+/// Play audio block
+///
+/// Starts synchronously like MIDI blocks and plays the audio file through default system audio
+/// output from the start beat, until it ends or gets interrupted, see [audio_worker].
+async fn audio_engine_audio_main(
+    uuid: String,
+    source: block::AudioSource,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<()> {
+    let (session_state, origin) = start.resolve(&app_state, quantum).await;
 
-                let sleep_time = (time_passed - current_time) / 120.0 * 60.0;
+    let result = {
+        let app_state = app_state.clone();
+        tokio::task::spawn_blocking(move || {
+            audio_worker(
+                &app_state,
+                &uuid,
+                &source,
+                &interrupts,
+                session_state,
+                quantum,
+                origin,
+            )
+        })
+        .await
+    };
+
+    stop_synchronized(&app_state).await;
+    match result {
+        // Panic is passed on, so the engine can recover from it, see [recover]
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        result => result?,
+    }
+}
+
+/// Worker that plays audio block from the `origin` beat
+///
+/// Audio doesn't follow tempo changes, so length of the block in beats is estimated with the tempo
+/// from the start.
+fn audio_worker(
+    app_state: &AppState,
+    uuid: &str,
+    source: &block::AudioSource,
+    interrupts: &(std::sync::Mutex<bool>, std::sync::Condvar),
+    mut session_state: SessionState,
+    quantum: f64,
+    origin: f64,
+) -> anyhow::Result<()> {
+    let (_stream, handle) =
+        rodio::OutputStream::try_default().context("failed to open audio output")?;
+    let sink = rodio::Sink::try_new(&handle).context("failed to create audio sink")?;
+    let decoder = rodio::Decoder::new(std::io::Cursor::new(source.bytes.clone()))
+        .with_context(|| format!("failed to decode {:?}", source.file_name))?;
+    let duration = rodio::Source::total_duration(&decoder);
+    sink.pause();
+    sink.append(decoder);
+
+    mark_playing(
+        app_state,
+        uuid,
+        Progress {
+            quantum,
+            origin,
+            length: duration.map(|duration| duration.as_secs_f64() * session_state.tempo() / 60.0),
+            ..Default::default()
+        },
+    );
+
+    let (interrupt, interruptable_sleep) = interrupts;
+    loop {
+        app_state.link.capture_app_session_state(&mut session_state);
+        let current_time = session_state.beat_at_time(app_state.clock.micros(), quantum);
+        if current_time >= origin {
+            break;
+        }
+
+        let sleep_time = (origin - current_time) * 60.0 / session_state.tempo();
+        let guard = interrupt.lock().unwrap();
+        let (interrupted, _) = interruptable_sleep
+            .wait_timeout(guard, Duration::from_secs_f64(sleep_time))
+            .unwrap();
+        if *interrupted {
+            mark_stopped(app_state, uuid);
+            return Ok(());
+        }
+    }
+
+    info!("playing audio {:?}", source.file_name);
+    sink.play();
+    while !sink.empty() {
+        let guard = interrupt.lock().unwrap();
+        let (interrupted, _) = interruptable_sleep
+            .wait_timeout(guard, SAMPLE_POLL_INTERVAL)
+            .unwrap();
+        if *interrupted {
+            sink.stop();
+            break;
+        }
+    }
+
+    mark_stopped(app_state, uuid);
+    Ok(())
+}
+
+/// Condition that becomes true after the next [interrupt]
+fn until_interrupt(app_state: &AppState) -> impl Fn() -> bool + Send + 'static {
+    let generation = app_state
+        .audio_engine
+        .read()
+        .unwrap()
+        .one_shot_generation
+        .clone();
+    let started_in = generation.load(atomic::Ordering::SeqCst);
+
+    move || generation.load(atomic::Ordering::SeqCst) != started_in
+}
+
+/// Play sample without interrupting currently played block
+///
+/// Sample is stopped by the next [interrupt].
+fn play_one_shot(app_state: Arc<AppState>, sample: block::AudioSource, quantum: Option<f64>) {
+    let should_stop = until_interrupt(&app_state);
+
+    std::thread::spawn(move || {
+        if let Err(err) = sample_worker(&app_state, &sample, quantum, should_stop) {
+            tracing::error!("sample worker failed: {err:#}");
+        }
+    });
+}
+
+/// How often tempo ramp updates the tempo of Link session
+const TEMPO_RAMP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Set tempo of Link session to `bpm`, changing it linearly over `ramp_beats` beats
+///
+/// Ramp is counted in elapsed beats like [wait_beats], so blocks started in the meantime don't
+/// disturb it. When `should_stop` returns true the ramp ends at the tempo reached so far.
+async fn change_tempo(
+    app_state: &AppState,
+    bpm: f64,
+    ramp_beats: f64,
+    should_stop: impl Fn() -> bool,
+) {
+    let mut session_state = SessionState::new();
+    app_state.link.capture_app_session_state(&mut session_state);
+    let from = session_state.tempo();
+    info!("changing tempo from {from} to {bpm} BPM over {ramp_beats} beats");
+
+    let mut ramped = 0.0;
+    let mut last = app_state.clock.micros();
+    while ramped < ramp_beats {
+        tokio::time::sleep(TEMPO_RAMP_INTERVAL).await;
+        if should_stop() {
+            app_state.link.capture_app_session_state(&mut session_state);
+            timeline::record(
+                app_state,
+                timeline::Kind::TempoChanged,
+                None,
+                format!(
+                    "ramp from {from} to {bpm} BPM stopped at {} BPM",
+                    session_state.tempo()
+                ),
+            );
+            return;
+        }
+
+        app_state.link.capture_app_session_state(&mut session_state);
+        let now = app_state.clock.micros();
+        ramped += (now - last) as f64 / 1_000_000.0 * session_state.tempo() / 60.0;
+        last = now;
+
+        let progress = (ramped / ramp_beats).min(1.0);
+        session_state.set_tempo(from + (bpm - from) * progress, now);
+        app_state.link.commit_app_session_state(&session_state);
+    }
+
+    app_state.link.capture_app_session_state(&mut session_state);
+    session_state.set_tempo(bpm, app_state.clock.micros());
+    app_state.link.commit_app_session_state(&session_state);
+    timeline::record(
+        app_state,
+        timeline::Kind::TempoChanged,
+        None,
+        format!("from {from} to {bpm} BPM"),
+    );
+}
+
+/// How often command block checks if launched program is still running
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Run command block
+///
+/// Starts synchronously and launches the program on the start beat. Block ends when the program
+/// exits, and the program is killed when the block gets interrupted.
+async fn audio_engine_command_main(
+    uuid: String,
+    mut command: std::process::Command,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<()> {
+    let (mut session_state, origin) = start.resolve(&app_state, quantum).await;
+
+    let result = {
+        let app_state = app_state.clone();
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            let (interrupt, interruptable_sleep) = &*interrupts;
+
+            // Quantized start may land in the future, so wait for the start beat
+            loop {
+                app_state.link.capture_app_session_state(&mut session_state);
+                let current_time = session_state.beat_at_time(app_state.clock.micros(), quantum);
+                if current_time >= origin {
+                    break;
+                }
+
+                let sleep_time = (origin - current_time) * 60.0 / session_state.tempo();
                 let guard = interrupt.lock().unwrap();
-                let (interrupted, sleep_result) = interruptable_sleep
+                let (interrupted, _) = interruptable_sleep
                     .wait_timeout(guard, Duration::from_secs_f64(sleep_time))
                     .unwrap();
                 if *interrupted {
-                    break 'audio_loop;
+                    return Ok(());
                 }
-                if sleep_result.timed_out() {
+            }
+
+            let program = command.get_program().to_owned();
+            let mut child = command
+                .spawn()
+                .with_context(|| format!("failed to launch {program:?}"))?;
+            info!("launched {program:?} with pid {pid}", pid = child.id());
+
+            mark_playing(
+                &app_state,
+                &uuid,
+                Progress {
+                    quantum,
+                    origin,
+                    ..Default::default()
+                },
+            );
+
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    info!("{program:?} exited with {status}");
                     break;
                 }
-            }
 
-            match event.kind {
-                midly::TrackEventKind::Meta(meta) => match meta {
-                    // http://midi.teragonaudio.com/tech/midifile/ppqn.htm
-                    midly::MetaMessage::Tempo(tempo) => {
-                        let tempo: f32 = 60_000_000.0 / (tempo.as_int() as f32);
-                        info!("changing tempo to {}", tempo)
+                let guard = interrupt.lock().unwrap();
+                let (interrupted, _) = interruptable_sleep
+                    .wait_timeout(guard, COMMAND_POLL_INTERVAL)
+                    .unwrap();
+                if *interrupted {
+                    info!("killing {program:?}");
+                    if let Err(err) = child.kill() {
+                        warn!("failed to kill {program:?}: {err}");
                     }
+                    let _ = child.wait();
+                    break;
+                }
+            }
 
-                    // http://midi.teragonaudio.com/tech/midifile/time.htm
-                    midly::MetaMessage::TimeSignature(num, den, _, _) => {
-                        info!(
-                            "time signature is: {num}/{den}",
-                            den = 2_usize.pow(den.into())
-                        )
-                    }
+            mark_stopped(&app_state, &uuid);
+            Ok(())
+        })
+        .await
+    };
 
-                    // These are obligatory at the end of track so we don't need to handle them
-                    midly::MetaMessage::EndOfTrack => {}
-                    msg => {
-                        warn!("unknown meta message: {msg:?}")
-                    }
+    stop_synchronized(&app_state).await;
+    match result {
+        // Panic is passed on, so the engine can recover from it, see [recover]
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        result => result?,
+    }
+}
+
+/// How often silence block checks if it reached it's end
+const SILENCE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Play silence block
+///
+/// Starts synchronously like any other block and reports progress until given number of beats
+/// passed or interrupt was requested.
+async fn audio_engine_silence_main(
+    uuid: String,
+    beats: f64,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<()> {
+    let (mut session_state, origin) = start.resolve(&app_state, quantum).await;
+
+    let result = {
+        let app_state = app_state.clone();
+        tokio::task::spawn_blocking(move || {
+            mark_playing(
+                &app_state,
+                &uuid,
+                Progress {
+                    quantum,
+                    origin,
+                    length: Some(beats),
+                    ..Default::default()
+                },
+            );
+
+            let end = origin + beats;
+            loop {
+                app_state.link.capture_app_session_state(&mut session_state);
+                let current_time = session_state.beat_at_time(app_state.clock.micros(), quantum);
+                if current_time >= end {
+                    break;
+                }
+
+                let sleep_time = (end - current_time) * 60.0 / session_state.tempo();
+                let sleep_time = Duration::from_secs_f64(sleep_time).min(SILENCE_POLL_INTERVAL);
+                if app_state.clock.sleep(&interrupts, sleep_time) {
+                    break;
+                }
+            }
+
+            mark_stopped(&app_state, &uuid);
+        })
+        .await
+    };
+
+    stop_synchronized(&app_state).await;
+    result?;
+    Ok(())
+}
+
+/// Play shared memory block
+///
+/// Starts synchronously and then updates time stored in shared memory. Creates and deletes shared
+/// memory.
+async fn audio_engine_shered_memory_main(
+    uuid: String,
+    path: String,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> Result<(), String> {
+    let (mut session_state, origin) = start.resolve(&app_state, quantum).await;
+
+    tokio::task::spawn_blocking(move || {
+        let shm = match shared_memory::ShmemConf::new()
+            .size(std::mem::size_of::<f64>())
+            .os_id(&path)
+            .create()
+        {
+            Ok(shm) => shm,
+            Err(error) => return Err(error.to_string()),
+        };
+        let p = shm.as_ptr() as *mut f64;
+        tracing::info!("creating shared_memory instance {path}");
+
+        mark_playing(
+            &app_state,
+            &uuid,
+            Progress {
+                quantum,
+                origin,
+                ..Default::default()
+            },
+        );
+        info!("commiting start state");
+
+        loop {
+            let (interrupt, interruptable_sleep) = &*interrupts;
+            let interrupted = interrupt.try_lock().map(|x| *x).unwrap_or(false);
+            if interrupted {
+                break;
+            }
+
+            app_state.link.capture_app_session_state(&mut session_state);
+
+            let time = session_state.beat_at_time(app_state.clock.micros(), quantum) - origin;
+            unsafe { *p = time };
+
+            let guard = interrupt.lock().unwrap();
+            let (interrupted, _) = interruptable_sleep
+                .wait_timeout(guard, Duration::from_secs_f64(0.0001))
+                .unwrap();
+
+            if *interrupted {
+                break;
+            }
+        }
+
+        mark_stopped(&app_state, &uuid);
+        let task = {
+            let app_state = app_state.clone();
+            tokio::spawn(async move { stop_synchronized(&app_state).await })
+        };
+        Ok(task)
+    })
+    .await
+    .unwrap()?
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+/// Connection to MIDI output port together with configuration of the port
+struct Output<'a> {
+    /// Connection used to send messages
+    connection: &'a mut dyn MidiSink,
+
+    /// Channels forwarded to this port, see [channels]
+    channels: channels::ChannelFilter,
+
+    /// Time in microseconds by which messages are sent earlier, see [latency]
+    latency: i64,
+}
+
+/// Send MIDI message to the output and record it if recording is enabled
+///
+/// Messages on channels that are not forwarded to the output are dropped.
+fn send_midi(
+    app_state: &AppState,
+    output: &mut Output,
+    tempo: f64,
+    bytes: &[u8],
+) -> Result<(), midir::SendError> {
+    if !output.channels.allows_message(bytes) {
+        return Ok(());
+    }
+    app_state
+        .recorder
+        .record(app_state.clock.micros(), tempo, bytes);
+    output.connection.send(bytes)
+}
+
+/// Connect to MIDI output port with given number and pass the connection to `play`
+///
+/// Port 0 is the virtual port created by Harmonia (see [crate::virtual_port]), other ports are counted from 1
+/// in the order reported by the system.
+fn with_midi_output<T>(
+    app_state: &AppState,
+    port: usize,
+    play: impl FnOnce(&mut Output) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let port_number = port.max(crate::handlers::MIN_PORT_NUMBER);
+    if port_number == 0 {
+        #[cfg(not(any(unix, all(windows, feature = "windows-virtual-port"))))]
+        unreachable!();
+
+        #[cfg(any(unix, all(windows, feature = "windows-virtual-port")))]
+        {
+            // Only the port is kept, so refreshing ports doesn't wait for the playback to end
+            let mut virtual_port = app_state
+                .connection
+                .read()
+                .unwrap()
+                .as_ref()
+                .map(|conn| conn.virtual_port.clone())
+                .map_err(|err| anyhow!("MIDI unavailable: {err}"))?;
+            return play(&mut Output {
+                connection: &mut virtual_port,
+                channels: channels::filter(app_state, channels::VIRTUAL_PORT_NAME),
+                latency: latency::offset(app_state, channels::VIRTUAL_PORT_NAME) as i64 * 1000,
+            });
+        }
+    }
+
+    let out = MidiOutput::new("harmonia")?;
+    let ports = out.ports();
+    let Some(midi_port) = ports.get(port_number - 1) else {
+        return Err(anyhow!(
+            "failed to connect to unknown midi port number {} (max {})",
+            port_number,
+            ports.len()
+        ));
+    };
+    let port_name = out.port_name(midi_port).unwrap_or_default();
+    info!("outputing to output port #{port_number} named: {port_name}");
+    let channels = channels::filter(app_state, &port_name);
+    let latency = latency::offset(app_state, &port_name) as i64 * 1000;
+
+    let connection = out
+        .connect(midi_port, /* TODO: Better name */ "harmonia-play")
+        .map_err(|err| anyhow::Error::msg(format!("failed to connect to midi port: {err}")))?;
+    let mut connection = ReconnectingPort {
+        port_name,
+        connection: Some(connection),
+        last_attempt: std::time::Instant::now(),
+    };
+    play(&mut Output {
+        connection: &mut connection,
+        channels,
+        latency,
+    })
+}
+
+/// How often [ReconnectingPort] looks for the device that disappeared
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Connection to system MIDI port that survives the device being unplugged
+///
+/// When sending fails, messages are dropped until port with the same name appears again, so the
+/// played block keeps it's place in the Link session and continues on the reconnected device.
+/// Port is found by name, since numbers of the other ports shift when device disappears.
+struct ReconnectingPort {
+    /// Name of the port, as reported by the system
+    port_name: String,
+
+    /// Connection to the port, [None] while device is gone
+    connection: Option<midir::MidiOutputConnection>,
+
+    /// When connection was made or last attempted
+    last_attempt: std::time::Instant,
+}
+
+impl ReconnectingPort {
+    /// Connect to the port with given name
+    fn connect(port_name: &str) -> anyhow::Result<midir::MidiOutputConnection> {
+        let out = MidiOutput::new("harmonia")?;
+        let port = out
+            .ports()
+            .into_iter()
+            .find(|port| out.port_name(port).map_or(false, |name| name == port_name))
+            .ok_or_else(|| anyhow!("midi port {port_name:?} is not connected"))?;
+        out.connect(&port, "harmonia-play")
+            .map_err(|err| anyhow!("failed to connect to midi port: {err}"))
+    }
+}
+
+impl MidiSink for ReconnectingPort {
+    fn send(&mut self, message: &[u8]) -> Result<(), midir::SendError> {
+        if self.connection.is_none() && self.last_attempt.elapsed() >= RECONNECT_INTERVAL {
+            self.last_attempt = std::time::Instant::now();
+            if let Ok(connection) = Self::connect(&self.port_name) {
+                info!("reconnected to midi port {:?}", self.port_name);
+                self.connection = Some(connection);
+            }
+        }
+
+        // Messages sent while device is gone are dropped
+        let Some(connection) = &mut self.connection else {
+            return Ok(());
+        };
+        connection.send(message).map_err(|err| {
+            warn!(
+                "midi port {:?} disappeared, output is paused until it's back: {err}",
+                self.port_name
+            );
+            self.connection = None;
+            self.last_attempt = std::time::Instant::now();
+            err
+        })
+    }
+}
+
+/// Controller number of sustain (damper) pedal
+const SUSTAIN_PEDAL: u8 = 64;
+
+/// Controller number of All Sound Off channel mode message
+const ALL_SOUND_OFF: u8 = 120;
+
+/// Controller number of All Notes Off channel mode message
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Controller messages sent to every channel by [reset_channels], as (controller, value) pairs
+const RESET_CONTROLLERS: [(u8, u8); 4] = [
+    (SUSTAIN_PEDAL, 0),
+    (ALL_NOTES_OFF, 0),
+    (ALL_SOUND_OFF, 0),
+    // Reset All Controllers
+    (121, 0),
+];
+
+/// Controller number of Expression, lowered by [fade_out]
+const EXPRESSION: u8 = 11;
+
+/// Number of Expression messages sent by [fade_out]
+const FADE_OUT_STEPS: u32 = 16;
+
+/// Lower Expression of given channels from their current value to zero over `duration`
+///
+/// Sent before note offs of the interrupted block, so sustained textures fade away instead of
+/// being cut. Expression is restored afterwards by Reset All Controllers from [reset_channels].
+fn fade_out(
+    app_state: &AppState,
+    output: &mut Output,
+    tempo: f64,
+    expression_per_channel: &[Option<u8>; 16],
+    duration: Duration,
+) {
+    let mut buf = Vec::new();
+    for step in 1..=FADE_OUT_STEPS {
+        std::thread::sleep(duration / FADE_OUT_STEPS);
+        for (channel, expression) in expression_per_channel.iter().enumerate() {
+            let Some(expression) = expression else {
+                continue;
+            };
+            let value = *expression as u32 * (FADE_OUT_STEPS - step) / FADE_OUT_STEPS;
+            buf.clear();
+            LiveEvent::Midi {
+                channel: (channel as u8).into(),
+                message: midly::MidiMessage::Controller {
+                    controller: EXPRESSION.into(),
+                    value: (value as u8).into(),
+                },
+            }
+            .write(&mut buf)
+            .expect("expression messages are valid so they must serialize");
+            if let Err(error) = send_midi(app_state, output, tempo, &buf) {
+                tracing::error!("failed to send fade out message: {error}");
+            }
+        }
+    }
+}
+
+/// Silence everything on all channels of the output after interrupt
+///
+/// Tracked note offs are not enough, since notes may still ring held by sustain pedal or other
+/// controllers set by interrupted block.
+fn reset_channels(app_state: &AppState, output: &mut Output, tempo: f64) {
+    let mut buf = Vec::new();
+    for channel in 0..16_u8 {
+        for (controller, value) in RESET_CONTROLLERS {
+            buf.clear();
+            LiveEvent::Midi {
+                channel: channel.into(),
+                message: midly::MidiMessage::Controller {
+                    controller: controller.into(),
+                    value: value.into(),
+                },
+            }
+            .write(&mut buf)
+            .expect("reset messages are valid so they must serialize");
+            if let Err(error) = send_midi(app_state, output, tempo, &buf) {
+                tracing::error!("failed to send reset message: {error}");
+            }
+        }
+    }
+}
+
+/// How long test note sent by [test_port] is held
+const TEST_NOTE_DURATION: Duration = Duration::from_millis(500);
+
+/// Send program change and short middle C on given channel (counted from 0) to given port
+///
+/// Test messages are not recorded nor filtered by [channels], since they are not a part of the
+/// performance.
+pub async fn test_port(app_state: Arc<AppState>, port: usize, channel: u8) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        with_midi_output(&app_state, port, |output| {
+            let channel = channel.into();
+            let messages = [
+                midly::MidiMessage::ProgramChange { program: 0.into() },
+                midly::MidiMessage::NoteOn {
+                    key: 60.into(),
+                    vel: 100.into(),
+                },
+                midly::MidiMessage::NoteOff {
+                    key: 60.into(),
+                    vel: 0.into(),
+                },
+            ];
+
+            let mut buf = Vec::new();
+            for message in messages {
+                if matches!(message, midly::MidiMessage::NoteOff { .. }) {
+                    std::thread::sleep(TEST_NOTE_DURATION);
+                }
+                buf.clear();
+                LiveEvent::Midi { channel, message }
+                    .write(&mut buf)
+                    .expect("test messages are valid so they must serialize");
+                output
+                    .connection
+                    .send(&buf)
+                    .map_err(|err| anyhow!("failed to send test message: {err}"))?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|err| format!("test worker failed: {err}"))?
+    .map_err(|err| format!("{err:#}"))
+}
+
+/// Time before the event when [wait_for_beat] stops sleeping and starts busy-waiting
+///
+/// Sleeps and condvar timeouts may overshoot by the granularity of the operating system
+/// scheduler, often a millisecond or more, which is audible on fast material. Busy-waiting is
+/// the trade-off: with [real-time priority][priority] the playback thread keeps the core for the
+/// whole spin and only threads of the same priority run when it yields, so the longer the spin the
+/// longer other programs (and Harmonia itself) wait on a busy machine. A millisecond covers the
+/// usual overshoot, while dense material spins for at most that long per event.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(1000);
+
+/// Wait until Link reaches the `beat` (shifted by `latency` of the port), returns current beat
+///
+/// Tempo may change while waiting, so the time of the beat is checked again after each sleep, see
+/// [wait_until]. Returns `None` when playback was interrupted.
+fn wait_for_beat(
+    app_state: &AppState,
+    session_state: &mut SessionState,
+    interrupts: &(std::sync::Mutex<bool>, std::sync::Condvar),
+    beat: f64,
+    quantum: f64,
+    latency: i64,
+) -> Option<f64> {
+    let mut current_time = 0.0;
+    let reached = wait_until(&*app_state.clock, interrupts, || {
+        app_state.link.capture_app_session_state(session_state);
+        current_time = session_state.beat_at_time(app_state.clock.micros() + latency, quantum);
+        (current_time < beat).then(|| session_state.time_at_beat(beat, quantum) - latency)
+    });
+    reached.then_some(current_time)
+}
+
+/// Wait on the clock until `target` host time in microseconds, returns false when interrupted
+///
+/// Target is asked again after each wait and `None` means that it's reached. Sleeps directly until
+/// the target, waking up [SPIN_THRESHOLD] early and busy-waiting for the rest, giving
+/// sub-millisecond accuracy without keeping CPU busy between events.
+fn wait_until(
+    clock: &dyn Clock,
+    interrupts: &(std::sync::Mutex<bool>, std::sync::Condvar),
+    mut target: impl FnMut() -> Option<i64>,
+) -> bool {
+    while let Some(target_time) = target() {
+        let remaining =
+            Duration::from_micros(target_time.saturating_sub(clock.micros()).max(0) as u64);
+        let interrupted = if remaining > SPIN_THRESHOLD {
+            clock.sleep(interrupts, remaining - SPIN_THRESHOLD)
+        } else {
+            clock.spin(interrupts, remaining)
+        };
+        if interrupted {
+            return false;
+        }
+    }
+    true
+}
+
+/// Worker that actually plays the MIDI source
+fn midi_worker(
+    app_state: Arc<AppState>,
+    uuid: String,
+    midi_source: block::MidiSource,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+    mut session_state: SessionState,
+    quantum: f64,
+    origin: f64,
+) -> anyhow::Result<()> {
+    // TODO: Find better solution then checking two times if we have this source
+    let midi = midi_source
+        .midi()
+        .map_err(|err| format!("failed to parse midi: {err}"))
+        .unwrap();
+
+    // Timecode is converted to beats with the tempo from the start, so the file keeps it's
+    // duration in seconds
+    let ticks_per_beat = block::ticks_per_beat(midi.header.timing, session_state.tempo());
+
+    // All tracks are played, interleaved by the time of their events. Each event is scheduled
+    // on it's absolute beat from the start of the block, so rounding errors don't accumulate
+    let schedule: Vec<(f64, midly::TrackEventKind)> = block::merge_tracks(&midi)
+        .into_iter()
+        .map(|(ticks, _, event)| (ticks as f64 / ticks_per_beat, event.kind))
+        .collect();
+    let length = schedule.last().map(|(beat, _)| *beat);
+
+    // Next repetition starts on the quantum boundary, so looped block stays aligned with the rest
+    // of the session
+    let loop_length = (length.unwrap_or_default() / quantum).ceil().max(1.0) * quantum;
+
+    let result = with_midi_output(&app_state, midi_source.associated_port, |output| {
+        app_state
+            .timing
+            .lock()
+            .unwrap()
+            .insert(uuid.clone(), TimingStats::default());
+        info!("commiting start state");
+
+        let mut notes_played_per_channel = [[false; 128]; 16];
+        let mut sustain_per_channel = [false; 16];
+        let mut expression_per_channel = [127_u8; 16];
+        let mut buf = Vec::new();
+        let mut origin = origin;
+        let latency = output.latency;
+
+        'audio_loop: loop {
+            mark_playing(
+                &app_state,
+                &uuid,
+                Progress {
+                    quantum,
+                    origin,
+                    length,
+                    meter: midi_source.meter(),
                 },
-                midly::TrackEventKind::Midi { channel, message } => match message {
-                    // TODO: Remember currenlty played notes so we can unwind this musical stack when
-                    // we turn it off.
-                    midly::MidiMessage::NoteOn { key, vel } => {
-                        notes_played_per_channel[channel.as_int() as usize]
-                            [key.as_int() as usize] = vel != 0;
-                        output.send(bytes).unwrap();
+            );
+
+            for &(beat, kind) in &schedule {
+                let (interrupt, _) = &*interrupts;
+                let interrupted = interrupt.try_lock().map(|x| *x).unwrap_or(false);
+                if interrupted {
+                    break 'audio_loop;
+                }
+
+                let time_passed = origin + beat;
+                tracing::trace!("[passed={time_passed}] {kind:?}");
+
+                let Some(current_time) = wait_for_beat(
+                    &app_state,
+                    &mut session_state,
+                    &interrupts,
+                    time_passed,
+                    quantum,
+                    latency,
+                ) else {
+                    break 'audio_loop;
+                };
+
+                // Events are scheduled on Link beats, so being late delays only this event
+                // and the following ones are sent on their own beats again
+                let lateness = (current_time - time_passed) * 60.0 / session_state.tempo();
+                if let Some(stats) = app_state.timing.lock().unwrap().get_mut(&uuid) {
+                    stats.record(lateness);
+                }
+
+                match kind {
+                    midly::TrackEventKind::Meta(meta) => match meta {
+                        // http://midi.teragonaudio.com/tech/midifile/ppqn.htm
+                        midly::MetaMessage::Tempo(tempo) => {
+                            let tempo: f32 = 60_000_000.0 / (tempo.as_int() as f32);
+                            tracing::debug!("changing tempo to {}", tempo)
+                        }
+
+                        // http://midi.teragonaudio.com/tech/midifile/time.htm
+                        midly::MetaMessage::TimeSignature(num, den, _, _) => {
+                            tracing::debug!(
+                                "time signature is: {num}/{den}",
+                                den = 2_usize.pow(den.into())
+                            )
+                        }
+
+                        // These are obligatory at the end of track so we don't need to handle them
+                        midly::MetaMessage::EndOfTrack => {}
+                        msg => {
+                            warn!("unknown meta message: {msg:?}")
+                        }
+                    },
+                    midly::TrackEventKind::Midi { channel, message } => {
+                        let message = match message {
+                            midly::MidiMessage::NoteOn { key, vel } => midly::MidiMessage::NoteOn {
+                                key,
+                                vel: midi_source.velocity.apply(vel.as_int()).into(),
+                            },
+                            message => message,
+                        };
+
+                        // Muted channels lose only their note ons, see [mix]
+                        if matches!(message, midly::MidiMessage::NoteOn { vel, .. } if vel > 0)
+                            && !mix::allows(&app_state, &uuid, channel.as_int())
+                        {
+                            continue;
+                        }
+
+                        // Remember what is sounding, so it can be released when playback stops
+                        let notes = &mut notes_played_per_channel[channel.as_int() as usize];
+                        match message {
+                            midly::MidiMessage::NoteOn { key, vel } => {
+                                notes[key.as_int() as usize] = vel != 0;
+                            }
+                            midly::MidiMessage::NoteOff { key, .. } => {
+                                notes[key.as_int() as usize] = false;
+                            }
+                            midly::MidiMessage::Controller { controller, value } => {
+                                match controller.as_int() {
+                                    SUSTAIN_PEDAL => {
+                                        sustain_per_channel[channel.as_int() as usize] =
+                                            value.as_int() >= 64;
+                                    }
+                                    EXPRESSION => {
+                                        expression_per_channel[channel.as_int() as usize] =
+                                            value.as_int();
+                                    }
+                                    ALL_SOUND_OFF | ALL_NOTES_OFF => *notes = [false; 128],
+                                    _ => {}
+                                }
+                            }
+                            midly::MidiMessage::Aftertouch { .. }
+                            | midly::MidiMessage::ProgramChange { .. }
+                            | midly::MidiMessage::ChannelAftertouch { .. }
+                            | midly::MidiMessage::PitchBend { .. } => {}
+                        }
+
+                        // Message is serialized again, since bytes of the file may use running
+                        // status and miss the status byte
+                        buf.clear();
+                        LiveEvent::Midi { channel, message }
+                            .write(&mut buf)
+                            .expect("messages parsed from MIDI file must serialize");
+                        if let Err(error) =
+                            send_midi(&app_state, output, session_state.tempo(), &buf)
+                        {
+                            tracing::error!("failed to send midi message: {error}");
+                        }
                     }
-                    midly::MidiMessage::NoteOff { key, .. } => {
-                        notes_played_per_channel[channel.as_int() as usize]
-                            [key.as_int() as usize] = false;
-                        output.send(bytes).unwrap();
+                    midly::TrackEventKind::SysEx(_) => {
+                        // TODO: They should probably be forwarded
+                        warn!("sysex messages are not handled yet");
                     }
-                    msg => {
-                        warn!("unknown midi message: {msg:?}")
+                    midly::TrackEventKind::Escape(_) => {
+                        // TODO: They should probably be forwarded
+                        warn!("escape messages are not handled yet");
                     }
-                },
-                midly::TrackEventKind::SysEx(_) => {
-                    // TODO: They should probably be forwarded
-                    warn!("sysex messages are not handled yet");
                 }
-                midly::TrackEventKind::Escape(_) => {
-                    // TODO: They should probably be forwarded
-                    warn!("escape messages are not handled yet");
+            }
+
+            // Checked on every repetition, so looping can be turned off while block plays
+            let loop_playback = app_state
+                .blocks
+                .read()
+                .unwrap()
+                .get(&uuid)
+                .map_or(false, |block| block.loop_playback);
+            if !loop_playback || schedule.is_empty() {
+                break;
+            }
+            origin += loop_length;
+            info!("looping block#{uuid} from beat {origin}");
+        }
+
+        let fading = app_state.audio_engine.write().unwrap().fading.remove(&uuid);
+        if fading && *interrupts.0.lock().unwrap() {
+            // Only channels that still sound are faded, the rest would stay silent anyway
+            let mut sounding = [None; 16];
+            for (channel, expression) in sounding.iter_mut().enumerate() {
+                if sustain_per_channel[channel] || notes_played_per_channel[channel].contains(&true)
+                {
+                    *expression = Some(expression_per_channel[channel]);
                 }
             }
+            info!("fading out block#{uuid}");
+            fade_out(
+                &app_state,
+                output,
+                session_state.tempo(),
+                &sounding,
+                app_state.fade_out,
+            );
         }
 
-        let mut buf = Vec::new();
+        buf.clear();
         for (channel, notes) in notes_played_per_channel.iter().enumerate() {
             for (key, played) in notes.iter().enumerate() {
                 if *played {
@@ -381,19 +1820,322 @@ fn midi_worker(
                     event.write(&mut buf).expect(
                         "this notes were produced from valid MIDI messages so they must serialize",
                     );
-                    if let Err(error) = output.send(&buf) {
+                    if let Err(error) = send_midi(&app_state, output, session_state.tempo(), &buf) {
                         tracing::error!("failed to send cleanup note off message: {error}");
                     }
                     buf.clear();
                 }
             }
         }
+
+        for (channel, sustained) in sustain_per_channel.iter().enumerate() {
+            if *sustained {
+                LiveEvent::Midi {
+                    channel: (channel as u8).into(),
+                    message: midly::MidiMessage::Controller {
+                        controller: SUSTAIN_PEDAL.into(),
+                        value: 0.into(),
+                    },
+                }
+                .write(&mut buf)
+                .expect("sustain pedal release is valid so it must serialize");
+                if let Err(error) = send_midi(&app_state, output, session_state.tempo(), &buf) {
+                    tracing::error!("failed to send cleanup sustain pedal release: {error}");
+                }
+                buf.clear();
+            }
+        }
+
+        if *interrupts.0.lock().unwrap() {
+            reset_channels(&app_state, output, session_state.tempo());
+        }
+
+        Ok(())
+    });
+
+    mark_stopped(&app_state, &uuid);
+    app_state.recorder.save();
+    result
+}
+
+/// Worker that loops the pattern block
+///
+/// Pattern is read from [AppState] on every step, so edits made through API are heard on the next
+/// step. Playback stops when interrupted or when the block is removed.
+fn pattern_worker(
+    app_state: Arc<AppState>,
+    uuid: String,
+    port: usize,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+    mut session_state: SessionState,
+    quantum: f64,
+    origin: f64,
+) -> anyhow::Result<()> {
+    let result = with_midi_output(&app_state, port, |output| {
+        mark_playing(
+            &app_state,
+            &uuid,
+            Progress {
+                quantum,
+                origin,
+                ..Default::default()
+            },
+        );
+
+        let (interrupt, _) = &*interrupts;
+        let latency = output.latency;
+        let mut time_passed = origin;
+        let mut sounding: Vec<(u8, u8)> = Vec::new();
+        let mut buf = Vec::new();
+        let mut send = |channel: u8, message: midly::MidiMessage, tempo: f64| {
+            buf.clear();
+            LiveEvent::Midi {
+                channel: channel.into(),
+                message,
+            }
+            .write(&mut buf)
+            .expect("pattern notes were validated so they must serialize");
+            if let Err(err) = send_midi(&app_state, output, tempo, &buf) {
+                tracing::error!("failed to send pattern note: {err}");
+            }
+        };
+
+        'pattern_loop: for step in 0.. {
+            let pattern = {
+                let blocks = app_state.blocks.read().unwrap();
+                match blocks.get(&uuid).map(|block| &block.content) {
+                    Some(block::Content::Pattern(pattern)) => pattern.clone(),
+                    _ => {
+                        warn!("pattern block#{uuid} disappeared, stopping");
+                        break;
+                    }
+                }
+            };
+
+            if wait_for_beat(
+                &app_state,
+                &mut session_state,
+                &interrupts,
+                time_passed,
+                quantum,
+                latency,
+            )
+            .is_none()
+            {
+                break 'pattern_loop;
+            }
+
+            let tempo = session_state.tempo();
+            for (channel, key) in sounding.drain(..) {
+                let message = midly::MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                };
+                send(channel, message, tempo);
+            }
+
+            let index = step % pattern.steps;
+            let heard = |row: &&block::PatternRow| mix::allows(&app_state, &uuid, row.channel);
+            for row in pattern
+                .rows
+                .iter()
+                .filter(|row| row.steps[index])
+                .filter(heard)
+            {
+                let message = midly::MidiMessage::NoteOn {
+                    key: row.key.into(),
+                    vel: row.velocity.into(),
+                };
+                send(row.channel, message, tempo);
+                sounding.push((row.channel, row.key));
+            }
+
+            time_passed += pattern.step_beats;
+        }
+
+        let tempo = session_state.tempo();
+        for (channel, key) in sounding.drain(..) {
+            let message = midly::MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            };
+            send(channel, message, tempo);
+        }
+
+        if *interrupt.lock().unwrap() {
+            reset_channels(&app_state, output, tempo);
+        }
+        Ok(())
+    });
+
+    mark_stopped(&app_state, &uuid);
+    app_state.recorder.save();
+    result
+}
+
+/// Worker that clicks every beat of the metronome block until interrupted
+fn metronome_worker(
+    app_state: Arc<AppState>,
+    uuid: String,
+    metronome: block::Metronome,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+    mut session_state: SessionState,
+    quantum: f64,
+    origin: f64,
+) -> anyhow::Result<()> {
+    let result = with_midi_output(&app_state, metronome.associated_port, |output| {
+        mark_playing(
+            &app_state,
+            &uuid,
+            Progress {
+                quantum,
+                origin,
+                ..Default::default()
+            },
+        );
+
+        let latency = output.latency;
+        let mut sounding: Option<u8> = None;
+        let mut buf = Vec::new();
+        let mut send = |message: midly::MidiMessage, tempo: f64| {
+            buf.clear();
+            LiveEvent::Midi {
+                channel: metronome.channel.into(),
+                message,
+            }
+            .write(&mut buf)
+            .expect("metronome was validated so it's messages must serialize");
+            if let Err(err) = send_midi(&app_state, output, tempo, &buf) {
+                tracing::error!("failed to send metronome click: {err}");
+            }
+        };
+
+        'metronome_loop: for beat in 0.. {
+            let time_passed = origin + beat as f64;
+            if wait_for_beat(
+                &app_state,
+                &mut session_state,
+                &interrupts,
+                time_passed,
+                quantum,
+                latency,
+            )
+            .is_none()
+            {
+                break 'metronome_loop;
+            }
+
+            let tempo = session_state.tempo();
+            if let Some(key) = sounding.take() {
+                let message = midly::MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                };
+                send(message, tempo);
+            }
+
+            // Bars are quantum long, like everywhere else in Harmonia
+            let key = if time_passed.rem_euclid(quantum) == 0.0 {
+                metronome.accent_note
+            } else {
+                metronome.note
+            };
+            let message = midly::MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            };
+            send(message, tempo);
+            sounding = Some(key);
+        }
+
+        let tempo = session_state.tempo();
+        if let Some(key) = sounding {
+            let message = midly::MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            };
+            send(message, tempo);
+        }
+        Ok(())
+    });
+
+    mark_stopped(&app_state, &uuid);
+    app_state.recorder.save();
+    result
+}
+
+/// Play metronome block
+///
+/// Starts synchronously and clicks every beat until interrupted, see [metronome_worker].
+async fn audio_engine_metronome_main(
+    uuid: String,
+    metronome: block::Metronome,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<()> {
+    let (session_state, origin) = start.resolve(&app_state, quantum).await;
+
+    let result = {
+        let app_state = app_state.clone();
+        tokio::task::spawn_blocking(move || {
+            metronome_worker(
+                app_state,
+                uuid,
+                metronome,
+                interrupts,
+                session_state,
+                quantum,
+                origin,
+            )
+        })
+        .await
+    };
+
+    stop_synchronized(&app_state).await;
+    match result {
+        // Panic is passed on, so the engine can recover from it, see [recover]
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        result => result?,
     }
+}
 
-    conn_storage.take().map(|x| x.close());
-    *app_state.currently_playing_uuid.write().unwrap() = None;
+/// Play pattern block
+///
+/// Starts synchronously and loops the pattern until interrupted, see [pattern_worker].
+async fn audio_engine_pattern_main(
+    uuid: String,
+    port: usize,
+    start: Start,
+    quantum: f64,
+    app_state: Arc<AppState>,
+    interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
+) -> anyhow::Result<()> {
+    let (session_state, origin) = start.resolve(&app_state, quantum).await;
 
-    Ok(())
+    let result = {
+        let app_state = app_state.clone();
+        tokio::task::spawn_blocking(move || {
+            pattern_worker(
+                app_state,
+                uuid,
+                port,
+                interrupts,
+                session_state,
+                quantum,
+                origin,
+            )
+        })
+        .await
+    };
+
+    stop_synchronized(&app_state).await;
+    match result {
+        // Panic is passed on, so the engine can recover from it, see [recover]
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        result => result?,
+    }
 }
 
 /// Play MIDI block
@@ -405,35 +2147,22 @@ fn midi_worker(
 // TODO: Support more MIDI messages
 async fn audio_engine_main_midi(
     uuid: String,
-    group: String,
+    start: Start,
+    quantum: f64,
     app_state: Arc<AppState>,
     midi_source: block::MidiSource,
     interrupts: Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>,
 ) -> anyhow::Result<()> {
-    let mut session_state = SessionState::new();
-    let quantum = 1.0;
-
-    if group.is_empty() {
-        tracing::info!("Empty group, starting using request_beat_at_time");
-        app_state.link.capture_app_session_state(&mut session_state);
-        session_state.request_beat_at_time(0.0, app_state.link.clock_micros(), quantum);
-        app_state.link.commit_app_session_state(&session_state);
-    } else {
-        tracing::info!("Starting with group: {group:?}");
-        app_state
-            .groups
-            .as_ref()
-            .unwrap()
-            .start(&group)
-            .await
-            .unwrap();
-    }
+    // Trimmed before synchronizing, so the block starts on the beat with it's start tick
+    let midi_source = midi_source.trimmed().map_err(anyhow::Error::msg)?;
+    let (session_state, origin) = start.resolve(&app_state, quantum).await;
 
     let (mark_thread_end, thread_ended) = tokio::sync::oneshot::channel::<()>();
 
     let worker = {
         let app_state = app_state.clone();
         std::thread::spawn(move || {
+            let _priority = app_state.realtime_priority.then(priority::raise);
             if let Err(err) = midi_worker(
                 app_state,
                 uuid,
@@ -441,6 +2170,7 @@ async fn audio_engine_main_midi(
                 interrupts,
                 session_state,
                 quantum,
+                origin,
             ) {
                 tracing::error!("midi worker failed: {err}");
             }
@@ -453,18 +2183,20 @@ async fn audio_engine_main_midi(
     if thread_ended.await.is_err() {
         tracing::warn!("Failed to wait for thread end");
     }
-    if let Err(err) = tokio::task::spawn_blocking(|| {
+    let joined = tokio::task::spawn_blocking(|| {
         tracing::info!("Waiting for midi worker thread to finish");
         worker.join()
     })
-    .await
-    {
-        tracing::error!("audio engine worker thread failed with panic: {err}");
-    }
+    .await;
 
-    app_state.groups.as_ref().unwrap().stop().await;
+    stop_synchronized(&app_state).await;
 
-    Ok(())
+    match joined {
+        // Panic is passed on, so the engine can recover from it, see [recover]
+        Ok(Err(panic)) => std::panic::resume_unwind(panic),
+        Ok(Ok(())) => Ok(()),
+        Err(err) => Err(anyhow!("failed to wait for midi worker thread: {err}")),
+    }
 }
 
 impl Default for AudioEngine {
@@ -473,64 +2205,264 @@ impl Default for AudioEngine {
     /// Creates worker that handles intteruptions and requests from the other
     /// threads; with communication nessesary to contact worker from other threads.
     fn default() -> Self {
-        // TODO: We assume that we handle this request relativly quickly (and thus only 1 in queue)
-        let (work_in, mut work) = tokio::sync::mpsc::channel(1);
+        let (worker, work_in) = spawn_worker();
+
+        Self {
+            state: Default::default(),
+            worker: Some(worker),
+            work_in,
+            sequence: None,
+            one_shot_generation: Default::default(),
+            fading: Default::default(),
+            health: Default::default(),
+        }
+    }
+}
 
-        let mut interrupt: Option<Arc<(std::sync::Mutex<bool>, std::sync::Condvar)>> = None;
-        let mut current_worker: Option<tokio::task::JoinHandle<()>> = None;
+/// Spawn worker executing requests sent to the returned channel
+fn spawn_worker() -> (
+    tokio::task::JoinHandle<()>,
+    tokio::sync::mpsc::Sender<Request>,
+) {
+    // TODO: We assume that we handle this request relativly quickly (and thus only 1 in queue)
+    let (work_in, mut work) = tokio::sync::mpsc::channel(1);
+
+    let mut players: HashMap<String, Player> = HashMap::new();
+
+    let worker = tokio::spawn(async move {
+        while let Some(request) = work.recv().await {
+            info!("received request: {request:?}");
+            players.retain(|_, player| !player.task.is_finished());
+
+            let mut request = match request {
+                Request::Play(request) => request,
+                Request::Interrupt(Some(uuid)) => {
+                    let player_uuid = players
+                        .iter()
+                        .find(|(player_uuid, player)| {
+                            **player_uuid == uuid || player.members.contains(&uuid)
+                        })
+                        .map(|(player_uuid, _)| player_uuid.clone());
+                    if let Some(player) = player_uuid.and_then(|uuid| players.remove(&uuid)) {
+                        player.interrupt();
+                        player.join().await;
+                    }
+                    continue;
+                }
+                Request::Interrupt(None) => {
+                    Player::stop_all(&mut players).await;
+                    continue;
+                }
+                Request::Quit => {
+                    Player::stop_all(&mut players).await;
+                    break;
+                }
+            };
 
-        let worker = tokio::spawn(async move {
-            while let Some(request) = work.recv().await {
-                info!("received request: {request:?}");
+            // Block that is already playing is started again from the beginning
+            if let Some(player) = players.remove(&request.uuid) {
+                player.interrupt();
+                player.join().await;
+            }
 
-                if let Some(interrupt) = interrupt.take() {
-                    *interrupt.0.lock().unwrap() = true;
-                    interrupt.1.notify_one();
-                    if let Some(current_worker) = current_worker.take() {
-                        current_worker.await.unwrap();
+            let interrupt = Arc::new((std::sync::Mutex::new(false), std::sync::Condvar::new()));
+            let worker_interrupt = interrupt.clone();
+            let player_uuid = request.uuid.clone();
+            let members = match request.app_state.blocks.read().unwrap().get(&request.uuid) {
+                Some(block::Block {
+                    content: block::Content::Scene(scene),
+                    ..
+                }) => scene.blocks.clone(),
+                _ => Vec::new(),
+            };
+            let task = tokio::spawn(async move {
+                let done = request.done.take();
+                let app_state = request.app_state.clone();
+                let uuid = request.uuid.clone();
+                let name = app_state
+                    .blocks
+                    .read()
+                    .unwrap()
+                    .get(&uuid)
+                    .map(|block| block.content.name())
+                    .unwrap_or_default();
+                timeline::record(&app_state, timeline::Kind::BlockStarted, Some(&uuid), name);
+
+                let result = match std::panic::AssertUnwindSafe(audio_engine_main(
+                    request,
+                    worker_interrupt.clone(),
+                ))
+                .catch_unwind()
+                .await
+                {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        let failure =
+                            format!("playing block#{uuid} panicked: {}", panic_message(&*panic));
+                        recover(&app_state, Some(&uuid), failure.clone()).await;
+                        Err(anyhow!(failure))
                     }
                 }
+                .map(|_| {
+                    if *worker_interrupt.0.lock().unwrap() {
+                        Ending::Interrupted
+                    } else {
+                        Ending::Finished
+                    }
+                })
+                .map_err(|err| format!("{err:#}"));
 
-                let request = match request {
-                    Request::Play(request) => request,
-                    Request::Interrupt => continue,
-                    Request::Quit => break,
+                if let Err(err) = &result {
+                    crate::error!("{err}")
+                }
+                let detail = match &result {
+                    Ok(ending) => ending.to_string(),
+                    Err(err) => format!("failed: {err}"),
                 };
+                timeline::record(
+                    &app_state,
+                    timeline::Kind::BlockStopped,
+                    Some(&uuid),
+                    detail,
+                );
+                if let Some(done) = done {
+                    let _ = done.send(result);
+                }
+            });
+            players.insert(
+                player_uuid,
+                Player {
+                    interrupt,
+                    task,
+                    members,
+                },
+            );
+        }
+    });
 
-                interrupt = Some(Arc::new((
-                    std::sync::Mutex::new(false),
-                    std::sync::Condvar::new(),
-                )));
-                let worker_interrupt = interrupt.clone().unwrap();
-                let worker = tokio::spawn(async move {
-                    if let Err(err) = audio_engine_main(request, worker_interrupt).await {
-                        crate::error!("{err:#}")
-                    }
-                });
-                current_worker = Some(worker);
+    (worker, work_in)
+}
+
+/// How often [watchdog] checks whether [AudioEngine] worker is still running
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Extract message from the payload of the panic
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Silence all ports, reset playing state and remember the failure in [Health]
+///
+/// When failure concerns single block only this block is marked as stopped, otherwise all are.
+async fn recover(app_state: &Arc<AppState>, uuid: Option<&str>, failure: String) {
+    tracing::error!("audio engine failed, recovering: {failure}");
+    {
+        let mut audio_engine = app_state.audio_engine.write().unwrap();
+        audio_engine.health.failures += 1;
+        audio_engine.health.last_failure = Some(failure);
+        audio_engine.health.last_failure_time = Some(chrono::Local::now().to_rfc3339());
+    }
+    match uuid {
+        Some(uuid) => mark_stopped(app_state, uuid),
+        None => app_state.currently_playing.write().unwrap().clear(),
+    }
+    stop_synchronized(app_state).await;
+    silence_all_ports(app_state.clone()).await;
+}
+
+/// Reset all channels of all ports, stopping every sound played through MIDI
+async fn silence_all_ports(app_state: Arc<AppState>) {
+    let silenced = tokio::task::spawn_blocking(move || {
+        let mut session_state = SessionState::new();
+        app_state.link.capture_app_session_state(&mut session_state);
+        let ports = app_state.midi_port_count();
+        for port in crate::handlers::MIN_PORT_NUMBER..=ports {
+            let result = with_midi_output(&app_state, port, |output| {
+                reset_channels(&app_state, output, session_state.tempo());
+                Ok(())
+            });
+            if let Err(err) = result {
+                warn!("failed to silence port {port}: {err:#}");
             }
-        });
+        }
+    })
+    .await;
+    if let Err(err) = silenced {
+        tracing::error!("failed to silence ports: {err}");
+    }
+}
 
-        Self {
-            state: Default::default(),
-            worker: Some(worker),
-            work_in,
+/// Emergency stop: interrupt all blocks and silence all ports
+///
+/// Unlike [interrupt] it also resets channels that blocks didn't use, so sound left by crashed
+/// blocks or other programs stops as well.
+pub async fn panic(app_state: Arc<AppState>) -> Result<(), String> {
+    warn!("panic, stopping everything");
+    let interrupted = interrupt_all(app_state.clone(), false).await;
+    silence_all_ports(app_state).await;
+    interrupted
+}
+
+/// Restart [AudioEngine] worker whenever it stops without being asked to [quit]
+pub async fn watchdog(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(WATCHDOG_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let stopped = {
+            let mut audio_engine = app_state.audio_engine.write().unwrap();
+            match &audio_engine.worker {
+                Some(worker) if worker.is_finished() => audio_engine.worker.take(),
+                _ => None,
+            }
+        };
+        let Some(stopped) = stopped else {
+            continue;
+        };
+
+        let failure = match stopped.await {
+            Err(err) if err.is_panic() => format!(
+                "audio engine worker panicked: {}",
+                panic_message(&*err.into_panic())
+            ),
+            _ => "audio engine worker stopped unexpectedly".to_string(),
+        };
+
+        let (worker, work_in) = spawn_worker();
+        {
+            let mut audio_engine = app_state.audio_engine.write().unwrap();
+            audio_engine.worker = Some(worker);
+            audio_engine.work_in = work_in;
         }
+        recover(&app_state, None, failure).await;
+        info!("audio engine worker restarted");
     }
 }
 
 /// Send quit command to the [AudioEngine] worker
 pub async fn quit(app_state: Arc<AppState>) {
-    let work_in = {
-        let audio_engine = app_state.audio_engine.write().unwrap();
-        audio_engine.work_in.clone()
+    // Worker is taken before it's asked to quit, so [watchdog] doesn't restart it
+    let (work_in, worker) = {
+        let mut audio_engine = app_state.audio_engine.write().unwrap();
+        (audio_engine.work_in.clone(), audio_engine.worker.take())
     };
 
+    if let Some(sequence) = app_state.audio_engine.write().unwrap().sequence.take() {
+        sequence.abort();
+    }
+
     if work_in.send(Request::Quit).await.is_err() {
         return;
     }
 
-    let worker = app_state.audio_engine.write().unwrap().worker.take();
     if let Some(worker) = worker {
         let _ = worker.await;
     }
@@ -538,20 +2470,187 @@ pub async fn quit(app_state: Arc<AppState>) {
 
 // TODO: Since axum is using tokio under the hood this should be compatible with http handlers
 /// Send interrupt request to [AudioEngine] worker
+///
+/// Also cancels sequence started with [play_sequence]. Blocks fade out first when `--fade-out`
+/// is given, see [fade_out].
 pub async fn interrupt(app_state: Arc<AppState>) -> Result<(), String> {
+    interrupt_all(app_state, true).await
+}
+
+/// Send interrupt request to [AudioEngine] worker, letting played blocks fade out if `fade`
+async fn interrupt_all(app_state: Arc<AppState>, fade: bool) -> Result<(), String> {
+    timeline::record(&app_state, timeline::Kind::Interrupted, None, "");
     let work_in = {
-        let audio_engine = app_state.audio_engine.write().unwrap();
+        let mut audio_engine = app_state.audio_engine.write().unwrap();
+        if let Some(sequence) = audio_engine.sequence.take() {
+            sequence.abort();
+        }
+        audio_engine
+            .one_shot_generation
+            .fetch_add(1, atomic::Ordering::SeqCst);
+        if fade && !app_state.fade_out.is_zero() {
+            let playing = app_state.currently_playing.read().unwrap();
+            audio_engine.fading.extend(playing.keys().cloned());
+        }
+        audio_engine.work_in.clone()
+    };
+
+    work_in
+        .send(Request::Interrupt(None))
+        .await
+        .map_err(|err| format!("failed to send job: {err}"))
+}
+
+/// Send interrupt request to [AudioEngine] worker stopping only given block
+///
+/// Other playing blocks, sequence and one-shots are not affected. Block fades out first when
+/// `--fade-out` is given, see [fade_out]. Block played as a part of the scene stops the whole
+/// scene.
+pub async fn interrupt_block(app_state: Arc<AppState>, uuid: &str) -> Result<(), String> {
+    timeline::record(&app_state, timeline::Kind::Interrupted, Some(uuid), "");
+    let work_in = {
+        let mut audio_engine = app_state.audio_engine.write().unwrap();
+        let playing = app_state
+            .currently_playing
+            .read()
+            .unwrap()
+            .contains_key(uuid);
+        if playing && !app_state.fade_out.is_zero() {
+            audio_engine.fading.insert(uuid.to_string());
+        }
         audio_engine.work_in.clone()
     };
 
     work_in
-        .send(Request::Interrupt)
+        .send(Request::Interrupt(Some(uuid.to_string())))
         .await
         .map_err(|err| format!("failed to send job: {err}"))
 }
 
+/// When interrupted blocks stop, see [stop]
+#[derive(
+    clap::ValueEnum,
+    serde::Deserialize,
+    serde::Serialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopAt {
+    /// Right away, even in the middle of the beat
+    #[default]
+    Now,
+
+    /// On the next bar (quantum boundary) of Link session, so the music ends on the downbeat
+    BarEnd,
+}
+
+impl StopAt {
+    /// All stop modes, in order presented to the user
+    pub const ALL: [Self; 2] = [Self::Now, Self::BarEnd];
+
+    /// Name of the stop mode, the same as in JSON and on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Now => "now",
+            Self::BarEnd => "bar-end",
+        }
+    }
+}
+
+/// Stop given block, or all blocks when none is given, right away or at the end of the bar
+///
+/// Stopping at the end of the bar keeps blocks playing until the next boundary of the quantum
+/// they were started with, then interrupts them like [interrupt_block], flushing their notes.
+/// Only blocks playing at the time of the request are stopped: blocks started (or restarted) in
+/// the meantime keep playing. Stopping all blocks also cancels sequence or setlist right away, so
+/// it doesn't start another block.
+pub async fn stop(app_state: Arc<AppState>, uuid: Option<&str>, at: StopAt) -> Result<(), String> {
+    if at == StopAt::Now {
+        return match uuid {
+            Some(uuid) => interrupt_block(app_state, uuid).await,
+            None => interrupt(app_state).await,
+        };
+    }
+
+    let stopped: Vec<(String, Progress)> = app_state
+        .currently_playing
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(playing, _)| uuid.map_or(true, |uuid| uuid == playing.as_str()))
+        .map(|(uuid, progress)| (uuid.clone(), progress.clone()))
+        .collect();
+    if uuid.is_none() {
+        if let Some(sequence) = app_state.audio_engine.write().unwrap().sequence.take() {
+            sequence.abort();
+        }
+    }
+
+    for (uuid, progress) in stopped {
+        info!("block#{uuid} stops at the end of the bar");
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            wait_for_next_quantum(&app_state, progress.quantum).await;
+            let restarted = app_state
+                .currently_playing
+                .read()
+                .unwrap()
+                .get(&uuid)
+                .map_or(true, |playing| playing.origin != progress.origin);
+            if restarted {
+                return;
+            }
+            if let Err(err) = interrupt_block(app_state, &uuid).await {
+                tracing::error!("failed to stop block#{uuid} at the end of the bar: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
 /// Send play request to [AudioEngine] worker with the id of the block to be played
+///
+/// Samples and tempo changes are played as one-shots next to the currently played block instead.
 pub async fn play(app_state: Arc<AppState>, uuid: &str) -> Result<(), String> {
+    let one_shot = app_state
+        .blocks
+        .read()
+        .unwrap()
+        .get(uuid)
+        .and_then(|block| match &block.content {
+            block::Content::Sample(_) | block::Content::Tempo { .. } => {
+                Some((block.content.clone(), block.quantum))
+            }
+            _ => None,
+        });
+    if let Some((content, _)) = &one_shot {
+        timeline::record(
+            &app_state,
+            timeline::Kind::BlockStarted,
+            Some(uuid),
+            content.name(),
+        );
+    }
+    match one_shot {
+        Some((block::Content::Sample(sample), quantum)) => {
+            play_one_shot(app_state, sample, quantum);
+            return Ok(());
+        }
+        Some((block::Content::Tempo { bpm, ramp_beats }, _)) => {
+            let should_stop = until_interrupt(&app_state);
+            tokio::spawn(async move {
+                change_tempo(&app_state, bpm, ramp_beats, should_stop).await;
+            });
+            return Ok(());
+        }
+        _ => {}
+    }
+
     // TODO: This is wrong approach, we should select what will be played, not what to play now.
     let work_in = app_state.audio_engine.write().unwrap().work_in.clone();
 
@@ -559,9 +2658,298 @@ pub async fn play(app_state: Arc<AppState>, uuid: &str) -> Result<(), String> {
         .send(Request::Play(RequestPlay {
             uuid: uuid.to_string(),
             app_state: app_state.clone(),
+            section: None,
+            done: None,
         }))
         .await
         .map_err(|err| format!("failed to send job: {err}"))?;
 
     Ok(())
 }
+
+/// Send play request to [AudioEngine] worker to play only given section of the MIDI block
+///
+/// See [block::MidiSource::sections] for how sections are numbered.
+pub async fn play_section(
+    app_state: Arc<AppState>,
+    uuid: &str,
+    section: usize,
+) -> Result<(), String> {
+    let work_in = app_state.audio_engine.write().unwrap().work_in.clone();
+
+    work_in
+        .send(Request::Play(RequestPlay {
+            uuid: uuid.to_string(),
+            app_state: app_state.clone(),
+            section: Some(section),
+            done: None,
+        }))
+        .await
+        .map_err(|err| format!("failed to send job: {err}"))
+}
+
+/// Send play request to [AudioEngine] worker and wait until the block stops playing
+///
+/// Tempo changes are waited for here instead, since [play] doesn't pass them to the worker either.
+pub async fn play_and_wait(app_state: Arc<AppState>, uuid: &str) -> Result<Ending, String> {
+    let tempo = app_state
+        .blocks
+        .read()
+        .unwrap()
+        .get(uuid)
+        .and_then(|block| match block.content {
+            block::Content::Tempo { bpm, ramp_beats } => {
+                Some((bpm, ramp_beats, block.content.name()))
+            }
+            _ => None,
+        });
+    if let Some((bpm, ramp_beats, name)) = tempo {
+        timeline::record(&app_state, timeline::Kind::BlockStarted, Some(uuid), name);
+        let should_stop = until_interrupt(&app_state);
+        change_tempo(&app_state, bpm, ramp_beats, &should_stop).await;
+        return Ok(if should_stop() {
+            Ending::Interrupted
+        } else {
+            Ending::Finished
+        });
+    }
+
+    let work_in = app_state.audio_engine.write().unwrap().work_in.clone();
+    let (done, ended) = tokio::sync::oneshot::channel();
+
+    work_in
+        .send(Request::Play(RequestPlay {
+            uuid: uuid.to_string(),
+            app_state: app_state.clone(),
+            section: None,
+            done: Some(done),
+        }))
+        .await
+        .map_err(|err| format!("failed to send job: {err}"))?;
+
+    ended
+        .await
+        .map_err(|_| "audio engine stopped before block ended".to_string())?
+}
+
+/// Longest sleep between checks of the Link tempo while waiting for the next sequence entry
+const SEQUENCE_RESOLUTION: Duration = Duration::from_millis(10);
+
+/// Wait given number of beats, following tempo changes of Link session
+///
+/// Beats are counted from the elapsed time instead of the Link beat grid, since block started when
+/// nothing else is playing realigns the grid to it's own start.
+async fn wait_beats(app_state: &AppState, beats: f64) {
+    let mut session_state = SessionState::new();
+    let mut waited = 0.0;
+    let mut last = app_state.clock.micros();
+
+    while waited < beats {
+        app_state.link.capture_app_session_state(&mut session_state);
+        let tempo = session_state.tempo();
+        let remaining = Duration::from_secs_f64((beats - waited) * 60.0 / tempo);
+        tokio::time::sleep(remaining.min(SEQUENCE_RESOLUTION)).await;
+
+        let now = app_state.clock.micros();
+        waited += (now - last) as f64 / 1_000_000.0 * tempo / 60.0;
+        last = now;
+    }
+}
+
+/// Play given blocks one after another, each starting at it's own beat
+///
+/// Each block of the sequence stops the previous one, like a single track. Sequence replaces
+/// previously started one and runs until the last block is started, or [interrupt] is requested.
+pub fn play_sequence(app_state: Arc<AppState>, mut entries: Vec<SequenceEntry>) {
+    entries.sort_by(|lhs, rhs| lhs.start_at_beat.total_cmp(&rhs.start_at_beat));
+
+    let task = {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            let mut current_beat = 0.0;
+            let mut previous: Option<String> = None;
+            for SequenceEntry {
+                uuid,
+                start_at_beat,
+            } in entries
+            {
+                wait_beats(&app_state, start_at_beat - current_beat).await;
+                current_beat = start_at_beat;
+
+                info!("sequence starts block#{uuid} at beat {start_at_beat}");
+                if let Some(previous) = previous.replace(uuid.clone()) {
+                    if previous != uuid {
+                        if let Err(err) = interrupt_block(app_state.clone(), &previous).await {
+                            tracing::error!("sequence failed to stop block#{previous}: {err}");
+                        }
+                    }
+                }
+                if let Err(err) = play(app_state.clone(), &uuid).await {
+                    tracing::error!("sequence failed to play block#{uuid}: {err}");
+                }
+            }
+        })
+    };
+
+    if let Some(previous) = app_state
+        .audio_engine
+        .write()
+        .unwrap()
+        .sequence
+        .replace(task)
+    {
+        previous.abort();
+    }
+}
+
+/// Wait until the next quantum boundary of Link session, following tempo changes
+async fn wait_for_next_bar(app_state: &AppState) {
+    wait_for_next_quantum(app_state, app_state.quantum).await;
+}
+
+/// Wait until the next boundary of given quantum in Link session, following tempo changes
+async fn wait_for_next_quantum(app_state: &AppState, quantum: f64) {
+    let mut session_state = SessionState::new();
+    app_state.link.capture_app_session_state(&mut session_state);
+    let beat = session_state.beat_at_time(app_state.clock.micros(), quantum);
+    let next_bar = (beat / quantum).ceil() * quantum;
+    wait_beats(app_state, next_bar - beat).await;
+}
+
+/// Play given blocks of the [setlist][crate::setlist] one after another
+///
+/// Each block starts on the bar after the previous one finished. Setlist ends with it's last block
+/// or when any of the blocks gets interrupted. Like [play_sequence], it replaces previously
+/// started sequence or setlist and is cancelled by [interrupt].
+pub fn play_setlist(app_state: Arc<AppState>, blocks: Vec<String>) {
+    let task = {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            for (nth, uuid) in blocks.iter().enumerate() {
+                if nth > 0 {
+                    wait_for_next_bar(&app_state).await;
+                }
+
+                if !app_state.blocks.read().unwrap().contains_key(uuid) {
+                    tracing::error!("setlist skips block#{uuid} since it no longer exists");
+                    continue;
+                }
+
+                info!("setlist starts block#{uuid}");
+                match play_and_wait(app_state.clone(), uuid).await {
+                    Ok(Ending::Finished) => {}
+                    Ok(Ending::Interrupted) => {
+                        info!("setlist stopped, since block#{uuid} was interrupted");
+                        return;
+                    }
+                    Err(err) => {
+                        tracing::error!("setlist failed to play block#{uuid}: {err}");
+                        return;
+                    }
+                }
+            }
+            info!("setlist finished");
+        })
+    };
+
+    if let Some(previous) = app_state
+        .audio_engine
+        .write()
+        .unwrap()
+        .sequence
+        .replace(task)
+    {
+        previous.abort();
+    }
+}
+
+/// Scheduling driven by [SimulatedClock][crate::clock::SimulatedClock]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    /// Interrupt flag that is not set
+    fn not_interrupted() -> (std::sync::Mutex<bool>, std::sync::Condvar) {
+        (std::sync::Mutex::new(false), std::sync::Condvar::new())
+    }
+
+    /// Waiting ends exactly on the target, after sleeping and spinning for the rest
+    #[test]
+    fn waits_until_target() {
+        let clock = SimulatedClock::new(1_000);
+        let interrupts = not_interrupted();
+        let mut waits = 0;
+        let reached = wait_until(&clock, &interrupts, || {
+            waits += 1;
+            (clock.micros() < 11_000).then_some(11_000)
+        });
+        assert!(reached);
+        assert_eq!(clock.micros(), 11_000);
+        // Sleep until the spin threshold, spin for the rest and check the target once more
+        assert_eq!(waits, 3);
+    }
+
+    /// Target that has already passed doesn't move the clock
+    #[test]
+    fn passed_target_is_reached_immediately() {
+        let clock = SimulatedClock::new(5_000);
+        let interrupts = not_interrupted();
+        assert!(wait_until(&clock, &interrupts, || None));
+        assert_eq!(clock.micros(), 5_000);
+    }
+
+    /// Target is asked again after each wait, so tempo changes move it
+    #[test]
+    fn target_is_followed_when_it_moves() {
+        let clock = SimulatedClock::new(0);
+        let interrupts = not_interrupted();
+        let mut target = 10_000;
+        let reached = wait_until(&clock, &interrupts, || {
+            // Tempo halves after the first wait
+            if clock.micros() > 0 {
+                target = 20_000;
+            }
+            (clock.micros() < target).then_some(target)
+        });
+        assert!(reached);
+        assert_eq!(clock.micros(), 20_000);
+    }
+
+    /// Interrupted wait ends without reaching the target
+    #[test]
+    fn interrupted_wait_returns_false() {
+        let clock = SimulatedClock::new(0);
+        let interrupts = (std::sync::Mutex::new(true), std::sync::Condvar::new());
+        let reached = wait_until(&clock, &interrupts, || {
+            (clock.micros() < 10_000).then_some(10_000)
+        });
+        assert!(!reached);
+        assert_eq!(clock.micros(), 0);
+    }
+
+    /// Run of events far behind Link clock is counted once
+    #[test]
+    fn falling_behind_is_counted_once_per_run() {
+        let mut stats = TimingStats::default();
+        for lateness in [0.0, 0.1, 0.2, 0.06, 0.001, 0.07, 0.0] {
+            stats.record(lateness);
+        }
+        assert_eq!(stats.events, 7);
+        assert_eq!(stats.late_events, 4);
+        assert_eq!(stats.fell_behind, 2);
+        assert_eq!(stats.max_lateness_ms, 200.0);
+    }
+
+    /// Spinning covers waits shorter than the spin threshold
+    #[test]
+    fn short_waits_only_spin() {
+        let clock = SimulatedClock::new(0);
+        let interrupts = not_interrupted();
+        let target = SPIN_THRESHOLD.as_micros() as i64 / 2;
+        assert!(wait_until(&clock, &interrupts, || {
+            (clock.micros() < target).then_some(target)
+        }));
+        assert_eq!(clock.micros(), target);
+    }
+}
@@ -0,0 +1,121 @@
+//! Audit trail of requests that changed the state of Harmonia
+//!
+//! Every request that is not a plain read (anything other then `GET` and `HEAD`) is recorded by
+//! [record] middleware into [Log], a ring buffer held in [AppState]. The log can be inspected with
+//! `GET /api/audit` to reconstruct what happened during a performance and who did it.
+
+use crate::AppState;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Query, State},
+    http::{header::USER_AGENT, Method, Request},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, net::SocketAddr, sync::Arc};
+
+/// Number of entries kept in the audit log before the oldest are forgotten
+pub const CAPACITY: usize = 1024;
+
+/// Single recorded request
+#[derive(Serialize, Clone)]
+pub struct Entry {
+    /// Local time when request was received in RFC 3339 format
+    pub time: String,
+
+    /// HTTP method of the request
+    pub method: String,
+
+    /// Path (with query) of the request
+    pub path: String,
+
+    /// Address from which request was made
+    pub address: SocketAddr,
+
+    /// User agent of the client, if provided
+    pub user_agent: Option<String>,
+
+    /// Status code with which Harmonia responded
+    pub status: u16,
+}
+
+/// Ring buffer of recorded requests
+#[derive(Default)]
+pub struct Log {
+    /// Recorded entries, oldest first
+    entries: VecDeque<Entry>,
+}
+
+impl Log {
+    /// Remember new entry, forgetting the oldest one when [CAPACITY] is reached
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// At most `limit` most recent entries, oldest first
+    pub fn recent(&self, limit: usize) -> Vec<Entry> {
+        let skip = self.entries.len().saturating_sub(limit);
+        self.entries.iter().skip(skip).cloned().collect()
+    }
+}
+
+/// Middleware recording all mutating requests in [AppState::audit]
+pub async fn record(
+    State(app_state): State<Arc<AppState>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+
+    let time = chrono::Local::now().to_rfc3339();
+    let method = request.method().to_string();
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.to_string())
+        .unwrap_or_default();
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(str::to_owned);
+
+    let response = next.run(request).await;
+    let status = response.status().as_u16();
+
+    tracing::info!("audit: {method} {path} from {address} responded with {status}");
+    app_state.audit.lock().unwrap().push(Entry {
+        time,
+        method,
+        path,
+        address,
+        user_agent,
+        status,
+    });
+
+    response
+}
+
+/// Query parameters of audit log request
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    /// Maximum number of the most recent entries to return
+    limit: Option<usize>,
+}
+
+/// Responds with recorded entries, oldest first
+pub async fn audit(
+    State(app_state): State<Arc<AppState>>,
+    Query(AuditQuery { limit }): Query<AuditQuery>,
+) -> Json<Vec<Entry>> {
+    let log = app_state.audit.lock().unwrap();
+    Json(log.recent(limit.unwrap_or(CAPACITY)))
+}
@@ -0,0 +1,392 @@
+//! Exporting and importing the whole state as a single archive
+//!
+//! Prepared concert setup can be moved between laptops, or kept as a backup, by downloading
+//! [export] and uploading it to [import] on the other instance. Archive is a zip of the same files
+//! that Harmonia keeps in it's cache: blocks, trash, nick, defaults, per port settings and content
+//! of blocks in [storage::CONTENT_PATH]. Secrets (session key, TLS certificate) are never
+//! exported.
+//!
+//! Import replaces the current state with the archived one. Every file is validated before
+//! anything is replaced, so a broken archive leaves the state untouched.
+
+use crate::{
+    block, cache_path, channels, latency, midi_clock, network, setlist, storage, trash, upload,
+    AppState, DEFAULTS_PATH, NICK_PATH, STATE_PATH,
+};
+use anyhow::{anyhow, Context};
+use axum::{
+    body::Full,
+    extract::{ConnectInfo, Multipart, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderMap, Response, StatusCode,
+    },
+    response::IntoResponse,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Read, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+use tracing::{error, info, warn};
+
+/// Files of the cache that make up the state, in the order in which they are archived
+const ENTRIES: &[&str] = &[
+    STATE_PATH,
+    trash::TRASH_PATH,
+    NICK_PATH,
+    DEFAULTS_PATH,
+    channels::PORT_CHANNELS_PATH,
+    latency::PORT_LATENCIES_PATH,
+    midi_clock::CLOCK_PORTS_PATH,
+    setlist::SETLISTS_PATH,
+    network::DISABLED_INTERFACES_PATH,
+];
+
+/// Serialize current state into files named like in the cache
+async fn collect(app_state: &AppState) -> Vec<(&'static str, anyhow::Result<Vec<u8>>)> {
+    /// Serialize value like it's stored in the cache
+    fn bson<T: serde::Serialize>(value: &T) -> anyhow::Result<Vec<u8>> {
+        bson::to_vec(value).context("serializing to bson")
+    }
+
+    let nick = app_state.nick.read().await.clone();
+    vec![
+        (STATE_PATH, bson(&*app_state.blocks.read().unwrap())),
+        (trash::TRASH_PATH, bson(&*app_state.trash.lock().unwrap())),
+        (NICK_PATH, Ok(nick.into_bytes())),
+        (DEFAULTS_PATH, bson(&*app_state.defaults.read().unwrap())),
+        (
+            channels::PORT_CHANNELS_PATH,
+            bson(&*app_state.port_channels.read().unwrap()),
+        ),
+        (
+            latency::PORT_LATENCIES_PATH,
+            bson(&*app_state.port_latencies.read().unwrap()),
+        ),
+        (
+            midi_clock::CLOCK_PORTS_PATH,
+            bson(
+                &app_state
+                    .clock_ports
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .collect::<Vec<_>>(),
+            ),
+        ),
+        (
+            setlist::SETLISTS_PATH,
+            bson(&*app_state.setlists.read().unwrap()),
+        ),
+        (
+            network::DISABLED_INTERFACES_PATH,
+            std::fs::read(cache_path().join(network::DISABLED_INTERFACES_PATH))
+                .context("reading disabled interfaces"),
+        ),
+    ]
+}
+
+/// Content of blocks and removed blocks, each stored content once
+fn contents(app_state: &AppState) -> Vec<storage::StoredBytes> {
+    let blocks = app_state.blocks.read().unwrap();
+    let trash = app_state.trash.lock().unwrap();
+    let mut contents: Vec<_> = blocks
+        .values()
+        .chain(trash.blocks())
+        .filter_map(|block| storage::of(&block.content))
+        .cloned()
+        .collect();
+    contents.sort_by(|a, b| a.hash().cmp(b.hash()));
+    contents.dedup_by(|a, b| a.hash() == b.hash());
+    contents
+}
+
+/// Name of the archived file with given content
+fn content_entry(content: &storage::StoredBytes) -> String {
+    format!("{}/{}", storage::CONTENT_PATH, content.hash())
+}
+
+/// Zip archive of the serialized state `entries` and content of blocks
+///
+/// Reads content of blocks from the disk, so it's meant to run on blocking thread.
+fn archive(
+    app_state: &AppState,
+    entries: Vec<(&'static str, anyhow::Result<Vec<u8>>)>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut write = |name: &str, bytes: &[u8]| -> anyhow::Result<()> {
+        archive.start_file(name, zip::write::FileOptions::default())?;
+        archive.write_all(bytes)?;
+        Ok(())
+    };
+
+    for (name, bytes) in entries {
+        let bytes = match bytes {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                // Missing settings just don't get exported, like they don't get loaded
+                warn!("skipping {name} in exported state: {err:#}");
+                continue;
+            }
+        };
+        write(name, &bytes)?;
+    }
+
+    for content in contents(app_state) {
+        let bytes = match content.read() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                // Block stays in the archive and is reported by integrity verification after import
+                warn!(
+                    "skipping missing content {} in exported state: {err}",
+                    content.hash()
+                );
+                continue;
+            }
+        };
+        write(&content_entry(&content), &bytes)?;
+    }
+    Ok(archive.finish()?.into_inner())
+}
+
+/// Responds with zip archive of the whole state
+pub async fn export(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+    let entries = collect(&app_state).await;
+    let archived = tokio::task::spawn_blocking(move || archive(&app_state, entries))
+        .await
+        .context("exporting state")
+        .and_then(|archived| archived);
+    let bytes = match archived {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            error!("failed to export state: {err:#}");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")));
+        }
+    };
+
+    info!("exported state");
+    let file_name = format!(
+        "harmonia-state-{}.zip",
+        chrono::Local::now().format("%Y-%m-%d")
+    );
+    let mut response = Response::new(Full::from(bytes));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{file_name}\"")
+            .parse()
+            .unwrap(),
+    );
+    headers.insert(CONTENT_TYPE, "application/zip".parse().unwrap());
+    Ok(response)
+}
+
+/// State read from the archive, see [read_archive]
+#[derive(Default)]
+struct Imported {
+    /// Blocks, see [STATE_PATH]
+    blocks: Option<HashMap<String, block::Block>>,
+
+    /// Trash, see [trash::TRASH_PATH]
+    trash: Option<trash::Trash>,
+
+    /// Nick, see [NICK_PATH]
+    nick: Option<String>,
+
+    /// Defaults for new blocks, see [DEFAULTS_PATH]
+    defaults: Option<block::Defaults>,
+
+    /// Channels forwarded to ports, see [channels::PORT_CHANNELS_PATH]
+    port_channels: Option<HashMap<String, channels::ChannelFilter>>,
+
+    /// Latencies of ports, see [latency::PORT_LATENCIES_PATH]
+    port_latencies: Option<HashMap<String, i32>>,
+
+    /// Ports receiving MIDI clock, see [midi_clock::CLOCK_PORTS_PATH]
+    clock_ports: Option<Vec<String>>,
+
+    /// Setlists, see [setlist::SETLISTS_PATH]
+    setlists: Option<BTreeMap<String, setlist::Setlist>>,
+
+    /// Raw content of each file, written to the cache once everything is validated
+    files: Vec<(&'static str, Vec<u8>)>,
+}
+
+/// Extract file with given name from the archive, [None] when it's not there
+fn extract<R: Read + std::io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+    max_file_size: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("reading {name}")),
+    };
+    let mut bytes = Vec::new();
+    (&mut file)
+        .take(max_file_size as u64 + 1)
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("extracting {name}"))?;
+    if bytes.len() > max_file_size {
+        return Err(anyhow!("{name} is bigger than the maximum file size"));
+    }
+    Ok(Some(bytes))
+}
+
+/// Read and validate every state file in the archive, unknown files are ignored
+fn read_archive(bytes: &[u8], max_file_size: usize) -> anyhow::Result<Imported> {
+    let mut archive =
+        zip::ZipArchive::new(std::io::Cursor::new(bytes)).context("opening state archive")?;
+    let mut imported = Imported::default();
+
+    for &name in ENTRIES {
+        let Some(bytes) = extract(&mut archive, name, max_file_size)? else {
+            continue;
+        };
+
+        let invalid = || format!("{name} is not a valid state file");
+        match name {
+            STATE_PATH => imported.blocks = Some(bson::from_slice(&bytes).with_context(invalid)?),
+            trash::TRASH_PATH => {
+                imported.trash = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            NICK_PATH => {
+                imported.nick = Some(String::from_utf8(bytes.clone()).with_context(invalid)?)
+            }
+            DEFAULTS_PATH => {
+                imported.defaults = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            channels::PORT_CHANNELS_PATH => {
+                imported.port_channels = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            latency::PORT_LATENCIES_PATH => {
+                imported.port_latencies = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            midi_clock::CLOCK_PORTS_PATH => {
+                imported.clock_ports = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            setlist::SETLISTS_PATH => {
+                imported.setlists = Some(bson::from_slice(&bytes).with_context(invalid)?)
+            }
+            network::DISABLED_INTERFACES_PATH => {
+                // Takes effect after restart, so it's only written to the cache
+                bson::from_slice::<bson::Document>(&bytes).with_context(invalid)?;
+            }
+            _ => unreachable!("all entries are handled"),
+        }
+        imported.files.push((name, bytes));
+    }
+
+    if imported.files.is_empty() {
+        return Err(anyhow!("archive doesn't contain Harmonia state"));
+    }
+
+    // Archives exported by older versions have content inside the state file instead
+    let contents = imported
+        .blocks
+        .iter()
+        .flat_map(HashMap::values)
+        .chain(imported.trash.iter().flat_map(trash::Trash::blocks))
+        .filter_map(|block| storage::of(&block.content))
+        .filter(|content| !content.is_available());
+    for content in contents {
+        let name = content_entry(content);
+        let Some(bytes) = extract(&mut archive, &name, max_file_size)? else {
+            return Err(anyhow!("{name} is missing from the archive"));
+        };
+        content
+            .fill(bytes)
+            .map_err(|err| anyhow!("{name} is corrupted: {err}"))?;
+    }
+    Ok(imported)
+}
+
+/// Replaces current state with the one from uploaded archive (`state` field)
+///
+/// Responds with names of imported files, one per line. UI is refreshed to show imported state.
+/// Archives with command blocks are accepted only from this computer, like creating them.
+pub async fn import(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut field = multipart
+        .next_field()
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("upload failed: {err}")))?
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "missing state archive".to_string()))?;
+    let bytes = upload::receive(&app_state, &mut field)
+        .await
+        .and_then(|upload| {
+            upload
+                .into_bytes()
+                .map_err(|err| format!("failed to read upload: {err}"))
+        })
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let imported = read_archive(&bytes, app_state.max_file_size)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))?;
+
+    let has_commands = imported
+        .blocks
+        .iter()
+        .flat_map(HashMap::values)
+        .chain(imported.trash.iter().flat_map(trash::Trash::blocks))
+        .any(|block| matches!(block.content, block::Content::Command { .. }));
+    if has_commands && !addr.ip().is_loopback() {
+        warn!("refused to import command blocks for {addr}");
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Archives with command blocks can be imported only from this computer".to_string(),
+        ));
+    }
+
+    if let Some(blocks) = imported.blocks {
+        *app_state.blocks.write().unwrap() = blocks;
+    }
+    if let Some(trash) = imported.trash {
+        *app_state.trash.lock().unwrap() = trash;
+    }
+    if let Some(nick) = imported.nick {
+        app_state.discovery.set_name(&nick);
+        *app_state.nick.write().await = nick;
+    }
+    if let Some(defaults) = imported.defaults {
+        *app_state.defaults.write().unwrap() = defaults;
+    }
+    if let Some(port_channels) = imported.port_channels {
+        *app_state.port_channels.write().unwrap() = port_channels;
+    }
+    if let Some(port_latencies) = imported.port_latencies {
+        *app_state.port_latencies.write().unwrap() = port_latencies;
+    }
+    if let Some(clock_ports) = imported.clock_ports {
+        *app_state.clock_ports.write().unwrap() = HashSet::from_iter(clock_ports);
+    }
+    if let Some(setlists) = imported.setlists {
+        *app_state.setlists.write().unwrap() = setlists;
+    }
+
+    if !app_state.ephemeral {
+        for (name, bytes) in &imported.files {
+            let path = cache_path().join(name);
+            if let Err(err) = std::fs::write(&path, bytes) {
+                warn!("failed to write imported {name} to {path:?}: {err}");
+            }
+        }
+    }
+    // Writes imported content of blocks next to the state referencing it
+    if let Err(err) = app_state.remember_current_blocks() {
+        warn!("failed to save imported blocks: {err:#}");
+    }
+
+    let names: Vec<_> = imported.files.iter().map(|(name, _)| *name).collect();
+    info!("imported state: {}", names.join(", "));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Refresh", "true".parse().unwrap());
+    Ok((headers, names.join("\n")))
+}
@@ -5,7 +5,9 @@
 //! and specialized information about the thing that will be played. See [Content] for currently
 //! playable types in Harmonia.
 
+use crate::storage::StoredBytes;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
 /// Representation of anything that can be played with Harmonia
 #[derive(Serialize, Deserialize, Clone)]
@@ -19,8 +21,234 @@ pub struct Block {
     /// Associated user keybind if any
     pub keybind: String,
 
+    /// Free-text notes shown next to the block, like "intro - wait for conductor's cue"
+    #[serde(default)]
+    pub description: String,
+
+    /// Labels grouping blocks of large libraries, like concert or rehearsal variant
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Color label visually separating pieces, sections or instruments in the blocks list
+    #[serde(default)]
+    pub color: Option<Color>,
+
+    /// MIDI message from controller starting and stopping the block, see [crate::midi_input]
+    #[serde(default)]
+    pub midi_trigger: Option<crate::midi_input::Trigger>,
+
+    /// Quantum used when starting this block, overrides session default
+    #[serde(default)]
+    pub quantum: Option<f64>,
+
+    /// Whether MIDI content starts again from the beginning when it ends, until interrupted
+    #[serde(default)]
+    pub loop_playback: bool,
+
+    /// How block starts when nothing else is playing, overrides session default
+    #[serde(default)]
+    pub launch: Option<Launch>,
+
+    /// Number of beats counted in before block starts, see [audio_engine]
+    ///
+    /// [audio_engine]: crate::audio_engine
+    #[serde(default)]
+    pub count_in: u32,
+
     /// Description of what and how will be played
     pub content: Content,
+
+    /// Reason why block failed last [integrity verification][crate::integrity], if it did
+    #[serde(skip)]
+    pub integrity_error: Option<String>,
+}
+
+impl Block {
+    /// Create new block with given content and default settings
+    pub fn new(content: Content) -> Self {
+        Self {
+            content,
+            group: Default::default(),
+            keybind: Default::default(),
+            description: Default::default(),
+            tags: Default::default(),
+            color: Default::default(),
+            midi_trigger: Default::default(),
+            order: Default::default(),
+            quantum: Default::default(),
+            loop_playback: Default::default(),
+            launch: Default::default(),
+            count_in: Default::default(),
+            integrity_error: Default::default(),
+        }
+    }
+}
+
+/// Longest name given with [Content::rename], in characters
+pub const MAX_NAME_LENGTH: usize = 200;
+
+/// Check whether name can be given with [Content::rename]
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("name cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "name cannot be longer than {MAX_NAME_LENGTH} characters"
+        ));
+    }
+    // Name of the file is sent in Content-Disposition header when downloaded
+    if name.chars().any(|c| c.is_control() || c == '"') {
+        return Err("name cannot contain quotes or control characters".to_string());
+    }
+    Ok(())
+}
+
+/// Settings applied to newly created blocks instead of empty group and minimal port
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Defaults {
+    /// Group assigned to new blocks
+    pub group: String,
+
+    /// Port assigned to new blocks played through MIDI, minimal port if not set
+    pub port: Option<usize>,
+
+    /// Whether UI uploads files into group chosen in the filter bar instead of [Self::group]
+    #[serde(default)]
+    pub upload_to_filtered_group: bool,
+}
+
+impl Defaults {
+    /// Create new block with given content, using these defaults instead of [Block::new] ones
+    pub fn block(&self, content: Content) -> Block {
+        let mut block = Block::new(content);
+        block.group = self.group.clone();
+        if let (Some(port), Some(associated_port)) = (self.port, block.content.port_mut()) {
+            *associated_port = port;
+        }
+        block
+    }
+}
+
+/// How block starts when no other block is playing
+///
+/// Blocks started while others are playing always start on the next quantum boundary.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Launch {
+    /// Timeline of Link session is realigned so the block starts at beat 0 right away
+    #[default]
+    Immediate,
+
+    /// Timeline of Link session is kept and the block starts on it's next quantum boundary
+    ///
+    /// Allows joining already running session without disturbing it's bars.
+    Quantized,
+}
+
+impl Launch {
+    /// All launch modes, in order presented to the user
+    pub const ALL: [Self; 2] = [Self::Immediate, Self::Quantized];
+
+    /// Name of the launch mode, the same as in JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Immediate => "immediate",
+            Self::Quantized => "quantized",
+        }
+    }
+}
+
+impl std::str::FromStr for Launch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|launch| launch.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown launch mode {s:?}, expected immediate or quantized"))
+    }
+}
+
+impl std::fmt::Display for Launch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Color label of the block, from a small palette readable on dark background
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    /// Red
+    Red,
+
+    /// Orange
+    Orange,
+
+    /// Yellow
+    Yellow,
+
+    /// Green
+    Green,
+
+    /// Teal
+    Teal,
+
+    /// Blue
+    Blue,
+
+    /// Purple
+    Purple,
+
+    /// Pink
+    Pink,
+}
+
+impl Color {
+    /// All colors, in order presented in the palette
+    pub const ALL: [Self; 8] = [
+        Self::Red,
+        Self::Orange,
+        Self::Yellow,
+        Self::Green,
+        Self::Teal,
+        Self::Blue,
+        Self::Purple,
+        Self::Pink,
+    ];
+
+    /// Name of the color, the same as in JSON and in CSS class `color-<name>`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Red => "red",
+            Self::Orange => "orange",
+            Self::Yellow => "yellow",
+            Self::Green => "green",
+            Self::Teal => "teal",
+            Self::Blue => "blue",
+            Self::Purple => "purple",
+            Self::Pink => "pink",
+        }
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|color| color.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown color {s:?}"))
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 /// Different kinds of contents that can be played with Harmonia
@@ -39,7 +267,65 @@ pub enum Content {
     SharedMemory {
         /// Path pointing to shared memory
         path: String,
+
+        /// Name shown instead of the path, see [Content::rename]
+        #[serde(default)]
+        label: Option<String>,
     },
+
+    /// Short audio file played once through system audio output
+    ///
+    /// Samples don't interrupt currently played block, so they can be cued during the piece.
+    Sample(AudioSource),
+
+    /// Launch external program on start and kill it on interrupt
+    Command {
+        /// Program to execute
+        program: String,
+
+        /// Arguments passed to the program
+        args: Vec<String>,
+    },
+
+    /// Change tempo of Link session, instantly or gradually
+    ///
+    /// Like samples, tempo changes don't interrupt currently played block.
+    Tempo {
+        /// Tempo to set in beats per minute
+        bpm: f64,
+
+        /// Number of beats over which tempo changes linearly, zero for instant change
+        ramp_beats: f64,
+    },
+
+    /// Synchronized silence lasting given number of beats
+    Silence {
+        /// Length of silence in beats
+        beats: f64,
+    },
+
+    /// Chord described with text specification, see [chord]
+    ///
+    /// [chord]: crate::chord
+    Chord(ChordSource),
+
+    /// Step sequencer pattern looped until interrupted
+    Pattern(Pattern),
+
+    /// Reference to other MIDI or chord block played with overridden settings
+    Alias(Alias),
+
+    /// Several blocks started together on the same beat
+    Scene(Scene),
+
+    /// Click on every beat of Link session, until interrupted
+    Metronome(Metronome),
+
+    /// Audio file played as a backing track, started like MIDI blocks
+    ///
+    /// Unlike [Content::Sample] it is not a one-shot: it starts on the same beat as MIDI blocks
+    /// of the group and is reported as playing until it ends.
+    Audio(AudioSource),
 }
 
 impl Content {
@@ -47,27 +333,1027 @@ impl Content {
     pub fn name(&self) -> String {
         match self {
             Self::Midi(midi_source) => midi_source.file_name.clone(),
-            Self::SharedMemory { path } => path.clone(),
+            Self::SharedMemory { path, label } => label.clone().unwrap_or_else(|| path.clone()),
+            Self::Sample(audio_source) | Self::Audio(audio_source) => {
+                audio_source.file_name.clone()
+            }
+            Self::Command { program, args } => std::iter::once(program)
+                .chain(args)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" "),
+            Self::Tempo { bpm, ramp_beats } if *ramp_beats > 0.0 => {
+                format!("{bpm} BPM over {ramp_beats} beats")
+            }
+            Self::Tempo { bpm, .. } => format!("{bpm} BPM"),
+            Self::Silence { beats } => format!("{beats} beats of silence"),
+            Self::Chord(chord_source) => chord_source.spec.clone(),
+            Self::Pattern(pattern) => pattern.name.clone(),
+            Self::Alias(alias) if alias.transpose != 0 => {
+                format!("{} ({:+} semitones)", alias.name, alias.transpose)
+            }
+            Self::Alias(alias) => alias.name.clone(),
+            Self::Scene(scene) => scene.name.clone(),
+            Self::Metronome(metronome) => format!(
+                "Metronome (key {}/{} on ch{})",
+                metronome.accent_note,
+                metronome.note,
+                metronome.channel + 1
+            ),
+        }
+    }
+
+    /// Change human readable name of file based and shared memory content
+    ///
+    /// Extension of the file is kept when the new name doesn't have it, so downloaded file still
+    /// opens in the right program. Shared memory keeps it's path, only the shown name changes.
+    pub fn rename(&mut self, name: &str) -> Result<(), String> {
+        validate_name(name)?;
+        let name = name.trim();
+
+        /// Name with extension of the previous file name, unless it already has it
+        fn with_extension(previous: &str, name: &str) -> String {
+            match std::path::Path::new(previous)
+                .extension()
+                .map(|extension| format!(".{}", extension.to_string_lossy()))
+            {
+                Some(extension) if !name.to_lowercase().ends_with(&extension.to_lowercase()) => {
+                    format!("{name}{extension}")
+                }
+                _ => name.to_string(),
+            }
+        }
+
+        match self {
+            Self::Midi(MidiSource { file_name, .. })
+            | Self::Sample(AudioSource { file_name, .. })
+            | Self::Audio(AudioSource { file_name, .. }) => {
+                *file_name = with_extension(file_name, name);
+            }
+            Self::SharedMemory { path, label } => {
+                *label = (name != path.as_str()).then(|| name.to_string());
+            }
+            _ => return Err(format!("{} blocks cannot be renamed", self.kind())),
+        }
+        Ok(())
+    }
+
+    /// Whether content can be renamed with [Content::rename]
+    pub fn can_rename(&self) -> bool {
+        matches!(
+            self,
+            Self::Midi(_) | Self::Sample(_) | Self::Audio(_) | Self::SharedMemory { .. }
+        )
+    }
+
+    /// Machine readable kind of given content
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Midi(_) => "midi",
+            Self::SharedMemory { .. } => "shared_memory",
+            Self::Sample(_) => "sample",
+            Self::Command { .. } => "command",
+            Self::Tempo { .. } => "tempo",
+            Self::Silence { .. } => "silence",
+            Self::Chord(_) => "chord",
+            Self::Pattern(_) => "pattern",
+            Self::Alias(_) => "alias",
+            Self::Scene(_) => "scene",
+            Self::Metronome(_) => "metronome",
+            Self::Audio(_) => "audio",
+        }
+    }
+
+    /// MIDI port to which content is played, [None] if it's not played through MIDI
+    pub fn port(&self) -> Option<usize> {
+        match self {
+            Self::Midi(midi_source) => Some(midi_source.associated_port),
+            Self::Chord(chord_source) => Some(chord_source.associated_port),
+            Self::Pattern(pattern) => Some(pattern.associated_port),
+            Self::Alias(alias) => Some(alias.associated_port),
+            Self::Metronome(metronome) => Some(metronome.associated_port),
+            Self::SharedMemory { .. }
+            | Self::Sample(_)
+            | Self::Audio(_)
+            | Self::Command { .. }
+            | Self::Tempo { .. }
+            | Self::Silence { .. }
+            | Self::Scene(_) => None,
+        }
+    }
+
+    /// Mutable reference to MIDI port, see [Content::port]
+    pub fn port_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Self::Midi(midi_source) => Some(&mut midi_source.associated_port),
+            Self::Chord(chord_source) => Some(&mut chord_source.associated_port),
+            Self::Pattern(pattern) => Some(&mut pattern.associated_port),
+            Self::Alias(alias) => Some(&mut alias.associated_port),
+            Self::Metronome(metronome) => Some(&mut metronome.associated_port),
+            Self::SharedMemory { .. }
+            | Self::Sample(_)
+            | Self::Audio(_)
+            | Self::Command { .. }
+            | Self::Tempo { .. }
+            | Self::Silence { .. }
+            | Self::Scene(_) => None,
         }
     }
+
+    /// Whether content starts synchronized with Link session, honoring [Block::launch] and
+    /// [Block::count_in]
+    pub fn is_synchronized(&self) -> bool {
+        !matches!(self, Self::Sample(_) | Self::Tempo { .. })
+    }
+
+    /// Whether content is played as MIDI file, which can be looped with [Block::loop_playback]
+    pub fn loops(&self) -> bool {
+        matches!(self, Self::Midi(_) | Self::Chord(_) | Self::Alias(_))
+    }
+}
+
+/// Click track played on MIDI port, driven by Link beats
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Metronome {
+    /// MIDI channel (counted from 0) on which clicks are played
+    pub channel: u8,
+
+    /// Key played on beats other than the first beat of the bar
+    pub note: u8,
+
+    /// Key played on the first beat of the bar, bar being quantum long
+    pub accent_note: u8,
+
+    /// Refers to allocated MIDI ports list
+    pub associated_port: usize,
+}
+
+impl Metronome {
+    /// Check that channel and keys are valid MIDI values
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channel > 15 {
+            return Err(format!("invalid channel {}", self.channel));
+        }
+        if let Some(key) = [self.note, self.accent_note]
+            .into_iter()
+            .find(|key| *key > 127)
+        {
+            return Err(format!("invalid key {key}"));
+        }
+        Ok(())
+    }
+}
+
+/// Description of chord sources
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChordSource {
+    /// Text specification of the chord, see [chord]
+    ///
+    /// [chord]: crate::chord
+    pub spec: String,
+
+    /// Refers to allocated MIDI ports list
+    pub associated_port: usize,
+}
+
+impl ChordSource {
+    /// Render chord as MIDI source played like any other MIDI block
+    pub fn to_midi_source(&self) -> Result<MidiSource, String> {
+        let chord: crate::chord::Chord = self.spec.parse()?;
+        Ok(MidiSource::new(
+            chord.to_midi(),
+            self.spec.clone(),
+            self.associated_port,
+        ))
+    }
+}
+
+/// Description of audio sources
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AudioSource {
+    /// Encoded audio file itself
+    pub bytes: StoredBytes,
+
+    /// Original file name of audio source
+    pub file_name: String,
+}
+
+/// Reference to other block with overridden settings
+///
+/// Allows the same MIDI file to appear multiple times in the setlist without duplicating it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Alias {
+    /// Identifier of the referenced block
+    pub target: String,
+
+    /// Name of the referenced block at the moment of alias creation
+    pub name: String,
+
+    /// MIDI port used instead of the one of referenced block
+    pub associated_port: usize,
+
+    /// Number of semitones by which all notes are transposed
+    #[serde(default)]
+    pub transpose: i8,
+}
+
+impl Alias {
+    /// Can given content be referenced by alias
+    pub fn can_reference(content: &Content) -> bool {
+        matches!(content, Content::Midi(_) | Content::Chord(_))
+    }
+
+    /// Resolve alias into MIDI source with overrides applied, given the referenced content
+    pub fn resolve(&self, target: &Content) -> Result<MidiSource, String> {
+        let midi_source = match target {
+            Content::Midi(midi_source) => midi_source.clone(),
+            Content::Chord(chord_source) => chord_source.to_midi_source()?,
+            other => {
+                return Err(format!(
+                    "alias cannot reference block of kind {}",
+                    other.kind()
+                ))
+            }
+        };
+
+        Ok(MidiSource {
+            associated_port: self.associated_port,
+            ..midi_source.transposed(self.transpose)?
+        })
+    }
+}
+
+/// Group of blocks started together with one action
+///
+/// Blocks keep their own ports, but are played in the group of the scene, so they all start on
+/// the same beat.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Scene {
+    /// Human readable name of the scene
+    pub name: String,
+
+    /// Identifiers of the blocks started by the scene
+    pub blocks: Vec<String>,
+}
+
+impl Scene {
+    /// Can given content be part of the scene
+    ///
+    /// Scenes cannot be nested, samples and tempo changes are fine since they are one-shots anyway.
+    pub fn can_include(content: &Content) -> bool {
+        !matches!(content, Content::Scene(_))
+    }
+}
+
+/// Grid of steps played by step sequencer
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pattern {
+    /// Human readable name of the pattern
+    pub name: String,
+
+    /// Number of steps in a single repetition of the pattern
+    pub steps: usize,
+
+    /// Length of a single step in beats
+    pub step_beats: f64,
+
+    /// Notes played by the pattern, each with it's own steps
+    pub rows: Vec<PatternRow>,
+
+    /// Refers to allocated MIDI ports list
+    #[serde(default)]
+    pub associated_port: usize,
+}
+
+/// Single note of the [Pattern] with steps on which it is played
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PatternRow {
+    /// MIDI channel (counted from 0) on which note is played
+    pub channel: u8,
+
+    /// MIDI key of the note
+    pub key: u8,
+
+    /// Velocity of the note
+    pub velocity: u8,
+
+    /// For each step of the pattern, whether note is played on it
+    pub steps: Vec<bool>,
+}
+
+impl Pattern {
+    /// Check if pattern describes something that can be played
+    pub fn validate(&self) -> Result<(), String> {
+        if self.steps == 0 {
+            return Err("pattern must have at least one step".to_string());
+        }
+        if !self.step_beats.is_finite() || self.step_beats <= 0.0 {
+            return Err(format!("invalid step length {}", self.step_beats));
+        }
+        for (nth, row) in self.rows.iter().enumerate() {
+            if row.channel > 15 {
+                return Err(format!("row {nth} has invalid channel {}", row.channel));
+            }
+            if row.key > 127 {
+                return Err(format!("row {nth} has invalid key {}", row.key));
+            }
+            if row.velocity == 0 || row.velocity > 127 {
+                return Err(format!("row {nth} has invalid velocity {}", row.velocity));
+            }
+            if row.steps.len() != self.steps {
+                return Err(format!(
+                    "row {nth} has {} steps, expected {}",
+                    row.steps.len(),
+                    self.steps
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Description of MIDI sources
 #[derive(Serialize, Deserialize, Clone)]
 pub struct MidiSource {
     /// MIDI source itself
-    pub bytes: Vec<u8>,
+    pub bytes: StoredBytes,
 
     /// Original file name of MIDI source
     pub file_name: String,
 
     /// Refers to allocated MIDI ports list
     pub associated_port: usize,
+
+    /// Sections defined by the user, see [MidiSource::sections]
+    #[serde(default)]
+    pub sections: Vec<Section>,
+
+    /// Tick from which playback starts, see [MidiSource::trimmed]
+    #[serde(default)]
+    pub start_tick: Option<u64>,
+
+    /// Tick on which playback ends, [None] to play until the end of the file
+    #[serde(default)]
+    pub end_tick: Option<u64>,
+
+    /// Scaling of note velocities applied when block is played
+    #[serde(default)]
+    pub velocity: Velocity,
+
+    /// Properties derived from the file when block is created, see [MidiSource::with_metadata]
+    #[serde(default)]
+    pub metadata: Option<MidiMetadata>,
+}
+
+/// Properties of the MIDI file derived once, so rendering and playing block doesn't parse it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MidiMetadata {
+    /// Format of the file: `single`, `parallel` or `sequential`
+    pub format: String,
+
+    /// Number of tracks in the file
+    pub track_count: usize,
+
+    /// Ticks per quarter note, [None] for timecode timing
+    pub ticks_per_quater_note: Option<u16>,
+
+    /// Time of the last event of all tracks played together in ticks, see [merge_tracks]
+    pub length_ticks: u64,
+
+    /// Beats and names of marker events, ordered by beat, see [MidiSource::sections]
+    pub markers: Vec<(f64, String)>,
+
+    /// Beats at which time signature changes with beats per bar from this point, see [Meter]
+    pub meter: Vec<(f64, f64)>,
+}
+
+impl MidiMetadata {
+    /// Derive metadata of parsed MIDI file
+    ///
+    /// Markers and time signatures are collected from all tracks, since they are usually stored
+    /// in the first track of multitrack files. They are empty for timecode timing.
+    pub fn analyze(midi: &midly::SmfBytemap<'_>) -> Self {
+        let format = match midi.header.format {
+            midly::Format::SingleTrack => "single",
+            midly::Format::Parallel => "parallel",
+            midly::Format::Sequential => "sequential",
+        };
+        let ticks_per_quater_note = match midi.header.timing {
+            midly::Timing::Metrical(ticks) => Some(ticks.as_int()),
+            midly::Timing::Timecode(..) => None,
+        };
+
+        let mut markers = Vec::new();
+        let mut meter = Vec::new();
+        if let Some(ticks_per_quater_note) = ticks_per_quater_note {
+            let ticks_per_quater_note = ticks_per_quater_note as f64;
+            for track in &midi.tracks {
+                let mut ticks = 0_u64;
+                for (_, event) in track.iter() {
+                    ticks += event.delta.as_int() as u64;
+                    let beat = ticks as f64 / ticks_per_quater_note;
+                    match event.kind {
+                        midly::TrackEventKind::Meta(midly::MetaMessage::Marker(name)) => {
+                            markers.push((beat, String::from_utf8_lossy(name).into_owned()));
+                        }
+                        // http://midi.teragonaudio.com/tech/midifile/time.htm
+                        midly::TrackEventKind::Meta(midly::MetaMessage::TimeSignature(
+                            numerator,
+                            denominator,
+                            _,
+                            _,
+                        )) => {
+                            let beats_per_bar =
+                                numerator as f64 * 4.0 / 2_f64.powi(denominator as i32);
+                            meter.push((beat, beats_per_bar));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        markers.sort_by(|(lhs, _), (rhs, _)| lhs.total_cmp(rhs));
+        meter.sort_by(|(lhs, _), (rhs, _)| lhs.total_cmp(rhs));
+
+        Self {
+            format: format.to_string(),
+            track_count: midi.tracks.len(),
+            ticks_per_quater_note,
+            length_ticks: merge_tracks(midi).last().map_or(0, |(ticks, _, _)| *ticks),
+            markers,
+            meter,
+        }
+    }
+}
+
+/// Scaling of note velocities, so loud and quiet files can be balanced against each other
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Velocity {
+    /// Multiplier of the velocity from the file
+    pub scale: f64,
+
+    /// Lowest velocity of scaled notes
+    pub min: u8,
+
+    /// Highest velocity of scaled notes
+    pub max: u8,
+}
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            min: 1,
+            max: 127,
+        }
+    }
+}
+
+impl Velocity {
+    /// Check if scaling can be applied
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.scale.is_finite() || self.scale < 0.0 {
+            return Err(format!("invalid velocity scale {}", self.scale));
+        }
+        if !(1..=127).contains(&self.min) || !(1..=127).contains(&self.max) || self.min > self.max {
+            return Err(format!(
+                "invalid velocity range {min}..={max}, expected values between 1 and 127",
+                min = self.min,
+                max = self.max
+            ));
+        }
+        Ok(())
+    }
+
+    /// Scale velocity of Note On message
+    ///
+    /// Velocity 0 means Note Off, so it's kept as is.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        if velocity == 0 {
+            return 0;
+        }
+        (velocity as f64 * self.scale)
+            .round()
+            .clamp(self.min as f64, self.max as f64) as u8
+    }
+}
+
+/// Named part of MIDI block that can be played on it's own, like a rehearsal mark
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Section {
+    /// Name of the section
+    pub name: String,
+
+    /// Beat on which section starts
+    pub start: f64,
+
+    /// Beat on which section ends, [None] to play until the end of the block
+    pub end: Option<f64>,
+}
+
+/// Number of ticks in a beat (quarter note) of the MIDI file with given timing
+///
+/// Timecode timing counts ticks in subframes of SMPTE frames independently of tempo, so it's
+/// converted to beats using given tempo.
+pub fn ticks_per_beat(timing: midly::Timing, tempo: f64) -> f64 {
+    match timing {
+        midly::Timing::Metrical(ticks_per_quater_note) => ticks_per_quater_note.as_int() as f64,
+        midly::Timing::Timecode(fps, subframes_per_frame) => {
+            let ticks_per_second = fps.as_f32() as f64 * subframes_per_frame as f64;
+            ticks_per_second * 60.0 / tempo
+        }
+    }
+}
+
+/// Event of the MIDI file together with it's bytes and time in ticks from the start of the file
+pub type TimedEvent<'a> = (u64, &'a [u8], midly::TrackEvent<'a>);
+
+/// Events of all tracks merged into single sequence ordered by time in ticks
+///
+/// Tracks of [parallel][midly::Format::Parallel] files are played at the same time, so their events
+/// are interleaved, keeping the order of tracks for events on the same tick. Tracks of
+/// [sequential][midly::Format::Sequential] files are played one after another.
+pub fn merge_tracks<'a>(midi: &midly::SmfBytemap<'a>) -> Vec<TimedEvent<'a>> {
+    let mut events = Vec::new();
+    let mut track_start = 0_u64;
+    for track in &midi.tracks {
+        let mut ticks = track_start;
+        for (bytes, event) in track.iter() {
+            ticks += event.delta.as_int() as u64;
+            events.push((ticks, *bytes, *event));
+        }
+        if midi.header.format == midly::Format::Sequential {
+            track_start = ticks;
+        }
+    }
+
+    // Sort is stable, so events on the same tick keep the order of their tracks
+    events.sort_by_key(|(ticks, _, _)| *ticks);
+    events
 }
 
 impl MidiSource {
+    /// Source of the whole MIDI file played through given port, with metadata derived from it
+    pub fn new(bytes: impl Into<StoredBytes>, file_name: String, associated_port: usize) -> Self {
+        MidiSource {
+            bytes: bytes.into(),
+            file_name,
+            associated_port,
+            sections: Vec::new(),
+            start_tick: None,
+            end_tick: None,
+            velocity: Velocity::default(),
+            metadata: None,
+        }
+        .with_metadata()
+    }
+
     /// Return midi contained by midi source
     pub fn midi(&self) -> Result<midly::SmfBytemap<'_>, midly::Error> {
         midly::SmfBytemap::parse(&self.bytes)
     }
+
+    /// Source with [metadata][MidiSource::metadata] derived from the file, left empty when the
+    /// file cannot be parsed
+    pub fn with_metadata(self) -> Self {
+        let metadata = self.midi().ok().map(|midi| MidiMetadata::analyze(&midi));
+        Self { metadata, ..self }
+    }
+
+    /// Metadata derived when block was created, or from the file when missing
+    pub fn metadata(&self) -> Option<Cow<'_, MidiMetadata>> {
+        match &self.metadata {
+            Some(metadata) => Some(Cow::Borrowed(metadata)),
+            None => {
+                let midi = self.midi().ok()?;
+                Some(Cow::Owned(MidiMetadata::analyze(&midi)))
+            }
+        }
+    }
+
+    /// Copy of the MIDI source with all notes moved by given number of semitones
+    ///
+    /// Notes that would fall outside of MIDI range are clamped to the lowest or highest key.
+    pub fn transposed(&self, semitones: i8) -> Result<MidiSource, String> {
+        if semitones == 0 {
+            return Ok(self.clone());
+        }
+
+        let mut smf =
+            midly::Smf::parse(&self.bytes).map_err(|err| format!("failed to parse midi: {err}"))?;
+        for event in smf.tracks.iter_mut().flatten() {
+            if let midly::TrackEventKind::Midi {
+                message:
+                    midly::MidiMessage::NoteOn { key, .. }
+                    | midly::MidiMessage::NoteOff { key, .. }
+                    | midly::MidiMessage::Aftertouch { key, .. },
+                ..
+            } = &mut event.kind
+            {
+                *key = ((key.as_int() as i16 + semitones as i16).clamp(0, 127) as u8).into();
+            }
+        }
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)
+            .map_err(|err| format!("failed to write transposed midi: {err}"))?;
+        Ok(MidiSource {
+            bytes: bytes.into(),
+            file_name: self.file_name.clone(),
+            associated_port: self.associated_port,
+            sections: self.sections.clone(),
+            start_tick: self.start_tick,
+            end_tick: self.end_tick,
+            velocity: self.velocity,
+            // Only notes are moved, so everything else stays the same
+            metadata: self.metadata.clone(),
+        })
+    }
+
+    /// Sections of the block
+    ///
+    /// Sections defined by the user take precedence, otherwise sections are derived from marker
+    /// events of the MIDI file, each lasting until the next marker.
+    pub fn sections(&self) -> Vec<Section> {
+        if !self.sections.is_empty() {
+            return self.sections.clone();
+        }
+
+        let Some(metadata) = self.metadata() else {
+            return Vec::new();
+        };
+        let markers = metadata.markers.clone();
+
+        let ends: Vec<_> = markers
+            .iter()
+            .skip(1)
+            .map(|(start, _)| Some(*start))
+            .chain(std::iter::once(None))
+            .collect();
+        markers
+            .into_iter()
+            .zip(ends)
+            .map(|((start, name), end)| Section { name, start, end })
+            .collect()
+    }
+
+    /// Copy of the MIDI source containing only given section, see [MidiSource::sections]
+    pub fn section(&self, index: usize) -> Result<MidiSource, String> {
+        let sections = self.sections();
+        let section = sections.get(index).ok_or_else(|| {
+            format!(
+                "section {index} not found in {file_name:?}",
+                file_name = self.file_name
+            )
+        })?;
+        self.cut(section.start, section.end)
+    }
+
+    /// Copy of the MIDI source limited to [start_tick][MidiSource::start_tick] and
+    /// [end_tick][MidiSource::end_tick], as it is played
+    pub fn trimmed(&self) -> Result<MidiSource, String> {
+        if self.start_tick.is_none() && self.end_tick.is_none() {
+            return Ok(self.clone());
+        }
+        self.cut_ticks(self.start_tick.unwrap_or_default(), self.end_tick)
+    }
+
+    /// Copy of the MIDI source containing only events between `start` and `end` beats
+    ///
+    /// See [MidiSource::cut_ticks] for which events from before `start` are kept.
+    pub fn cut(&self, start: f64, end: Option<f64>) -> Result<MidiSource, String> {
+        let midi = self
+            .midi()
+            .map_err(|err| format!("failed to parse midi: {err}"))?;
+        let midly::Timing::Metrical(ticks_per_quater_note) = midi.header.timing else {
+            return Err("sections are not supported for timecode timing".to_string());
+        };
+        let ticks_per_quater_note = ticks_per_quater_note.as_int() as f64;
+        self.cut_ticks(
+            (start * ticks_per_quater_note).round() as u64,
+            end.map(|end| (end * ticks_per_quater_note).round() as u64),
+        )
+    }
+
+    /// Copy of the MIDI source containing only events between `start_tick` and `end_tick`
+    ///
+    /// State from before `start_tick` is fast-forwarded to it: tempo and time signature changes,
+    /// program changes, control changes and pitch bends are kept, notes are not. Notes still
+    /// sounding at `end_tick` are released there.
+    pub fn cut_ticks(&self, start_tick: u64, end_tick: Option<u64>) -> Result<MidiSource, String> {
+        let mut smf =
+            midly::Smf::parse(&self.bytes).map_err(|err| format!("failed to parse midi: {err}"))?;
+
+        for track in smf.tracks.iter_mut() {
+            let track_end: u64 = track.iter().map(|event| event.delta.as_int() as u64).sum();
+            let mut ticks = 0_u64;
+            let mut last_kept = start_tick;
+            let mut cut = Vec::with_capacity(track.len());
+            let mut sounding = Vec::new();
+
+            for event in track.iter() {
+                ticks += event.delta.as_int() as u64;
+                if end_tick.map_or(false, |end| ticks >= end) {
+                    break;
+                }
+
+                match event.kind {
+                    midly::TrackEventKind::Meta(midly::MetaMessage::EndOfTrack) => break,
+                    midly::TrackEventKind::Meta(
+                        midly::MetaMessage::Tempo(_) | midly::MetaMessage::TimeSignature(..),
+                    )
+                    | midly::TrackEventKind::Midi {
+                        message:
+                            midly::MidiMessage::ProgramChange { .. }
+                            | midly::MidiMessage::Controller { .. }
+                            | midly::MidiMessage::PitchBend { .. },
+                        ..
+                    } if ticks < start_tick => cut.push(midly::TrackEvent {
+                        delta: 0.into(),
+                        kind: event.kind,
+                    }),
+                    _ if ticks < start_tick => {}
+                    kind => {
+                        if let midly::TrackEventKind::Midi { channel, message } = kind {
+                            match message {
+                                midly::MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                                    sounding.push((channel, key));
+                                }
+                                midly::MidiMessage::NoteOn { key, .. }
+                                | midly::MidiMessage::NoteOff { key, .. } => {
+                                    sounding.retain(|note| *note != (channel, key));
+                                }
+                                _ => {}
+                            }
+                        }
+                        cut.push(midly::TrackEvent {
+                            delta: ((ticks - last_kept) as u32).into(),
+                            kind,
+                        });
+                        last_kept = ticks;
+                    }
+                }
+            }
+
+            let end_of_track = end_tick
+                .map_or(track_end, |end| end.min(track_end))
+                .max(last_kept);
+
+            // Notes cut in the middle are released where the cut ends
+            let mut delta = (end_of_track - last_kept) as u32;
+            for (channel, key) in sounding {
+                cut.push(midly::TrackEvent {
+                    delta: delta.into(),
+                    kind: midly::TrackEventKind::Midi {
+                        channel,
+                        message: midly::MidiMessage::NoteOff { key, vel: 0.into() },
+                    },
+                });
+                delta = 0;
+            }
+            cut.push(midly::TrackEvent {
+                delta: delta.into(),
+                kind: midly::TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+            });
+            *track = cut;
+        }
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)
+            .map_err(|err| format!("failed to write cut midi: {err}"))?;
+        Ok(MidiSource {
+            bytes: bytes.into(),
+            file_name: self.file_name.clone(),
+            associated_port: self.associated_port,
+            sections: Vec::new(),
+            start_tick: None,
+            end_tick: None,
+            velocity: self.velocity,
+            metadata: None,
+        }
+        .with_metadata())
+    }
+
+    /// Length of all tracks played together in beats (quarter notes), see [merge_tracks]
+    ///
+    /// Returns [None] if MIDI cannot be parsed or uses timecode timing.
+    pub fn length_in_beats(&self) -> Option<f64> {
+        let metadata = self.metadata()?;
+        let ticks_per_quater_note = metadata.ticks_per_quater_note?;
+        Some(metadata.length_ticks as f64 / ticks_per_quater_note as f64)
+    }
+
+    /// Time signatures of the MIDI file
+    ///
+    /// Collected from all tracks, since time signatures are usually stored in the first track of
+    /// multitrack files. Defaults to 4/4 when file has none or cannot be parsed.
+    pub fn meter(&self) -> Meter {
+        let Some(metadata) = self.metadata() else {
+            return Meter::default();
+        };
+        let mut changes = metadata.meter.clone();
+        if changes.first().map_or(true, |(beat, _)| *beat > 0.0) {
+            changes.insert(0, (0.0, COMMON_TIME_BEATS_PER_BAR));
+        }
+        Meter { changes }
+    }
+}
+
+/// Number of beats (quarter notes) in a bar of 4/4 time signature
+const COMMON_TIME_BEATS_PER_BAR: f64 = 4.0;
+
+/// Time signatures of the piece, used to present position in bars
+#[derive(Clone, Debug)]
+pub struct Meter {
+    /// Beats at which time signature changes with beats per bar from this point
+    changes: Vec<(f64, f64)>,
+}
+
+impl Default for Meter {
+    /// Common time (4/4) for the whole piece
+    fn default() -> Self {
+        Self {
+            changes: vec![(0.0, COMMON_TIME_BEATS_PER_BAR)],
+        }
+    }
+}
+
+impl Meter {
+    /// Number of bars (including fractional part) that passed until given beat
+    pub fn bars(&self, beat: f64) -> f64 {
+        let mut bars = 0.0;
+        for (nth, (start, beats_per_bar)) in self.changes.iter().enumerate() {
+            if beat <= *start {
+                break;
+            }
+            let end = self
+                .changes
+                .get(nth + 1)
+                .map_or(beat, |(next, _)| next.min(beat));
+            bars += (end - start) / beats_per_bar;
+        }
+        bars
+    }
+
+    /// Bar (counted from 1) in which given beat lies
+    pub fn bar_at(&self, beat: f64) -> usize {
+        self.bars(beat).floor() as usize + 1
+    }
+}
+
+/// Cutting MIDI sources and counting bars
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{MetaMessage, MidiMessage, TrackEventKind};
+
+    /// Ticks per quarter note of MIDI files built by [source]
+    const TICKS_PER_QUATER_NOTE: u16 = 480;
+
+    /// Single track MIDI source with given events, as pairs of delta and event
+    fn source(events: &[(u32, TrackEventKind<'static>)]) -> MidiSource {
+        let track = events
+            .iter()
+            .map(|(delta, kind)| midly::TrackEvent {
+                delta: (*delta).into(),
+                kind: *kind,
+            })
+            .collect();
+        let smf = midly::Smf {
+            header: midly::Header::new(
+                midly::Format::SingleTrack,
+                midly::Timing::Metrical(TICKS_PER_QUATER_NOTE.into()),
+            ),
+            tracks: vec![track],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        MidiSource::new(bytes, "test.mid".to_string(), 0)
+    }
+
+    /// Events of the only track of the source, as pairs of delta and event
+    fn events(source: &MidiSource) -> Vec<(u32, TrackEventKind<'_>)> {
+        let midi = source.midi().unwrap();
+        midi.tracks[0]
+            .iter()
+            .map(|(_, event)| (event.delta.as_int(), event.kind))
+            .collect()
+    }
+
+    /// Note on event on the first channel
+    fn note_on(key: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        }
+    }
+
+    /// Note off event on the first channel
+    fn note_off(key: u8) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            },
+        }
+    }
+
+    /// Program change on the first channel
+    fn program_change() -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::ProgramChange { program: 5.into() },
+        }
+    }
+
+    /// Tempo change to 120 BPM
+    fn tempo() -> TrackEventKind<'static> {
+        TrackEventKind::Meta(MetaMessage::Tempo(500_000.into()))
+    }
+
+    /// End of the track
+    const END_OF_TRACK: TrackEventKind<'static> = TrackEventKind::Meta(MetaMessage::EndOfTrack);
+
+    /// Two notes, each held for a beat, with a beat of rest between them
+    fn two_notes() -> MidiSource {
+        source(&[
+            (0, tempo()),
+            (0, program_change()),
+            (0, note_on(60)),
+            (480, note_off(60)),
+            (480, note_on(62)),
+            (480, note_off(62)),
+            (0, END_OF_TRACK),
+        ])
+    }
+
+    /// State from before the start is kept, notes are not, and sounding notes end with the cut
+    #[test]
+    fn cut_ticks_fast_forwards_state_and_releases_notes() {
+        let cut = two_notes().cut_ticks(960, Some(1200)).unwrap();
+        assert_eq!(
+            events(&cut),
+            vec![
+                (0, tempo()),
+                (0, program_change()),
+                (0, note_on(62)),
+                (240, note_off(62)),
+                (0, END_OF_TRACK),
+            ]
+        );
+    }
+
+    /// Without the end everything after the start is kept with it's timing
+    #[test]
+    fn cut_ticks_without_end_keeps_the_rest() {
+        let cut = two_notes().cut_ticks(720, None).unwrap();
+        assert_eq!(
+            events(&cut),
+            vec![
+                (0, tempo()),
+                (0, program_change()),
+                (240, note_on(62)),
+                (480, note_off(62)),
+                (0, END_OF_TRACK),
+            ]
+        );
+    }
+
+    /// Cut measured in beats converts them with the resolution of the file
+    #[test]
+    fn cut_in_beats() {
+        let source = two_notes();
+        assert_eq!(
+            events(&source.cut(2.0, Some(2.5)).unwrap()),
+            events(&source.cut_ticks(960, Some(1200)).unwrap())
+        );
+    }
+
+    /// Bars are counted in common time without time signatures
+    #[test]
+    fn meter_defaults_to_common_time() {
+        let meter = Meter::default();
+        assert_eq!(meter.bars(0.0), 0.0);
+        assert_eq!(meter.bars(6.0), 1.5);
+        assert_eq!(meter.bar_at(0.0), 1);
+        assert_eq!(meter.bar_at(3.9), 1);
+        assert_eq!(meter.bar_at(4.0), 2);
+    }
+
+    /// Bars follow changes of time signature
+    #[test]
+    fn meter_follows_time_signature_changes() {
+        // Two bars of 4/4 followed by 3/4
+        let meter = Meter {
+            changes: vec![(0.0, 4.0), (8.0, 3.0)],
+        };
+        assert_eq!(meter.bars(8.0), 2.0);
+        assert_eq!(meter.bars(14.0), 4.0);
+        assert_eq!(meter.bar_at(10.9), 3);
+        assert_eq!(meter.bar_at(11.0), 4);
+    }
 }
@@ -0,0 +1,192 @@
+//! Per-port filtering of MIDI channels
+//!
+//! Single multi-channel MIDI file can feed several single-purpose devices, each connected to a
+//! different port, when each port forwards only some of the channels (for example only channel 10
+//! to the drum machine). Filters are applied by [audio_engine][crate::audio_engine] when messages
+//! are sent, so blocks themselves stay unchanged.
+//!
+//! Filters are indexed by port name instead of port number, since numbers change when devices are
+//! connected or disconnected.
+
+use crate::{cache_path, AppState};
+use axum::{extract::State, http::StatusCode, Form};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tracing::{info, warn};
+
+/// Filename under which Harmonia stores channel filters of ports
+pub const PORT_CHANNELS_PATH: &str = "harmonia_port_channels.bson";
+
+/// Name of the virtual port created by Harmonia, used as a key for its filter
+pub const VIRTUAL_PORT_NAME: &str = "Harmonia";
+
+/// Set of MIDI channels forwarded to the port
+///
+/// Bit `n` is set when channel `n` (counted from 0) is forwarded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelFilter(u16);
+
+impl Default for ChannelFilter {
+    fn default() -> Self {
+        Self(u16::MAX)
+    }
+}
+
+impl ChannelFilter {
+    /// Whether all channels are forwarded
+    pub fn is_all(&self) -> bool {
+        self.0 == u16::MAX
+    }
+
+    /// Whether given channel (counted from 0) is forwarded
+    pub fn allows(&self, channel: u8) -> bool {
+        channel < 16 && self.0 & (1 << channel) != 0
+    }
+
+    /// Whether given serialized MIDI message should be forwarded
+    ///
+    /// Only channel messages are filtered, system messages are always forwarded.
+    pub fn allows_message(&self, bytes: &[u8]) -> bool {
+        match bytes.first() {
+            Some(status @ 0x80..=0xef) => self.allows(status & 0x0f),
+            _ => true,
+        }
+    }
+}
+
+impl std::str::FromStr for ChannelFilter {
+    type Err = String;
+
+    /// Parse comma separated list of channels and channel ranges, like `1-9, 11`
+    ///
+    /// Channels are counted from 1. Empty string or `all` means all channels.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case("all") {
+            return Ok(Self::default());
+        }
+
+        let channel = |s: &str| match s.trim().parse::<u8>() {
+            Ok(channel @ 1..=16) => Ok(channel - 1),
+            _ => Err(format!(
+                "invalid channel {:?}, expected number from 1 to 16",
+                s.trim()
+            )),
+        };
+
+        let mut mask = 0_u16;
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (channel(first)?, channel(last)?),
+                None => (channel(part)?, channel(part)?),
+            };
+            if first > last {
+                return Err(format!("invalid channel range {:?}", part.trim()));
+            }
+            for channel in first..=last {
+                mask |= 1 << channel;
+            }
+        }
+        Ok(Self(mask))
+    }
+}
+
+impl std::fmt::Display for ChannelFilter {
+    /// Format as comma separated list of channel ranges, counted from 1
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_all() {
+            return write!(f, "all");
+        }
+
+        let mut first = true;
+        let mut channel = 0;
+        while channel < 16 {
+            if !self.allows(channel) {
+                channel += 1;
+                continue;
+            }
+            let start = channel;
+            while channel + 1 < 16 && self.allows(channel + 1) {
+                channel += 1;
+            }
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            if start == channel {
+                write!(f, "{}", start + 1)?;
+            } else {
+                write!(f, "{}-{}", start + 1, channel + 1)?;
+            }
+            channel += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Load channel filters stored in [PORT_CHANNELS_PATH], missing or invalid file means no filters
+pub fn load() -> HashMap<String, ChannelFilter> {
+    std::fs::read(cache_path().join(PORT_CHANNELS_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Channel filter of the port with given name
+pub fn filter(app_state: &AppState, port_name: &str) -> ChannelFilter {
+    app_state
+        .port_channels
+        .read()
+        .unwrap()
+        .get(port_name)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Payload to set channels forwarded to the port
+#[derive(Deserialize)]
+pub struct SetChannels {
+    /// Name of the port, as reported by the system
+    port: String,
+
+    /// Channels as accepted by [ChannelFilter::from_str]
+    channels: String,
+}
+
+/// Set which channels are forwarded to port with given name
+pub async fn set_channels(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetChannels {
+        port: port_name,
+        channels,
+    }): Form<SetChannels>,
+) -> Result<String, (StatusCode, String)> {
+    let filter: ChannelFilter = channels
+        .parse()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    info!("forwarding channels {filter} to port {port_name:?}");
+    let port_channels = {
+        let mut port_channels = app_state.port_channels.write().unwrap();
+        if filter.is_all() {
+            port_channels.remove(&port_name);
+        } else {
+            port_channels.insert(port_name, filter);
+        }
+        port_channels.clone()
+    };
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(PORT_CHANNELS_PATH);
+        match bson::to_vec(&port_channels) {
+            Ok(bytes) => {
+                if let Err(err) = std::fs::write(&path, bytes) {
+                    warn!("failed to write port channels to {path:?}: {err}");
+                }
+            }
+            Err(err) => warn!("failed to serialize port channels: {err}"),
+        }
+    }
+
+    Ok(filter.to_string())
+}
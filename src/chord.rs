@@ -0,0 +1,326 @@
+//! Chords described with short text specification
+//!
+//! Specification starts with chord symbol followed by optional settings, for example
+//! `Cmaj7 hold 16 beats on ch1`. Supported settings are:
+//!
+//! - `hold <N> beats` - how long chord is held, 4 beats by default
+//! - `on ch<N>` - MIDI channel (counted from 1) on which chord is played, first by default
+//! - `octave <N>` - octave of the root note, 4 by default (so `C` is middle C)
+//! - `vel <N>` - velocity of the played notes, 100 by default
+//!
+//! Chord is rendered into Standard MIDI File, so it can be played like any other MIDI block.
+
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::str::FromStr;
+
+/// Resolution of the rendered MIDI file
+const TICKS_PER_BEAT: u16 = 480;
+
+/// Intervals (in semitones from the root) of supported chord qualities
+const QUALITIES: &[(&str, &[u8])] = &[
+    ("", &[0, 4, 7]),
+    ("maj", &[0, 4, 7]),
+    ("m", &[0, 3, 7]),
+    ("min", &[0, 3, 7]),
+    ("dim", &[0, 3, 6]),
+    ("aug", &[0, 4, 8]),
+    ("+", &[0, 4, 8]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("5", &[0, 7]),
+    ("6", &[0, 4, 7, 9]),
+    ("m6", &[0, 3, 7, 9]),
+    ("7", &[0, 4, 7, 10]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("M7", &[0, 4, 7, 11]),
+    ("m7", &[0, 3, 7, 10]),
+    ("min7", &[0, 3, 7, 10]),
+    ("mmaj7", &[0, 3, 7, 11]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("add9", &[0, 4, 7, 14]),
+    ("9", &[0, 4, 7, 10, 14]),
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("m9", &[0, 3, 7, 10, 14]),
+];
+
+/// Chord parsed from the text specification
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+    /// MIDI keys of the notes in the chord
+    pub notes: Vec<u8>,
+
+    /// How long chord is held in beats
+    pub beats: f64,
+
+    /// MIDI channel (counted from 0) on which chord is played
+    pub channel: u8,
+
+    /// Velocity of the played notes
+    pub velocity: u8,
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut tokens = spec.split_whitespace();
+        let symbol = tokens.next().ok_or("missing chord symbol")?;
+
+        let mut beats = 4.0;
+        let mut channel = 0;
+        let mut octave = 4;
+        let mut velocity = 100;
+
+        while let Some(token) = tokens.next() {
+            let mut argument = |name: &str| {
+                tokens
+                    .next()
+                    .ok_or_else(|| format!("missing value after {name:?}"))
+            };
+
+            match token.to_lowercase().as_str() {
+                "hold" => {
+                    let value = argument("hold")?;
+                    beats = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|beats| beats.is_finite() && *beats > 0.0)
+                        .ok_or_else(|| format!("invalid number of beats {value:?}"))?;
+                }
+                "beat" | "beats" => {}
+                "on" => {
+                    let value = argument("on")?.to_lowercase();
+                    let number = value.strip_prefix("ch").unwrap_or(&value);
+                    channel = number
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|channel| (1..=16).contains(channel))
+                        .ok_or_else(|| format!("invalid channel {value:?}"))?
+                        - 1;
+                }
+                "octave" => {
+                    let value = argument("octave")?;
+                    octave = value
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|octave| (-1..=9).contains(octave))
+                        .ok_or_else(|| format!("invalid octave {value:?}"))?;
+                }
+                "vel" => {
+                    let value = argument("vel")?;
+                    velocity = value
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|velocity| (1..=127).contains(velocity))
+                        .ok_or_else(|| format!("invalid velocity {value:?}"))?;
+                }
+                _ => return Err(format!("unknown setting {token:?}")),
+            }
+        }
+
+        let mut chars = symbol.chars();
+        let pitch_class: i32 = match chars.next() {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => {
+                return Err(format!(
+                    "chord {symbol:?} should start with note from A to G"
+                ))
+            }
+        };
+
+        let quality = chars.as_str();
+        let (pitch_class, quality) = if let Some(quality) = quality.strip_prefix('#') {
+            (pitch_class + 1, quality)
+        } else if let Some(quality) = quality.strip_prefix('b') {
+            (pitch_class - 1, quality)
+        } else {
+            (pitch_class, quality)
+        };
+
+        let Some((_, intervals)) = QUALITIES.iter().find(|(name, _)| *name == quality) else {
+            return Err(format!("unknown chord quality {quality:?}"));
+        };
+
+        let root = (octave + 1) * 12 + pitch_class;
+        let notes = intervals
+            .iter()
+            .map(|interval| {
+                u8::try_from(root + *interval as i32)
+                    .ok()
+                    .filter(|key| *key <= 127)
+                    .ok_or_else(|| format!("chord {symbol:?} doesn't fit in MIDI key range"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            notes,
+            beats,
+            channel,
+            velocity,
+        })
+    }
+}
+
+impl Chord {
+    /// Render chord as Standard MIDI File holding all notes for [Chord::beats]
+    pub fn to_midi(&self) -> Vec<u8> {
+        let channel = self.channel.into();
+        let length = (self.beats * TICKS_PER_BEAT as f64).round() as u32;
+
+        let note_ons = self.notes.iter().map(|key| TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOn {
+                    key: (*key).into(),
+                    vel: self.velocity.into(),
+                },
+            },
+        });
+
+        let note_offs = self.notes.iter().enumerate().map(|(i, key)| TrackEvent {
+            delta: if i == 0 { length.into() } else { 0.into() },
+            kind: TrackEventKind::Midi {
+                channel,
+                message: MidiMessage::NoteOff {
+                    key: (*key).into(),
+                    vel: 0.into(),
+                },
+            },
+        });
+
+        let track = note_ons
+            .chain(note_offs)
+            .chain(std::iter::once(TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            }))
+            .collect();
+
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(TICKS_PER_BEAT.into())),
+            tracks: vec![track],
+        };
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)
+            .expect("writing to memory should never fail");
+        bytes
+    }
+}
+
+/// Parsing and rendering of chord specifications
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chord symbol alone uses default settings
+    #[test]
+    fn parses_symbol_with_defaults() {
+        assert_eq!(
+            "Cmaj7".parse::<Chord>(),
+            Ok(Chord {
+                notes: vec![60, 64, 67, 71],
+                beats: 4.0,
+                channel: 0,
+                velocity: 100,
+            })
+        );
+    }
+
+    /// All settings can be given in any order
+    #[test]
+    fn parses_settings() {
+        assert_eq!(
+            "Am vel 90 hold 8 beats octave 3 on ch2".parse::<Chord>(),
+            Ok(Chord {
+                notes: vec![57, 60, 64],
+                beats: 8.0,
+                channel: 1,
+                velocity: 90,
+            })
+        );
+    }
+
+    /// Sharps and flats move the root by a semitone
+    #[test]
+    fn parses_accidentals() {
+        assert_eq!("F#".parse::<Chord>().unwrap().notes, vec![66, 70, 73]);
+        assert_eq!("Bbm7".parse::<Chord>().unwrap().notes, vec![70, 73, 77, 80]);
+    }
+
+    /// Invalid specifications are rejected
+    #[test]
+    fn rejects_invalid_specifications() {
+        for spec in [
+            "",
+            "H",
+            "Cxyz",
+            "C hold",
+            "C hold 0 beats",
+            "C on ch17",
+            "C vel 0",
+            "C octave 10",
+            "C loudly",
+            "G9 octave 9",
+        ] {
+            assert!(
+                spec.parse::<Chord>().is_err(),
+                "{spec:?} should be rejected"
+            );
+        }
+    }
+
+    /// Rendered file holds all notes together for the length of the chord
+    #[test]
+    fn renders_held_notes() {
+        let chord: Chord = "C hold 2 beats on ch3 vel 80".parse().unwrap();
+        let bytes = chord.to_midi();
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.timing, Timing::Metrical(TICKS_PER_BEAT.into()));
+
+        let events: Vec<_> = smf.tracks[0]
+            .iter()
+            .map(|event| (event.delta.as_int(), event.kind))
+            .collect();
+        let note = |delta: u32, key: u8, on: bool| {
+            let message = if on {
+                MidiMessage::NoteOn {
+                    key: key.into(),
+                    vel: 80.into(),
+                }
+            } else {
+                MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                }
+            };
+            (
+                delta,
+                TrackEventKind::Midi {
+                    channel: 2.into(),
+                    message,
+                },
+            )
+        };
+        assert_eq!(
+            events,
+            vec![
+                note(0, 60, true),
+                note(0, 64, true),
+                note(0, 67, true),
+                note(960, 60, false),
+                note(0, 64, false),
+                note(0, 67, false),
+                (0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! Source of time for the [audio_engine][crate::audio_engine]
+//!
+//! Engine asks [Clock] for the current host time instead of calling [AblLink::clock_micros]
+//! directly, and waits through it instead of sleeping itself, so scheduling can be driven by
+//! `SimulatedClock` that advances only when told to or when engine waits. This makes timing of
+//! the engine deterministic in tests.
+
+use rusty_link::AblLink;
+#[cfg(test)]
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::{
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+/// Interrupt flag of the played block and condition variable notified when it's set
+pub type Interrupts = (Mutex<bool>, Condvar);
+
+/// Source of host time in microseconds, in the same time base as Link
+pub trait Clock: Send + Sync {
+    /// Current host time in microseconds
+    fn micros(&self) -> i64;
+
+    /// Sleep for given duration or until interrupted, returns whether playback was interrupted
+    fn sleep(&self, interrupts: &Interrupts, duration: Duration) -> bool {
+        let (interrupt, interruptable_sleep) = interrupts;
+        let guard = interrupt.lock().unwrap();
+        let (interrupted, _) = interruptable_sleep.wait_timeout(guard, duration).unwrap();
+        *interrupted
+    }
+
+    /// Busy-wait for given duration, more precise than [Clock::sleep]
+    ///
+    /// Returns whether playback was interrupted. Other threads are let to run between checks of
+    /// time, see [SPIN_THRESHOLD][crate::audio_engine::SPIN_THRESHOLD].
+    fn spin(&self, interrupts: &Interrupts, duration: Duration) -> bool {
+        let deadline = self.micros() + duration.as_micros() as i64;
+        while self.micros() < deadline {
+            if interrupts.0.try_lock().map_or(false, |x| *x) {
+                return true;
+            }
+            std::thread::yield_now();
+        }
+        false
+    }
+}
+
+impl Clock for AblLink {
+    fn micros(&self) -> i64 {
+        self.clock_micros()
+    }
+}
+
+/// Clock that moves only when advanced explicitly or when engine waits on it
+///
+/// Waiting moves the clock by the awaited duration right away, unless playback was interrupted.
+/// Used only by tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct SimulatedClock {
+    /// Current time in microseconds
+    micros: AtomicI64,
+}
+
+#[cfg(test)]
+impl SimulatedClock {
+    /// Create clock starting at given time in microseconds
+    pub fn new(micros: i64) -> Self {
+        Self {
+            micros: AtomicI64::new(micros),
+        }
+    }
+
+    /// Move clock forward by given number of microseconds
+    pub fn advance(&self, micros: i64) {
+        self.micros.fetch_add(micros, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for SimulatedClock {
+    fn micros(&self) -> i64 {
+        self.micros.load(Ordering::SeqCst)
+    }
+
+    fn sleep(&self, interrupts: &Interrupts, duration: Duration) -> bool {
+        if *interrupts.0.lock().unwrap() {
+            return true;
+        }
+        self.advance(duration.as_micros() as i64);
+        false
+    }
+
+    fn spin(&self, interrupts: &Interrupts, duration: Duration) -> bool {
+        self.sleep(interrupts, duration)
+    }
+}
@@ -0,0 +1,121 @@
+//! Measuring clock drift and jitter between peers
+//!
+//! Every [GroupFrame][crate::GroupFrame] is followed by the ghost time (shared [Link][rusty_link]
+//! timeline) at which it was sent. Comparing it with local ghost time at receiving gives offset
+//! between both timelines, including network latency. For well behaving peers offset is small and
+//! stable; laptop with a bad clock shows growing offset (drift) or scattered one (jitter) before
+//! it's audible.
+//!
+//! Frames are only sent while playing in a group, so peers are measured only then.
+
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many recent samples are used for statistics of each peer (10 seconds of frames)
+const WINDOW: usize = 200;
+
+/// Peers without samples for this long are forgotten
+pub const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// Clock statistics of the peer
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerClock {
+    /// Address from which frames are received
+    pub address: IpAddr,
+
+    /// Mean difference between local and peer ghost time in microseconds, including latency
+    pub offset: f64,
+
+    /// Standard deviation of the offset in microseconds
+    pub jitter: f64,
+
+    /// How fast offset changes, in microseconds per second
+    pub drift: f64,
+
+    /// Number of samples used for statistics
+    pub samples: usize,
+}
+
+/// Recent offsets of the peer
+struct Samples {
+    /// Local ghost time at receiving and measured offset, both in microseconds
+    offsets: VecDeque<(i64, i64)>,
+
+    /// When the last sample was recorded
+    last_seen: Instant,
+}
+
+/// Offsets measured for each peer
+#[derive(Default)]
+pub(crate) struct Measurements {
+    /// Samples of each peer, by address
+    peers: Mutex<HashMap<IpAddr, Samples>>,
+}
+
+impl Measurements {
+    /// Record frame sent by peer at `sent_at` and received at `received_at`, both in ghost time
+    pub(crate) fn record(&self, address: IpAddr, sent_at: i64, received_at: i64) {
+        let mut peers = self.peers.lock().unwrap();
+        let samples = peers.entry(address).or_insert_with(|| Samples {
+            offsets: VecDeque::with_capacity(WINDOW),
+            last_seen: Instant::now(),
+        });
+        if samples.offsets.len() >= WINDOW {
+            samples.offsets.pop_front();
+        }
+        samples
+            .offsets
+            .push_back((received_at, received_at - sent_at));
+        samples.last_seen = Instant::now();
+    }
+
+    /// Statistics of recently measured peers, ordered by address
+    pub(crate) fn stats(&self) -> Vec<PeerClock> {
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, samples| samples.last_seen.elapsed() < STALE_AFTER);
+
+        let mut stats: Vec<_> = peers
+            .iter()
+            .map(|(address, samples)| statistics(*address, &samples.offsets))
+            .collect();
+        stats.sort_by_key(|peer| peer.address);
+        stats
+    }
+}
+
+/// Compute mean, standard deviation and slope (least squares) of the offsets
+fn statistics(address: IpAddr, offsets: &VecDeque<(i64, i64)>) -> PeerClock {
+    let count = offsets.len() as f64;
+    let (first_time, _) = offsets.front().copied().unwrap_or_default();
+    // Times relative to the first sample, in seconds, to keep values small
+    let points = offsets
+        .iter()
+        .map(|(time, offset)| ((time - first_time) as f64 / 1e6, *offset as f64));
+
+    let mean_time = points.clone().map(|(time, _)| time).sum::<f64>() / count;
+    let offset = points.clone().map(|(_, offset)| offset).sum::<f64>() / count;
+
+    let (mut variance, mut covariance, mut time_variance) = (0.0, 0.0, 0.0);
+    for (time, value) in points {
+        variance += (value - offset).powi(2);
+        covariance += (time - mean_time) * (value - offset);
+        time_variance += (time - mean_time).powi(2);
+    }
+
+    PeerClock {
+        address,
+        offset,
+        jitter: (variance / count).sqrt(),
+        drift: if time_variance > 0.0 {
+            covariance / time_variance
+        } else {
+            0.0
+        },
+        samples: offsets.len(),
+    }
+}
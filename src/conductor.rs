@@ -0,0 +1,225 @@
+//! Conductor mode, cueing the whole orchestra from one instance
+//!
+//! Instead of each player pressing their own key, instance started with `--conductor` can start
+//! and stop a group on chosen peers at once. Commands are sent over HTTP to
+//! [play_group] and [interrupt_group] routes of each peer, which start or stop all of their blocks
+//! in the given group. Blocks in the same group are synchronized by [linky_groups], so they start
+//! together even though commands arrive at slightly different times.
+//!
+//! When conductor has `--token`, it's sent with the commands, so orchestra sharing one token can
+//! protect itself from others in the network, see [access][crate::access].
+
+use crate::{audio_engine, handlers, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    Form,
+};
+use maud::{html, Markup};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// How long to wait for the peer to accept the command
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Form field selecting this instance as one of the targets
+const LOCAL_TARGET: &str = "local";
+
+/// Identifiers of blocks in given group, in the order shown in UI
+fn group_blocks(app_state: &AppState, group: &str) -> Vec<String> {
+    let blocks = app_state.blocks.read().unwrap();
+    handlers::ordered_blocks(&blocks)
+        .into_iter()
+        .filter(|(_, block)| block.group == group)
+        .map(|(uuid, _)| uuid.clone())
+        .collect()
+}
+
+/// Starts all blocks in the given group, responds with their identifiers
+pub async fn play_group(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Path(group): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let uuids = group_blocks(&app_state, &group);
+    if uuids.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("no blocks in group {group:?}"),
+        ));
+    }
+
+    handlers::ensure_local_programs(&app_state, addr, uuids.iter().map(String::as_str))?;
+
+    info!("starting group {group:?}");
+    for uuid in &uuids {
+        audio_engine::play(app_state.clone(), uuid)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    }
+    Ok(uuids.join("\n"))
+}
+
+/// Stops played blocks in the given group, responds with their identifiers
+pub async fn interrupt_group(
+    State(app_state): State<Arc<AppState>>,
+    Path(group): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let uuids: Vec<String> = {
+        let playing = app_state.currently_playing.read().unwrap();
+        group_blocks(&app_state, &group)
+            .into_iter()
+            .filter(|uuid| playing.contains_key(uuid))
+            .collect()
+    };
+
+    info!("stopping group {group:?}");
+    for uuid in &uuids {
+        audio_engine::interrupt_block(app_state.clone(), uuid)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    }
+    Ok(uuids.join("\n"))
+}
+
+/// Send command to the peer with given base URL
+async fn send(
+    client: &reqwest::Client,
+    token: Option<&str>,
+    base_url: &str,
+    group: &str,
+    action: &str,
+) -> Result<(), String> {
+    let mut request = client.post(format!("{base_url}/api/groups/{group}/{action}"));
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.map_err(|err| err.to_string())?;
+    if response.status().is_success() {
+        return Ok(());
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("{status}: {body}"))
+}
+
+/// Sends play or interrupt command for a group to the chosen targets
+///
+/// Accepts form with `group`, `action` (`play` or `interrupt`) and `target` fields, one for each
+/// chosen peer (its URL) or [LOCAL_TARGET] for this instance. Responds with outcome for each
+/// target. Blocks running programs are started locally only for requests from this computer,
+/// like with [play_group].
+pub async fn command(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Markup, (StatusCode, String)> {
+    let field = |wanted: &str| {
+        fields
+            .iter()
+            .find(|(name, _)| name == wanted)
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default()
+    };
+    let group = handlers::limit_group_length(&field("group"));
+    let action = field("action");
+    if !matches!(action.as_str(), "play" | "interrupt") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("unknown action {action:?}, expected play or interrupt"),
+        ));
+    }
+    let targets: Vec<&str> = fields
+        .iter()
+        .filter(|(name, _)| name == "target")
+        .map(|(_, value)| value.as_str())
+        .collect();
+
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+
+    let outcomes = futures::future::join_all(targets.iter().map(|target| {
+        let app_state = app_state.clone();
+        let client = &client;
+        let group = group.clone();
+        let action = action.clone();
+        async move {
+            let outcome = if *target == LOCAL_TARGET {
+                let result = if action == "play" {
+                    play_group(ConnectInfo(addr), State(app_state), Path(group)).await
+                } else {
+                    interrupt_group(State(app_state), Path(group)).await
+                };
+                result.map(|_| ()).map_err(|(_, err)| err)
+            } else {
+                let token = app_state.token.as_deref();
+                send(client, token, target, &group, &action).await
+            };
+            (target.to_string(), outcome)
+        }
+    }))
+    .await;
+
+    for (target, outcome) in &outcomes {
+        match outcome {
+            Ok(()) => info!("conductor sent {action} of group {group:?} to {target}"),
+            Err(err) => error!("conductor failed to {action} group {group:?} on {target}: {err}"),
+        }
+    }
+
+    Ok(html! {
+        ul {
+            @for (target, outcome) in &outcomes {
+                li {
+                    (target) ": ";
+                    @match outcome {
+                        Ok(()) => "ok",
+                        Err(err) => span class="upload-error" { (err) },
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Render form choosing group and peers to command
+///
+/// Peers are listed when form is rendered, so choices are not lost when someone joins.
+pub async fn render(State(app_state): State<Arc<AppState>>) -> Markup {
+    let peers = app_state.discovery.peers();
+    let group = app_state.defaults.read().unwrap().group.clone();
+
+    html! {
+        form hx-post="/conductor" hx-target="#conductor-outcome" hx-swap="innerHTML" {
+            input
+                type="text" value=(group)
+                pattern=(format!("(\\w| ){{0,{}}}", linky_groups::MAX_GROUP_ID_LENGTH))
+                maxlength=(linky_groups::MAX_GROUP_ID_LENGTH)
+                name="group"
+                placeholder="Group";
+            ul {
+                li {
+                    label {
+                        input type="checkbox" name="target" value=(LOCAL_TARGET) checked;
+                        " This instance"
+                    }
+                }
+                @for peer in &peers {
+                    li {
+                        label {
+                            input type="checkbox" name="target" value=(peer.url()) checked;
+                            " " (peer.name) " (" (peer.address) ")"
+                        }
+                    }
+                }
+            }
+            button name="action" value="play" { "Play group" }
+            " ";
+            button name="action" value="interrupt" { "Stop group" }
+        }
+        button hx-get="/conductor" hx-target="#conductor" hx-swap="innerHTML" { "Refresh peers" }
+        div id="conductor-outcome" {}
+    }
+}
@@ -0,0 +1,142 @@
+//! Optional configuration file with settings otherwise given on the command line
+//!
+//! Setup used on every rehearsal can be written once into `harmonia.toml`, instead of repeating
+//! the same flags. File is read from `--config` path or, when it's not given, from the first
+//! existing of:
+//!
+//! * `harmonia.toml` in the configuration directory (like `~/.config/harmonia/`)
+//! * `harmonia.toml` in the cache directory (like `~/.cache/harmonia/`)
+//!
+//! Flags given on the command line (or through environment variables) override the file:
+//!
+//! ```toml
+//! ip = "0.0.0.0"
+//! port = 8080
+//! disable-link = false
+//! no-color = false
+//! quantum = 4.0
+//! multicast = "224.76.78.75:20810"
+//! token = "rehearsal"
+//! state-dir = "~/harmonia"
+//! update-check = true
+//! ```
+
+use crate::{access, Cli};
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+use std::{net::IpAddr, path::PathBuf};
+
+/// Name of the configuration file in configuration and cache directories
+pub const CONFIG_FILE_NAME: &str = "harmonia.toml";
+
+/// Settings read from the configuration file, each overridden by the command line
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// IP for UI, see `--ip`
+    ip: Option<IpAddr>,
+
+    /// Port for UI, see `--port`
+    port: Option<u16>,
+
+    /// Don't start link connection, see `--disable-link`
+    disable_link: Option<bool>,
+
+    /// Disable colors, see `--no-color`
+    no_color: Option<bool>,
+
+    /// Default quantum of the session, see `--quantum`
+    quantum: Option<f64>,
+
+    /// Multicast address used to synchronize groups, see `--multicast`
+    multicast: Option<String>,
+
+    /// Token required for changes requested from other computers, see `--token`
+    token: Option<String>,
+
+    /// Directory in which blocks, logs and other state is stored, see `--state-dir`
+    state_dir: Option<PathBuf>,
+
+    /// Check GitHub for newer releases, see `--update-check`
+    update_check: Option<bool>,
+}
+
+/// Paths at which configuration file is looked for when `--config` isn't given
+fn default_paths() -> Vec<PathBuf> {
+    [dirs::config_dir(), dirs::cache_dir()]
+        .into_iter()
+        .flatten()
+        .map(|directory| directory.join("harmonia").join(CONFIG_FILE_NAME))
+        .collect()
+}
+
+/// Read configuration from given path, or from the first existing default path
+///
+/// Returns path from which configuration was read, [None] when there is no configuration file.
+pub fn load(path: Option<&PathBuf>) -> Result<Option<(PathBuf, Config)>, String> {
+    let path = match path {
+        Some(path) => path.clone(),
+        None => match default_paths().into_iter().find(|path| path.exists()) {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+
+    let text = std::fs::read_to_string(&path)
+        .map_err(|err| format!("failed to read configuration {path:?}: {err}"))?;
+    let config =
+        toml::from_str(&text).map_err(|err| format!("invalid configuration {path:?}: {err}"))?;
+    Ok(Some((path, config)))
+}
+
+/// Expand leading `~` to home directory, so paths in the file don't depend on the user
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(relative), Some(home)) => home.join(relative),
+        _ => path,
+    }
+}
+
+impl Config {
+    /// Fill settings of `cli` that weren't given on the command line
+    pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) -> Result<(), String> {
+        let given = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+
+        if let (Some(ip), false) = (self.ip, given("ip")) {
+            cli.ip = ip.to_string();
+        }
+        if let (Some(port), false) = (self.port, given("port")) {
+            cli.port = port;
+        }
+        if let (Some(disable_link), false) = (self.disable_link, given("disable_link")) {
+            cli.disable_link = disable_link;
+        }
+        if let (Some(no_color), false) = (self.no_color, given("disable_colors")) {
+            cli.disable_colors = no_color;
+        }
+        if let (Some(quantum), false) = (self.quantum, given("quantum")) {
+            if !(quantum.is_finite() && quantum > 0.0) {
+                return Err(format!("quantum must be a positive number, not {quantum}"));
+            }
+            cli.quantum = quantum;
+        }
+        if let (Some(multicast), false) = (self.multicast, given("multicast")) {
+            cli.multicast = linky_groups::parse_multicast(&multicast)?;
+        }
+        if let (Some(token), false) = (self.token, given("token")) {
+            cli.token = Some(access::parse_token(&token)?);
+        }
+        if let (Some(state_dir), false) = (self.state_dir, given("state_dir")) {
+            cli.state_dir = Some(expand_home(state_dir));
+        }
+        if let (Some(update_check), false) = (self.update_check, given("update_check")) {
+            cli.update_check = update_check;
+        }
+        Ok(())
+    }
+}
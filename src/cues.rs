@@ -0,0 +1,161 @@
+//! Cue list: blocks started and interrupted at given beats of Link session
+//!
+//! Unlike [sequences][crate::audio_engine::play_sequence], which count beats from their own start,
+//! cues are scheduled on absolute beats of the Link session, so all instances given the same cues
+//! fire them together. Keep in mind that a block started while nothing is playing realigns the
+//! session to it's start, unless it uses [quantized launch][crate::block::Launch::Quantized].
+//!
+//! Cues are kept only in memory and managed with:
+//!
+//! - `GET /api/cues` - pending cues, earliest first
+//! - `POST /api/cues` - schedule JSON list of [Cue]s
+//! - `DELETE /api/cues` - cancel all pending cues
+
+use crate::{audio_engine, handlers, timeline, AppState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    Json,
+};
+use rusty_link::SessionState;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// How often scheduler checks the beat of Link session
+const CUE_RESOLUTION: Duration = Duration::from_millis(5);
+
+/// What happens when cue fires
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Start given block
+    Play {
+        /// Identifier of the block
+        block: String,
+    },
+
+    /// Stop given block, or everything when no block is given
+    Interrupt {
+        /// Identifier of the block
+        #[serde(default)]
+        block: Option<String>,
+    },
+}
+
+/// Action scheduled on the beat of Link session
+///
+/// In JSON action is flattened into the cue, like
+/// `{"beat": 128, "action": "play", "block": "midi-..."}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cue {
+    /// Beat of Link session on which cue fires
+    pub beat: f64,
+
+    /// What happens when cue fires
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Responds with pending cues, earliest first
+pub async fn cues(State(app_state): State<Arc<AppState>>) -> Json<Vec<Cue>> {
+    Json(app_state.cues.lock().unwrap().clone())
+}
+
+/// Schedules given cues next to already pending ones
+pub async fn add_cues(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Json(new_cues): Json<Vec<Cue>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    {
+        let blocks = app_state.blocks.read().unwrap();
+        for cue in &new_cues {
+            if !cue.beat.is_finite() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid cue beat {}", cue.beat),
+                ));
+            }
+            let block = match &cue.action {
+                Action::Play { block } => Some(block),
+                Action::Interrupt { block } => block.as_ref(),
+            };
+            if let Some(block) = block.filter(|block| !blocks.contains_key(*block)) {
+                return Err((StatusCode::NOT_FOUND, format!("block#{block} not found")));
+            }
+        }
+    }
+
+    handlers::ensure_local_programs(
+        &app_state,
+        addr,
+        new_cues.iter().filter_map(|cue| match &cue.action {
+            Action::Play { block } => Some(block.as_str()),
+            Action::Interrupt { .. } => None,
+        }),
+    )?;
+
+    info!("scheduling {} cues", new_cues.len());
+    let mut cues = app_state.cues.lock().unwrap();
+    cues.extend(new_cues);
+    cues.sort_by(|lhs, rhs| lhs.beat.total_cmp(&rhs.beat));
+    Ok(StatusCode::OK)
+}
+
+/// Cancels all pending cues
+pub async fn clear_cues(State(app_state): State<Arc<AppState>>) {
+    info!("cancelling all cues");
+    app_state.cues.lock().unwrap().clear();
+}
+
+/// Fire pending cues when their beat comes, never returns
+///
+/// Cues which beat has already passed fire immediately, in order.
+pub async fn run(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(CUE_RESOLUTION);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        let due: Vec<Cue> = {
+            let mut cues = app_state.cues.lock().unwrap();
+            if cues.is_empty() {
+                continue;
+            }
+
+            let mut session_state = SessionState::new();
+            app_state.link.capture_app_session_state(&mut session_state);
+            let beat = session_state.beat_at_time(app_state.clock.micros(), app_state.quantum);
+            let count = cues.partition_point(|cue| cue.beat <= beat);
+            cues.drain(..count).collect()
+        };
+
+        for cue in due {
+            info!("cue on beat {} fires {:?}", cue.beat, cue.action);
+            let (action, block) = match &cue.action {
+                Action::Play { block } => ("play", Some(block.as_str())),
+                Action::Interrupt { block } => ("interrupt", block.as_deref()),
+            };
+            timeline::record(
+                &app_state,
+                timeline::Kind::CueFired,
+                block,
+                format!("{action} on beat {}", cue.beat),
+            );
+            let result = match cue.action {
+                Action::Play { block } => audio_engine::play(app_state.clone(), &block).await,
+                Action::Interrupt { block: Some(block) } => {
+                    audio_engine::interrupt_block(app_state.clone(), &block).await
+                }
+                Action::Interrupt { block: None } => {
+                    audio_engine::interrupt(app_state.clone()).await
+                }
+            };
+            if let Err(err) = result {
+                error!("cue on beat {} failed: {err}", cue.beat);
+            }
+        }
+    }
+}
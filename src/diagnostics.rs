@@ -0,0 +1,201 @@
+//! Diagnostic bundle attached to bug reports
+//!
+//! [bundle] responds with a zip containing everything needed to investigate a problem without
+//! asking the reporter follow up questions:
+//!
+//! * `version.txt` - [Version] of the build with operating system and architecture
+//! * `logs/` - the most recent daily logs, see [MAX_LOGS]
+//! * [STATE_PATH] - blocks as currently known by Harmonia
+//! * `network.txt` - Link session, network interfaces, discovered peers and their clocks
+//! * [storage::CONTENT_PATH] - MIDI and audio files of the blocks, only with `content=true` since
+//!   they may be large or private
+//!
+//! Bundle contains logs with addresses of other computers, so it's only available from this
+//! computer.
+
+use crate::{logging, storage, AppState, Version, STATE_PATH};
+use anyhow::Context;
+use axum::{
+    body::Full,
+    extract::{ConnectInfo, Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        Response, StatusCode,
+    },
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use std::{fmt::Write as _, io::Write, net::SocketAddr, sync::Arc};
+use tracing::{error, info, warn};
+
+/// How many of the most recent daily logs are included
+const MAX_LOGS: usize = 7;
+
+/// Query of [bundle]
+#[derive(Deserialize)]
+pub struct Bundle {
+    /// Include MIDI and audio files of blocks
+    #[serde(default)]
+    content: bool,
+}
+
+/// Paths of the most recent daily logs, oldest first
+fn recent_logs() -> Vec<std::path::PathBuf> {
+    let mut logs = logging::daily_logs();
+    let skipped = logs.len().saturating_sub(MAX_LOGS);
+    logs.split_off(skipped)
+}
+
+/// Describe build and platform
+fn version() -> String {
+    format!(
+        "Harmonia {}\n{} {}\n",
+        Version::default(),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+/// Describe state of synchronization and network
+fn network(app_state: &AppState) -> String {
+    let mut report = String::new();
+    let link = &app_state.link;
+    let _ = writeln!(
+        report,
+        "Link: {}, {} peers",
+        if link.is_enabled() {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        link.num_peers()
+    );
+
+    let used = app_state
+        .groups
+        .as_ref()
+        .map(|groups| groups.interfaces().to_vec());
+    let disabled = app_state.disabled_interfaces.read().unwrap();
+    let _ = writeln!(report, "\nInterfaces:");
+    for (name, address) in linky_groups::interfaces::list() {
+        let status = if disabled.contains(&name) {
+            "disabled in UI"
+        } else if !app_state.interface_filter.allows(&name, address) {
+            "excluded by command line"
+        } else if used.as_ref().is_some_and(|used| used.contains(&name)) {
+            "used"
+        } else {
+            "unused"
+        };
+        let _ = writeln!(report, "  {name} ({address}) - {status}");
+    }
+    if used.is_none() {
+        let _ = writeln!(report, "  groups synchronization is not running");
+    }
+
+    let _ = writeln!(report, "\nPeers: {:#?}", app_state.discovery.peers());
+    let clocks = app_state
+        .groups
+        .as_ref()
+        .map(linky_groups::Groups::clocks)
+        .unwrap_or_default();
+    let _ = writeln!(report, "\nClocks: {clocks:#?}");
+    report
+}
+
+/// Build the zip archive
+fn archive(app_state: &AppState, content: bool) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut write = |name: &str, bytes: &[u8]| -> anyhow::Result<()> {
+        archive
+            .start_file(name, zip::write::FileOptions::default())
+            .with_context(|| format!("adding {name}"))?;
+        archive
+            .write_all(bytes)
+            .with_context(|| format!("writing {name}"))?;
+        Ok(())
+    };
+
+    write("version.txt", version().as_bytes())?;
+
+    for path in recent_logs() {
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                write(&format!("logs/{name}"), &bytes)?;
+            }
+            Err(err) => warn!("skipping log {path:?} in diagnostic bundle: {err}"),
+        }
+    }
+
+    let blocks = bson::to_vec(&*app_state.blocks.read().unwrap()).context("serializing blocks")?;
+    write(STATE_PATH, &blocks)?;
+
+    write("network.txt", network(app_state).as_bytes())?;
+
+    if content {
+        let contents: Vec<_> = app_state
+            .blocks
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|block| storage::of(&block.content))
+            .cloned()
+            .collect();
+        for stored in contents {
+            match stored.read() {
+                Ok(bytes) => write(
+                    &format!("{}/{}", storage::CONTENT_PATH, stored.hash()),
+                    &bytes,
+                )?,
+                // Missing content is itself worth reporting, it's visible in the logs
+                Err(err) => warn!(
+                    "skipping content {} in diagnostic bundle: {err}",
+                    stored.hash()
+                ),
+            }
+        }
+    }
+
+    Ok(archive.finish().context("finishing archive")?.into_inner())
+}
+
+/// Responds with zip archive describing this instance, see [module documentation][self]
+///
+/// Available only from this computer.
+pub async fn bundle(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Query(Bundle { content }): Query<Bundle>,
+) -> impl IntoResponse {
+    if !addr.ip().is_loopback() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Diagnostic bundle is available only from this computer".to_string(),
+        ));
+    }
+
+    let bytes = match archive(&app_state, content) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            error!("failed to create diagnostic bundle: {err:#}");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")));
+        }
+    };
+
+    info!("created diagnostic bundle");
+    let file_name = format!(
+        "harmonia-diagnostics-{}.zip",
+        chrono::Local::now().format("%Y-%m-%d-%H%M")
+    );
+    let mut response = Response::new(Full::from(bytes));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{file_name}\"")
+            .parse()
+            .unwrap(),
+    );
+    headers.insert(CONTENT_TYPE, "application/zip".parse().unwrap());
+    Ok(response)
+}
@@ -0,0 +1,297 @@
+//! Discovery of other instances in local network
+//!
+//! Each instance periodically announces its name, version and HTTP port over multicast (on
+//! [next port][net::discovery_multicast] after [GroupFrame][crate::GroupFrame]s) and listens
+//! for announcements of others. Instances that were not heard from for [PEER_TIMEOUT] are
+//! considered gone.
+//!
+//! Since version 2 announcement is followed by [Status] of the instance: it's group and whether it
+//! plays, so everyone can see who is on the network and what they are doing. Announcements of
+//! version 1 are still understood, peers sending them have empty status.
+//!
+//! Discovery doesn't need any central server or mDNS support from the network, only the same
+//! multicast that is already required for group synchronization.
+
+use crate::{net, GroupId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Max length of the announced instance name, longer names are cut
+pub const MAX_NAME_LENGTH: usize = 32;
+
+/// Max length of the announced version, longer versions are cut
+pub const MAX_VERSION_LENGTH: usize = 16;
+
+/// How often instance announces itself
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// After what time without announcement peer is forgotten
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parsed Network Packet announcing instance
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct Announcement {
+    /// Magic sequence distinguishing packets
+    magic: [u8; 4],
+
+    /// Version of the packet
+    version: u8,
+
+    /// Random identifier of the instance, stable for the lifetime of the process
+    instance: [u8; 16],
+
+    /// Name of the instance, padded with zeros
+    name: [u8; MAX_NAME_LENGTH],
+
+    /// Version of the software running the instance, padded with zeros
+    software_version: [u8; MAX_VERSION_LENGTH],
+
+    /// Port on which instance serves HTTP
+    http_port: u16,
+}
+
+/// Status of the instance, sent after [Announcement] since version 2
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+struct Status {
+    /// Group in which instance plays, or will play when not playing, padded with zeros
+    group: GroupId,
+
+    /// Whether instance plays any block
+    playing: bool,
+}
+
+/// Copy string into zero padded buffer, cutting it on character boundary if it doesn't fit
+fn pad<const N: usize>(s: &str) -> [u8; N] {
+    let mut cut = s.len().min(N);
+    while !s.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let mut buffer = [0; N];
+    buffer[..cut].copy_from_slice(&s.as_bytes()[..cut]);
+    buffer
+}
+
+/// Read string from zero padded buffer
+fn unpad(buffer: &[u8]) -> String {
+    let end = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
+
+impl Announcement {
+    /// Check if current implementation supports this packet.
+    fn is_supported(&self) -> bool {
+        self.magic == *b"anno" && (1..=2).contains(&self.version)
+    }
+}
+
+/// Description of the instance that is announced to others
+#[derive(Debug, Clone)]
+pub struct Instance {
+    /// Human readable name of the instance, like user's nick
+    pub name: String,
+
+    /// Version of the software running the instance
+    pub version: String,
+
+    /// Port on which instance serves HTTP
+    pub http_port: u16,
+}
+
+/// Other instance found in the local network
+#[derive(Debug, Clone, Serialize)]
+pub struct Peer {
+    /// Human readable name of the instance
+    pub name: String,
+
+    /// Version of the software running the instance
+    pub version: String,
+
+    /// Address from which announcement was received
+    pub address: IpAddr,
+
+    /// Port on which instance serves HTTP
+    pub http_port: u16,
+
+    /// Group in which instance plays, or will play when not playing, empty when unknown
+    pub group: String,
+
+    /// Whether instance plays any block
+    pub playing: bool,
+
+    /// When the last announcement was received
+    #[serde(skip)]
+    pub last_seen: Instant,
+}
+
+impl Peer {
+    /// Address of the HTTP interface of the peer
+    pub fn url(&self) -> String {
+        format!("http://{}:{}", self.address, self.http_port)
+    }
+}
+
+/// State of discovery mechanism
+pub struct Discovery {
+    /// Announcement sent by this instance
+    announcement: Arc<Mutex<Announcement>>,
+
+    /// Status sent by this instance after [Discovery::announcement]
+    status: Arc<Mutex<Status>>,
+
+    /// Instances heard from, indexed by their random identifiers
+    peers: Arc<Mutex<HashMap<[u8; 16], Peer>>>,
+
+    /// Announcing and listening tasks
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+}
+
+impl Discovery {
+    /// Change the name under which this instance is announced
+    pub fn set_name(&self, name: &str) {
+        self.announcement.lock().unwrap().name = pad(name);
+    }
+
+    /// Change the status announced by this instance, group is cut to [crate::MAX_GROUP_ID_LENGTH]
+    pub fn set_status(&self, group: &str, playing: bool) {
+        *self.status.lock().unwrap() = Status {
+            group: pad(group),
+            playing,
+        };
+    }
+
+    /// Currently known peers, sorted by name
+    pub fn peers(&self) -> Vec<Peer> {
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, peer| peer.last_seen.elapsed() < PEER_TIMEOUT);
+        let mut peers: Vec<_> = peers.values().cloned().collect();
+        peers.sort_by(|a, b| a.name.cmp(&b.name).then(a.address.cmp(&b.address)));
+        peers
+    }
+
+    /// Stop announcing and listening
+    pub fn shutdown(self) {
+        for task in self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Start announcing given instance and listening for others
+///
+/// `multicast` is the address used for group synchronization and `filter` chooses interfaces,
+/// see [crate::listen]. When not `enabled` nothing is sent or received and there are never any
+/// peers.
+pub fn announce(
+    enabled: bool,
+    multicast: SocketAddr,
+    filter: &crate::interfaces::InterfaceFilter,
+    instance: Instance,
+) -> Discovery {
+    let sockets = Arc::new(net::Sockets::bind_to(
+        enabled,
+        net::discovery_multicast(multicast),
+        filter,
+    ));
+    let announcement = Arc::new(Mutex::new(Announcement {
+        magic: *b"anno",
+        version: 2,
+        instance: uuid::Uuid::new_v4().into_bytes(),
+        name: pad(&instance.name),
+        software_version: pad(&instance.version),
+        http_port: instance.http_port,
+    }));
+    let status: Arc<Mutex<Status>> = Default::default();
+    let own_id = announcement.lock().unwrap().instance;
+    let peers: Arc<Mutex<HashMap<_, Peer>>> = Default::default();
+    let mut tasks = Vec::new();
+
+    if enabled {
+        let announcement = announcement.clone();
+        let status = status.clone();
+        let sockets = sockets.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let mut packet = bincode::serialize(&*announcement.lock().unwrap()).unwrap();
+                packet.extend(bincode::serialize(&*status.lock().unwrap()).unwrap());
+                sockets.send_bytes(&packet).await;
+            }
+        }));
+    }
+
+    for socket in &sockets.sockets {
+        let socket = socket.clone();
+        let peers = peers.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut buf =
+                [0u8; std::mem::size_of::<Announcement>() + std::mem::size_of::<Status>()];
+            loop {
+                let (len, remote) = match socket.recv_from(&mut buf).await {
+                    Ok(received) => received,
+                    Err(err) => {
+                        tracing::warn!("failed to receive announcement: {err}");
+                        tokio::time::sleep(ANNOUNCE_INTERVAL).await;
+                        continue;
+                    }
+                };
+                let announcement: Announcement = match bincode::deserialize(&buf[..len]) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        tracing::error!("Failed to decode bincoded Announcement: {err}");
+                        continue;
+                    }
+                };
+                if !announcement.is_supported() || announcement.instance == own_id {
+                    continue;
+                }
+
+                // Status follows the announcement, which has fixed size
+                let status = if announcement.version >= 2 {
+                    let offset = bincode::serialized_size(&announcement).unwrap() as usize;
+                    match bincode::deserialize::<Status>(&buf[offset.min(len)..len]) {
+                        Ok(status) => status,
+                        Err(err) => {
+                            tracing::error!("Failed to decode bincoded Status: {err}");
+                            continue;
+                        }
+                    }
+                } else {
+                    Status::default()
+                };
+
+                let peer = Peer {
+                    name: unpad(&announcement.name),
+                    version: unpad(&announcement.software_version),
+                    address: remote.ip(),
+                    http_port: announcement.http_port,
+                    group: unpad(&status.group),
+                    playing: status.playing,
+                    last_seen: Instant::now(),
+                };
+                if let Some(previous) = peers.lock().unwrap().insert(announcement.instance, peer) {
+                    // Announcement is received on each interface, so only report new peers
+                    if previous.last_seen.elapsed() < PEER_TIMEOUT {
+                        continue;
+                    }
+                }
+                tracing::info!(
+                    "discovered {name:?} at {remote}",
+                    name = unpad(&announcement.name)
+                );
+            }
+        }));
+    }
+
+    Discovery {
+        announcement,
+        status,
+        peers,
+        tasks,
+    }
+}
@@ -0,0 +1,199 @@
+//! Importing MIDI files from URLs
+//!
+//! Ensembles often distribute parts as links (like shared Nextcloud folders) instead of files.
+//! [import] downloads the file from HTTP(S) URL and creates block from it like an upload would,
+//! including zip archives. Size is checked against `--max-file-size` while downloading, and the
+//! file must be valid MIDI, so a login page behind an expired link doesn't become a block.
+
+use crate::{api::Format, handlers, AppState};
+use axum::{extract::State, Form};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// How long to wait for the download before giving up
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// File name used when neither response nor URL provides one
+const DEFAULT_FILE_NAME: &str = "download.mid";
+
+/// Payload importing MIDI file from URL
+#[derive(Deserialize)]
+pub struct Import {
+    /// URL of the file
+    url: String,
+
+    /// Group for created blocks, overriding the default one
+    group: Option<String>,
+}
+
+/// URL from which the file can be downloaded
+///
+/// Nextcloud share links (`/s/<token>`) lead to the web page presenting the file, direct
+/// download is available under `/s/<token>/download`.
+fn download_url(url: &str) -> Result<reqwest::Url, String> {
+    let mut url = reqwest::Url::parse(url.trim()).map_err(|err| format!("invalid URL: {err}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "unsupported URL scheme {:?}, expected http or https",
+            url.scheme()
+        ));
+    }
+
+    let segments: Vec<_> = url
+        .path_segments()
+        .map(|segments| segments.filter(|segment| !segment.is_empty()).collect())
+        .unwrap_or_default();
+    if let [.., "s", _token] = segments.as_slice() {
+        let path = format!("{}/download", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+    }
+    Ok(url)
+}
+
+/// Name of the downloaded file, from `Content-Disposition` header or the last segment of URL
+fn file_name(response: &reqwest::Response) -> String {
+    let from_header = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .split(';')
+                .map(str::trim)
+                .find_map(|part| part.strip_prefix("filename="))
+        })
+        .map(|name| name.trim_matches('"').to_string());
+
+    from_header
+        .or_else(|| {
+            response
+                .url()
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|segment| !segment.is_empty() && *segment != "download")
+                .map(str::to_string)
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_FILE_NAME.to_string())
+}
+
+/// Download the file, rejecting it as soon as it exceeds `--max-file-size`
+async fn download(app_state: &AppState, url: reqwest::Url) -> Result<(String, Vec<u8>), String> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .map_err(|err| format!("failed to create HTTP client: {err}"))?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| format!("download failed: {err}"))?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        });
+    if content_type.as_deref() == Some("text/html") {
+        return Err("URL leads to a web page, not a file, use direct download link".to_string());
+    }
+    if !handlers::is_midi_content_type(content_type.as_deref()) {
+        return Err(format!(
+            "URL doesn't lead to a MIDI file or zip archive ({})",
+            content_type.unwrap_or_default()
+        ));
+    }
+
+    let too_big = || {
+        format!(
+            "file is bigger than {} MiB",
+            app_state.max_file_size / handlers::MIB
+        )
+    };
+    if response
+        .content_length()
+        .map_or(false, |length| length > app_state.max_file_size as u64)
+    {
+        return Err(too_big());
+    }
+
+    let file_name = file_name(&response);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| format!("download failed: {err}"))?
+    {
+        if bytes.len() + chunk.len() > app_state.max_file_size {
+            return Err(too_big());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok((file_name, bytes))
+}
+
+/// Creates MIDI block (or blocks from zip archive) from file downloaded from the `url`
+///
+/// Responds with current blocks, with rows describing failures like uploads do.
+pub async fn import(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(Import { url, group }): Form<Import>,
+) -> axum::response::Response {
+    let group = group.filter(|group| !group.trim().is_empty());
+    let mut errors = Vec::new();
+
+    let downloaded = match download_url(&url) {
+        Ok(download_url) => download(&app_state, download_url).await,
+        Err(err) => Err(err),
+    };
+    match downloaded {
+        Ok((file_name, bytes)) if handlers::is_zip_archive(&file_name) => {
+            match handlers::import_zip_archive(
+                app_state.clone(),
+                std::io::Cursor::new(bytes),
+                group,
+            )
+            .await
+            {
+                Ok(import) => {
+                    info!("added {} blocks from {url}", import.uuids.len());
+                    errors.extend(
+                        import
+                            .skipped
+                            .into_iter()
+                            .map(|skipped| format!("{file_name:?}: skipped {skipped}")),
+                    );
+                }
+                Err(err) => errors.push(format!("{url}: {err:#}")),
+            }
+        }
+        Ok((file_name, bytes)) => match midly::Smf::parse(&bytes) {
+            Ok(_) => {
+                let uuid =
+                    handlers::insert_midi_block(&app_state, file_name, bytes, group.as_deref());
+                info!("added block#{uuid} from {url}");
+            }
+            Err(err) => errors.push(format!("{url} is not a valid MIDI file: {err}")),
+        },
+        Err(err) => errors.push(format!("{url}: {err}")),
+    }
+
+    for err in &errors {
+        error!("import from URL failed: {err}");
+    }
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("download::import failed to remember current sources: {err:#}")
+    }
+
+    handlers::uploaded_blocks(app_state, format, errors).await
+}
@@ -0,0 +1,69 @@
+//! Clock drift and jitter of peers, see [linky_groups::clocks]
+//!
+//! Shown in the UI so ensembles can spot a laptop with a bad clock during the soundcheck, before
+//! it's audible. Peers exceeding [MAX_JITTER] or [MAX_DRIFT] are highlighted.
+
+use crate::{api::Format, AppState};
+use axum::{extract::State, response::IntoResponse, Json};
+use linky_groups::clocks::PeerClock;
+use maud::html;
+use std::sync::Arc;
+
+/// Jitter (microseconds) above which peer clock is highlighted, about one 32nd note at 120 BPM
+pub const MAX_JITTER: f64 = 5_000.0;
+
+/// Drift (microseconds per second) above which peer clock is highlighted
+pub const MAX_DRIFT: f64 = 100.0;
+
+/// Whether peer should be checked before it's audible
+fn is_suspicious(peer: &PeerClock) -> bool {
+    peer.jitter > MAX_JITTER || peer.drift.abs() > MAX_DRIFT
+}
+
+/// Format microseconds as milliseconds
+fn millis(micros: f64) -> String {
+    format!("{:.2} ms", micros / 1000.0)
+}
+
+/// Lists clock statistics of peers playing in any group
+///
+/// For JSON clients responds with list of [PeerClock]s.
+pub async fn clocks(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+) -> axum::response::Response {
+    let clocks = app_state
+        .groups
+        .as_ref()
+        .map(linky_groups::Groups::clocks)
+        .unwrap_or_default();
+    if format == Format::Json {
+        return Json(clocks).into_response();
+    }
+
+    let peers = app_state.discovery.peers();
+    html! {
+        @if clocks.is_empty() {
+            p { "No peers are playing in a group, clocks are measured only while playing" }
+        } @else {
+            table class="clocks" {
+                tr { th { "Peer" } th { "Offset" } th { "Jitter" } th { "Drift" } th { "Samples" } }
+                @for clock in &clocks {
+                    tr class=[is_suspicious(clock).then_some("suspicious")] {
+                        td {
+                            @match peers.iter().find(|peer| peer.address == clock.address) {
+                                Some(peer) => { (peer.name) " (" (clock.address) ")" },
+                                None => (clock.address),
+                            }
+                        }
+                        td { (millis(clock.offset)) }
+                        td { (millis(clock.jitter)) }
+                        td { (format!("{:.1} µs/s", clock.drift)) }
+                        td { (clock.samples) }
+                    }
+                }
+            }
+        }
+    }
+    .into_response()
+}
@@ -8,35 +8,64 @@
 //!
 //! [HTMX]: https://htmx.org
 //!
+//! Routes shared with programmatic clients respect requested [Format] and respond with JSON
+//! described in [api] module when asked to.
+//!
 //! Structs inside this file are only schemas for HTTP forms.
 
 // TODO:  This triplets of {SetX, midi_set_x_for_source, render_x_cell} maybe should be
 // consolidated
 
-use crate::{audio_engine, block, cache_path, AppState, Version};
+use crate::{
+    api::{self, Format},
+    audio_engine, block, cache_path, channels, latency, midi_clock, midi_input, mix, storage,
+    trash, update, upload, AppState, Version,
+};
+use anyhow::Context;
 use axum::{
-    body::{Bytes, Full},
-    extract::{ConnectInfo, Multipart, Path, State},
+    body::Full,
+    extract::{ConnectInfo, Multipart, Path, Query, State},
     http::{
         header::{CONTENT_DISPOSITION, CONTENT_TYPE},
-        HeaderMap, Response, StatusCode,
+        HeaderMap, HeaderValue, Response, StatusCode,
     },
-    Form,
+    response::IntoResponse,
+    Form, Json,
 };
 use maud::{html, Markup, PreEscaped, DOCTYPE};
-use midir::MidiOutput;
 use rusty_link::SessionState;
 use serde::Deserialize;
 use sha1::{Digest, Sha1};
-use std::{path::PathBuf, sync::Arc, time::Duration};
-use tracing::{error, info};
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::{error, info, warn};
 
 /// Main route, "/" handler, renders whole interface as HTML
+///
+/// For JSON clients responds with [api::Overview].
 pub async fn index(
     addr: ConnectInfo<crate::SocketAddr>,
+    format: Format,
     app_state: State<Arc<AppState>>,
-) -> Markup {
-    html! {
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if format == Format::Json {
+        return Json(api::Overview {
+            status: api::collect_status(&app_state),
+            blocks: api::block_summaries(&app_state),
+        })
+        .into_response();
+    }
+
+    // Clients that cannot change anything get the interface without controls
+    let viewer = !crate::access::can_change(&app_state, &addr, &headers);
+
+    let markup = html! {
         (DOCTYPE);
         html lang="en" {
             head {
@@ -47,7 +76,7 @@ pub async fn index(
                 script src="htmx.min.js" {}
                 link rel="stylesheet" href="index.css";
             }
-            body {
+            body class=[viewer.then_some("viewer")] {
                 noscript {
                     div style="font-weight: bold; color: red; font-size: 1.1em; max-width: 800px; margin: 1em auto 1em auto" {
                         "This is a web application, and thus requires JavaScript to work. But fear not!
@@ -61,6 +90,29 @@ pub async fn index(
 
                 header {
                     h1 { "Harmonia" }
+                    input
+                        type="search"
+                        id="block-search"
+                        name="query"
+                        placeholder="Search blocks"
+                        title="Search blocks by file name, group, tag or description"
+                        hx-get="/blocks"
+                        hx-trigger="input changed delay:200ms, search"
+                        hx-target="#blocks"
+                        hx-swap="innerHTML";
+                    @if let Err(err) = app_state.connection.read().unwrap().as_ref() {
+                        (midi_unavailable(err))
+                    }
+                    (update::banner(&app_state))
+                    @if viewer {
+                        div.viewer-notice {
+                            "Viewing only";
+                            @if app_state.token.is_some() {
+                                " ";
+                                a href="#" onclick="ask_for_access_token(); return false" { "(log in)" }
+                            }
+                        }
+                    }
                     div {
                         div {
                             "Version: ";
@@ -68,6 +120,9 @@ pub async fn index(
                         }
                         div {
                             "Data: ";
+                            @if app_state.ephemeral {
+                                "not stored (ephemeral mode)"
+                            } @else {
                             ({
                                 let cache = cache_path();
                                 let c: &std::path::Path = &cache;
@@ -80,12 +135,20 @@ pub async fn index(
                                     .unwrap()
                                     .to_owned()
                             });
+                            }
                         }
                     }
                 }
 
                 aside {
                     (runtime_status(app_state.clone()).await);
+                    button class="tap" hx-post="/api/tap" hx-swap="none" title="Tap to set tempo" {
+                        "TAP"
+                    }
+                    button class="panic" hx-post="/panic" hx-swap="none" title="Stop playback and silence MIDI on every instance in the network" {
+                        "PANIC"
+                    }
+                    (stop_at_select(*app_state.stop_at.read().unwrap()))
                     div {
                         label for="midi" { "New MIDI" }
                         input
@@ -94,11 +157,101 @@ pub async fn index(
                             id="midi"
                             name="midi"
                             multiple
-                            accept="audio/midi"
+                            accept="audio/midi,.mid,.midi,.zip,application/zip"
                             hx-put="/blocks/midi"
                             hx-target="#blocks"
                             hx-swap="innerHTML"
                             hx-encoding="multipart/form-data";
+                        form hx-post="/blocks/midi/url" hx-target="#blocks" hx-swap="innerHTML" {
+                            input type="url" name="url" placeholder="MIDI file URL" required;
+                            button { "Import" }
+                        }
+                        label for="sample" { "New sample" }
+                        input
+                            style="display: none"
+                            type="file"
+                            id="sample"
+                            name="sample"
+                            multiple
+                            accept="audio/*"
+                            hx-put="/blocks/sample"
+                            hx-target="#blocks"
+                            hx-swap="innerHTML"
+                            hx-encoding="multipart/form-data";
+                        label for="audio" { "New audio" }
+                        input
+                            style="display: none"
+                            type="file"
+                            id="audio"
+                            name="audio"
+                            multiple
+                            accept="audio/*"
+                            hx-put="/blocks/audio"
+                            hx-target="#blocks"
+                            hx-swap="innerHTML"
+                            hx-encoding="multipart/form-data";
+                        form hx-put="/blocks/tempo" hx-target="#blocks" hx-swap="innerHTML" {
+                            input
+                                type="number"
+                                name="bpm"
+                                min=(MIN_TEMPO)
+                                max=(MAX_TEMPO)
+                                step="any"
+                                placeholder="BPM"
+                                required;
+                            input
+                                type="number"
+                                name="ramp_beats"
+                                min="0"
+                                step="any"
+                                placeholder="Ramp (beats)";
+                            button { "New tempo" }
+                        }
+                        form hx-put="/blocks/silence" hx-target="#blocks" hx-swap="innerHTML" {
+                            input
+                                type="number"
+                                name="beats"
+                                min="0"
+                                step="any"
+                                placeholder="Beats"
+                                required;
+                            button { "New silence" }
+                        }
+                        form hx-put="/blocks/chord" hx-target="#blocks" hx-swap="innerHTML" {
+                            input
+                                type="text"
+                                name="spec"
+                                placeholder="Cmaj7 hold 16 beats on ch1"
+                                required;
+                            button { "New chord" }
+                        }
+                        form hx-put="/blocks/metronome" hx-target="#blocks" hx-swap="innerHTML" {
+                            input
+                                type="number"
+                                name="channel"
+                                min="1"
+                                max="16"
+                                value="10"
+                                title="Channel"
+                                required;
+                            input
+                                type="number"
+                                name="accent_note"
+                                min="0"
+                                max="127"
+                                value="76"
+                                title="Key on the first beat of the bar"
+                                required;
+                            input
+                                type="number"
+                                name="note"
+                                min="0"
+                                max="127"
+                                value="77"
+                                title="Key on other beats"
+                                required;
+                            button { "New metronome" }
+                        }
                         button {
                             // TODO: Handle SHM adding
                             "New SHM"
@@ -116,15 +269,113 @@ pub async fn index(
                 details class="midi-outputs" {
                     summary { "MIDI Outputs" }
                     p {
-                        "Refresh page (F5 or CTRL-R) to refresh list of available MIDI ports";
+                        "List is updated when MIDI devices are connected or disconnected";
+                    }
+                    div id="midi-ports" {
+                        (midi_ports(app_state.clone()).await)
+                    }
+                }
+
+                details class="peers" {
+                    summary { "Peers" }
+                    div hx-get="/peers" hx-trigger="load, every 2s" {}
+                }
+
+                details class="clocks" {
+                    summary { "Clock drift of peers" }
+                    div hx-get="/clocks" hx-trigger="load, every 2s" {}
+                }
+
+                details class="interfaces" {
+                    summary { "Network interfaces" }
+                    p {
+                        "Interfaces over which groups are synchronized with other instances"
+                    }
+                    div id="interfaces" hx-get="/network/interfaces" hx-trigger="load" {}
+                    p { "Session key authenticating synchronization with other instances" }
+                    div id="session-key" hx-get="/network/session-key" hx-trigger="load" {}
+                }
+
+                details class="logging" {
+                    summary { "Logging" }
+                    div id="log-filter" hx-get="/logs/filter" hx-trigger="load" {}
+                }
+
+                @if app_state.conductor {
+                    details class="conductor" open {
+                        summary { "Conductor" }
+                        div id="conductor" hx-get="/conductor" hx-trigger="load" {}
+                    }
+                }
+
+                details class="pull" {
+                    summary { "Pull blocks from peer" }
+                    form hx-post="/pull/list" hx-target="#pull-list" hx-swap="innerHTML" {
+                        input type="text" name="address" placeholder="Address, like 192.168.0.2:8080";
+                        button { "List blocks" }
+                    }
+                    div id="pull-list" {}
+                }
+
+                details class="defaults" {
+                    summary { "Defaults for new blocks" }
+                    (defaults_form(&app_state.defaults.read().unwrap()))
+                }
+
+                details class="trash" {
+                    summary { "Trash" }
+                    p {
+                        (format!("Removed blocks can be restored for {} days", trash::RETENTION_DAYS));
+                    }
+                    div id="trash" {
+                        (trash::render(&app_state));
+                    }
+                }
+
+                details class="backup" {
+                    summary { "Backup" }
+                    p {
+                        "Blocks, trash, nick, defaults, setlists and port settings as a single archive"
                     }
-                    (midi_ports(app_state.clone()).await)
+                    p { a href="/state/export" { "Export state" } }
+                    label for="state" { "Import state" }
+                    input
+                        style="display: none"
+                        type="file"
+                        id="state"
+                        name="state"
+                        accept=".zip,application/zip"
+                        hx-post="/state/import"
+                        hx-confirm="Importing replaces current blocks and settings. Continue?"
+                        hx-swap="none"
+                        hx-encoding="multipart/form-data";
                 }
 
                 details class="system-information" {
                     summary { "System information" }
                     (system_information(app_state.clone()).await);
+                    p {
+                        "Timeline of this session: "
+                        a href="/api/timeline/csv" { "CSV" }
+                        " "
+                        a href="/api/timeline" { "JSON" }
+                    }
+                    @if app_state.recorder.is_enabled() {
+                        p {
+                            a href="/recording" { "Download recording of this session" }
+                        }
+                    }
+                    details {
+                        summary { "Stored recordings" }
+                        div hx-get="/recordings" hx-trigger="load" {}
+                    }
                     @if addr.ip().is_loopback() {
+                        p {
+                            a href="/debug/bundle" { "Download diagnostic bundle" }
+                            " (to attach to bug reports, "
+                            a href="/debug/bundle?content=true" { "with MIDI and audio files" }
+                            ")"
+                        }
                         button hx-post="/abort" hx-confirm="Are you sure that you want to close Harmonia?"  {
                             "Abort Harmonia instance"
                         }
@@ -143,7 +394,8 @@ pub async fn index(
                 }
             }
         }
-    }
+    };
+    markup.into_response()
 }
 
 /// Renders synchronization state, including current time (beats)
@@ -153,10 +405,9 @@ pub async fn runtime_status(app_state: State<Arc<AppState>>) -> Markup {
     app_state.link.capture_app_session_state(&mut session_state);
     let time = app_state.link.clock_micros();
 
-    // TODO: Move quantum to state
-    let quantum = 1.0;
-    let beat = session_state.beat_at_time(time, quantum);
+    let beat = session_state.beat_at_time(time, app_state.quantum);
     let peers = app_state.link.num_peers();
+    let health = app_state.audio_engine.read().unwrap().health();
 
     html! {
         table id="status" {
@@ -169,63 +420,94 @@ pub async fn runtime_status(app_state: State<Arc<AppState>>) -> Markup {
             tr { th { "Peers" } td { (peers) } }
             tr { th { "Beat" } td { (format!("{beat:.1}")) } }
             tr { th { "BPM" } td { (session_state.tempo()) } }
+            @if let Some(failure) = &health.last_failure {
+                tr class="engine-failure" title=(failure) {
+                    th { "Engine" }
+                    td { "recovered from " (health.failures) " failure(s)" }
+                }
+            }
         }
     }
 }
 
 /// Renders playing state or nothing (if nothing is played)
+///
+/// Progress bar shows the block that started first, all played blocks are listed below it.
 pub async fn playing_status(app_state: State<Arc<AppState>>) -> Markup {
-    let playing = app_state.groups.as_ref().unwrap().is_playing();
+    let currently_playing = app_state.currently_playing.read().unwrap().clone();
+    let blocks = app_state.blocks.read().unwrap();
 
-    let currently_playing_uuid = app_state.currently_playing_uuid.read().unwrap();
-    let current_playing_progress = app_state.current_playing_progress.read().unwrap();
+    let mut session_state = SessionState::default();
+    app_state.link.capture_app_session_state(&mut session_state);
+    let time = app_state.link.clock_micros();
+    let tempo = session_state.tempo();
 
-    let is_infinite =
-        current_playing_progress.0 == current_playing_progress.1 && current_playing_progress.0 == 0;
+    let first = currently_playing
+        .values()
+        .min_by(|lhs, rhs| lhs.origin.total_cmp(&rhs.origin));
 
     html! {
         div id="playing-status" {
-            @if playing {
-                @if is_infinite {
-                    div class="progress infinite" {
-                        div style="height: 100%; background-color: gray" {}
-                        (maud::PreEscaped("&#x221E;"));
+            @if let Some(progress) = first {
+                @let beat = session_state.beat_at_time(time, progress.quantum);
+                @if let Some(countdown) = progress.countdown(beat) {
+                    div class="progress countdown" {
+                        (format!("starts in {}…", countdown.ceil()))
                     }
-                } else {
-                    div class="progress" {
-                        div style="height: 100%; background-color: gray" {}
-                        (format!("{}%", current_playing_progress.0 * 100 / current_playing_progress.1));
+                } @else {
+                    @match (progress.fraction(beat), progress.bar_count()) {
+                        (Some(fraction), Some(bar_count)) => {
+                            div class="progress" {
+                                div style=(format!("height: 100%; width: {:.1}%; background-color: gray", fraction * 100.0)) {}
+                                (format!("bar {} of {bar_count}", progress.bar(beat)));
+                            }
+                        }
+                        _ => {
+                            div class="progress infinite" {
+                                div style="height: 100%; background-color: gray" {}
+                                (maud::PreEscaped("&#x221E;"));
+                            }
+                        }
                     }
                 }
                 div style="grid-are: info" {
-                    ({
-                        // TODO: Unnesesary clone
-                        if let Some(uuid) = currently_playing_uuid.clone() {
-                            let blocks = app_state.blocks.read().unwrap();
-                            if let Some(block) = blocks.get(&uuid) {
-                                match &block.content {
-                                    block::Content::Midi(m) => m.file_name.clone(),
-                                    block::Content::SharedMemory { path } => path.to_string(),
-                                }
-                            } else {
-                                String::new()
+                    @for (uuid, progress) in &currently_playing {
+                        span class="playing" {
+                            // Status is delivered through websocket, outside of htmx
+                            button
+                                class="icon-control"
+                                title="Stop this block"
+                                onclick=(format!("fetch('/interrupt/{uuid}', {{ method: 'POST' }})"))
+                            {
+                                (PreEscaped("&#x23f9;"))
+                            }
+                            " "
+                            (blocks.get(uuid).map(|block| block.content.name()).unwrap_or_default())
+                            @let beat = session_state.beat_at_time(time, progress.quantum);
+                            @if let Some(remaining) = progress.remaining_seconds(beat, tempo) {
+                                " (" (format_duration(remaining)) " remaining)"
                             }
-                        } else {
-                            String::new()
                         }
-                    })
+                    }
                 }
             }
         }
     }
 }
 
+/// Format duration given in seconds as `m:ss`
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
 /// Renders information about the system
 ///
 /// Since Harmonia is by default accessible on all interfaces, a convenient way to use share files
 /// is to ask colleague what IP address they have and quickly enter their instance in browser
 async fn system_information(app_state: State<Arc<AppState>>) -> Markup {
     let port = app_state.port;
+    let scheme = app_state.scheme;
     let mut interfaces = match local_ip_address::list_afinet_netifas() {
         Ok(list) => list,
         Err(err) => {
@@ -245,7 +527,7 @@ async fn system_information(app_state: State<Arc<AppState>>) -> Markup {
 
     html! {
         p {
-            "Hostname: "; a href=(format!("http://{hostname}:{port}")) {
+            "Hostname: "; a href=(format!("{scheme}://{hostname}:{port}")) {
                 (hostname)
             }
         }
@@ -266,7 +548,7 @@ async fn system_information(app_state: State<Arc<AppState>>) -> Markup {
                     @if !ip.is_loopback() {
                         li {
                             (format!("{iface} -"));
-                            a href=(format!("http://{ip}:{port}")) {
+                            a href=(format!("{scheme}://{ip}:{port}")) {
                                 (ip);
                             }
                         }
@@ -280,14 +562,15 @@ async fn system_information(app_state: State<Arc<AppState>>) -> Markup {
 
 /// Render list of currently held ports in [AppState]
 pub async fn midi_ports(State(app_state): State<Arc<AppState>>) -> Markup {
-    let out = MidiOutput::new("harmonia").unwrap();
+    crate::refresh_midi_ports(&app_state);
 
-    if let Ok(mut midi_conn) = app_state.connection.try_write() {
-        midi_conn.refresh();
-    }
-
-    let midi_conn = app_state.connection.read().unwrap();
+    let midi_conn = app_state.connection.read().unwrap().clone();
+    let midi_conn = match midi_conn.as_ref() {
+        Ok(midi_conn) => midi_conn,
+        Err(err) => return midi_unavailable(err),
+    };
 
+    let out = midi_conn.conn.lock().unwrap();
     let ports = midi_conn
         .ports
         .iter()
@@ -297,38 +580,296 @@ pub async fn midi_ports(State(app_state): State<Arc<AppState>>) -> Markup {
         ol start="0" {
             li {
                 "Builtin Harmonia MIDI Virtual Port"
+                @if MIN_PORT_NUMBER == 0 {
+                    " " (test_port_button(0))
+                    " " (port_channels_input(&app_state, channels::VIRTUAL_PORT_NAME))
+                    " " (port_latency_input(&app_state, channels::VIRTUAL_PORT_NAME))
+                }
             }
-            @for port_name in ports {
-                li { (port_name) }
+            @for (index, port_name) in ports.enumerate() {
+                li {
+                    (port_name) " " (test_port_button(index + 1))
+                    " " (port_channels_input(&app_state, &port_name))
+                    " " (port_latency_input(&app_state, &port_name))
+                    " " (port_clock_input(&app_state, &port_name))
+                }
             }
         }
     }
 }
 
-/// Render currently held blocks
-async fn blocks(app_state: State<Arc<AppState>>) -> Markup {
+/// Render banner explaining that MIDI is unavailable
+fn midi_unavailable(err: &str) -> Markup {
+    html! {
+        p class="midi-unavailable" {
+            "MIDI unavailable: " (err) ". Blocks played through MIDI won't sound, other blocks work as usual. "
+            "Harmonia tries to initialize MIDI again every few seconds, refresh page to check."
+        }
+    }
+}
+
+/// Render button that sends test note to given port
+fn test_port_button(port: usize) -> Markup {
+    html! {
+        button hx-post=(format!("/midi/test/{port}")) hx-swap="none" { "Test" }
+    }
+}
+
+/// Render input setting which channels are forwarded to the port with given name
+fn port_channels_input(app_state: &AppState, port_name: &str) -> Markup {
+    let filter = channels::filter(app_state, port_name);
+    html! {
+        form class="port-channels" hx-post="/midi/channels" hx-trigger="change" hx-swap="none" {
+            input type="hidden" name="port" value=(port_name);
+            label {
+                "Channels: "
+                input
+                    type="text"
+                    name="channels"
+                    placeholder="all"
+                    value=[(!filter.is_all()).then(|| filter.to_string())]
+                    title="Channels forwarded to this port, like 10 or 1-9,11"
+                    size="8";
+            }
+        }
+    }
+}
+
+/// Render input setting latency offset of the port with given name
+fn port_latency_input(app_state: &AppState, port_name: &str) -> Markup {
+    let offset = latency::offset(app_state, port_name);
+    html! {
+        form class="port-latency" hx-post="/midi/latency" hx-trigger="change" hx-swap="none" {
+            input type="hidden" name="port" value=(port_name);
+            label {
+                "Latency: "
+                input
+                    type="number"
+                    name="latency"
+                    min=(-latency::MAX_LATENCY_MS)
+                    max=(latency::MAX_LATENCY_MS)
+                    placeholder="0"
+                    value=[(offset != 0).then_some(offset)]
+                    title="Milliseconds by which messages are sent earlier to this port, negative to send them later"
+                    size="5";
+                " ms"
+            }
+        }
+    }
+}
+
+/// Render checkbox enabling MIDI Beat Clock for the port with given name
+fn port_clock_input(app_state: &AppState, port_name: &str) -> Markup {
+    html! {
+        form class="port-clock" hx-post="/midi/clock" hx-trigger="change" hx-swap="none" {
+            input type="hidden" name="port" value=(port_name);
+            label title="Send MIDI Beat Clock with Start and Stop to this port while playing" {
+                input
+                    type="checkbox"
+                    name="enabled"
+                    checked[midi_clock::is_enabled(app_state, port_name)];
+                " Clock"
+            }
+        }
+    }
+}
+
+/// Query parameters of port test request
+#[derive(Deserialize)]
+pub struct TestPort {
+    /// MIDI channel (counted from 1) on which test note is sent, first by default
+    channel: Option<u8>,
+}
+
+/// Sends program change and short middle C to given port, to verify cabling and routing
+pub async fn test_port(
+    State(app_state): State<Arc<AppState>>,
+    Path(port): Path<usize>,
+    Query(TestPort { channel }): Query<TestPort>,
+) -> Result<(), (StatusCode, String)> {
+    let channel = channel.unwrap_or(1);
+    if !(1..=16).contains(&channel) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid channel {channel}, expected number from 1 to 16"),
+        ));
+    }
+
+    info!("testing port {port} on channel {channel}");
+    audio_engine::test_port(app_state, port, channel - 1)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err))
+}
+
+/// Sort blocks in the order in which they are presented to the user
+pub fn ordered_blocks(
+    blocks: &std::collections::HashMap<String, block::Block>,
+) -> Vec<(&String, &block::Block)> {
     use crate::block::Content;
 
-    let blocks = app_state.blocks.read().unwrap();
+    /// Position of the block kind when blocks don't have custom order
+    fn kind_rank(content: &Content) -> usize {
+        match content {
+            Content::SharedMemory { .. } => 0,
+            Content::Midi(_) => 1,
+            Content::Sample(_) => 2,
+            Content::Command { .. } => 3,
+            Content::Tempo { .. } => 4,
+            Content::Silence { .. } => 5,
+            Content::Chord(_) => 6,
+            Content::Pattern(_) => 7,
+            Content::Alias(_) => 8,
+            Content::Scene(_) => 9,
+            Content::Metronome(_) => 10,
+            Content::Audio(_) => 11,
+        }
+    }
+
     let mut orderered_blocks: Vec<_> = blocks.iter().collect();
 
     orderered_blocks.sort_by(|(_, lhs), (_, rhs)| match (lhs.order, rhs.order) {
         (Some(lhs), Some(rhs)) => lhs.cmp(&rhs),
         (Some(_), _) => std::cmp::Ordering::Less,
         (None, Some(_)) => std::cmp::Ordering::Greater,
-        (None, None) => match (&lhs.content, &rhs.content) {
-            (Content::Midi(lhs), Content::Midi(rhs)) => lhs.file_name.cmp(&rhs.file_name),
-            (Content::SharedMemory { path: lhs }, Content::SharedMemory { path: rhs }) => {
-                lhs.cmp(rhs)
-            }
-            (Content::SharedMemory { .. }, Content::Midi(_)) => std::cmp::Ordering::Less,
-            (Content::Midi(_), Content::SharedMemory { .. }) => std::cmp::Ordering::Greater,
-        },
+        (None, None) => (kind_rank(&lhs.content), lhs.content.name())
+            .cmp(&(kind_rank(&rhs.content), rhs.content.name())),
     });
 
+    orderered_blocks
+}
+
+/// Set custom order of blocks to the order of given identifiers, like from dragging in the UI
+///
+/// When only some of the blocks are given (for example ones found by search), they are reordered
+/// among the places they already take, and other blocks stay where they were.
+pub async fn reorder(
+    State(app_state): State<Arc<AppState>>,
+    Json(order): Json<Vec<String>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    {
+        let mut blocks = app_state.blocks.write().unwrap();
+        for (index, uuid) in order.iter().enumerate() {
+            if !blocks.contains_key(uuid) {
+                return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+            }
+            if order[..index].contains(uuid) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("block#{uuid} is given more than once"),
+                ));
+            }
+        }
+
+        let mut current: Vec<String> = ordered_blocks(&blocks)
+            .into_iter()
+            .map(|(uuid, _)| uuid.clone())
+            .collect();
+        let mut reordered = order.iter();
+        for uuid in current.iter_mut() {
+            if order.contains(uuid) {
+                *uuid = reordered.next().unwrap().clone();
+            }
+        }
+
+        for (position, uuid) in current.iter().enumerate() {
+            if let Some(block) = blocks.get_mut(uuid) {
+                block.order = Some(position);
+            }
+        }
+        info!("reordered {} blocks", order.len());
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("reorder failed to remember current sources: {err:#}")
+    }
+    Ok(StatusCode::OK)
+}
+
+/// Respond with currently held blocks in requested format
+async fn blocks_in_format(app_state: Arc<AppState>, format: Format) -> axum::response::Response {
+    match format {
+        Format::Json => Json(api::block_summaries(&app_state)).into_response(),
+        Format::Html => blocks(State(app_state)).await.into_response(),
+    }
+}
+
+/// Query of [search_blocks]
+#[derive(Deserialize)]
+pub struct Search {
+    /// Words that each shown block must contain, all blocks are shown when empty
+    #[serde(default)]
+    pub query: String,
+}
+
+/// Whether block matches every word of the search query
+///
+/// Words are looked for, ignoring case, in the name (file name for file based blocks), group,
+/// tags and description.
+fn matches_search(block: &block::Block, words: &[String]) -> bool {
+    let fields = [
+        block.content.name().to_lowercase(),
+        block.group.to_lowercase(),
+        block.description.to_lowercase(),
+    ];
+    words.iter().all(|word| {
+        fields.iter().any(|field| field.contains(word.as_str()))
+            || block
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(word.as_str()))
+    })
+}
+
+/// Render blocks matching the search query, see [matches_search]
+///
+/// Blocks are searched on the server, so large libraries don't have to be filtered in the
+/// browser of every performer.
+pub async fn search_blocks(
+    app_state: State<Arc<AppState>>,
+    Query(Search { query }): Query<Search>,
+) -> Markup {
+    render_blocks(&app_state, &query)
+}
+
+/// Render currently held blocks
+pub async fn blocks(app_state: State<Arc<AppState>>) -> Markup {
+    render_blocks(&app_state, "")
+}
+
+/// Render blocks matching the search query, all of them when query is empty
+fn render_blocks(app_state: &AppState, query: &str) -> Markup {
+    use crate::block::Content;
+
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    let blocks = app_state.blocks.read().unwrap();
+    let mut orderered_blocks = ordered_blocks(&blocks);
+    orderered_blocks.retain(|(_, block)| matches_search(block, &words));
+    let can_preview = app_state.can_preview();
+    let midi_learn = app_state.midi_learn.lock().unwrap().clone();
+    let mixes = app_state.mixes.read().unwrap();
+    let all_tags: BTreeSet<&str> = blocks
+        .values()
+        .flat_map(|block| block.tags.iter().map(String::as_str))
+        .collect();
+    let all_groups: BTreeSet<&str> = blocks
+        .values()
+        .map(|block| block.group.as_str())
+        .filter(|group| !group.is_empty())
+        .collect();
+
     html! {
+        (block_filter(&all_tags, &all_groups))
+        @if orderered_blocks.is_empty() && !words.is_empty() {
+            p class="no-results" { "No blocks match " q { (query.trim()) } }
+        }
         @for (uuid, block) in orderered_blocks.iter() {
-            section class="block" {
+            section
+                class=(block.color.map_or_else(|| "block".to_string(), |color| format!("block color-{color}")))
+                data-uuid=(uuid)
+                data-tags=(block.tags.join(","))
+                data-group=(block.group)
+            {
+                span class="drag-handle" draggable="true" title="Drag to reorder" { "⠿" }
                 button
                     class="delete-mode icon-control"
                     hx-delete=(format!("/blocks/{uuid}"))
@@ -344,26 +885,142 @@ async fn blocks(app_state: State<Arc<AppState>>) -> Markup {
                 {
                     "▶"
                 }
+                @if can_preview && matches!(block.content, Content::Midi(_) | Content::Chord(_) | Content::Alias(_)) {
+                    button
+                        onclick=(format!("preview('{uuid}')"))
+                        class="icon-control"
+                        title="Preview through SoundFont"
+                    {
+                        "🎧"
+                    }
+                }
                 div {
+                    @if let Some(err) = &block.integrity_error {
+                        span class="corrupted" title=(err) { "⚠ " }
+                    }
                     @match &block.content {
                         Content::Midi(source) => {
                             a href=(format!("/blocks/{uuid}")) { (source.file_name) }
                         }
-                        Content::SharedMemory { path } => (path),
+                        Content::Sample(source) | Content::Audio(source) => {
+                            a href=(format!("/blocks/{uuid}")) { (source.file_name) }
+                        }
+                        Content::SharedMemory { .. } => (block.content.name()),
+                        Content::Command { .. } => code { (block.content.name()) },
+                        Content::Tempo { .. } | Content::Silence { .. } | Content::Metronome(_) => (block.content.name()),
+                        Content::Chord(source) => code { (source.spec) },
+                        Content::Pattern(pattern) => {
+                            a href=(format!("/blocks/{uuid}")) { (pattern.name) }
+                        }
+                        Content::Alias(alias) => {
+                            "→ " a href=(format!("/blocks/{}", alias.target)) { (block.content.name()) }
+                        }
+                        Content::Scene(scene) => {
+                            (scene.name)
+                            " ("
+                            @for (i, member) in scene.blocks.iter().enumerate() {
+                                @if i > 0 { ", " }
+                                (blocks.get(member).map(|member| member.content.name()).unwrap_or_else(|| "missing".to_string()))
+                            }
+                            ")"
+                        }
+                    }
+                    @if block.content.can_rename() {
+                        " "
+                        button
+                            class="icon-control rename"
+                            title="Rename"
+                            data-uuid=(uuid)
+                            data-name=(block.content.name())
+                            onclick="rename_block(this)"
+                        {
+                            "✎"
+                        }
+                    }
+                }
+
+                (description(uuid, &block.description));
+
+                @if let Some(port) = block.content.port() {
+                    (port_cell(uuid, port))
+                }
+
+                @if let Content::Midi(source) = &block.content {
+                    @let sections = source.sections();
+                    @if !sections.is_empty() {
+                        div class="sections" {
+                            @for (index, section) in sections.iter().enumerate() {
+                                button
+                                    hx-post=(format!("/blocks/play-section/{uuid}/{index}"))
+                                    hx-swap="none"
+                                {
+                                    (section.name)
+                                }
+                            }
+                        }
                     }
                 }
 
                 @if let Content::Midi(source) = &block.content {
-                    (port_cell(uuid, source.associated_port))
+                    (trim(uuid, source.start_tick, source.end_tick))
+                    (velocity(uuid, source.velocity))
+                }
+
+                @if let Content::Alias(alias) = &block.content {
+                    (transpose(uuid, alias.transpose))
+                }
+
+                @if matches!(block.content, Content::Midi(_) | Content::Chord(_) | Content::Pattern(_) | Content::Alias(_)) {
+                    @let mix = mixes.get(uuid.as_str()).copied().unwrap_or_default();
+                    (mix::render(uuid, mix, !mix.is_empty()))
+                }
+
+                @if block::Alias::can_reference(&block.content) {
+                    button
+                        hx-put=(format!("/blocks/alias/{uuid}"))
+                        hx-target="#blocks"
+                        hx-swap="innerHTML"
+                        class="icon-control"
+                        title="Create alias"
+                    {
+                        "⧉"
+                    }
                 }
 
                 (group(uuid, &block.group));
                 (keybind(uuid, &block.keybind));
+                (tags(uuid, &block.tags));
+                (color_palette(uuid, block.color));
+                (midi_trigger(uuid, block.midi_trigger, midi_learn.as_deref() == Some(uuid)));
+                (quantum(uuid, block.quantum));
+
+                @if block.content.loops() {
+                    (loop_toggle(uuid, block.loop_playback))
+                }
+                @if block.content.is_synchronized() {
+                    (launch(uuid, block.launch))
+                    (count_in(uuid, block.count_in))
+                }
             }
         }
     }
 }
 
+/// Cut group name to [linky_groups::MAX_GROUP_ID_LENGTH], respecting character boundaries
+pub fn limit_group_length(group: &str) -> String {
+    // TODO: Unnesesary string allocation
+    if group.len() > linky_groups::MAX_GROUP_ID_LENGTH {
+        let mut cut = linky_groups::MAX_GROUP_ID_LENGTH;
+        while !group.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        &group[..cut]
+    } else {
+        group
+    }
+    .to_owned()
+}
+
 /// Render group input
 fn group(uuid: &str, group: &str) -> Markup {
     html! {
@@ -388,11 +1045,12 @@ pub struct SetGroup {
 /// Set group for given block
 pub async fn set_group(
     app_state: State<Arc<AppState>>,
+    format: Format,
     Path(uuid): Path<String>,
     Form(SetGroup {
         group: group_to_set,
     }): Form<SetGroup>,
-) -> Result<Markup, StatusCode> {
+) -> Result<axum::response::Response, StatusCode> {
     let response = {
         let mut blocks = app_state.blocks.write().unwrap();
 
@@ -401,23 +1059,16 @@ pub async fn set_group(
             return Err(StatusCode::NOT_FOUND);
         };
 
-        // TODO: Unnesesary string allocation
-        midi_source.group = if group_to_set.len() > linky_groups::MAX_GROUP_ID_LENGTH {
-            let mut cut = linky_groups::MAX_GROUP_ID_LENGTH;
-            while !group_to_set.is_char_boundary(cut) {
-                cut -= 1;
-            }
-            &group_to_set[..cut]
-        } else {
-            &group_to_set[..]
-        }
-        .to_owned();
+        midi_source.group = limit_group_length(&group_to_set);
 
         tracing::info!(
             "Switched block#{uuid} to group {group:?}",
             group = midi_source.group
         );
-        Ok(group(&uuid, &midi_source.group))
+        Ok(match format {
+            Format::Json => Json(api::BlockSummary::new(&uuid, midi_source)).into_response(),
+            Format::Html => group(&uuid, &midi_source.group).into_response(),
+        })
     };
 
     if let Err(err) = app_state.remember_current_blocks() {
@@ -426,249 +1077,2021 @@ pub async fn set_group(
     response
 }
 
-/// Render current keybind for block in input form
-fn keybind(uuid: &str, keybind: &str) -> Markup {
+/// Longest description of the block, in characters
+pub const MAX_DESCRIPTION_LENGTH: usize = 500;
+
+/// Cut description to [MAX_DESCRIPTION_LENGTH] characters, without surrounding whitespace
+pub fn limit_description_length(description: &str) -> String {
+    description
+        .trim()
+        .chars()
+        .take(MAX_DESCRIPTION_LENGTH)
+        .collect()
+}
+
+/// Render description input
+fn description(uuid: &str, description: &str) -> Markup {
     html! {
         input
-            name="keybind"
-            data-uuid=(uuid)
-            onchange="update_key_binding(this)"
             type="text"
-            hx-post=(format!("/blocks/set-keybind/{uuid}"))
+            class="description"
+            name="description"
+            maxlength=(MAX_DESCRIPTION_LENGTH)
+            placeholder="Description"
+            title=(description)
+            hx-post=(format!("/blocks/set-description/{uuid}"))
             hx-swap="none"
-            placeholder="Keybind"
-            value=(keybind);
+            value=(description);
     }
 }
 
-/// Schema for request that sets keybind for given block
+/// Schema for request that sets description of given block
 #[derive(Deserialize)]
-pub struct SetKeybind {
-    /// Kebind to set
-    pub keybind: String,
+pub struct SetDescription {
+    /// Description to set
+    pub description: String,
 }
 
-/// Sets keybind for given block
-pub async fn set_keybind(
+/// Sets description of given block
+pub async fn set_description(
     app_state: State<Arc<AppState>>,
     Path(uuid): Path<String>,
-    Form(SetKeybind { keybind }): Form<SetKeybind>,
+    Form(SetDescription { description }): Form<SetDescription>,
 ) -> StatusCode {
     {
-        let mut midi_sources = app_state.blocks.write().unwrap();
+        let mut blocks = app_state.blocks.write().unwrap();
 
-        let Some(block) = midi_sources.get_mut(&uuid) else {
+        let Some(block) = blocks.get_mut(&uuid) else {
             error!("block#{uuid} not found");
             return StatusCode::NOT_FOUND;
         };
 
-        info!("Changing keybind for block#{uuid} to {keybind}");
-        block.keybind = keybind;
+        block.description = limit_description_length(&description);
+        info!(
+            "Changing description of block#{uuid} to {:?}",
+            block.description
+        );
     }
 
     if let Err(err) = app_state.remember_current_blocks() {
-        error!("set_keybind failed to remember current sources: {err:#}")
+        error!("set_description failed to remember current sources: {err:#}")
     }
 
     StatusCode::OK
 }
 
-// TODO: Should be select
-// TODO: max should be dynamic
-/// Renders port input for MIDI port
-fn port_cell(uuid: &str, associated_port: usize) -> Markup {
-    html! {
-        input
-            type="number" value=(format!("{}", associated_port))
-            name="port"
-            min=(MIN_PORT_NUMBER)
-            hx-target="this"
-            hx-swap="outerHTML"
-            hx-post=(format!("/blocks/midi/set-port/{uuid}"));
+/// Schema for request that renames given block
+#[derive(Deserialize)]
+pub struct Rename {
+    /// New name of the block
+    pub name: String,
+}
+
+/// Renames file based or shared memory block, see [block::Content::rename]
+pub async fn rename(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(Rename { name }): Form<Rename>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        block
+            .content
+            .rename(&name)
+            .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+        info!("Renamed block#{uuid} to {:?}", block.content.name());
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("rename failed to remember current sources: {err:#}")
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Longest tag, in characters
+pub const MAX_TAG_LENGTH: usize = 32;
+
+/// Trim tags, cut them to [MAX_TAG_LENGTH] characters and remove empty and repeated ones
+pub fn normalize_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag: String = tag.trim().chars().take(MAX_TAG_LENGTH).collect();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Render tags input, tags are separated with commas
+fn tags(uuid: &str, tags: &[String]) -> Markup {
+    html! {
+        input
+            type="text"
+            class="tags"
+            name="tags"
+            placeholder="Tags"
+            title="Tags separated with commas"
+            hx-post=(format!("/blocks/set-tags/{uuid}"))
+            hx-swap="none"
+            value=(tags.join(", "));
+    }
+}
+
+/// Schema for request that sets tags of given block
+#[derive(Deserialize)]
+pub struct SetTags {
+    /// Tags separated with commas
+    pub tags: String,
+}
+
+/// Sets tags of given block
+pub async fn set_tags(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetTags { tags }): Form<SetTags>,
+) -> StatusCode {
+    {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            error!("block#{uuid} not found");
+            return StatusCode::NOT_FOUND;
+        };
+
+        block.tags = normalize_tags(tags.split(','));
+        info!("Changing tags of block#{uuid} to {:?}", block.tags);
+    }
+
+    // Tags offered by the filter are updated by reloading blocks
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_tags failed to remember current sources: {err:#}")
+    }
+
+    StatusCode::OK
+}
+
+/// Render bar filtering blocks by tag and group, see `apply_block_filter` in `index.js`
+fn block_filter(tags: &BTreeSet<&str>, groups: &BTreeSet<&str>) -> Markup {
+    html! {
+        @if !tags.is_empty() || !groups.is_empty() {
+            div class="tag-filter" {
+                @if !tags.is_empty() {
+                    label {
+                        "Show "
+                        select id="tag-filter" onchange="filter_blocks()" {
+                            option value="" { "All blocks" }
+                            @for tag in tags {
+                                option value=(tag) { (tag) }
+                            }
+                        }
+                    }
+                    " "
+                }
+                @if !groups.is_empty() {
+                    label {
+                        "Group "
+                        select id="group-filter" onchange="filter_blocks()" {
+                            option value="" { "All groups" }
+                            @for group in groups {
+                                option value=(group) { (group) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Render current keybind for block in input form
+fn keybind(uuid: &str, keybind: &str) -> Markup {
+    html! {
+        input
+            name="keybind"
+            data-uuid=(uuid)
+            onchange="update_key_binding(this)"
+            type="text"
+            hx-post=(format!("/blocks/set-keybind/{uuid}"))
+            hx-swap="none"
+            placeholder="Keybind"
+            value=(keybind);
+    }
+}
+
+/// Render MIDI learn button of the block, showing it's current trigger
+///
+/// While waiting for MIDI message, button polls until the trigger is learned.
+fn midi_trigger(uuid: &str, trigger: Option<midi_input::Trigger>, learning: bool) -> Markup {
+    html! {
+        span class="midi-trigger" hx-target="this" hx-swap="outerHTML" {
+            @if learning {
+                button
+                    hx-get=(format!("/blocks/midi-learn/{uuid}"))
+                    hx-trigger="every 1s"
+                    title="Press pad, key or foot switch of MIDI controller"
+                {
+                    "Waiting for MIDI…"
+                }
+            } @else {
+                button
+                    hx-post=(format!("/blocks/midi-learn/{uuid}"))
+                    title="Learn MIDI trigger starting and stopping this block"
+                {
+                    (trigger.map_or_else(|| "Learn MIDI".to_string(), |trigger| trigger.to_string()))
+                }
+                @if trigger.is_some() {
+                    button
+                        class="icon-control"
+                        hx-delete=(format!("/blocks/midi-learn/{uuid}"))
+                        title="Remove MIDI trigger"
+                    {
+                        "✕"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Render current MIDI trigger of given block
+pub async fn midi_learn_state(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Markup, (StatusCode, String)> {
+    let learning = app_state.midi_learn.lock().unwrap().as_deref() == Some(uuid.as_str());
+    let blocks = app_state.blocks.read().unwrap();
+    let Some(block) = blocks.get(&uuid) else {
+        return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+    };
+    Ok(midi_trigger(&uuid, block.midi_trigger, learning))
+}
+
+/// Makes the next MIDI trigger received from controllers the trigger of given block
+///
+/// Only one block learns at a time, requesting learn for another block cancels the previous one.
+pub async fn start_midi_learn(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Markup, (StatusCode, String)> {
+    let blocks = app_state.blocks.read().unwrap();
+    let Some(block) = blocks.get(&uuid) else {
+        return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+    };
+
+    info!("waiting for MIDI trigger of block#{uuid}");
+    *app_state.midi_learn.lock().unwrap() = Some(uuid.clone());
+    Ok(midi_trigger(&uuid, block.midi_trigger, true))
+}
+
+/// Removes MIDI trigger of given block
+pub async fn remove_midi_trigger(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Markup, (StatusCode, String)> {
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        info!("removing MIDI trigger of block#{uuid}");
+        block.midi_trigger = None;
+        midi_trigger(&uuid, None, false)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("remove_midi_trigger failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Schema for request that sets keybind for given block
+#[derive(Deserialize)]
+pub struct SetKeybind {
+    /// Kebind to set
+    pub keybind: String,
+}
+
+/// Sets keybind for given block
+pub async fn set_keybind(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetKeybind { keybind }): Form<SetKeybind>,
+) -> StatusCode {
+    {
+        let mut midi_sources = app_state.blocks.write().unwrap();
+
+        let Some(block) = midi_sources.get_mut(&uuid) else {
+            error!("block#{uuid} not found");
+            return StatusCode::NOT_FOUND;
+        };
+
+        info!("Changing keybind for block#{uuid} to {keybind}");
+        block.keybind = keybind;
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_keybind failed to remember current sources: {err:#}")
+    }
+
+    StatusCode::OK
+}
+
+/// Render transposition input for alias block
+fn transpose(uuid: &str, transpose: i8) -> Markup {
+    html! {
+        input
+            type="number"
+            name="transpose"
+            min=(i8::MIN)
+            max=(i8::MAX)
+            placeholder="Transpose"
+            value=(transpose)
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-transpose/{uuid}"));
+    }
+}
+
+/// Schema for request that sets transposition of alias block
+#[derive(Deserialize)]
+pub struct SetTranspose {
+    /// Number of semitones by which notes are transposed
+    pub transpose: i8,
+}
+
+/// Sets transposition of given alias block
+pub async fn set_transpose(
+    app_state: State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+    Form(SetTranspose {
+        transpose: transpose_to_set,
+    }): Form<SetTranspose>,
+) -> Result<axum::response::Response, StatusCode> {
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            error!("block#{uuid} not found");
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        let block::Content::Alias(ref mut alias) = block.content else {
+            error!("block#{uuid} is not an alias");
+            return Err(StatusCode::BAD_REQUEST);
+        };
+
+        info!("Changing transposition for block#{uuid} to {transpose_to_set}");
+        alias.transpose = transpose_to_set;
+        match format {
+            Format::Json => Json(api::BlockSummary::new(&uuid, block)).into_response(),
+            Format::Html => transpose(&uuid, transpose_to_set).into_response(),
+        }
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_transpose failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render quantum override input for block
+fn quantum(uuid: &str, quantum: Option<f64>) -> Markup {
+    html! {
+        input
+            type="number"
+            name="quantum"
+            min="0"
+            step="any"
+            placeholder="Quantum"
+            value=[quantum]
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-quantum/{uuid}"));
+    }
+}
+
+/// Schema for request that sets quantum override for given block
+#[derive(Deserialize)]
+pub struct SetQuantum {
+    /// Quantum to set, empty to use session default
+    pub quantum: String,
+}
+
+/// Sets quantum override for given block
+pub async fn set_quantum(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetQuantum {
+        quantum: quantum_to_set,
+    }): Form<SetQuantum>,
+) -> Result<Markup, StatusCode> {
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            error!("block#{uuid} not found");
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        let quantum_to_set = quantum_to_set.trim();
+        if quantum_to_set.is_empty() {
+            info!("Using session quantum for block#{uuid}");
+            block.quantum = None;
+        } else {
+            match quantum_to_set.parse::<f64>() {
+                Ok(value) if value.is_finite() && value > 0.0 => {
+                    info!("Changing quantum for block#{uuid} to {value}");
+                    block.quantum = Some(value);
+                }
+                _ => error!("invalid quantum {quantum_to_set:?} for block#{uuid}"),
+            }
+        }
+        quantum(&uuid, block.quantum)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_quantum failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render launch mode select of the block
+fn launch(uuid: &str, launch: Option<block::Launch>) -> Markup {
+    html! {
+        select
+            name="launch"
+            title="How block starts when nothing else is playing"
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-launch/{uuid}"))
+        {
+            option value="" selected[launch.is_none()] { "Default launch" }
+            @for mode in block::Launch::ALL {
+                option value=(mode.name()) selected[launch == Some(mode)] { (mode.name()) }
+            }
+        }
+    }
+}
+
+/// Render palette setting color label of the block
+fn color_palette(uuid: &str, color: Option<block::Color>) -> Markup {
+    html! {
+        details class="color-palette" {
+            summary
+                class=(format!("swatch color-{}", color.map_or("none", block::Color::name)))
+                title="Color label"
+            {}
+            div {
+                button
+                    class="swatch color-none"
+                    title="No color"
+                    hx-post=(format!("/blocks/set-color/{uuid}"))
+                    hx-vals="{\"color\": \"\"}"
+                    hx-swap="none"
+                {}
+                @for option in block::Color::ALL {
+                    button
+                        class=(format!("swatch color-{option}"))
+                        title=(option)
+                        hx-post=(format!("/blocks/set-color/{uuid}"))
+                        hx-vals=(format!("{{\"color\": \"{option}\"}}"))
+                        hx-swap="none"
+                    {}
+                }
+            }
+        }
+    }
+}
+
+/// Schema for request that sets color label of given block
+#[derive(Deserialize)]
+pub struct SetColor {
+    /// Color to set, empty to remove color label
+    pub color: String,
+}
+
+/// Sets color label of given block
+pub async fn set_color(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetColor { color }): Form<SetColor>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let color = match color.trim() {
+        "" => None,
+        color => Some(
+            color
+                .parse::<block::Color>()
+                .map_err(|err| (StatusCode::BAD_REQUEST, err))?,
+        ),
+    };
+
+    {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        match color {
+            Some(color) => info!("Changing color of block#{uuid} to {color}"),
+            None => info!("Removing color of block#{uuid}"),
+        }
+        block.color = color;
+    }
+
+    // Whole block is colored, so it's updated by reloading blocks
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_color failed to remember current sources: {err:#}")
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Schema for request that sets launch mode override for given block
+#[derive(Deserialize)]
+pub struct SetLaunch {
+    /// Launch mode to set, empty to use session default
+    pub launch: String,
+}
+
+/// Sets launch mode override for given block
+pub async fn set_launch(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetLaunch {
+        launch: launch_to_set,
+    }): Form<SetLaunch>,
+) -> Result<Markup, (StatusCode, String)> {
+    let launch_to_set = match launch_to_set.trim() {
+        "" => None,
+        launch => Some(
+            launch
+                .parse::<block::Launch>()
+                .map_err(|err| (StatusCode::BAD_REQUEST, err))?,
+        ),
+    };
+
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        match launch_to_set {
+            Some(mode) => info!("Changing launch for block#{uuid} to {mode}"),
+            None => info!("Using session launch for block#{uuid}"),
+        }
+        block.launch = launch_to_set;
+        launch(&uuid, block.launch)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_launch failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render count-in input of the block
+fn count_in(uuid: &str, count_in: u32) -> Markup {
+    html! {
+        input
+            type="number"
+            name="count_in"
+            min="0"
+            placeholder="Count-in"
+            title="Beats counted in before block starts"
+            value=[(count_in > 0).then_some(count_in)]
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-count-in/{uuid}"));
+    }
+}
+
+/// Schema for request that sets count-in of given block
+#[derive(Deserialize)]
+pub struct SetCountIn {
+    /// Number of beats, empty for no count-in
+    pub count_in: String,
+}
+
+/// Sets count-in of given block
+pub async fn set_count_in(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetCountIn {
+        count_in: count_in_to_set,
+    }): Form<SetCountIn>,
+) -> Result<Markup, (StatusCode, String)> {
+    let count_in_to_set = match count_in_to_set.trim() {
+        "" => 0,
+        beats => beats.parse::<u32>().map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid count-in {beats:?}: {err}"),
+            )
+        })?,
+    };
+
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        info!("Changing count-in for block#{uuid} to {count_in_to_set} beats");
+        block.count_in = count_in_to_set;
+        count_in(&uuid, block.count_in)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_count_in failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render start and end tick inputs of the MIDI block
+fn trim(uuid: &str, start_tick: Option<u64>, end_tick: Option<u64>) -> Markup {
+    html! {
+        form
+            class="trim"
+            hx-trigger="change"
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-trim/{uuid}"))
+        {
+            input
+                type="number"
+                name="start_tick"
+                min="0"
+                placeholder="Start tick"
+                title="MIDI tick from which block starts"
+                value=[start_tick];
+            input
+                type="number"
+                name="end_tick"
+                min="1"
+                placeholder="End tick"
+                title="MIDI tick on which block ends"
+                value=[end_tick];
+        }
+    }
+}
+
+/// Schema for request that sets start and end tick of MIDI block
+#[derive(Deserialize)]
+pub struct SetTrim {
+    /// Tick from which block starts, empty to start from the beginning
+    pub start_tick: String,
+
+    /// Tick on which block ends, empty to play until the end
+    pub end_tick: String,
+}
+
+/// Parse tick given in the form, empty means no tick
+fn parse_tick(tick: &str) -> Result<Option<u64>, (StatusCode, String)> {
+    match tick.trim() {
+        "" => Ok(None),
+        tick => tick.parse::<u64>().map(Some).map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid tick {tick:?}: {err}"),
+            )
+        }),
+    }
+}
+
+/// Sets start and end tick of given MIDI block
+pub async fn set_trim(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(SetTrim {
+        start_tick,
+        end_tick,
+    }): Form<SetTrim>,
+) -> Result<Markup, (StatusCode, String)> {
+    let start_tick = parse_tick(&start_tick)?;
+    let end_tick = parse_tick(&end_tick)?;
+    if let (Some(start), Some(end)) = (start_tick, end_tick) {
+        if end <= start {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("end tick {end} must be after start tick {start}"),
+            ));
+        }
+    }
+
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        let block::Content::Midi(ref mut source) = block.content else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("block#{uuid} is not a MIDI block"),
+            ));
+        };
+
+        info!("Trimming block#{uuid} to ticks {start_tick:?}..{end_tick:?}");
+        source.start_tick = start_tick;
+        source.end_tick = end_tick;
+        trim(&uuid, start_tick, end_tick)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_trim failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render velocity scaling inputs of the MIDI block
+fn velocity(uuid: &str, velocity: block::Velocity) -> Markup {
+    html! {
+        form
+            class="velocity"
+            hx-trigger="change"
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/set-velocity/{uuid}"))
+        {
+            input
+                type="number"
+                name="scale"
+                min="0"
+                step="any"
+                title="Velocity multiplier"
+                value=(velocity.scale);
+            input
+                type="number"
+                name="min"
+                min="1"
+                max="127"
+                title="Lowest velocity"
+                value=(velocity.min);
+            input
+                type="number"
+                name="max"
+                min="1"
+                max="127"
+                title="Highest velocity"
+                value=(velocity.max);
+        }
+    }
+}
+
+/// Sets velocity scaling of given MIDI block
+pub async fn set_velocity(
+    app_state: State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+    Form(velocity_to_set): Form<block::Velocity>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    velocity_to_set
+        .validate()
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+
+        let block::Content::Midi(ref mut source) = block.content else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("block#{uuid} is not a MIDI block"),
+            ));
+        };
+
+        info!("Changing velocity scaling for block#{uuid} to {velocity_to_set:?}");
+        source.velocity = velocity_to_set;
+        match format {
+            Format::Json => Json(api::BlockSummary::new(&uuid, block)).into_response(),
+            Format::Html => velocity(&uuid, velocity_to_set).into_response(),
+        }
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("set_velocity failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+/// Render button toggling looped playback of the block
+fn loop_toggle(uuid: &str, loop_playback: bool) -> Markup {
+    html! {
+        button
+            class="icon-control loop"
+            aria-pressed=(if loop_playback { "true" } else { "false" })
+            title=(if loop_playback { "Looped, click to play once" } else { "Played once, click to loop" })
+            hx-post=(format!("/blocks/toggle-loop/{uuid}"))
+            hx-target="this"
+            hx-swap="outerHTML"
+        {
+            "🔁"
+        }
+    }
+}
+
+/// Toggles looped playback of given block
+pub async fn toggle_loop(
+    app_state: State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<Markup, StatusCode> {
+    let response = {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(block) = blocks.get_mut(&uuid) else {
+            error!("block#{uuid} not found");
+            return Err(StatusCode::NOT_FOUND);
+        };
+
+        block.loop_playback = !block.loop_playback;
+        info!(
+            "{} looped playback for block#{uuid}",
+            if block.loop_playback {
+                "Enabling"
+            } else {
+                "Disabling"
+            }
+        );
+        loop_toggle(&uuid, block.loop_playback)
+    };
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("toggle_loop failed to remember current sources: {err:#}")
+    }
+
+    Ok(response)
+}
+
+// TODO: Should be select
+// TODO: max should be dynamic
+/// Renders port input for MIDI port
+fn port_cell(uuid: &str, associated_port: usize) -> Markup {
+    html! {
+        input
+            type="number" value=(format!("{}", associated_port))
+            name="port"
+            min=(MIN_PORT_NUMBER)
+            hx-target="this"
+            hx-swap="outerHTML"
+            hx-post=(format!("/blocks/midi/set-port/{uuid}"));
+    }
+}
+
+/// Schema for port selection for block containing MIDI
+#[derive(Deserialize)]
+pub struct SetPort {
+    /// MIDI port to set for Midi block
+    pub port: usize,
+}
+
+/// Minimum MIDI port number
+///
+/// On windows it is 1 since we don't have virtual ports, unless they are provided by
+/// `windows-virtual-port` feature, see [crate::virtual_port].
+/// On unix'es it's 0 since we have virtual ports.
+pub const MIN_PORT_NUMBER: usize =
+    if cfg!(any(unix, all(windows, feature = "windows-virtual-port"))) {
+        0
+    } else {
+        1
+    };
+
+/// Set port for MIDI block
+pub async fn set_port_for_midi(
+    app_state: State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+    Form(SetPort { port }): Form<SetPort>,
+) -> Result<axum::response::Response, StatusCode> {
+    let mut blocks = app_state.blocks.write().unwrap();
+
+    let Some(block) = blocks.get_mut(&uuid) else {
+        error!("block#{uuid} was not found");
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let Some(associated_port) = block.content.port_mut() else {
+        error!("block#{uuid} is not played through MIDI");
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    let max = app_state.midi_port_count();
+
+    #[allow(clippy::absurd_extreme_comparisons)]
+    if port < MIN_PORT_NUMBER || port > max {
+        error!("port number should be between {MIN_PORT_NUMBER} and {max}");
+        return match format {
+            Format::Json => Err(StatusCode::BAD_REQUEST),
+            Format::Html => Ok(port_cell(&uuid, *associated_port).into_response()),
+        };
+    }
+
+    info!("setting port {port} for {uuid}");
+    *associated_port = port;
+    Ok(match format {
+        Format::Json => Json(api::BlockSummary::new(&uuid, block)).into_response(),
+        Format::Html => port_cell(&uuid, port).into_response(),
+    })
+}
+
+/// Responds with content of block if block had any
+///
+/// For JSON clients responds with [api::BlockSummary] of the block instead.
+pub async fn download_block_content(
+    app_state: State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+) -> axum::response::Response {
+    let not_found = || {
+        let mut response = Response::new(Full::from("not found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, "text/html".parse().unwrap());
+        response.into_response()
+    };
+
+    let blocks = app_state.blocks.read().unwrap();
+    let Some(block) = blocks.get(&uuid) else {
+        error!("block#{uuid} not found");
+        return not_found();
+    };
+
+    if format == Format::Json {
+        return Json(api::BlockSummary::new(&uuid, block)).into_response();
+    }
+
+    match &block.content {
+        block::Content::SharedMemory { .. }
+        | block::Content::Command { .. }
+        | block::Content::Tempo { .. }
+        | block::Content::Silence { .. }
+        | block::Content::Metronome(_)
+        | block::Content::Chord(_)
+        | block::Content::Alias(_) => not_found(),
+        block::Content::Pattern(pattern) => Json(pattern).into_response(),
+        block::Content::Scene(scene) => Json(scene).into_response(),
+        block::Content::Midi(midi_source) => {
+            // TODO: Unnesesary clone?
+            let mut response = Response::new(Full::from(midi_source.bytes.to_vec()));
+            let headers = &mut response.headers_mut();
+            headers.insert(CONTENT_DISPOSITION, attachment(&midi_source.file_name));
+            headers.insert(CONTENT_TYPE, "audio/midi".parse().unwrap());
+            response.into_response()
+        }
+        block::Content::Sample(audio_source) | block::Content::Audio(audio_source) => {
+            let mut response = Response::new(Full::from(audio_source.bytes.to_vec()));
+            let headers = &mut response.headers_mut();
+            headers.insert(CONTENT_DISPOSITION, attachment(&audio_source.file_name));
+            headers.insert(
+                CONTENT_TYPE,
+                mime_guess::from_path(&audio_source.file_name)
+                    .first_or_octet_stream()
+                    .as_ref()
+                    .parse()
+                    .unwrap(),
+            );
+            response.into_response()
+        }
+    }
+}
+
+/// Content disposition of the downloaded block content with given file name
+///
+/// File names come from uploads, so characters that would end the quoted name or that are not
+/// allowed in header values are replaced with `_`.
+fn attachment(file_name: &str) -> HeaderValue {
+    let file_name: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    HeaderValue::from_str(&format!("attachment; filename=\"{file_name}\""))
+        .unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+/// Responds with MIDI file containing everything that was sent during this session
+pub async fn download_recording(
+    State(app_state): State<Arc<AppState>>,
+) -> Result<axum::response::Response, StatusCode> {
+    let Some((file_name, bytes)) = app_state.recorder.render().await else {
+        error!("recording was requested, but it is not enabled");
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let mut response = Response::new(Full::from(bytes));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{file_name}\"")
+            .parse()
+            .unwrap(),
+    );
+    headers.insert(CONTENT_TYPE, "audio/midi".parse().unwrap());
+    Ok(response.into_response())
+}
+
+/// Removes block based on ID and caches currently held blocks
+pub async fn remove_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+) -> axum::response::Response {
+    let removed = app_state.blocks.write().unwrap().remove(&uuid);
+    if let Some(block) = removed {
+        info!("moving block#{uuid} to trash");
+        app_state.trash.lock().unwrap().put(uuid, block);
+    }
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("remove_midi_source_handler failed to remember current sources: {err:#}")
+    }
+
+    trash::blocks_with_trash(app_state, format).await
+}
+
+/// Starts playing given block
+pub async fn play(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<(), (StatusCode, String)> {
+    ensure_local_programs(&app_state, addr, [uuid.as_str()])?;
+    let _ = audio_engine::play(app_state.clone(), &uuid).await;
+    Ok(())
+}
+
+/// Starts playing given section of the MIDI block
+pub async fn play_section(
+    State(app_state): State<Arc<AppState>>,
+    Path((uuid, section)): Path<(String, usize)>,
+) {
+    if let Err(error) = audio_engine::play_section(app_state, &uuid, section).await {
+        tracing::error!("failed to play section {section} of block#{uuid}: {error}");
+    }
+}
+
+/// Query of [interrupt] and [interrupt_block]
+#[derive(Deserialize)]
+pub struct Stop {
+    /// When blocks stop, mode chosen in the UI (see [set_stop_at]) if not given
+    #[serde(default)]
+    at: Option<audio_engine::StopAt>,
+}
+
+/// Interrupts all currently played blocks (or does nothing)
+pub async fn interrupt(State(app_state): State<Arc<AppState>>, Query(Stop { at }): Query<Stop>) {
+    let at = at.unwrap_or_else(|| *app_state.stop_at.read().unwrap());
+    if let Err(error) = audio_engine::stop(app_state, None, at).await {
+        tracing::error!("failed to interrupt: {error}");
+    }
+}
+
+/// Render select of the mode in which interrupted blocks stop
+fn stop_at_select(current: audio_engine::StopAt) -> Markup {
+    html! {
+        select
+            class="stop-at"
+            name="at"
+            title="When interrupted blocks stop"
+            hx-post="/stop-at"
+            hx-target="this"
+            hx-swap="outerHTML"
+        {
+            @for mode in audio_engine::StopAt::ALL {
+                option value=(mode.name()) selected[mode == current] {
+                    @match mode {
+                        audio_engine::StopAt::Now => "Stop now",
+                        audio_engine::StopAt::BarEnd => "Stop at bar end",
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Schema for setting when interrupted blocks stop
+#[derive(Deserialize)]
+pub struct SetStopAt {
+    /// Mode used by interrupts that don't choose their own
+    at: audio_engine::StopAt,
+}
+
+/// Set when interrupted blocks stop if the interrupt doesn't say
+pub async fn set_stop_at(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetStopAt { at }): Form<SetStopAt>,
+) -> Markup {
+    info!("interrupted blocks now stop at {}", at.name());
+    *app_state.stop_at.write().unwrap() = at;
+    stop_at_select(at)
+}
+
+/// Emergency stop of every instance in the network, see [audio_engine::panic]
+///
+/// Panic is broadcasted with [linky_groups], other instances stop when they receive it.
+pub async fn panic(State(app_state): State<Arc<AppState>>) {
+    if let Some(groups) = &app_state.groups {
+        groups.panic().await;
+    }
+    if let Err(error) = audio_engine::panic(app_state).await {
+        tracing::error!("failed to panic: {error}");
+    }
+}
+
+/// Stop playing given block, leaving other played blocks untouched
+pub async fn interrupt_block(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Query(Stop { at }): Query<Stop>,
+) {
+    let at = at.unwrap_or_else(|| *app_state.stop_at.read().unwrap());
+    if let Err(error) = audio_engine::stop(app_state, Some(&uuid), at).await {
+        tracing::error!("failed to interrupt block#{uuid}: {error}");
+    }
+}
+
+/// Schema for creation of new shared memory block
+#[derive(Deserialize)]
+pub struct AddSharedMemoryBlock {
+    /// Path to shared memory block
+    path: String,
+}
+
+/// Add new shared memory block and cache list of blocks
+pub async fn add_new_shered_memory_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(AddSharedMemoryBlock { path }): Form<AddSharedMemoryBlock>,
+) -> axum::response::Response {
+    let mut hasher = Sha1::new();
+    hasher.update(path.as_bytes());
+    let uuid = hex::encode(hasher.finalize());
+
+    let content = block::Content::SharedMemory { path, label: None };
+
+    let block = app_state.defaults.read().unwrap().block(content);
+
+    {
+        let blocks = &mut app_state.blocks.write().unwrap();
+        blocks.insert(format!("shm-{uuid}"), block);
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_shered_memory_block failed to remember current sources: {err:#}")
+    }
+
+    blocks_in_format(app_state, format).await
+}
+
+/// Whether playing given block runs a program: it's a command block or a scene starting one
+pub fn runs_program(blocks: &HashMap<String, block::Block>, uuid: &str) -> bool {
+    let is_command = |uuid: &str| {
+        matches!(
+            blocks.get(uuid).map(|block| &block.content),
+            Some(block::Content::Command { .. })
+        )
+    };
+    match blocks.get(uuid).map(|block| &block.content) {
+        Some(block::Content::Scene(scene)) => scene.blocks.iter().any(|member| is_command(member)),
+        _ => is_command(uuid),
+    }
+}
+
+/// Refuse playing blocks that run programs when asked by other computers
+///
+/// Command blocks run any program on this computer, so they are created and played only from this
+/// computer, regardless of `--token` and `--read-only-remote`.
+pub fn ensure_local_programs<'a>(
+    app_state: &AppState,
+    addr: SocketAddr,
+    uuids: impl IntoIterator<Item = &'a str>,
+) -> Result<(), (StatusCode, String)> {
+    if addr.ip().is_loopback() {
+        return Ok(());
+    }
+    let blocks = app_state.blocks.read().unwrap();
+    match uuids.into_iter().find(|uuid| runs_program(&blocks, uuid)) {
+        Some(uuid) => {
+            warn!("refused to run program of block#{uuid} for {addr}");
+            Err((
+                StatusCode::FORBIDDEN,
+                format!("block#{uuid} runs a program, it can be played only from this computer"),
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Schema for creation of new command block
+#[derive(Deserialize)]
+pub struct AddCommandBlock {
+    /// Program to execute
+    program: String,
+
+    /// Arguments for the program, passed as they are without going through the shell
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// Add new command block and cache list of blocks
+///
+/// Accepts JSON object with `program` and list of `args`. Only from this computer, see
+/// [ensure_local_programs].
+pub async fn add_new_command_block(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Json(AddCommandBlock { program, args }): Json<AddCommandBlock>,
+) -> axum::response::Response {
+    if !addr.ip().is_loopback() {
+        warn!("refused to create command block for {addr}");
+        return (
+            StatusCode::FORBIDDEN,
+            "Command blocks can be created only from this computer",
+        )
+            .into_response();
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(program.as_bytes());
+    for arg in &args {
+        hasher.update([0]);
+        hasher.update(arg.as_bytes());
+    }
+    let uuid = hex::encode(hasher.finalize());
+
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Command { program, args });
+
+    {
+        let blocks = &mut app_state.blocks.write().unwrap();
+        blocks.insert(format!("cmd-{uuid}"), block);
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_command_block failed to remember current sources: {err:#}")
+    }
+
+    blocks_in_format(app_state, format).await
+}
+
+/// Lowest tempo supported by Link
+pub const MIN_TEMPO: f64 = 20.0;
+
+/// Highest tempo supported by Link
+pub const MAX_TEMPO: f64 = 999.0;
+
+/// Schema for creation of new tempo block
+#[derive(Deserialize)]
+pub struct AddTempoBlock {
+    /// Tempo to set in beats per minute
+    bpm: f64,
+
+    /// Number of beats over which tempo changes, empty for instant change
+    #[serde(default)]
+    ramp_beats: String,
+}
+
+/// Add new tempo block and cache list of blocks
+pub async fn add_new_tempo_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(AddTempoBlock { bpm, ramp_beats }): Form<AddTempoBlock>,
+) -> axum::response::Response {
+    if !(MIN_TEMPO..=MAX_TEMPO).contains(&bpm) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("tempo must be between {MIN_TEMPO} and {MAX_TEMPO} BPM"),
+        )
+            .into_response();
+    }
+
+    let ramp_beats = ramp_beats.trim();
+    let ramp_beats = if ramp_beats.is_empty() {
+        0.0
+    } else {
+        match ramp_beats.parse::<f64>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => value,
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid ramp length {ramp_beats:?}"),
+                )
+                    .into_response()
+            }
+        }
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.update(bpm.to_le_bytes());
+    hasher.update(ramp_beats.to_le_bytes());
+    let uuid = format!("tempo-{}", hex::encode(hasher.finalize()));
+
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Tempo { bpm, ramp_beats });
+    app_state.blocks.write().unwrap().insert(uuid, block);
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_tempo_block failed to remember current sources: {err:#}")
+    }
+
+    blocks_in_format(app_state, format).await
+}
+
+/// Add new alias block referencing given block and cache list of blocks
+///
+/// Alias starts with the port of referenced block and without transposition.
+pub async fn add_new_alias_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Path(target): Path<String>,
+) -> axum::response::Response {
+    {
+        let mut blocks = app_state.blocks.write().unwrap();
+
+        let Some(referenced) = blocks.get(&target) else {
+            error!("block#{target} not found");
+            return StatusCode::NOT_FOUND.into_response();
+        };
+
+        if !block::Alias::can_reference(&referenced.content) {
+            error!("block#{target} cannot be referenced by alias");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+
+        let alias = block::Alias {
+            target: target.clone(),
+            name: referenced.content.name(),
+            associated_port: referenced.content.port().unwrap_or(MIN_PORT_NUMBER),
+            transpose: 0,
+        };
+
+        let mut hasher = Sha1::new();
+        hasher.update(target.as_bytes());
+        let prefix = format!("alias-{}", hex::encode(hasher.finalize()));
+        let uuid = (1..)
+            .map(|nth| format!("{prefix}-{nth}"))
+            .find(|uuid| !blocks.contains_key(uuid))
+            .expect("there is always free alias identifier");
+
+        info!("adding alias block#{uuid} of block#{target}");
+        blocks.insert(uuid, block::Block::new(block::Content::Alias(alias)));
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_alias_block failed to remember current sources: {err:#}")
     }
+
+    blocks_in_format(app_state, format).await
 }
 
-/// Schema for port selection for block containing MIDI
+/// Schema for creation of new silence block
 #[derive(Deserialize)]
-pub struct SetPort {
-    /// MIDI port to set for Midi block
-    pub port: usize,
+pub struct AddSilenceBlock {
+    /// Length of silence in beats
+    beats: f64,
 }
 
-/// Minimum MIDI port number
-///
-/// On windows it is 1 since we don't have virtual ports.
-/// On unix'es it's 0 since we have virtual ports.
-pub const MIN_PORT_NUMBER: usize = if cfg!(unix) { 0 } else { 1 };
+/// Add new silence block and cache list of blocks
+pub async fn add_new_silence_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(AddSilenceBlock { beats }): Form<AddSilenceBlock>,
+) -> axum::response::Response {
+    if !beats.is_finite() || beats <= 0.0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid silence length {beats}"),
+        )
+            .into_response();
+    }
 
-/// Set port for MIDI block
-pub async fn set_port_for_midi(
-    app_state: State<Arc<AppState>>,
-    Path(uuid): Path<String>,
-    Form(SetPort { port }): Form<SetPort>,
-) -> Result<Markup, StatusCode> {
-    let mut blocks = app_state.blocks.write().unwrap();
+    let mut hasher = Sha1::new();
+    hasher.update(beats.to_le_bytes());
+    let uuid = format!("silence-{}", hex::encode(hasher.finalize()));
 
-    let Some(block) = blocks.get_mut(&uuid) else {
-        error!("block#{uuid} was not found");
-        return Err(StatusCode::NOT_FOUND);
-    };
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Silence { beats });
+    app_state.blocks.write().unwrap().insert(uuid, block);
 
-    let block::Content::Midi(ref mut midi) = block.content else {
-        error!("block#{uuid} is not a MIDI source");
-        return Err(StatusCode::BAD_REQUEST);
-    };
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_silence_block failed to remember current sources: {err:#}")
+    }
 
-    let max = app_state.connection.read().unwrap().ports.len();
+    blocks_in_format(app_state, format).await
+}
 
-    #[allow(clippy::absurd_extreme_comparisons)]
-    if port < MIN_PORT_NUMBER || port > max {
-        error!("port number should be between {MIN_PORT_NUMBER} and {max}");
-        return Ok(port_cell(&uuid, midi.associated_port));
-    }
+/// Schema for creation of new metronome block
+#[derive(Deserialize)]
+pub struct AddMetronomeBlock {
+    /// MIDI channel, counted from 1
+    channel: u8,
 
-    info!("setting port {port} for {uuid}");
-    midi.associated_port = port;
-    Ok(port_cell(&uuid, midi.associated_port))
+    /// Key played on beats other than the first beat of the bar
+    note: u8,
+
+    /// Key played on the first beat of the bar
+    accent_note: u8,
 }
 
-/// Responds with content of block if block had any
-pub async fn download_block_content(
-    app_state: State<Arc<AppState>>,
-    Path(uuid): Path<String>,
-) -> Response<Full<Bytes>> {
-    let not_found = || {
-        let mut response = Response::new(Full::from("not found"));
-        *response.status_mut() = StatusCode::NOT_FOUND;
-        response
-            .headers_mut()
-            .insert(CONTENT_TYPE, "text/html".parse().unwrap());
-        response
-    };
+/// Add new metronome block and cache list of blocks
+pub async fn add_new_metronome_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(AddMetronomeBlock {
+        channel,
+        note,
+        accent_note,
+    }): Form<AddMetronomeBlock>,
+) -> axum::response::Response {
+    if !(1..=16).contains(&channel) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid channel {channel}"),
+        )
+            .into_response();
+    }
 
-    let blocks = app_state.blocks.read().unwrap();
-    let Some(block) = blocks.get(&uuid) else {
-        error!("block#{uuid} not found");
-        return not_found();
+    let metronome = block::Metronome {
+        channel: channel - 1,
+        note,
+        accent_note,
+        associated_port: MIN_PORT_NUMBER,
     };
+    if let Err(err) = metronome.validate() {
+        return (StatusCode::BAD_REQUEST, err).into_response();
+    }
 
-    match &block.content {
-        block::Content::SharedMemory { .. } => not_found(),
-        block::Content::Midi(midi_source) => {
-            // TODO: Unnesesary clone?
-            let mut response = Response::new(Full::from(midi_source.bytes.clone()));
-            let headers = &mut response.headers_mut();
-            headers.insert(
-                CONTENT_DISPOSITION,
-                format!("attachement; filename=\"{}\"", midi_source.file_name)
-                    .parse()
-                    .unwrap(),
-            );
-            headers.insert(CONTENT_TYPE, "audio/midi".parse().unwrap());
-            response
-        }
+    let mut hasher = Sha1::new();
+    hasher.update([metronome.channel, note, accent_note]);
+    let uuid = format!("metronome-{}", hex::encode(hasher.finalize()));
+
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Metronome(metronome));
+    app_state.blocks.write().unwrap().insert(uuid, block);
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_metronome_block failed to remember current sources: {err:#}")
     }
+
+    blocks_in_format(app_state, format).await
 }
 
-/// Removes block based on ID and caches currently held blocks
-pub async fn remove_block(app_state: State<Arc<AppState>>, Path(uuid): Path<String>) -> Markup {
-    {
-        let mut sources = app_state.blocks.write().unwrap();
-        sources.remove(&uuid);
+/// Schema for creation of new chord block
+#[derive(Deserialize)]
+pub struct AddChordBlock {
+    /// Text specification of the chord, see [crate::chord]
+    spec: String,
+}
+
+/// Add new chord block and cache list of blocks
+pub async fn add_new_chord_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(AddChordBlock { spec }): Form<AddChordBlock>,
+) -> axum::response::Response {
+    let spec = spec.trim().to_owned();
+    if let Err(err) = spec.parse::<crate::chord::Chord>() {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid chord {spec:?}: {err}"),
+        )
+            .into_response();
     }
+
+    let mut hasher = Sha1::new();
+    hasher.update(spec.as_bytes());
+    let uuid = format!("chord-{}", hex::encode(hasher.finalize()));
+
+    let block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Chord(block::ChordSource {
+            spec,
+            associated_port: MIN_PORT_NUMBER,
+        }));
+    app_state.blocks.write().unwrap().insert(uuid, block);
+
     if let Err(err) = app_state.remember_current_blocks() {
-        error!("remove_midi_source_handler failed to remember current sources: {err:#}")
+        error!("add_new_chord_block failed to remember current sources: {err:#}")
     }
 
-    blocks(app_state).await
+    blocks_in_format(app_state, format).await
 }
 
-/// Starts playing given block
-pub async fn play(State(app_state): State<Arc<AppState>>, Path(uuid): Path<String>) {
-    let _ = audio_engine::play(app_state.clone(), &uuid).await;
+/// Insert new MIDI block into [AppState] and return it's unique identifier
+///
+/// Identifier is derived from the content of the file, so uploading the same file twice results
+/// in the same block. Block is put into given group or the default one when group is not
+/// provided. Caller is responsible for caching current blocks.
+pub fn insert_midi_block(
+    app_state: &AppState,
+    file_name: String,
+    bytes: Vec<u8>,
+    group: Option<&str>,
+) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(&bytes);
+    let hash = hex::encode(hasher.finalize());
+    insert_hashed_midi_block(app_state, &hash, file_name, bytes.into(), group)
 }
 
-/// Interrupts any currently played block (or does nothing)
-pub async fn interrupt(State(app_state): State<Arc<AppState>>) {
-    if let Err(error) = audio_engine::interrupt(app_state).await {
-        tracing::error!("failed to interrupt: {error}");
+/// Insert new MIDI block like [insert_midi_block], given sha1 of the `bytes` as hex
+pub fn insert_hashed_midi_block(
+    app_state: &AppState,
+    hash: &str,
+    file_name: String,
+    bytes: storage::StoredBytes,
+    group: Option<&str>,
+) -> String {
+    let uuid = format!("midi-{hash}");
+
+    let midi_source = block::MidiSource::new(bytes, file_name, MIN_PORT_NUMBER);
+
+    let mut block = app_state
+        .defaults
+        .read()
+        .unwrap()
+        .block(block::Content::Midi(midi_source));
+    if let Some(group) = group {
+        block.group = limit_group_length(group);
     }
+
+    let midi_sources = &mut app_state.blocks.write().unwrap();
+    midi_sources.insert(uuid.clone(), block);
+    uuid
 }
 
-/// Schema for creation of new shared memory block
-#[derive(Deserialize)]
-pub struct AddSharedMemoryBlock {
-    /// Path to shared memory block
-    path: String,
+/// Is given file a zip archive, judging by it's name
+pub fn is_zip_archive(file_name: &str) -> bool {
+    std::path::Path::new(file_name)
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("zip"))
 }
 
-/// Add new shared memory block and cache list of blocks
-pub async fn add_new_shered_memory_block(
-    State(app_state): State<Arc<AppState>>,
-    Form(AddSharedMemoryBlock { path }): Form<AddSharedMemoryBlock>,
-) -> Markup {
-    let mut hasher = Sha1::new();
-    hasher.update(path.as_bytes());
-    let uuid = hex::encode(hasher.finalize());
+/// Is given file a MIDI file, judging by it's name
+fn is_midi_file(path: &std::path::Path) -> bool {
+    path.extension().map_or(false, |extension| {
+        extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+    })
+}
+
+/// Group name derived from the folders in which file was stored inside an archive
+///
+/// Folder names are joined with spaces and stripped of characters not allowed in group names.
+fn group_from_folders(path: &std::path::Path) -> String {
+    let folders: Vec<_> = path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.iter())
+        .map(|folder| folder.to_string_lossy())
+        .collect();
+
+    let mut group: String = folders
+        .join(" ")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == ' ')
+        .collect();
+    group.truncate(linky_groups::MAX_GROUP_ID_LENGTH);
+    group
+}
+
+/// Outcome of importing zip archive, see [insert_zip_archive]
+pub struct ZipImport {
+    /// Identifiers of blocks created from MIDI files in the archive
+    pub uuids: Vec<String>,
 
-    let content = block::Content::SharedMemory { path };
+    /// MIDI files that were skipped, with the reason
+    pub skipped: Vec<String>,
+}
+
+/// Largest number of entries in imported zip archive
+pub const MAX_ZIP_ENTRIES: usize = 10_000;
+
+/// Largest total size of MIDI files extracted from single zip archive in bytes
+///
+/// Small archive may extract into many files that are each within `--max-file-size`.
+pub const MAX_ZIP_EXTRACTED_SIZE: usize = 1024 * MIB;
+
+/// Insert MIDI block for each MIDI file inside zip archive, see [insert_zip_archive]
+///
+/// Extraction runs on the blocking thread pool, so it doesn't stall other requests.
+pub async fn import_zip_archive(
+    app_state: Arc<AppState>,
+    archive: impl std::io::Read + std::io::Seek + Send + 'static,
+    group: Option<String>,
+) -> anyhow::Result<ZipImport> {
+    tokio::task::spawn_blocking(move || insert_zip_archive(&app_state, archive, group.as_deref()))
+        .await
+        .context("importing zip archive")?
+}
+
+/// Insert MIDI block for each MIDI file inside zip archive
+///
+/// Files inside folders are put into group named after the folders, files at the top level into
+/// given group (or the default one). MIDI files that are too big or not valid are skipped and
+/// reported, archive without any MIDI file is an error. Archives with more than
+/// [MAX_ZIP_ENTRIES] entries or extracting to more than [MAX_ZIP_EXTRACTED_SIZE] bytes are
+/// rejected without creating any block. Caller is responsible for caching current blocks.
+pub fn insert_zip_archive(
+    app_state: &AppState,
+    archive: impl std::io::Read + std::io::Seek,
+    group: Option<&str>,
+) -> anyhow::Result<ZipImport> {
+    use std::io::Read;
+
+    let mut archive = zip::ZipArchive::new(archive).context("opening zip archive")?;
+    if archive.len() > MAX_ZIP_ENTRIES {
+        return Err(anyhow::anyhow!(
+            "zip archive has more than {MAX_ZIP_ENTRIES} entries"
+        ));
+    }
+    let too_much = || {
+        anyhow::anyhow!(
+            "zip archive extracts to more than {} MiB",
+            MAX_ZIP_EXTRACTED_SIZE / MIB
+        )
+    };
 
-    let block = block::Block {
-        content,
-        group: Default::default(),
-        keybind: Default::default(),
-        order: Default::default(),
+    let mut extracted = Vec::new();
+    let mut extracted_size = 0;
+    let mut skipped = Vec::new();
+    let mut skip = |path: &std::path::Path, reason: String| {
+        warn!("skipping {path:?} from zip archive, {reason}");
+        skipped.push(format!("{}: {reason}", path.display()));
     };
 
-    {
-        let blocks = &mut app_state.blocks.write().unwrap();
-        blocks.insert(format!("shm-{uuid}"), block);
+    for index in 0..archive.len() {
+        let mut file = archive
+            .by_index(index)
+            .with_context(|| format!("reading entry {index} of zip archive"))?;
+        let Some(path) = file.enclosed_name().map(std::path::Path::to_path_buf) else {
+            warn!("skipping zip entry with unsafe path {:?}", file.name());
+            continue;
+        };
+
+        // Archives created on macOS contain resource forks next to the actual files
+        if file.is_dir() || !is_midi_file(&path) || path.starts_with("__MACOSX") {
+            continue;
+        }
+
+        let too_big = format!("it's bigger than {} MiB", app_state.max_file_size / MIB);
+        if file.size() > app_state.max_file_size as u64 {
+            skip(&path, too_big);
+            continue;
+        }
+        let remaining = MAX_ZIP_EXTRACTED_SIZE - extracted_size;
+        if file.size() > remaining as u64 {
+            return Err(too_much());
+        }
+
+        // Declared size may lie, so extraction is limited as well
+        let limit = app_state.max_file_size.min(remaining);
+        let mut data = Vec::with_capacity(file.size() as usize);
+        (&mut file)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut data)
+            .with_context(|| format!("extracting {path:?} from zip archive"))?;
+        if data.len() > remaining {
+            return Err(too_much());
+        }
+        if data.len() > app_state.max_file_size {
+            skip(&path, too_big);
+            continue;
+        }
+        extracted_size += data.len();
+
+        if let Err(err) = midly::Smf::parse(&data) {
+            skip(&path, format!("it's not a valid MIDI file: {err}"));
+            continue;
+        }
+        extracted.push((path, data));
     }
 
-    if let Err(err) = app_state.remember_current_blocks() {
-        error!("add_new_shered_memory_block failed to remember current sources: {err:#}")
+    let mut uuids = Vec::new();
+    for (path, data) in extracted {
+        let file_name = path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "<unknown>".to_string());
+        let folders = group_from_folders(&path);
+        let group = if folders.is_empty() {
+            group
+        } else {
+            Some(folders.as_str())
+        };
+        let uuid = insert_midi_block(app_state, file_name, data, group);
+
+        info!("added block#{uuid} from {path:?} in zip archive");
+        uuids.push(uuid);
+    }
+
+    if uuids.is_empty() && skipped.is_empty() {
+        return Err(anyhow::anyhow!(
+            "zip archive doesn't contain any MIDI files"
+        ));
     }
+    Ok(ZipImport { uuids, skipped })
+}
+
+/// Query parameters of uploads creating new blocks
+#[derive(Deserialize)]
+pub struct Upload {
+    /// Group for created blocks, overriding the default one
+    pub group: Option<String>,
+}
+
+/// Number of bytes in MiB, unit of upload limits
+pub const MIB: usize = 1024 * 1024;
+
+/// Content types with which browsers send MIDI files and zip archives
+///
+/// Browsers that don't recognize the extension send `application/octet-stream`.
+const MIDI_CONTENT_TYPES: &[&str] = &[
+    "audio/midi",
+    "audio/mid",
+    "audio/x-midi",
+    "application/zip",
+    "application/x-zip-compressed",
+    "application/octet-stream",
+];
+
+/// Whether uploaded field has content type of a MIDI file or zip archive
+pub fn is_midi_content_type(content_type: Option<&str>) -> bool {
+    content_type.map_or(true, |content_type| {
+        MIDI_CONTENT_TYPES.contains(&content_type)
+    })
+}
 
-    blocks(axum::extract::State(app_state)).await
+/// Whether uploaded field has content type of an audio file
+fn is_audio_content_type(content_type: Option<&str>) -> bool {
+    content_type.map_or(true, |content_type| {
+        content_type.starts_with("audio/") || content_type == "application/octet-stream"
+    })
+}
+
+/// Rows describing uploads that failed, rendered above the blocks
+fn upload_errors(errors: &[String]) -> Markup {
+    html! {
+        @for error in errors {
+            section class="block upload-error" { (error) }
+        }
+    }
+}
+
+/// Respond to upload with current blocks, reporting failed files
+///
+/// JSON clients get `400 Bad Request` with one error per line, while blocks from files that
+/// uploaded correctly stay added.
+pub async fn uploaded_blocks(
+    app_state: Arc<AppState>,
+    format: Format,
+    errors: Vec<String>,
+) -> axum::response::Response {
+    if errors.is_empty() {
+        return blocks_in_format(app_state, format).await;
+    }
+    match format {
+        Format::Json => (StatusCode::BAD_REQUEST, errors.join("\n")).into_response(),
+        Format::Html => html! {
+            (upload_errors(&errors))
+            (blocks(State(app_state)).await)
+        }
+        .into_response(),
+    }
 }
 
 /// Adds new MIDI block(s) based on the provided files in HTML Form
 pub async fn add_new_midi_source_block(
     State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Query(Upload { group }): Query<Upload>,
     mut multipart: Multipart,
-) -> Markup {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        // TODO: Check that this is the name that we are expecting
-        let _name = field.name().unwrap().to_string();
+) -> axum::response::Response {
+    let mut errors = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                errors.push(format!("Upload failed: {err}"));
+                break;
+            }
+        };
         // TODO: Better default file name
         let file_name = field.file_name().unwrap_or("<unknown>").to_string();
-        let data = field.bytes().await.unwrap().to_vec();
-        let mut hasher = Sha1::new();
-        hasher.update(&data);
-        let uuid = hex::encode(hasher.finalize());
 
-        let midi_source = block::MidiSource {
-            bytes: data,
-            file_name: file_name.clone(),
-            associated_port: MIN_PORT_NUMBER,
-        };
+        if !is_midi_content_type(field.content_type()) {
+            errors.push(format!(
+                "{file_name:?} is not a MIDI file or zip archive ({})",
+                field.content_type().unwrap_or_default()
+            ));
+            continue;
+        }
 
-        let block = block::Block {
-            content: block::Content::Midi(midi_source),
-            group: Default::default(),
-            keybind: Default::default(),
-            order: Default::default(),
+        let upload = match upload::receive(&app_state, &mut field).await {
+            Ok(upload) => upload,
+            Err(err) => {
+                errors.push(format!("{file_name:?}: {err}"));
+                continue;
+            }
         };
 
-        let midi_sources = &mut app_state.blocks.write().unwrap();
-        midi_sources.insert(format!("midi-{uuid}"), block);
+        if is_zip_archive(&file_name) {
+            match upload
+                .import_zip_archive(app_state.clone(), group.clone())
+                .await
+            {
+                Ok(import) => errors.extend(
+                    import
+                        .skipped
+                        .into_iter()
+                        .map(|skipped| format!("{file_name:?}: skipped {skipped}")),
+                ),
+                Err(err) => {
+                    error!("failed to import {file_name:?}: {err:#}");
+                    errors.push(format!("{file_name:?}: {err:#}"));
+                }
+            }
+            continue;
+        }
+
+        let hash = upload.hash.clone();
+        if is_uploaded(&app_state, &format!("midi-{hash}"), &file_name) {
+            continue;
+        }
+        // Content that turns out invalid is removed from storage when the state is saved
+        let data = match upload.into_stored() {
+            Ok(data) => data,
+            Err(err) => {
+                errors.push(format!("{file_name:?}: failed to store upload: {err}"));
+                continue;
+            }
+        };
+        if let Err(err) = midly::Smf::parse(&data) {
+            errors.push(format!("{file_name:?} is not a valid MIDI file: {err}"));
+        } else {
+            insert_hashed_midi_block(&app_state, &hash, file_name, data, group.as_deref());
+        }
     }
 
     if let Err(err) = app_state.remember_current_blocks() {
         error!("add_new_midi_source_block failed to remember current sources: {err:#}")
     }
 
-    blocks(axum::extract::State(app_state)).await
+    uploaded_blocks(app_state, format, errors).await
+}
+
+/// Adds new sample block(s) based on the provided audio files in HTML Form
+pub async fn add_new_sample_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Query(Upload { group }): Query<Upload>,
+    multipart: Multipart,
+) -> axum::response::Response {
+    let errors = add_audio_blocks(
+        &app_state,
+        group,
+        multipart,
+        "sample",
+        block::Content::Sample,
+    )
+    .await;
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_sample_block failed to remember current sources: {err:#}")
+    }
+
+    uploaded_blocks(app_state, format, errors).await
+}
+
+/// Adds new audio block(s) based on the provided audio files in HTML Form
+pub async fn add_new_audio_block(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Query(Upload { group }): Query<Upload>,
+    multipart: Multipart,
+) -> axum::response::Response {
+    let errors =
+        add_audio_blocks(&app_state, group, multipart, "audio", block::Content::Audio).await;
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("add_new_audio_block failed to remember current sources: {err:#}")
+    }
+
+    uploaded_blocks(app_state, format, errors).await
+}
+
+/// Whether uploaded file already has a block with given identifier, which is then kept as it is
+fn is_uploaded(app_state: &AppState, uuid: &str, file_name: &str) -> bool {
+    let uploaded = app_state.blocks.read().unwrap().contains_key(uuid);
+    if uploaded {
+        info!("{file_name:?} is already uploaded as block#{uuid}");
+    }
+    uploaded
+}
+
+/// Create block for each uploaded audio file, identified by `prefix` and hash of the file
+///
+/// Returns errors of files that couldn't be uploaded.
+async fn add_audio_blocks(
+    app_state: &AppState,
+    group: Option<String>,
+    mut multipart: Multipart,
+    prefix: &str,
+    content: fn(block::AudioSource) -> block::Content,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                errors.push(format!("Upload failed: {err}"));
+                break;
+            }
+        };
+        let file_name = field.file_name().unwrap_or("<unknown>").to_string();
+
+        if !is_audio_content_type(field.content_type()) {
+            errors.push(format!(
+                "{file_name:?} is not an audio file ({})",
+                field.content_type().unwrap_or_default()
+            ));
+            continue;
+        }
+
+        let upload = match upload::receive(app_state, &mut field).await {
+            Ok(upload) => upload,
+            Err(err) => {
+                errors.push(format!("{file_name:?}: {err}"));
+                continue;
+            }
+        };
+
+        let uuid = format!("{prefix}-{}", upload.hash);
+        if is_uploaded(app_state, &uuid, &file_name) {
+            continue;
+        }
+        let bytes = match upload.into_stored() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                errors.push(format!("{file_name:?}: failed to store upload: {err}"));
+                continue;
+            }
+        };
+
+        let mut block = app_state
+            .defaults
+            .read()
+            .unwrap()
+            .block(content(block::AudioSource { bytes, file_name }));
+        if let Some(group) = &group {
+            block.group = limit_group_length(group);
+        }
+        app_state.blocks.write().unwrap().insert(uuid, block);
+    }
+    errors
 }
 
 /// Abort application on user's request
@@ -689,6 +3112,156 @@ pub async fn abort(
     headers
 }
 
+/// Responds with other Harmonia instances found in local network
+pub async fn peers(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+) -> axum::response::Response {
+    let peers = app_state.discovery.peers();
+    if format == Format::Json {
+        return Json(peers).into_response();
+    }
+
+    let markup = html! {
+        @if peers.is_empty() {
+            p { "No other instances found" }
+        } @else {
+            table class="roster" {
+                tr { th { "Nick" } th { "Group" } th { "Status" } th { "Address" } th { "Version" } }
+                @for peer in &peers {
+                    tr {
+                        td { a href=(peer.url()) target="_blank" { (peer.name) } }
+                        td { (peer.group) }
+                        td { @if peer.playing { "playing" } @else { "stopped" } }
+                        td { (peer.address) }
+                        td { (peer.version) }
+                        td {
+                            button
+                                hx-post="/pull/list"
+                                hx-vals=(format!("{{\"address\": \"{}\"}}", peer.url()))
+                                hx-target="#pull-list"
+                                hx-swap="innerHTML"
+                            { "Pull blocks" }
+                        }
+                    }
+                }
+            }
+        }
+    };
+    markup.into_response()
+}
+
+/// Render form setting group and port of newly created blocks
+fn defaults_form(defaults: &block::Defaults) -> Markup {
+    html! {
+        form id="defaults" hx-post="/defaults" hx-swap="outerHTML" {
+            input
+                type="text" value=(defaults.group)
+                pattern=(format!("(\\w| ){{0,{}}}", linky_groups::MAX_GROUP_ID_LENGTH))
+                maxlength=(linky_groups::MAX_GROUP_ID_LENGTH)
+                name="group"
+                placeholder="Group";
+            input
+                type="number" value=[defaults.port]
+                name="port"
+                min=(MIN_PORT_NUMBER)
+                placeholder="Port";
+            label {
+                input
+                    type="checkbox"
+                    name="upload_to_filtered_group"
+                    checked[defaults.upload_to_filtered_group];
+                " Apply current filter's group to uploads"
+            }
+            button { "Save defaults" }
+        }
+    }
+}
+
+/// Responds with defaults for new blocks
+pub async fn defaults(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+) -> axum::response::Response {
+    let defaults = app_state.defaults.read().unwrap().clone();
+    match format {
+        Format::Json => Json(defaults).into_response(),
+        Format::Html => defaults_form(&defaults).into_response(),
+    }
+}
+
+/// Schema for setting defaults for new blocks
+#[derive(Deserialize)]
+pub struct SetDefaults {
+    /// Group assigned to new blocks
+    #[serde(default)]
+    group: String,
+
+    /// Port assigned to new blocks, empty for minimal port
+    #[serde(default)]
+    port: String,
+
+    /// Checkbox putting uploads into group chosen in the filter bar, missing when unchecked
+    #[serde(default)]
+    upload_to_filtered_group: Option<String>,
+}
+
+/// Set group and port assigned to newly created blocks
+pub async fn set_defaults(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(SetDefaults {
+        group,
+        port,
+        upload_to_filtered_group,
+    }): Form<SetDefaults>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let port = port.trim();
+    let port = if port.is_empty() {
+        None
+    } else {
+        let max = app_state.midi_port_count();
+        match port.parse::<usize>() {
+            #[allow(clippy::absurd_extreme_comparisons)]
+            Ok(port) if port >= MIN_PORT_NUMBER && port <= max => Some(port),
+            _ => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("port number should be between {MIN_PORT_NUMBER} and {max}"),
+                ))
+            }
+        }
+    };
+
+    let defaults = block::Defaults {
+        group: limit_group_length(group.trim()),
+        port,
+        upload_to_filtered_group: upload_to_filtered_group.is_some(),
+    };
+    info!(
+        "setting defaults for new blocks to group {:?}, port {:?} and filtered group uploads {}",
+        defaults.group, defaults.port, defaults.upload_to_filtered_group
+    );
+    *app_state.defaults.write().unwrap() = defaults.clone();
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(crate::DEFAULTS_PATH);
+        match bson::to_vec(&defaults) {
+            Ok(bytes) => {
+                if let Err(err) = std::fs::write(&path, bytes) {
+                    warn!("failed to write defaults to {path:?}: {err}");
+                }
+            }
+            Err(err) => warn!("failed to serialize defaults: {err}"),
+        }
+    }
+
+    Ok(match format {
+        Format::Json => Json(defaults).into_response(),
+        Format::Html => defaults_form(&defaults).into_response(),
+    })
+}
+
 /// Payload to set a nick
 #[derive(Deserialize)]
 pub struct SetNick {
@@ -706,6 +3279,11 @@ pub async fn set_nick(app_state: State<Arc<AppState>>, Form(SetNick { nick }): F
     let nick = nick.trim();
     tracing::info!("setting nick to: {nick:?}");
     *nick_ref = nick.to_string();
+    app_state.discovery.set_name(nick);
+
+    if app_state.ephemeral {
+        return;
+    }
 
     let nick_full_path = cache_path().join(crate::NICK_PATH);
     if let Err(error) = std::fs::write(&nick_full_path, nick) {
@@ -17,17 +17,17 @@ use anyhow::Context;
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        ConnectInfo, State, WebSocketUpgrade,
+        ConnectInfo, DefaultBodyLimit, State, WebSocketUpgrade,
     },
     response::IntoResponse,
     routing::{delete, get, post, put},
     Router, TypedHeader,
 };
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use maud::html;
-use rusty_link::AblLink;
+use rusty_link::{AblLink, SessionState};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     io::BufReader,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
@@ -39,13 +39,54 @@ use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing::{error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
+mod access;
+mod api;
 mod audio_engine;
+mod audit;
 use audio_engine::AudioEngine;
 mod version;
 use version::Version;
+mod backup;
 mod block;
+mod channels;
+mod chord;
+mod clock;
+mod conductor;
+mod config;
+mod cues;
+mod diagnostics;
+mod download;
+mod drift;
 mod handlers;
+mod integrity;
+mod latency;
+mod logging;
+mod midi_clock;
+mod midi_input;
+mod mix;
+mod network;
+mod osc;
+#[cfg(feature = "soundfont")]
+mod preview;
+mod priority;
 mod public;
+mod pull;
+mod recorder;
+mod remote;
+mod service;
+mod setlist;
+mod storage;
+mod timeline;
+mod tls;
+mod trash;
+#[cfg(feature = "tray")]
+mod tray;
+mod update;
+mod upload;
+mod virtual_port;
+
+/// How long shutdown waits for open connections and for stopping playback, see [service]
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Filename under which Harmonia stores blocks, user info and other metadata
 const STATE_PATH: &str = "harmonia_state.bson";
@@ -53,7 +94,20 @@ const STATE_PATH: &str = "harmonia_state.bson";
 /// Filename under which Harmonia stores user's nick
 const NICK_PATH: &str = "harmonia_nick.txt";
 
+/// Filename under which Harmonia stores defaults for new blocks
+const DEFAULTS_PATH: &str = "harmonia_defaults.bson";
+
+/// Quantum used when nothing else was specified
+const DEFAULT_QUANTUM: f64 = 1.0;
+
+/// Longest fade out accepted by `--fade-out`, in milliseconds
+const MAX_FADE_OUT_MS: u64 = 10_000;
+
 /// All MIDI output connections that user may use
+///
+/// Clones share the connection to MIDI client and the virtual port, so a clone taken from
+/// [AppState::connection] can be used without holding the lock.
+#[derive(Clone)]
 pub struct MidiConnection {
     /// Connection to the MIDI Client
     pub conn: Arc<Mutex<midir::MidiOutput>>,
@@ -61,46 +115,176 @@ pub struct MidiConnection {
     /// Currently known [MidiOutputPort]s
     pub ports: Vec<midir::MidiOutputPort>,
 
-    /// Virtual port created by default on unix platforms
+    /// Virtual port created by default on unix platforms, and on Windows with
+    /// `windows-virtual-port` feature, see [virtual_port]
     ///
     /// On Linux it isn't necessary needed since it has default MIDI output port from operating
     /// system. On macOS it is required since by default there are no MIDI outputs to use.
-    #[cfg(unix)]
-    pub virtual_port: Arc<Mutex<midir::MidiOutputConnection>>,
+    #[cfg(any(unix, all(windows, feature = "windows-virtual-port")))]
+    pub virtual_port: Arc<Mutex<virtual_port::VirtualPort>>,
 }
 
-impl Default for MidiConnection {
-    fn default() -> Self {
-        let conn = midir::MidiOutput::new("Harmonia").expect("creating midi output connection");
+/// How often MIDI ports are checked for connected and disconnected devices, and initialization of
+/// MIDI is retried when it failed, see [watch_midi]
+const MIDI_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+impl MidiConnection {
+    /// Connect to MIDI system and create virtual port
+    ///
+    /// Fails when system doesn't provide MIDI, for example when CoreMIDI server isn't running.
+    pub fn new() -> anyhow::Result<Self> {
+        let conn = midir::MidiOutput::new("Harmonia")
+            .map_err(|err| anyhow::anyhow!("creating midi output connection: {err}"))?;
         let ports = conn.ports();
 
-        #[cfg(unix)]
-        let virtual_port = {
-            use midir::os::unix::VirtualOutput;
-            Arc::new(Mutex::new(
-                midir::MidiOutput::new("HarmoniaVirt")
-                    .expect("creating midi output connection")
-                    .create_virtual("Harmonia")
-                    .expect("creating virtual midi port: {}"),
-            ))
-        };
+        #[cfg(any(unix, all(windows, feature = "windows-virtual-port")))]
+        let virtual_port = Arc::new(Mutex::new(virtual_port::create()?));
 
-        Self {
+        Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
             ports,
-            #[cfg(unix)]
+            #[cfg(any(unix, all(windows, feature = "windows-virtual-port")))]
             virtual_port,
+        })
+    }
+
+    /// Copy of the connection with currently known [MidiOutputPort]s
+    pub fn refreshed(&self) -> Self {
+        Self {
+            ports: self.conn.lock().unwrap().ports(),
+            ..self.clone()
         }
     }
+
+    /// Names of currently known ports, in the order of port numbers
+    pub fn port_names(&self) -> Vec<String> {
+        let conn = self.conn.lock().unwrap();
+        self.ports
+            .iter()
+            .map(|port| conn.port_name(port).unwrap_or_default())
+            .collect()
+    }
 }
 
-impl MidiConnection {
-    /// Update list of currently known [MidiOutputPort]s
-    pub fn refresh(&mut self) {
-        self.ports = self.conn.lock().unwrap().ports();
+/// Update list of MIDI ports in [AppState::connection], or initialize MIDI when it's unavailable
+///
+/// Returns whether ports changed. Ports are listed without holding the lock, which is taken only to
+/// swap in the refreshed connection, so readers aren't blocked by the system call.
+pub fn refresh_midi_ports(app_state: &AppState) -> bool {
+    let current = app_state.connection.read().unwrap().clone();
+    let Ok(current) = current else {
+        return match MidiConnection::new() {
+            Ok(initialized) => {
+                info!("MIDI became available");
+                *app_state.connection.write().unwrap() = Ok(initialized);
+                true
+            }
+            Err(err) => {
+                *app_state.connection.write().unwrap() = Err(format!("{err:#}"));
+                false
+            }
+        };
+    };
+
+    let refreshed = current.refreshed();
+    let before = current.port_names();
+    let after = refreshed.port_names();
+    if before == after {
+        return false;
     }
+    for name in after.iter().filter(|name| !before.contains(name)) {
+        info!("MIDI output port {name:?} connected");
+    }
+    for name in before.iter().filter(|name| !after.contains(name)) {
+        warn!("MIDI output port {name:?} disconnected");
+    }
+    *app_state.connection.write().unwrap() = Ok(refreshed);
+    true
 }
 
+/// Watch MIDI ports every [MIDI_WATCH_INTERVAL], never returns
+///
+/// Connected and disconnected devices are announced to UIs with [Change::Ports]. While MIDI is
+/// unavailable it's initialized again instead, see [refresh_midi_ports].
+async fn watch_midi(app_state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(MIDI_WATCH_INTERVAL).await;
+
+        let refreshed_state = app_state.clone();
+        let changed =
+            tokio::task::spawn_blocking(move || refresh_midi_ports(&refreshed_state)).await;
+
+        match changed {
+            Ok(true) => app_state.notify(Change::Ports),
+            Ok(false) => {}
+            Err(err) => error!("watching MIDI ports failed: {err}"),
+        }
+    }
+}
+
+/// Stop everything when other instance broadcasts panic, see [handlers::panic]
+async fn listen_for_panics(app_state: Arc<AppState>) {
+    let Some(groups) = &app_state.groups else {
+        return;
+    };
+    let mut panics = groups.panics();
+    loop {
+        match panics.recv().await {
+            Ok(()) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                if let Err(err) = audio_engine::panic(app_state.clone()).await {
+                    error!("failed to stop after panic from the network: {err}");
+                }
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Keep status announced to other instances in sync with playback, never returns
+///
+/// Announced group is the group of a played block, or the default group when nothing plays.
+async fn announce_status(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(linky_groups::discovery::ANNOUNCE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let played = app_state
+            .currently_playing
+            .read()
+            .unwrap()
+            .keys()
+            .next()
+            .cloned();
+        let group = played
+            .as_ref()
+            .and_then(|uuid| {
+                app_state
+                    .blocks
+                    .read()
+                    .unwrap()
+                    .get(uuid)
+                    .map(|block| block.group.clone())
+            })
+            .unwrap_or_else(|| app_state.defaults.read().unwrap().group.clone());
+        app_state.discovery.set_status(&group, played.is_some());
+    }
+}
+
+/// Change of the state shown in UI, broadcasted to all open WebSockets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Block was added, removed or edited
+    Blocks,
+
+    /// Block started or stopped playing
+    Playback,
+
+    /// MIDI device was connected or disconnected
+    Ports,
+}
+
+/// Number of changes kept for WebSockets that didn't receive them yet
+const CHANGES_CAPACITY: usize = 64;
+
 /// Shared state between major modules of Harmonia
 ///
 /// Collection of references to particular state, each behind it's own synchronization mechanism to
@@ -115,8 +299,11 @@ pub struct AppState {
     /// [audio_engine]: crate::audio_engine
     pub blocks: RwLock<HashMap<String, block::Block>>,
 
-    /// List of all MIDI connections
-    pub connection: RwLock<MidiConnection>,
+    /// List of all MIDI connections, or the reason why MIDI is unavailable
+    ///
+    /// Everything that doesn't need MIDI keeps working without it, while
+    /// [retry_midi_initialization] tries to connect again.
+    pub connection: RwLock<Result<MidiConnection, String>>,
 
     /// Reference to [Ableton Link][rusty_link], base for synchronization mechanism.
     ///
@@ -126,18 +313,33 @@ pub struct AppState {
     /// [audio_engine] shared state that allows to send commands from UI to engine (and back)
     pub audio_engine: RwLock<AudioEngine>,
 
-    // TODO: Be better
-    /// Identifier of currently playing block, used in UI
-    pub currently_playing_uuid: RwLock<Option<String>>,
-
-    /// Progress on currently playing block in form `(done, len)`
+    /// Currently playing blocks with their musical progress, indexed by block identifier
     ///
-    /// For infinite blocks (like [block::Content::SharedMemory]) `(0, 0)`
-    pub current_playing_progress: RwLock<(usize, usize)>,
+    /// Several blocks can play at once, see [audio_engine]. For infinite blocks (like
+    /// [block::Content::SharedMemory]) progress is without length.
+    pub currently_playing: RwLock<BTreeMap<String, audio_engine::Progress>>,
+
+    /// Quantum used for synchronization with Link session
+    pub quantum: f64,
+
+    /// How blocks start when they don't override it, see `--launch` flag
+    pub launch: block::Launch,
+
+    /// When interrupted blocks stop if the request doesn't say, see `--stop-at` flag
+    pub stop_at: RwLock<audio_engine::StopAt>,
+
+    /// How long interrupted MIDI blocks fade out before their notes stop, see `--fade-out` flag
+    pub fade_out: Duration,
 
     /// Port on which to serve HTTP UI
     pub port: u16,
 
+    /// URL scheme of the UI, `https` when served with [tls]
+    pub scheme: &'static str,
+
+    /// Largest accepted uploaded file in bytes, see `--max-file-size`
+    pub max_file_size: usize,
+
     /// [linky_groups] synchronization mechanism
     pub groups: Option<linky_groups::Groups>,
 
@@ -148,16 +350,100 @@ pub struct AppState {
 
     /// Nick that helps users to identify each others
     pub nick: tokio::sync::RwLock<String>,
+
+    /// Recently received requests that modified the state, see [audit]
+    pub audit: Mutex<audit::Log>,
+
+    /// Recording of all MIDI messages sent in this session, see [recorder]
+    pub recorder: recorder::Recorder,
+
+    /// Timing accuracy of MIDI blocks by their identifiers, each since it was last started
+    pub timing: Mutex<HashMap<String, audio_engine::TimingStats>>,
+
+    /// Never read or write files, see `--ephemeral` flag
+    pub ephemeral: bool,
+
+    /// Whether MIDI playback thread runs with real-time [priority]
+    pub realtime_priority: bool,
+
+    /// Blocks removed by the user that can still be restored, see [trash]
+    pub trash: Mutex<trash::Trash>,
+
+    /// Group and port assigned to newly created blocks
+    pub defaults: RwLock<block::Defaults>,
+
+    /// Other Harmonia instances found in local network
+    pub discovery: linky_groups::discovery::Discovery,
+
+    /// Times of recent taps used to set tempo, see [api::tap]
+    pub taps: Mutex<Vec<std::time::Instant>>,
+
+    /// Transport events of the current session, see [timeline]
+    pub timeline: Mutex<timeline::Timeline>,
+
+    /// Source of time used by [audio_engine], Link clock unless simulated
+    pub clock: Arc<dyn clock::Clock>,
+
+    /// MIDI channels forwarded to ports, indexed by port name, see [channels]
+    pub port_channels: RwLock<HashMap<String, channels::ChannelFilter>>,
+
+    /// Muted and soloed channels of blocks, indexed by block identifier, see [mix]
+    pub mixes: RwLock<HashMap<String, mix::Mix>>,
+
+    /// Latency offsets of ports in milliseconds, indexed by port name, see [latency]
+    pub port_latencies: RwLock<HashMap<String, i32>>,
+
+    /// Names of ports receiving MIDI Beat Clock, see [midi_clock]
+    pub clock_ports: RwLock<HashSet<String>>,
+
+    /// Ordered lists of blocks indexed by their names, see [setlist]
+    pub setlists: RwLock<BTreeMap<String, setlist::Setlist>>,
+
+    /// Pending cues, earliest first, see [cues]
+    pub cues: Mutex<Vec<cues::Cue>>,
+
+    /// Block waiting for MIDI learn to assign it's trigger, see [midi_input]
+    pub midi_learn: Mutex<Option<String>>,
+
+    /// Changes of blocks and playback, pushed over WebSocket to the UI, see [AppState::notify]
+    pub changes: tokio::sync::broadcast::Sender<Change>,
+
+    /// Token required for changes requested from other computers, see [access]
+    pub token: Option<String>,
+
+    /// Whether other computers without token can only view, see [access]
+    pub read_only_remote: bool,
+
+    /// Whether UI shows [conductor] controls
+    pub conductor: bool,
+
+    /// Interfaces used for synchronization as chosen on the command line, see [network]
+    pub interface_filter: linky_groups::interfaces::InterfaceFilter,
+
+    /// Interfaces turned off for synchronization in the UI, see [network]
+    pub disabled_interfaces: RwLock<BTreeSet<String>>,
+
+    /// Latest release of Harmonia, when it was checked, see [update]
+    pub latest_release: RwLock<Option<update::Release>>,
+
+    /// SoundFont used to preview blocks in the browser, see [preview]
+    #[cfg(feature = "soundfont")]
+    pub soundfont: Option<Arc<rustysynth::SoundFont>>,
 }
 
+/// Directory given with `--state-dir` or in [config], used instead of the cache directory
+static STATE_DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
 /// Path to the cache location, based on OS convention
 ///
 /// Should conform to XDG_BASE_DIRECTORIES or any other particular operating system standard for
-/// cache storage.
+/// cache storage. Can be changed with `--state-dir`.
 fn cache_path() -> PathBuf {
-    let path = dirs::cache_dir()
-        .expect("documentation states that this function should work on all platforms")
-        .join("harmonia");
+    let path = STATE_DIR.get().cloned().unwrap_or_else(|| {
+        dirs::cache_dir()
+            .expect("documentation states that this function should work on all platforms")
+            .join("harmonia")
+    });
     std::fs::create_dir_all(&path).unwrap();
     path
 }
@@ -178,45 +464,230 @@ impl AppState {
         let link = Arc::new(AblLink::new(120.));
         link.enable(!cli.disable_link);
 
-        let nick = std::fs::read_to_string(cache_path().join(NICK_PATH)).unwrap_or_else(|_| {
-            let username = whoami::realname();
-            tracing::warn!("Failed to find a nick file, using username {username:?}");
-            username
+        let nick = if cli.ephemeral {
+            whoami::realname()
+        } else {
+            std::fs::read_to_string(cache_path().join(NICK_PATH)).unwrap_or_else(|_| {
+                let username = whoami::realname();
+                tracing::warn!("Failed to find a nick file, using username {username:?}");
+                username
+            })
+        };
+
+        let defaults = if cli.ephemeral {
+            Default::default()
+        } else {
+            std::fs::read(cache_path().join(DEFAULTS_PATH))
+                .ok()
+                .and_then(|bytes| bson::from_slice(&bytes).ok())
+                .unwrap_or_default()
+        };
+
+        let interface_filter = linky_groups::interfaces::InterfaceFilter {
+            allow: cli.interface.clone(),
+            deny: cli.exclude_interface.clone(),
+        };
+        let disabled_interfaces = if cli.ephemeral {
+            Default::default()
+        } else {
+            network::load()
+        };
+        let filter = network::filter(&interface_filter, &disabled_interfaces);
+        let session_key = cli.session_key.clone().or_else(|| {
+            if cli.ephemeral {
+                None
+            } else {
+                network::load_session_key()
+            }
         });
 
+        let discovery = linky_groups::discovery::announce(
+            link.is_enabled(),
+            cli.multicast,
+            &filter,
+            linky_groups::discovery::Instance {
+                name: nick.clone(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                http_port: cli.port,
+            },
+        );
+
+        let clock = link.clone();
+
+        #[cfg(feature = "soundfont")]
+        let soundfont = cli
+            .soundfont
+            .as_deref()
+            .and_then(|path| preview::load(path).map_err(|err| error!("{err:#}")).ok());
+
         Self {
             blocks: Default::default(),
-            connection: Default::default(),
+            connection: RwLock::new(MidiConnection::new().map_err(|err| {
+                error!("MIDI unavailable: {err:#}");
+                format!("{err:#}")
+            })),
             link: link.clone(),
             audio_engine: Default::default(),
-            currently_playing_uuid: Default::default(),
-            current_playing_progress: Default::default(),
+            currently_playing: Default::default(),
+            quantum: cli.quantum,
+            launch: cli.launch,
+            stop_at: RwLock::new(cli.stop_at),
+            fade_out: Duration::from_millis(cli.fade_out),
             port: cli.port,
-            groups: Some(linky_groups::listen(link)),
+            scheme: if cli.https() { "https" } else { "http" },
+            max_file_size: cli.max_file_size * handlers::MIB,
+            groups: Some(linky_groups::listen(
+                link,
+                cli.multicast,
+                &filter,
+                session_key.as_deref(),
+            )),
             abort: Default::default(),
             nick: tokio::sync::RwLock::new(nick),
+            audit: Default::default(),
+            recorder: recorder::Recorder::new(cli.record, !cli.ephemeral),
+            timing: Default::default(),
+            ephemeral: cli.ephemeral,
+            realtime_priority: !cli.disable_realtime_priority,
+            trash: Default::default(),
+            defaults: RwLock::new(defaults),
+            discovery,
+            taps: Default::default(),
+            timeline: Default::default(),
+            clock,
+            port_channels: RwLock::new(if cli.ephemeral {
+                Default::default()
+            } else {
+                channels::load()
+            }),
+            mixes: Default::default(),
+            port_latencies: RwLock::new(if cli.ephemeral {
+                Default::default()
+            } else {
+                latency::load()
+            }),
+            clock_ports: RwLock::new(if cli.ephemeral {
+                Default::default()
+            } else {
+                midi_clock::load()
+            }),
+            setlists: RwLock::new(if cli.ephemeral {
+                Default::default()
+            } else {
+                setlist::load()
+            }),
+            cues: Default::default(),
+            midi_learn: Default::default(),
+            changes: tokio::sync::broadcast::channel(CHANGES_CAPACITY).0,
+            token: cli.token.clone(),
+            read_only_remote: cli.read_only_remote,
+            conductor: cli.conductor,
+            interface_filter,
+            disabled_interfaces: RwLock::new(disabled_interfaces),
+            latest_release: RwLock::new(None),
+            #[cfg(feature = "soundfont")]
+            soundfont,
         }
     }
 
+    /// Whether blocks can be previewed in the browser, see [preview]
+    pub fn can_preview(&self) -> bool {
+        #[cfg(feature = "soundfont")]
+        return self.soundfont.is_some();
+        #[cfg(not(feature = "soundfont"))]
+        return false;
+    }
+
     /// Load stored [AppState] from [STATE_PATH]
     fn recollect_previous_blocks(&self) -> Result<(), anyhow::Error> {
         let path = cache_path().join(STATE_PATH);
         let file = std::fs::File::open(path).context("opening state file")?;
 
-        let new_sources: HashMap<String, block::Block> =
+        let mut new_sources: HashMap<String, block::Block> =
             bson::from_reader(BufReader::new(file)).context("reading bson file")?;
-        let mut sources = self.blocks.write().unwrap();
-        sources.extend(new_sources);
+
+        // State files from older versions don't have metadata of MIDI files, so it's derived once
+        // here instead of on every render
+        for block in new_sources.values_mut() {
+            if let block::Content::Midi(source) = &mut block.content {
+                if source.metadata.is_none() {
+                    let metadata = source
+                        .midi()
+                        .ok()
+                        .map(|midi| block::MidiMetadata::analyze(&midi));
+                    source.metadata = metadata;
+                }
+            }
+        }
+
+        self.blocks.write().unwrap().extend(new_sources);
+
+        // Trash is optional, it's missing when nothing was removed yet
+        let trash_path = cache_path().join(trash::TRASH_PATH);
+        if let Ok(file) = std::fs::File::open(trash_path) {
+            let mut trash: trash::Trash =
+                bson::from_reader(BufReader::new(file)).context("reading trash bson file")?;
+            trash.purge_expired();
+            *self.trash.lock().unwrap() = trash;
+        }
+
+        // State files from older versions contain content of blocks, which is moved to separate
+        // files right away
+        let inline_content = {
+            let blocks = self.blocks.read().unwrap();
+            let trash = self.trash.lock().unwrap();
+            let inline_content = blocks
+                .values()
+                .chain(trash.blocks())
+                .filter_map(|block| storage::of(&block.content))
+                .any(storage::StoredBytes::is_loaded);
+            inline_content
+        };
+        if inline_content {
+            info!("moving content of blocks out of the state file");
+            self.remember_current_blocks()
+                .context("moving content of blocks")?;
+        }
 
         Ok(())
     }
 
-    /// Store [AppState] in [STATE_PATH]
+    /// Tell all open UIs about the change
+    pub fn notify(&self, change: Change) {
+        // Fails only when no UI is open, so nobody needs to know
+        let _ = self.changes.send(change);
+    }
+
+    /// Number of MIDI output ports reported by the system, 0 when MIDI is unavailable
+    pub fn midi_port_count(&self) -> usize {
+        self.connection
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |connection| connection.ports.len())
+    }
+
+    /// Store [AppState] in [STATE_PATH], unless running in ephemeral mode
+    ///
+    /// Called after every change of blocks, so it also [notifies][AppState::notify] UIs about it.
     fn remember_current_blocks(&self) -> Result<(), anyhow::Error> {
+        self.notify(Change::Blocks);
+        if self.ephemeral {
+            return Ok(());
+        }
+
         let sources = self.blocks.read().unwrap();
+        let trash = self.trash.lock().unwrap();
+        // Content is written before the state referencing it
+        storage::persist(sources.values().chain(trash.blocks()));
+
         let path = cache_path().join(STATE_PATH);
         std::fs::write(path, bson::to_vec(&*sources).context("sources to vec")?)
             .context("saving sources to file")?;
+
+        let path = cache_path().join(trash::TRASH_PATH);
+        std::fs::write(path, bson::to_vec(&*trash).context("trash to vec")?)
+            .context("saving trash to file")?;
         Ok(())
     }
 }
@@ -244,14 +715,169 @@ struct Cli {
     /// Disable colors. Overwrites NO_COLOR environment variable
     #[arg(long = "no-color", default_value_t = false)]
     disable_colors: bool,
+
+    /// Format of log files, JSON lines are easier to aggregate from many computers
+    #[arg(long, value_enum, default_value_t = logging::LogFormat::Text)]
+    log_format: logging::LogFormat,
+
+    /// Record all sent MIDI messages into a MIDI file stored in cache
+    #[arg(long)]
+    record: bool,
+
+    /// Don't read or write any files (blocks, nick, logs, recordings), keep everything in memory
+    #[arg(long)]
+    ephemeral: bool,
+
+    /// How blocks start: `immediate` realigns Link timeline to the block, `quantized` waits for
+    /// the next bar of already running session
+    #[arg(long, default_value_t = block::Launch::Immediate)]
+    launch: block::Launch,
+
+    /// When interrupted blocks stop: `now` cuts them right away, `bar-end` lets them play until
+    /// the next bar of Link session. Can be changed in the UI
+    #[arg(long, value_enum, default_value_t = audio_engine::StopAt::Now)]
+    stop_at: audio_engine::StopAt,
+
+    /// Milliseconds over which interrupted MIDI blocks lower Expression (CC 11) to zero before
+    /// their notes stop, giving less jarring stop of sustained textures. 0 stops right away
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u64).range(..=MAX_FADE_OUT_MS))]
+    fade_out: u64,
+
+    /// UDP port on which to listen for OSC messages controlling playback
+    #[arg(long)]
+    osc_port: Option<u16>,
+
+    /// Don't listen on MIDI input ports for block triggers
+    #[arg(long)]
+    disable_midi_input: bool,
+
+    /// Don't raise priority of MIDI playback thread to real-time, see `priority` module
+    #[arg(long)]
+    disable_realtime_priority: bool,
+
+    /// Token required for changes requested from other computers, see `access` module
+    #[arg(long, env = "HARMONIA_TOKEN", value_parser = access::parse_token)]
+    token: Option<String>,
+
+    /// Let other computers only view blocks and playback, unless they have `--token`
+    #[arg(long)]
+    read_only_remote: bool,
+
+    /// Multicast address (`ip:port`) used to synchronize groups, instances synchronize only with
+    /// others using the same address. Discovery of other instances uses the next port.
+    #[arg(long, default_value_t = linky_groups::DEFAULT_MULTICAST.into(), value_parser = linky_groups::parse_multicast)]
+    multicast: SocketAddr,
+
+    /// Synchronize only over network interfaces matching the rule, either interface name (like
+    /// `eth0`) or network (like `192.168.0.0/24`). Can be repeated, all interfaces are used by default.
+    #[arg(long, value_name = "RULE", value_parser = linky_groups::interfaces::InterfaceRule::parse)]
+    interface: Vec<linky_groups::interfaces::InterfaceRule>,
+
+    /// Never synchronize over network interfaces matching the rule, like `--interface`
+    #[arg(long, value_name = "RULE", value_parser = linky_groups::interfaces::InterfaceRule::parse)]
+    exclude_interface: Vec<linky_groups::interfaces::InterfaceRule>,
+
+    /// Pre-shared key authenticating synchronization frames, instances with the key ignore frames
+    /// from instances without it. Overrides key set in the UI, see `network` module
+    #[arg(long, env = "HARMONIA_SESSION_KEY")]
+    session_key: Option<String>,
+
+    /// Show controls starting and stopping groups on chosen peers, see `conductor` module
+    #[arg(long)]
+    conductor: bool,
+
+    /// Serve UI over HTTPS with self-signed certificate generated on the first run
+    #[arg(long)]
+    tls: bool,
+
+    /// Certificate (PEM) with which to serve UI over HTTPS
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of the certificate given with `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Largest accepted request body in MiB, limits uploads of many files at once
+    #[arg(long, default_value_t = 256)]
+    max_upload_size: usize,
+
+    /// Largest accepted uploaded file in MiB, also applied to files extracted from zip archives
+    #[arg(long, default_value_t = 64)]
+    max_file_size: usize,
+
+    /// SoundFont (.sf2) used to preview MIDI blocks in the browser
+    #[cfg(feature = "soundfont")]
+    #[arg(long)]
+    soundfont: Option<PathBuf>,
+
+    /// Quantum of Link session used by blocks without their own
+    #[arg(long, default_value_t = DEFAULT_QUANTUM, value_parser = parse_quantum)]
+    quantum: f64,
+
+    /// Directory in which blocks, logs and other state is stored, instead of the cache directory
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
+    /// Configuration file read instead of `harmonia.toml` from configuration or cache directory,
+    /// see `config` module
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Check GitHub once a day for newer releases of Harmonia, see `update` module
+    #[arg(long)]
+    update_check: bool,
+
+    /// Show icon in system tray (menu bar on macOS) with basic controls, see `tray` module
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    tray: bool,
+
+    /// Control already running instance instead of starting a new one, see `remote` module
+    #[command(subcommand)]
+    command: Option<remote::Command>,
+}
+
+/// Parse quantum given on the command line, it has to be a positive number
+fn parse_quantum(quantum: &str) -> Result<f64, String> {
+    match quantum.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(format!(
+            "quantum must be a positive number, not {quantum:?}"
+        )),
+    }
+}
+
+impl Cli {
+    /// Whether UI is served over HTTPS, see [tls]
+    fn https(&self) -> bool {
+        self.tls || self.tls_cert.is_some()
+    }
 }
 
 /// Initialize Harmonia logging system
 ///
-/// Harmonia logs all the events inside log files, each file timestamped by day.
-fn setup_logging_system(cli: &Cli) -> tracing_appender::non_blocking::WorkerGuard {
-    let log_file_appender = tracing_appender::rolling::daily(log_path(), "logs");
-    let (log_file_appender, guard) = tracing_appender::non_blocking(log_file_appender);
+/// Harmonia logs all the events inside log files, each file timestamped by day, as text or JSON
+/// lines (see `--log-format`). In ephemeral mode logs are only printed.
+fn setup_logging_system(cli: &Cli) -> Option<tracing_appender::non_blocking::WorkerGuard> {
+    let (log_file_layer, guard) = if cli.ephemeral {
+        (None, None)
+    } else {
+        let log_file_appender =
+            tracing_appender::rolling::daily(log_path(), logging::LOG_FILE_PREFIX);
+        let (log_file_appender, guard) = tracing_appender::non_blocking(log_file_appender);
+        let layer = match cli.log_format {
+            logging::LogFormat::Text => tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(log_file_appender)
+                .boxed(),
+            logging::LogFormat::Json => tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(log_file_appender)
+                .boxed(),
+        };
+        (Some(layer), Some(guard))
+    };
 
     // https://no-color.org/
     let disable_colors = cli.disable_colors
@@ -259,20 +885,18 @@ fn setup_logging_system(cli: &Cli) -> tracing_appender::non_blocking::WorkerGuar
             .map(|x| !x.is_empty())
             .unwrap_or(false);
 
+    // Filter can be changed at runtime, see [logging]
+    let initial_filter = logging::initial_filter();
+    let (filter, filter_handle) =
+        tracing_subscriber::reload::Layer::new(tracing_subscriber::EnvFilter::new(&initial_filter));
+    logging::install(filter_handle, initial_filter);
+
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                "harmonia=info,linky_groups=info,linky_groups::net=info".into()
-            }),
-        )
+        .with(filter)
         .with(
             tracing_subscriber::fmt::layer()
                 .with_ansi(!disable_colors)
-                .and_then(
-                    tracing_subscriber::fmt::layer()
-                        .with_ansi(false)
-                        .with_writer(log_file_appender),
-                ),
+                .and_then(log_file_layer),
         )
         .init();
     guard
@@ -315,6 +939,62 @@ fn os_specific_initialization() {
     }
 }
 
+/// Parse command line and configuration, then serve Harmonia or control running instance
+///
+/// With `--tray` main thread belongs to the [tray] and Harmonia is served on another thread.
+fn main() -> ExitCode {
+    os_specific_initialization();
+
+    let matches = Cli::command().get_matches();
+    let mut cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(err) => err.exit(),
+    };
+    // Logging depends on the configuration, so errors can only be printed
+    let config = match config::load(cli.config.as_ref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let config_path = match config {
+        Some((path, config)) => match config.apply(&mut cli, &matches) {
+            Ok(()) => Some(path),
+            Err(err) => {
+                eprintln!("invalid configuration {path:?}: {err}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to start tokio runtime");
+
+    if let Some(command) = cli.command.take() {
+        return runtime.block_on(remote::run(command, &cli));
+    }
+    if let Some(state_dir) = &cli.state_dir {
+        STATE_DIR.set(state_dir.clone()).unwrap();
+    }
+
+    #[cfg(feature = "tray")]
+    if cli.tray {
+        return tray::run(move |commands| {
+            runtime.block_on(serve(cli, config_path, Some(commands)))
+        });
+    }
+
+    #[cfg(feature = "tray")]
+    let serving = serve(cli, config_path, None);
+    #[cfg(not(feature = "tray"))]
+    let serving = serve(cli, config_path);
+    runtime.block_on(serving)
+}
+
 /// Initialize Harmonia application
 ///
 /// Setup all synchronization mechanisms, HTTP server, recollect stored [AppState] from [cache],
@@ -323,17 +1003,22 @@ fn os_specific_initialization() {
 ///
 /// [cache]: cache_path()
 /// [logs]: setup_logging_system()
-#[tokio::main]
-async fn main() -> ExitCode {
-    os_specific_initialization();
-
-    let cli = Cli::parse();
+async fn serve(
+    cli: Cli,
+    config_path: Option<PathBuf>,
+    #[cfg(feature = "tray")] tray_commands: Option<tray::Commands>,
+) -> ExitCode {
     let _guard = setup_logging_system(&cli);
 
     info!("starting up version {}", Version::default());
+    if let Some(path) = config_path {
+        info!("read configuration from {path:?}");
+    }
 
     let app_state = Arc::new(AppState::new(&cli));
-    if let Err(err) = app_state.recollect_previous_blocks() {
+    if cli.ephemeral {
+        info!("running in ephemeral mode, nothing will be read from or written to disk");
+    } else if let Err(err) = app_state.recollect_previous_blocks() {
         warn!("trying to recollect previous sources: {err:#}")
     } else {
         info!(
@@ -342,7 +1027,32 @@ async fn main() -> ExitCode {
         )
     }
 
+    if !cli.ephemeral {
+        upload::remove_leftovers();
+    }
+
     app_state.audio_engine.write().unwrap().state = Arc::downgrade(&app_state);
+    tokio::spawn(integrity::verify_periodically(app_state.clone()));
+    if cli.update_check {
+        tokio::spawn(update::check_periodically(app_state.clone()));
+    }
+    tokio::spawn(audio_engine::watchdog(app_state.clone()));
+    tokio::spawn(cues::run(app_state.clone()));
+    tokio::spawn(watch_midi(app_state.clone()));
+    tokio::spawn(announce_status(app_state.clone()));
+    tokio::spawn(listen_for_panics(app_state.clone()));
+    {
+        let app_state = app_state.clone();
+        std::thread::spawn(move || midi_clock::run(app_state));
+    }
+    if !cli.disable_midi_input {
+        let app_state = app_state.clone();
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::spawn(move || midi_input::run(app_state, runtime));
+    }
+    if let Some(osc_port) = cli.osc_port {
+        tokio::spawn(osc::listen(app_state.clone(), cli.ip.clone(), osc_port));
+    }
     info!(
         "link {}",
         if cli.disable_link {
@@ -360,28 +1070,168 @@ async fn main() -> ExitCode {
             "/api/link-status-websocket",
             get(link_status_websocket_handler),
         )
+        .route(
+            "/api/blocks",
+            get(api::blocks).put(api::add_new_midi_source_block),
+        )
+        .route(
+            "/api/blocks/:uuid",
+            get(api::block)
+                .patch(api::update_block)
+                .delete(api::remove_block),
+        )
+        .route("/api/blocks/midi", put(api::add_new_midi_source_block))
+        .route("/blocks", get(handlers::search_blocks))
+        .route("/blocks/reorder", post(handlers::reorder))
+        .route("/api/status", get(api::status))
+        .route("/api/blocks/play-and-wait/:uuid", post(api::play_and_wait))
+        .route("/api/audit", get(audit::audit))
+        .route("/api/sequence", post(api::play_sequence))
+        .route("/api/setlists", get(setlist::setlists))
+        .route(
+            "/api/setlists/:name",
+            put(setlist::set_setlist).delete(setlist::remove_setlist),
+        )
+        .route("/api/setlists/:name/play", post(setlist::play_setlist))
+        .route(
+            "/api/cues",
+            get(cues::cues)
+                .post(cues::add_cues)
+                .delete(cues::clear_cues),
+        )
+        .route("/api/tap", post(api::tap))
+        .route("/api/timeline", get(timeline::timeline))
+        .route("/api/timeline/csv", get(timeline::timeline_csv))
+        .route("/api/blocks/pattern", put(api::add_new_pattern_block))
+        .route("/api/blocks/scene", put(api::add_new_scene_block))
+        .route(
+            "/api/blocks/sections/:uuid",
+            get(api::sections).put(api::set_sections),
+        )
+        .route("/api/blocks/pattern/:uuid", put(api::set_pattern))
+        .route(
+            "/api/blocks/pattern/:uuid/step",
+            post(api::set_pattern_step),
+        )
         .route("/blocks/midi", put(handlers::add_new_midi_source_block))
+        .route("/blocks/midi/url", post(download::import))
         .route(
             "/blocks/shared_memory",
             put(handlers::add_new_shered_memory_block),
         )
+        .route("/blocks/command", put(handlers::add_new_command_block))
+        .route("/blocks/sample", put(handlers::add_new_sample_block))
+        .route("/blocks/audio", put(handlers::add_new_audio_block))
+        .route("/blocks/tempo", put(handlers::add_new_tempo_block))
+        .route("/blocks/silence", put(handlers::add_new_silence_block))
+        .route("/blocks/metronome", put(handlers::add_new_metronome_block))
+        .route("/blocks/chord", put(handlers::add_new_chord_block))
+        .route("/blocks/alias/:uuid", put(handlers::add_new_alias_block))
         .route("/blocks/:uuid", delete(handlers::remove_block))
         .route("/blocks/:uuid", get(handlers::download_block_content))
         .route("/blocks/play/:uuid", post(handlers::play))
+        .route(
+            "/blocks/play-section/:uuid/:section",
+            post(handlers::play_section),
+        )
         .route(
             "/blocks/midi/set-port/:uuid",
             post(handlers::set_port_for_midi),
         )
+        .route("/recording", get(handlers::download_recording))
+        .route("/recordings", get(recorder::recordings))
+        .route("/recordings/:name", get(recorder::download))
+        .route("/state/export", get(backup::export))
+        .route("/state/import", post(backup::import))
+        .route("/trash", get(trash::trash).delete(trash::purge))
+        .route("/trash/restore/:uuid", post(trash::restore))
+        .route("/midi/test/:port", post(handlers::test_port))
+        .route("/midi/channels", post(channels::set_channels))
+        .route("/midi/latency", post(latency::set_latency))
+        .route("/midi/ports", get(handlers::midi_ports))
+        .route("/pull", post(pull::pull))
+        .route("/pull/list", post(pull::list))
+        .route(
+            "/conductor",
+            get(conductor::render).post(conductor::command),
+        )
+        .route("/api/groups/:group/play", post(conductor::play_group))
+        .route(
+            "/api/groups/:group/interrupt",
+            post(conductor::interrupt_group),
+        )
+        .route("/midi/clock", post(midi_clock::set_clock))
+        .route(
+            "/defaults",
+            get(handlers::defaults).post(handlers::set_defaults),
+        )
+        .route("/peers", get(handlers::peers))
+        .route("/clocks", get(drift::clocks))
+        .route(
+            "/network/interfaces",
+            get(network::render).post(network::set_interface),
+        )
+        .route(
+            "/network/session-key",
+            get(network::session_key).post(network::set_session_key),
+        )
         .route("/nick", post(handlers::set_nick))
         .route("/nick", get(handlers::nick))
         .route("/blocks/set-group/:uuid", post(handlers::set_group))
         .route("/blocks/set-keybind/:uuid", post(handlers::set_keybind))
+        .route(
+            "/blocks/set-description/:uuid",
+            post(handlers::set_description),
+        )
+        .route("/blocks/set-tags/:uuid", post(handlers::set_tags))
+        .route("/blocks/rename/:uuid", post(handlers::rename))
+        .route("/blocks/set-color/:uuid", post(handlers::set_color))
+        .route("/blocks/set-quantum/:uuid", post(handlers::set_quantum))
+        .route("/blocks/toggle-loop/:uuid", post(handlers::toggle_loop))
+        .route("/blocks/set-launch/:uuid", post(handlers::set_launch))
+        .route("/blocks/set-count-in/:uuid", post(handlers::set_count_in))
+        .route("/blocks/set-trim/:uuid", post(handlers::set_trim))
+        .route(
+            "/blocks/midi-learn/:uuid",
+            get(handlers::midi_learn_state)
+                .post(handlers::start_midi_learn)
+                .delete(handlers::remove_midi_trigger),
+        )
+        .route("/blocks/set-velocity/:uuid", post(handlers::set_velocity))
+        .route("/blocks/set-transpose/:uuid", post(handlers::set_transpose))
+        .route("/blocks/mix/:uuid", post(mix::toggle).delete(mix::clear))
         .route("/interrupt", post(handlers::interrupt))
+        .route("/panic", post(handlers::panic))
+        .route("/interrupt/:uuid", post(handlers::interrupt_block))
+        .route("/stop-at", post(handlers::set_stop_at))
         .route("/abort", post(handlers::abort))
+        .route("/debug/bundle", get(diagnostics::bundle))
+        .route("/logs", get(logging::viewer))
+        .route("/logs/websocket", get(logging::websocket))
+        .route(
+            "/logs/filter",
+            get(logging::render).post(logging::set_filter),
+        )
         .route("/", get(handlers::index))
         .route("/htmx.min.js", public::static_response!(get, "htmx.min.js"))
         .route("/index.js", public::static_response!(get, "index.js"))
-        .route("/index.css", public::static_response!(get, "index.css"))
+        .route("/logs.js", public::static_response!(get, "logs.js"))
+        .route("/index.css", public::static_response!(get, "index.css"));
+
+    #[cfg(feature = "soundfont")]
+    let app = app.route("/blocks/preview/:uuid", get(preview::preview));
+
+    let app = app
+        .route(access::LOGIN_PATH, post(access::login))
+        .layer(DefaultBodyLimit::max(cli.max_upload_size * handlers::MIB))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            access::restrict_changes,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            audit::record,
+        ))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
@@ -392,21 +1242,31 @@ async fn main() -> ExitCode {
 
     let addr = SocketAddr::from((ip, cli.port));
 
-    let Ok(builder) = axum::Server::try_bind(&addr) else {
-        error!("Address already in use at http://{addr}");
-        return ExitCode::FAILURE;
+    let tls_config = match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::load(cert, key).await),
+        _ if cli.tls => Some(tls::self_signed(cli.ephemeral).await),
+        _ => None,
+    };
+    let tls_config = match tls_config.transpose() {
+        Ok(tls_config) => tls_config,
+        Err(err) => {
+            error!("{err:#}");
+            return ExitCode::FAILURE;
+        }
     };
 
+    let scheme = app_state.scheme;
     let display_address = if addr.ip().is_unspecified() {
         SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), addr.port())
     } else {
         addr
     };
 
-    info!("Listening on http://{display_address}");
-    let server = builder
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .with_graceful_shutdown(async {
+    // Fired when shutdown starts, so waiting for open connections can be bounded
+    let (stopping, stopped) = tokio::sync::oneshot::channel();
+    let shutdown = {
+        let app_state = app_state.clone();
+        async move {
             let ctrl_c = async {
                 tokio::signal::ctrl_c()
                     .await
@@ -432,17 +1292,89 @@ async fn main() -> ExitCode {
                 _ = terminate => {},
                 _ = user_requested_abort => {},
             }
-        });
+            info!("shutting down");
+            service::stopping();
+            let _ = stopping.send(());
+        }
+    };
+
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let server: std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<()>>>> =
+        if let Some(tls_config) = tls_config {
+            let handle = axum_server::Handle::new();
+            {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    shutdown.await;
+                    handle.graceful_shutdown(None);
+                });
+            }
+            Box::pin(
+                axum_server::bind_rustls(addr, tls_config)
+                    .handle(handle)
+                    .serve(make_service),
+            )
+        } else {
+            let Ok(builder) = axum::Server::try_bind(&addr) else {
+                error!("Address already in use at {scheme}://{addr}");
+                return ExitCode::FAILURE;
+            };
+            Box::pin(async move {
+                builder
+                    .serve(make_service)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+                    .map_err(std::io::Error::other)
+            })
+        };
+
+    info!("Listening on {scheme}://{display_address}");
+    service::ready(&format!("{scheme}://{display_address}"));
+
+    #[cfg(feature = "tray")]
+    if let Some(commands) = tray_commands {
+        tokio::spawn(tray::handle(
+            app_state.clone(),
+            commands,
+            format!("{scheme}://{display_address}"),
+        ));
+    }
 
     if cli.open {
         info!("opening UI in default browser");
-        open::that_detached(format!("http://{display_address}")).unwrap();
+        open::that_detached(format!("{scheme}://{display_address}")).unwrap();
+    }
+
+    // Open WebSockets of the UI would keep graceful shutdown waiting forever
+    let connections_timeout = async {
+        if stopped.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(SHUTDOWN_TIMEOUT).await;
+        warn!("connections still open after {SHUTDOWN_TIMEOUT:?}, closing them");
+    };
+    let served = tokio::select! {
+        served = server => served,
+        () = connections_timeout => Ok(()),
+    };
+    if let Err(err) = served {
+        error!("serving UI at {scheme}://{addr}: {err}");
+        return ExitCode::FAILURE;
     }
 
-    server.await.unwrap();
-    audio_engine::quit(app_state.clone()).await;
-    app_state.link.enable(false);
-    // TODO: app_state.groups.take().expect("we are first to clean up this field so value should be here").shutdown().await;
+    let cleanup = async {
+        audio_engine::quit(app_state.clone()).await;
+        app_state.link.enable(false);
+        if let Some(groups) = &app_state.groups {
+            groups.shutdown().await;
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, cleanup)
+        .await
+        .is_err()
+    {
+        warn!("cleanup didn't finish in {SHUTDOWN_TIMEOUT:?}, exiting anyway");
+    }
     ExitCode::SUCCESS
 }
 
@@ -463,23 +1395,45 @@ async fn link_status_websocket_handler(
     ws.on_upgrade(move |socket| link_status_websocket_loop(socket, addr, app_state))
 }
 
+/// Longest time between status updates, used when beats are far apart
+const MAX_STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Delay after the beat boundary, so beat captured while rendering status is already the new one
+const BEAT_MARGIN: Duration = Duration::from_millis(1);
+
+/// Time until the next beat of the Link session
+fn until_next_beat(app_state: &AppState) -> Duration {
+    let mut session_state = SessionState::new();
+    app_state.link.capture_app_session_state(&mut session_state);
+    let now = app_state.link.clock_micros();
+    let beat = session_state.beat_at_time(now, app_state.quantum);
+    let next_beat = session_state.time_at_beat(beat.floor() + 1.0, app_state.quantum);
+    Duration::from_micros((next_beat - now).max(0) as u64) + BEAT_MARGIN
+}
+
+/// Message telling the UI to reload list of blocks, see [Change::Blocks]
+const BLOCKS_CHANGED_MESSAGE: &str = "blocks-changed";
+
+/// Message telling the UI to reload list of MIDI ports, see [Change::Ports]
+const PORTS_CHANGED_MESSAGE: &str = "ports-changed";
+
 /// Loop that sends over WebSocket current state of Harmonia
 ///
 /// This usage of WebSockets is mostly intended to not distract HTTP server with constant requests
 /// about application state and allow Harmonia to dictate the tempo of changes that is shown in UI.
-/// For constantly updating time the second part is not as important as the first one, but in case
-/// of more distinct and slow updates (like from [audio_engine]) this mechanism would be perfect
-/// (and probably will be included in future release).
+///
+/// Updates are sent on beat boundaries of the Link session, so beat counter in UI increments
+/// exactly on the beat. With slow tempo updates are also sent in between, at least every
+/// [MAX_STATUS_INTERVAL]. [Changes][Change] are pushed immediately: playback change sends status
+/// right away, blocks change sends [BLOCKS_CHANGED_MESSAGE] and ports change
+/// [PORTS_CHANGED_MESSAGE], so all open tabs stay in sync.
 async fn link_status_websocket_loop(
     mut socket: WebSocket,
     addr: SocketAddr,
     app_state: State<Arc<AppState>>,
 ) {
-    // TODO: Sleep should be based on BPM to keep in sync with clock as good as possible
-    let mut interval = tokio::time::interval(Duration::from_millis(100));
-
-    loop {
-        interval.tick().await;
+    let mut changes = app_state.changes.subscribe();
+    'updates: loop {
         let markup = html! {
             (handlers::runtime_status(app_state.clone()).await);
             (handlers::playing_status(app_state.clone()).await);
@@ -489,6 +1443,27 @@ async fn link_status_websocket_loop(
             error!("websocket send to {addr} failed: {err}");
             break;
         }
+
+        let messages: &[&str] = tokio::select! {
+            _ = tokio::time::sleep(until_next_beat(&app_state).min(MAX_STATUS_INTERVAL)) => &[],
+            change = changes.recv() => match change {
+                Ok(Change::Playback) => &[],
+                Ok(Change::Blocks) => &[BLOCKS_CHANGED_MESSAGE],
+                Ok(Change::Ports) => &[PORTS_CHANGED_MESSAGE],
+                // Missed changes may include any of them
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                    &[BLOCKS_CHANGED_MESSAGE, PORTS_CHANGED_MESSAGE]
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+        };
+
+        for message in messages {
+            if let Err(err) = socket.send(Message::Text(message.to_string())).await {
+                error!("websocket send to {addr} failed: {err}");
+                break 'updates;
+            }
+        }
     }
     let _ = socket.close().await;
 }
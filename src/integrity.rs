@@ -0,0 +1,147 @@
+//! Background verification of stored blocks
+//!
+//! Blocks created from files are identified by the hash of their content, so stored state can be
+//! checked for corruption by hashing the content again and comparing it with the identifier.
+//! Content is also parsed again, the same way it would be parsed when block starts playing.
+//!
+//! Verification runs every [VERIFICATION_INTERVAL] and its result is kept in
+//! [Block::integrity_error][block::Block::integrity_error], so corrupted blocks are visible in UI
+//! and API long before anyone tries to play them.
+
+use crate::{block, AppState};
+use sha1::{Digest, Sha1};
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// How often stored blocks are verified
+pub const VERIFICATION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Read content of the block, from disk without keeping it in memory when it wasn't played yet
+fn read(bytes: &crate::storage::StoredBytes) -> Result<Cow<'_, [u8]>, String> {
+    bytes
+        .read()
+        .map_err(|err| format!("failed to read content: {err}"))
+}
+
+/// Check that content of the block matches it's identifier and that it can be played
+fn verify(
+    uuid: &str,
+    block: &block::Block,
+    blocks: &HashMap<String, block::Block>,
+) -> Result<(), String> {
+    use block::Content;
+
+    let expect_hash = |prefix: &str, bytes: &[u8]| {
+        let Some(expected) = uuid.strip_prefix(prefix) else {
+            return Ok(());
+        };
+
+        let mut hasher = Sha1::new();
+        hasher.update(bytes);
+        let actual = hex::encode(hasher.finalize());
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(format!("content hash {actual} doesn't match identifier"))
+        }
+    };
+
+    match &block.content {
+        Content::Midi(source) => {
+            let bytes = read(&source.bytes)?;
+            expect_hash("midi-", &bytes)?;
+            midly::Smf::parse(&bytes).map_err(|err| format!("not a valid MIDI file: {err}"))?;
+        }
+        Content::Sample(source) => {
+            let bytes = read(&source.bytes)?;
+            expect_hash("sample-", &bytes)?;
+            rodio::Decoder::new(std::io::Cursor::new(bytes.into_owned()))
+                .map_err(|err| format!("not a supported audio file: {err}"))?;
+        }
+        Content::Audio(source) => {
+            let bytes = read(&source.bytes)?;
+            expect_hash("audio-", &bytes)?;
+            rodio::Decoder::new(std::io::Cursor::new(bytes.into_owned()))
+                .map_err(|err| format!("not a supported audio file: {err}"))?;
+        }
+        Content::Chord(source) => {
+            source
+                .spec
+                .parse::<crate::chord::Chord>()
+                .map_err(|err| format!("invalid chord: {err}"))?;
+        }
+        Content::Pattern(pattern) => pattern.validate()?,
+        Content::Alias(alias) => {
+            let Some(target) = blocks.get(&alias.target) else {
+                return Err(format!("referenced block#{} is missing", alias.target));
+            };
+            alias.resolve(&target.content)?;
+        }
+        Content::Scene(scene) => {
+            if let Some(missing) = scene
+                .blocks
+                .iter()
+                .find(|member| !blocks.contains_key(*member))
+            {
+                return Err(format!("block#{missing} of the scene is missing"));
+            }
+        }
+        Content::Metronome(metronome) => metronome.validate()?,
+        Content::SharedMemory { .. }
+        | Content::Command { .. }
+        | Content::Tempo { .. }
+        | Content::Silence { .. } => {}
+    }
+
+    Ok(())
+}
+
+/// Verify all blocks and mark the ones that failed verification
+///
+/// Returns number of corrupted blocks.
+pub fn verify_all(app_state: &AppState) -> usize {
+    let results: Vec<(String, Option<String>)> = {
+        let blocks = app_state.blocks.read().unwrap();
+        blocks
+            .iter()
+            .map(|(uuid, block)| (uuid.clone(), verify(uuid, block, &blocks).err()))
+            .collect()
+    };
+
+    let mut blocks = app_state.blocks.write().unwrap();
+    let mut corrupted = 0;
+    for (uuid, result) in results {
+        // Block could have been removed in the meantime
+        let Some(block) = blocks.get_mut(&uuid) else {
+            continue;
+        };
+
+        if let Some(err) = &result {
+            corrupted += 1;
+            if block.integrity_error.as_ref() != Some(err) {
+                error!("block#{uuid} failed integrity verification: {err}");
+            }
+        } else if block.integrity_error.is_some() {
+            info!("block#{uuid} passed integrity verification again");
+        }
+        block.integrity_error = result;
+    }
+    corrupted
+}
+
+/// Verify blocks every [VERIFICATION_INTERVAL], starting immediately
+pub async fn verify_periodically(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(VERIFICATION_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let app_state = app_state.clone();
+        match tokio::task::spawn_blocking(move || verify_all(&app_state)).await {
+            Ok(0) => {}
+            Ok(corrupted) => error!("{corrupted} blocks failed integrity verification"),
+            Err(err) => error!("integrity verification failed: {err}"),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Choosing network interfaces used for synchronization
+//!
+//! By default [Sockets][crate::net::Sockets] are bound to every IPv4 interface, including VPNs and
+//! virtualization bridges which often can't join multicast groups. [InterfaceFilter] limits them
+//! with rules matching either interface name (like `eth0`) or network in CIDR notation (like
+//! `192.168.0.0/24`).
+
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Rule matching network interfaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceRule {
+    /// Interface with given name
+    Name(String),
+
+    /// Interface with address in given network
+    Network {
+        /// Address of the network
+        address: Ipv4Addr,
+
+        /// Length of the network prefix in bits
+        prefix: u8,
+    },
+}
+
+impl InterfaceRule {
+    /// Parse rule, CIDR notation is recognized by `/`, anything else is interface name
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let rule = rule.trim();
+        if rule.is_empty() {
+            return Err("interface rule cannot be empty".to_string());
+        }
+        let Some((address, prefix)) = rule.split_once('/') else {
+            return Ok(Self::Name(rule.to_string()));
+        };
+        let address = address
+            .parse()
+            .map_err(|err| format!("invalid network address {address:?}: {err}"))?;
+        let prefix = match prefix.parse() {
+            Ok(prefix) if prefix <= 32 => prefix,
+            _ => {
+                return Err(format!(
+                    "invalid network prefix {prefix:?}, expected 0 to 32"
+                ))
+            }
+        };
+        Ok(Self::Network { address, prefix })
+    }
+
+    /// Check if the interface with given name and address matches the rule
+    pub fn matches(&self, name: &str, address: Ipv4Addr) -> bool {
+        match self {
+            Self::Name(expected) => expected == name,
+            Self::Network {
+                address: network,
+                prefix,
+            } => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(address) & mask == u32::from(*network) & mask
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for InterfaceRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "{name}"),
+            Self::Network { address, prefix } => write!(f, "{address}/{prefix}"),
+        }
+    }
+}
+
+/// Which interfaces are used for synchronization
+///
+/// Interface is used when it matches any of `allow` rules (or there are none) and none of `deny`
+/// rules.
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceFilter {
+    /// Rules of interfaces that may be used, all when empty
+    pub allow: Vec<InterfaceRule>,
+
+    /// Rules of interfaces that are never used
+    pub deny: Vec<InterfaceRule>,
+}
+
+impl InterfaceFilter {
+    /// Check if interface with given name and address should be used
+    pub fn allows(&self, name: &str, address: Ipv4Addr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|rule| rule.matches(name, address)))
+            && !self.deny.iter().any(|rule| rule.matches(name, address))
+    }
+}
+
+/// All IPv4 interfaces of the local machine, with their names
+pub fn list() -> Vec<(String, Ipv4Addr)> {
+    match local_ip_address::list_afinet_netifas() {
+        Ok(interfaces) => interfaces
+            .into_iter()
+            .filter_map(|(name, address)| match address {
+                IpAddr::V4(address) => Some((name, address)),
+                IpAddr::V6(_) => None,
+            })
+            .collect(),
+        Err(err) => {
+            tracing::error!("failed to list network interfaces: {err}");
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Per-port latency compensation
+//!
+//! Synthesizers and audio interfaces need different time to turn received MIDI message into
+//! sound, so blocks played on different ports drift apart by their difference. Each port can have
+//! signed offset in milliseconds: positive offset sends messages to the port earlier by this time,
+//! negative later. Offsets are applied by [audio_engine][crate::audio_engine] when it decides
+//! whether event is due.
+//!
+//! Like [channels][crate::channels], offsets are indexed by port name.
+
+use crate::{cache_path, AppState};
+use axum::{extract::State, http::StatusCode, Form};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc};
+use tracing::{info, warn};
+
+/// Filename under which Harmonia stores latency offsets of ports
+pub const PORT_LATENCIES_PATH: &str = "harmonia_port_latencies.bson";
+
+/// Largest accepted offset in milliseconds, in both directions
+pub const MAX_LATENCY_MS: i32 = 1000;
+
+/// Load offsets stored in [PORT_LATENCIES_PATH], missing or invalid file means no offsets
+pub fn load() -> HashMap<String, i32> {
+    std::fs::read(cache_path().join(PORT_LATENCIES_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Offset in milliseconds of the port with given name, 0 when not set
+pub fn offset(app_state: &AppState, port_name: &str) -> i32 {
+    app_state
+        .port_latencies
+        .read()
+        .unwrap()
+        .get(port_name)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Payload to set latency offset of the port
+#[derive(Deserialize)]
+pub struct SetLatency {
+    /// Name of the port, as reported by the system
+    port: String,
+
+    /// Offset in milliseconds, empty for no offset
+    latency: String,
+}
+
+/// Set latency offset of port with given name
+///
+/// Takes effect when the next block starts playing through the port.
+pub async fn set_latency(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetLatency {
+        port: port_name,
+        latency,
+    }): Form<SetLatency>,
+) -> Result<String, (StatusCode, String)> {
+    let latency = match latency.trim() {
+        "" => 0,
+        latency => match latency.parse::<i32>() {
+            Ok(latency) if latency.abs() <= MAX_LATENCY_MS => latency,
+            _ => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "invalid latency {latency:?}, expected milliseconds between -{MAX_LATENCY_MS} and {MAX_LATENCY_MS}"
+                    ),
+                ))
+            }
+        },
+    };
+
+    info!("compensating {latency}ms of latency on port {port_name:?}");
+    let port_latencies = {
+        let mut port_latencies = app_state.port_latencies.write().unwrap();
+        if latency == 0 {
+            port_latencies.remove(&port_name);
+        } else {
+            port_latencies.insert(port_name, latency);
+        }
+        port_latencies.clone()
+    };
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(PORT_LATENCIES_PATH);
+        match bson::to_vec(&port_latencies) {
+            Ok(bytes) => {
+                if let Err(err) = std::fs::write(&path, bytes) {
+                    warn!("failed to write port latencies to {path:?}: {err}");
+                }
+            }
+            Err(err) => warn!("failed to serialize port latencies: {err}"),
+        }
+    }
+
+    Ok(latency.to_string())
+}
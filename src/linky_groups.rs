@@ -13,13 +13,53 @@
 
 use rusty_link::{AblLink, SessionState};
 use serde::{Deserialize, Serialize};
-use std::{sync::atomic, sync::Arc};
-
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::atomic,
+    sync::Arc,
+};
+
+pub mod clocks;
+pub mod discovery;
+pub mod interfaces;
 mod net;
 
 /// Max length of the group name
 pub const MAX_GROUP_ID_LENGTH: usize = 15;
 
+/// Quantum used when starting group without specifying one
+pub const DEFAULT_QUANTUM: f64 = 1.0;
+
+/// Multicast address used for synchronization when not configured otherwise
+///
+/// Instances synchronize only with others using the same address, so independent ensembles in the
+/// same network can pick different ones.
+pub const DEFAULT_MULTICAST: SocketAddrV4 =
+    SocketAddrV4::new(Ipv4Addr::new(224, 76, 78, 75), 20810);
+
+/// Parse multicast address given as `ip:port`, like `224.76.78.75:20810`
+///
+/// Only IPv4 multicast addresses are supported. Port must not be the last one, since
+/// [discovery] uses the next port.
+pub fn parse_multicast(address: &str) -> Result<SocketAddr, String> {
+    let address: SocketAddrV4 = address
+        .parse()
+        .map_err(|err| format!("invalid multicast address {address:?}: {err}"))?;
+    if !address.ip().is_multicast() {
+        return Err(format!(
+            "{} is not IPv4 multicast address (224.0.0.0 to 239.255.255.255)",
+            address.ip()
+        ));
+    }
+    if address.port() == 0 || address.port() == u16::MAX {
+        return Err(format!(
+            "multicast port must be between 1 and {}",
+            u16::MAX - 1
+        ));
+    }
+    Ok(address.into())
+}
+
 /// ID that defines the group.
 type GroupId = [u8; MAX_GROUP_ID_LENGTH];
 
@@ -38,6 +78,9 @@ struct GroupFrame {
     /// Timestamp in microseconds that is a reference point using global host time for when group
     /// was started
     timestamp: i64,
+
+    /// Quantum with which the group was started, used by instances joining it
+    quantum: f64,
 }
 
 impl std::fmt::Display for GroupFrame {
@@ -57,18 +100,25 @@ impl std::fmt::Display for GroupFrame {
         } else {
             write!(f, "{group_id:?}", group_id = self.group_id)?;
         }
-        write!(f, ", timestamp = {timestamp})", timestamp = self.timestamp)
+        write!(
+            f,
+            ", timestamp = {timestamp}, quantum = {quantum})",
+            timestamp = self.timestamp,
+            quantum = self.quantum
+        )
     }
 }
 
 impl GroupFrame {
-    /// Create new packet based on `group_id` from user and `timestamp` from [link][rusty_link]
-    fn new(group_id: GroupId, timestamp: i64) -> Self {
+    /// Create new packet based on `group_id` and `quantum` from user and `timestamp` from
+    /// [link][rusty_link]
+    fn new(group_id: GroupId, timestamp: i64, quantum: f64) -> Self {
         Self {
             magic: *b"grup",
-            version: 1,
+            version: 2,
             group_id,
             timestamp,
+            quantum,
         }
     }
 
@@ -76,17 +126,57 @@ impl GroupFrame {
     ///
     /// Allows for backwards compatibility in future releases
     fn is_supported(&self) -> bool {
-        self.magic == *b"grup" && self.version == 1
+        self.magic == *b"grup" && self.version == 2
+    }
+}
+
+/// Network Packet asking every instance to stop playing immediately
+///
+/// Sent on the same multicast as [GroupFrame]s, distinguished by the magic sequence.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct PanicFrame {
+    /// Magic sequence distinguishing packets
+    magic: [u8; 4],
+
+    /// Version of the packet
+    version: u8,
+
+    /// Random identifier of the panic, the same for all repetitions of the frame
+    id: [u8; 16],
+}
+
+impl PanicFrame {
+    /// Magic sequence of [PanicFrame]
+    const MAGIC: [u8; 4] = *b"pnic";
+
+    /// How many times frame is sent, since UDP packets may be lost
+    const REPETITIONS: usize = 3;
+
+    /// Time between repetitions of the frame
+    const REPETITION_INTERVAL: std::time::Duration = std::time::Duration::from_millis(20);
+
+    /// Create new panic with random identifier
+    fn new() -> Self {
+        Self {
+            magic: Self::MAGIC,
+            version: 1,
+            id: uuid::Uuid::new_v4().into_bytes(),
+        }
+    }
+
+    /// Check if current implementation supports this packet.
+    fn is_supported(&self) -> bool {
+        self.magic == Self::MAGIC && self.version == 1
     }
 }
 
 /// State for Group synchronization system
 pub struct Groups {
-    /// Listening task that receives group messages
-    listener: tokio::task::JoinHandle<()>,
+    /// Listening task that receives group messages, taken by [Groups::shutdown]
+    listener: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
 
-    /// State consolidation worker
-    worker: tokio::task::JoinHandle<()>,
+    /// State consolidation worker, taken by [Groups::shutdown]
+    worker: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
 
     /// Channel used to issue cancelation request
     cancel: tokio::sync::mpsc::Sender<()>,
@@ -99,6 +189,15 @@ pub struct Groups {
 
     /// Is set when there is a group in which we are playing.
     is_playing: Arc<atomic::AtomicBool>,
+
+    /// Sockets shared with listener and worker, used to send [PanicFrame]s
+    connection: Arc<net::Sockets>,
+
+    /// Panics received from other instances, see [Groups::panic]
+    panics: tokio::sync::broadcast::Sender<()>,
+
+    /// Clock offsets measured from frames of peers, see [Groups::clocks]
+    clocks: Arc<clocks::Measurements>,
 }
 
 /// All the errors that this crate may produce
@@ -109,9 +208,14 @@ pub enum Error {
 }
 
 impl Groups {
-    // TODO: To avoid confusion make group_id_str case insensitive.
     /// Start or join the group pointed by the user
     pub async fn start(&self, group_id_str: &str) -> Result<(), Error> {
+        self.start_with_quantum(group_id_str, DEFAULT_QUANTUM).await
+    }
+
+    // TODO: To avoid confusion make group_id_str case insensitive.
+    /// Start or join the group pointed by the user, aligning the start to given quantum
+    pub async fn start_with_quantum(&self, group_id_str: &str, quantum: f64) -> Result<(), Error> {
         let mut group_id: GroupId = Default::default();
         if group_id_str.len() > group_id.len() {
             return Err(Error::GroupIdTooLong);
@@ -120,7 +224,7 @@ impl Groups {
 
         let host_time = self.link.clock_micros();
         let ghost_time = self.link.host_to_ghost(host_time);
-        let frame = GroupFrame::new(group_id, ghost_time);
+        let frame = GroupFrame::new(group_id, ghost_time, quantum);
         self.actions
             .send(Action::Start(frame))
             .await
@@ -136,6 +240,49 @@ impl Groups {
             .expect("receiver will never be closed unless in destructor");
     }
 
+    /// Ask every instance in the network to stop playing immediately
+    ///
+    /// This instance doesn't receive it's own panic, so caller should stop local playback itself.
+    pub async fn panic(&self) {
+        let frame = PanicFrame::new();
+        tracing::warn!("broadcasting panic");
+        let packet = bincode::serialize(&frame).unwrap();
+        for repetition in 0..PanicFrame::REPETITIONS {
+            if repetition > 0 {
+                tokio::time::sleep(PanicFrame::REPETITION_INTERVAL).await;
+            }
+            self.connection.send_bytes(&packet).await;
+        }
+    }
+
+    /// Receiver of panics sent by other instances with [Groups::panic]
+    pub fn panics(&self) -> tokio::sync::broadcast::Receiver<()> {
+        self.panics.subscribe()
+    }
+
+    /// Names of network interfaces used for synchronization
+    pub fn interfaces(&self) -> &[String] {
+        &self.connection.interfaces
+    }
+
+    /// Set pre-shared key authenticating [GroupFrame]s and [PanicFrame]s, `None` turns it off
+    ///
+    /// Instances with the key ignore frames not signed with it, so others in the network can't
+    /// restart their beat grid or stop them. Instances without the key accept all frames.
+    pub fn set_session_key(&self, key: Option<&str>) {
+        self.connection.set_key(key);
+    }
+
+    /// Whether frames are authenticated with the session key, see [Groups::set_session_key]
+    pub fn is_authenticated(&self) -> bool {
+        self.connection.is_authenticated()
+    }
+
+    /// Clock drift and jitter of peers playing in any group, see [clocks]
+    pub fn clocks(&self) -> Vec<clocks::PeerClock> {
+        self.clocks.stats()
+    }
+
     /// Check if we are playing
     pub fn is_playing(&self) -> bool {
         self.is_playing.load(atomic::Ordering::SeqCst)
@@ -143,24 +290,30 @@ impl Groups {
 
     /// Stop group synchronization mechanism
     ///
-    /// Only used in graceful shoutdown
-    pub async fn shutdown(self) {
+    /// Only used in graceful shoutdown, calling it again does nothing.
+    pub async fn shutdown(&self) {
         tracing::debug!("Issuing shutdown");
-        self.cancel.send(()).await.unwrap();
-        self.actions.send(Action::Quit).await.unwrap();
-
-        self.listener.await.unwrap();
-        self.worker.await.unwrap();
+        // Tasks that already stopped dropped their receivers, which is fine here
+        let _ = self.cancel.send(()).await;
+        let _ = self.actions.send(Action::Quit).await;
+
+        let listener = self.listener.lock().unwrap().take();
+        let worker = self.worker.lock().unwrap().take();
+        for task in [listener, worker].into_iter().flatten() {
+            if let Err(err) = task.await {
+                tracing::warn!("group synchronization task failed: {err}");
+            }
+        }
     }
 }
 
 /// Action is the description of requests for group synchronization worker
 #[derive(Debug)]
 enum Action {
-    /// Start playing in the provided group
+    /// Start playing in the provided group with it's quantum
     Start(GroupFrame),
 
-    /// Join the provided group if it matches currently played
+    /// Join the provided group if it matches currently played, taking over it's quantum
     Join(GroupFrame),
 
     /// Stop playing in the provided group (leave group)
@@ -186,8 +339,6 @@ async fn negotatior(
 
     #[allow(clippy::missing_docs_in_private_items)]
     const TIMEOUT_DURATION: Duration = Duration::from_millis(50);
-    #[allow(clippy::missing_docs_in_private_items)]
-    const QUANTUM: f64 = 1.0;
 
     let mut timeout = tokio::time::interval(TIMEOUT_DURATION);
 
@@ -213,7 +364,7 @@ async fn negotatior(
                     tracing::info!("starting {frame}");
                     let mut session_state = SessionState::new();
                     link.capture_app_session_state(&mut session_state);
-                    session_state.request_beat_at_time(0.0, host_time, QUANTUM);
+                    session_state.request_beat_at_time(0.0, host_time, frame.quantum);
                     link.commit_app_session_state(&session_state);
 
                     is_playing.store(true, atomic::Ordering::SeqCst);
@@ -224,6 +375,9 @@ async fn negotatior(
                         if current_frame.group_id == frame.group_id
                             && current_frame.timestamp > frame.timestamp
                         {
+                            // Beats are aligned to the quantum of the group starter, which may
+                            // differ from the one this instance started with
+                            let quantum = frame.quantum;
                             let foreign_host_time = link.ghost_to_host(frame.timestamp);
                             let my_host_time = link.clock_micros();
 
@@ -231,13 +385,18 @@ async fn negotatior(
                             link.capture_app_session_state(&mut session_state);
 
                             let beat_difference =
-                                session_state.beat_at_time(foreign_host_time, QUANTUM);
-                            let current_beat = session_state.beat_at_time(my_host_time, QUANTUM);
+                                session_state.beat_at_time(foreign_host_time, quantum);
+                            let current_beat =
+                                session_state.beat_at_time(my_host_time, quantum);
                             let desired_beat = current_beat - beat_difference;
 
                             tracing::info!("Transitioning from {current_beat} to {desired_beat} with frame {frame}");
 
-                            session_state.request_beat_at_time(desired_beat, my_host_time, QUANTUM);
+                            session_state.request_beat_at_time(
+                                desired_beat,
+                                my_host_time,
+                                quantum,
+                            );
                             link.commit_app_session_state(&session_state);
                             current_group = Some(frame);
                         }
@@ -254,7 +413,9 @@ async fn negotatior(
 
         if last_send_time.elapsed() >= TIMEOUT_DURATION {
             if let Some(frame) = current_group {
-                connection.send(frame).await;
+                connection
+                    .send(frame, link.host_to_ghost(link.clock_micros()))
+                    .await;
                 last_send_time = tokio::time::Instant::now();
             }
         }
@@ -262,27 +423,51 @@ async fn negotatior(
 }
 
 /// Create, initialize and start listening for group synchronization mechanism
-pub fn listen(link: std::sync::Arc<rusty_link::AblLink>) -> Groups {
-    let connection = Arc::new(net::Sockets::bind(link.is_enabled()));
+///
+/// Synchronizes with instances using the same `multicast` address, see [DEFAULT_MULTICAST], on
+/// interfaces allowed by the `filter`. With `session_key` frames are authenticated, see
+/// [Groups::set_session_key].
+pub fn listen(
+    link: std::sync::Arc<rusty_link::AblLink>,
+    multicast: SocketAddr,
+    filter: &interfaces::InterfaceFilter,
+    session_key: Option<&str>,
+) -> Groups {
+    let connection = Arc::new(net::Sockets::bind_to(link.is_enabled(), multicast, filter));
+    connection.set_key(session_key);
     let (cancel, wait_for_cancel) = tokio::sync::mpsc::channel(1);
     let (send_action, state) = tokio::sync::mpsc::channel(4);
     let is_playing = Arc::new(atomic::AtomicBool::new(false));
+    let (panics, _) = tokio::sync::broadcast::channel(1);
 
     let worker_connection = connection.clone();
-    let listener_connection = connection;
+    let listener_connection = connection.clone();
+    let listener_panics = panics.clone();
+    let clocks = Arc::new(clocks::Measurements::default());
+    let listener_clocks = clocks.clone();
+    let listener_link = link.clone();
 
     Groups {
         actions: send_action.clone(),
         link: link.clone(),
         is_playing: is_playing.clone(),
-        listener: tokio::spawn(async move {
+        connection,
+        panics,
+        clocks,
+        listener: std::sync::Mutex::new(Some(tokio::spawn(async move {
             listener_connection
-                .listen(send_action.clone(), wait_for_cancel)
+                .listen(
+                    send_action.clone(),
+                    listener_panics,
+                    listener_clocks,
+                    listener_link,
+                    wait_for_cancel,
+                )
                 .await;
-        }),
-        worker: tokio::spawn(async move {
+        }))),
+        worker: std::sync::Mutex::new(Some(tokio::spawn(async move {
             negotatior(state, link, worker_connection, is_playing).await;
-        }),
+        }))),
         cancel,
     }
 }
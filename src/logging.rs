@@ -0,0 +1,377 @@
+//! Changing which events are logged while Harmonia is running
+//!
+//! Filter given by `RUST_LOG` (or [DEFAULT_FILTER]) is installed as reloadable layer, so when
+//! something misbehaves during rehearsal it can be switched to debug logging from the UI, without
+//! restarting and losing state. Change is not persisted: after restart the filter is the initial
+//! one again.
+//!
+//! Like [abort][crate::handlers::abort], filter can only be changed from this computer.
+//!
+//! Daily log files can also be read in the browser with [viewer], which shows the end of the
+//! current log and streams new lines over WebSocket (see [websocket]), so logs can be read and
+//! copied without looking for the cache directory. Logs contain addresses of other computers, so
+//! the viewer is also available only from this computer.
+
+use crate::{log_path, AppState};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        ConnectInfo, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::IntoResponse,
+    Form,
+};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+use std::{
+    io::{Read, Seek, SeekFrom},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tracing::{error, info};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Format of daily log files
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines, like the ones printed in the terminal
+    #[default]
+    Text,
+
+    /// One JSON object per line, for log aggregation tools like Vector or Loki
+    Json,
+}
+
+/// Prefix of daily log files, followed by `.` and the date
+pub const LOG_FILE_PREFIX: &str = "logs";
+
+/// Number of lines from the end of the current log sent when viewer connects
+const TAIL_LINES: usize = 500;
+
+/// How often current log is checked for new lines
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Filter used when `RUST_LOG` is not set
+pub const DEFAULT_FILTER: &str = "harmonia=info,linky_groups=info,linky_groups::net=info";
+
+/// Filters offered in the UI, with their descriptions
+const PRESETS: &[(&str, &str)] = &[
+    (DEFAULT_FILTER, "Default"),
+    (
+        "harmonia=debug,linky_groups=debug,linky_groups::net=info",
+        "Debug",
+    ),
+    (
+        "harmonia=debug,linky_groups=debug,linky_groups::net=debug",
+        "Debug with network traffic",
+    ),
+    ("harmonia=trace,linky_groups=trace", "Trace (very verbose)"),
+    (
+        "harmonia=warn,linky_groups=warn",
+        "Warnings and errors only",
+    ),
+];
+
+/// Handle changing the filter of installed subscriber
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Installed filter, set once logging is set up
+struct Filter {
+    /// Handle replacing the filter
+    handle: FilterHandle,
+
+    /// Filter as it was given, since [EnvFilter] displays directives in it's own order
+    current: Mutex<String>,
+}
+
+/// Installed filter, see [install]
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+/// Initial filter, from `RUST_LOG` or [DEFAULT_FILTER]
+pub fn initial_filter() -> String {
+    std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|filter| EnvFilter::try_new(filter).is_ok())
+        .unwrap_or_else(|| DEFAULT_FILTER.to_string())
+}
+
+/// Remember handle of the installed `filter`, so it can be changed later
+pub fn install(handle: FilterHandle, filter: String) {
+    let _ = FILTER.set(Filter {
+        handle,
+        current: Mutex::new(filter),
+    });
+}
+
+/// Currently used filter, [None] when logging is not set up
+fn current() -> Option<String> {
+    Some(FILTER.get()?.current.lock().unwrap().clone())
+}
+
+/// Render form choosing the filter
+///
+/// Other computers only see the current filter.
+pub async fn render(ConnectInfo(addr): ConnectInfo<SocketAddr>) -> Markup {
+    let Some(current) = current() else {
+        return html! { p { "Logging is not set up" } };
+    };
+    let local = addr.ip().is_loopback();
+
+    html! {
+        @if local {
+            label {
+                "Log level "
+                select name="filter" hx-post="/logs/filter" hx-trigger="change" hx-target="#log-filter" hx-swap="innerHTML" {
+                    @for (filter, description) in PRESETS {
+                        option value=(filter) selected[current == *filter] { (description) }
+                    }
+                    @if !PRESETS.iter().any(|(filter, _)| current == *filter) {
+                        option value=(current) selected { "Custom" }
+                    }
+                }
+            }
+            details {
+                summary { "Custom filter" }
+                form hx-post="/logs/filter" hx-target="#log-filter" hx-swap="innerHTML" {
+                    input type="text" name="filter" value=(current) size="60";
+                    " "
+                    input type="submit" value="Apply";
+                }
+            }
+            p { "Changes last until restart" }
+            p { a href="/logs" target="_blank" { "Open log viewer" } }
+        } @else {
+            p { "Log filter: " code { (current) } }
+        }
+    }
+}
+
+/// Payload setting the filter
+#[derive(Deserialize)]
+pub struct SetFilter {
+    /// Filter in `RUST_LOG` syntax, like `harmonia=debug`
+    filter: String,
+}
+
+/// Change the filter, only from this computer
+pub async fn set_filter(
+    addr: ConnectInfo<SocketAddr>,
+    Form(SetFilter { filter }): Form<SetFilter>,
+) -> Result<Markup, (StatusCode, String)> {
+    if !addr.ip().is_loopback() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Log level can be changed only from this computer".to_string(),
+        ));
+    }
+
+    let filter = filter.trim();
+    let parsed = EnvFilter::try_new(filter)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid filter: {err}")))?;
+    let Some(installed) = FILTER.get() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Logging is not set up".to_string(),
+        ));
+    };
+    installed
+        .handle
+        .reload(parsed)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    *installed.current.lock().unwrap() = filter.to_string();
+    info!("changed log filter to {filter}");
+
+    Ok(render(addr).await)
+}
+
+/// Daily log files, oldest first
+pub fn daily_logs() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(log_path()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{LOG_FILE_PREFIX}.");
+    let mut logs: Vec<_> = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect();
+    // Date in the name sorts the same way as the days
+    logs.sort();
+    logs
+}
+
+/// Page showing the current log, updated as new lines are written
+pub async fn viewer(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+) -> Result<Markup, (StatusCode, String)> {
+    if !addr.ip().is_loopback() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Logs are available only from this computer".to_string(),
+        ));
+    }
+
+    Ok(html! {
+        (DOCTYPE);
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                title { "Harmonia logs" }
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                script src="logs.js" {}
+                link rel="stylesheet" href="index.css";
+            }
+            body {
+                header {
+                    h1 { "Harmonia logs" }
+                    a href="/" { "Back to Harmonia" }
+                }
+                main {
+                    @if app_state.ephemeral {
+                        p { "In ephemeral mode logs are only printed in the terminal" }
+                    } @else {
+                        p {
+                            "Logs are stored in "
+                            code { (log_path().display()) }
+                            " "
+                            button id="copy-logs" { "Copy" }
+                            " "
+                            label {
+                                input type="checkbox" id="follow-logs" checked;
+                                " Follow new lines"
+                            }
+                        }
+                        pre id="logs" class="logs" {}
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Current log and position up to which it was sent
+struct Tail {
+    /// Path of the log
+    path: PathBuf,
+
+    /// Number of bytes already sent
+    offset: u64,
+}
+
+impl Tail {
+    /// Last [TAIL_LINES] of the current log, [None] when there are no logs yet
+    fn start() -> std::io::Result<Option<(Self, String)>> {
+        let Some(path) = daily_logs().pop() else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(&path)?;
+        let start = bytes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, byte)| **byte == b'\n')
+            // Line ending the file doesn't start a line
+            .nth(TAIL_LINES)
+            .map_or(0, |(index, _)| index + 1);
+        let text = String::from_utf8_lossy(&bytes[start..]).into_owned();
+        let tail = Self {
+            path,
+            offset: bytes.len() as u64,
+        };
+        Ok(Some((tail, text)))
+    }
+
+    /// Lines written since the last call, following to the log of the next day
+    fn next(&mut self) -> std::io::Result<String> {
+        let mut text = self.read_new()?;
+        if let Some(newest) = daily_logs().pop() {
+            if newest != self.path {
+                self.path = newest;
+                self.offset = 0;
+                text += &self.read_new()?;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Bytes appended to [Self::path] after [Self::offset]
+    fn read_new(&mut self) -> std::io::Result<String> {
+        let mut file = std::fs::File::open(&self.path)?;
+        if file.metadata()?.len() < self.offset {
+            // Log was truncated, so it's read from the start
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        // Line may be written only partially, so it's sent when it's complete
+        let complete = bytes
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |index| index + 1);
+        self.offset += complete as u64;
+        Ok(String::from_utf8_lossy(&bytes[..complete]).into_owned())
+    }
+}
+
+/// Handler transferring log viewer from HTTP to WebSocket, see [tail_loop]
+pub async fn websocket(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> axum::response::Response {
+    if !addr.ip().is_loopback() {
+        return (
+            StatusCode::FORBIDDEN,
+            "Logs are available only from this computer",
+        )
+            .into_response();
+    }
+    ws.on_upgrade(move |socket| tail_loop(socket, addr))
+}
+
+/// Loop sending end of the current log and then new lines as they are written
+async fn tail_loop(mut socket: WebSocket, addr: SocketAddr) {
+    let mut tail: Option<Tail> = None;
+    loop {
+        let text = match &mut tail {
+            Some(tail) => tail.next(),
+            None => match Tail::start() {
+                Ok(Some((started, text))) => {
+                    tail = Some(started);
+                    Ok(text)
+                }
+                Ok(None) => Ok(String::new()),
+                Err(err) => Err(err),
+            },
+        };
+        let (text, failed) = match text {
+            Ok(text) => (text, false),
+            // Viewer reconnects, starting again from the end of the current log
+            Err(err) => (format!("failed to read logs: {err}\n"), true),
+        };
+
+        if !text.is_empty() {
+            if let Err(err) = socket.send(Message::Text(text)).await {
+                error!("log viewer send to {addr} failed: {err}");
+                break;
+            }
+        }
+        if failed {
+            break;
+        }
+
+        // Closed viewer is noticed here, since it never sends anything
+        tokio::select! {
+            _ = tokio::time::sleep(TAIL_INTERVAL) => {}
+            message = socket.recv() => match message {
+                None | Some(Err(_)) | Some(Ok(Message::Close(_))) => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.close().await;
+}
@@ -0,0 +1,201 @@
+//! MIDI Beat Clock following Link session
+//!
+//! External sequencers and drum machines can follow Harmonia's tempo when MIDI Beat Clock is
+//! enabled for their port. While any block is playing, [run] sends Timing Clock messages 24 times
+//! per Link beat to these ports, Start when the first block starts and Stop when the last one
+//! stops. Clock starts on the beat on which the earliest played block started.
+//!
+//! Like [channels][crate::channels], ports are remembered by name. Builtin virtual port is not
+//! supported, since it is held by the block that plays through it.
+
+use crate::{cache_path, AppState};
+use axum::{extract::State, Form};
+use midir::{MidiOutput, MidiOutputConnection};
+use rusty_link::SessionState;
+use serde::Deserialize;
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+/// Filename under which Harmonia stores names of ports receiving clock
+pub const CLOCK_PORTS_PATH: &str = "harmonia_clock_ports.bson";
+
+/// Number of Timing Clock messages per beat, as defined by MIDI specification
+const PULSES_PER_QUARTER_NOTE: f64 = 24.0;
+
+/// Timing Clock real-time message
+const TIMING_CLOCK: u8 = 0xf8;
+
+/// Start real-time message
+const START: u8 = 0xfa;
+
+/// Stop real-time message
+const STOP: u8 = 0xfc;
+
+/// How often clock checks if playback started or stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Load names of ports stored in [CLOCK_PORTS_PATH], missing or invalid file means no ports
+pub fn load() -> HashSet<String> {
+    std::fs::read(cache_path().join(CLOCK_PORTS_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice::<Vec<String>>(&bytes).ok())
+        .map(HashSet::from_iter)
+        .unwrap_or_default()
+}
+
+/// Whether port with given name receives MIDI clock
+pub fn is_enabled(app_state: &AppState, port_name: &str) -> bool {
+    app_state.clock_ports.read().unwrap().contains(port_name)
+}
+
+/// Payload to enable or disable clock for the port
+#[derive(Deserialize)]
+pub struct SetClock {
+    /// Name of the port, as reported by the system
+    port: String,
+
+    /// Present when clock should be sent, as sent by HTML checkbox
+    enabled: Option<String>,
+}
+
+/// Enable or disable MIDI clock for port with given name
+///
+/// Takes effect when the next block starts playing alone.
+pub async fn set_clock(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetClock {
+        port: port_name,
+        enabled,
+    }): Form<SetClock>,
+) {
+    let clock_ports = {
+        let mut clock_ports = app_state.clock_ports.write().unwrap();
+        if enabled.is_some() {
+            info!("sending MIDI clock to port {port_name:?}");
+            clock_ports.insert(port_name);
+        } else {
+            info!("no longer sending MIDI clock to port {port_name:?}");
+            clock_ports.remove(&port_name);
+        }
+        clock_ports.iter().cloned().collect::<Vec<_>>()
+    };
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(CLOCK_PORTS_PATH);
+        match bson::to_vec(&clock_ports) {
+            Ok(bytes) => {
+                if let Err(err) = std::fs::write(&path, bytes) {
+                    warn!("failed to write clock ports to {path:?}: {err}");
+                }
+            }
+            Err(err) => warn!("failed to serialize clock ports: {err}"),
+        }
+    }
+}
+
+/// Connect to all ports that should receive clock
+fn connect(app_state: &AppState) -> Vec<MidiOutputConnection> {
+    let clock_ports = app_state.clock_ports.read().unwrap().clone();
+    if clock_ports.is_empty() {
+        return Vec::new();
+    }
+
+    let Ok(out) = MidiOutput::new("harmonia-clock") else {
+        warn!("failed to create MIDI output for clock");
+        return Vec::new();
+    };
+
+    let mut connections = Vec::new();
+    for port in out.ports() {
+        let Ok(port_name) = out.port_name(&port) else {
+            continue;
+        };
+        if !clock_ports.contains(&port_name) {
+            continue;
+        }
+
+        let Ok(out) = MidiOutput::new("harmonia-clock") else {
+            continue;
+        };
+        match out.connect(&port, "harmonia-clock") {
+            Ok(connection) => connections.push(connection),
+            Err(err) => warn!("failed to connect to {port_name:?} to send clock: {err}"),
+        }
+    }
+    connections
+}
+
+/// Send message to all connections, reporting failures
+fn send(connections: &mut [MidiOutputConnection], message: u8) {
+    for connection in connections {
+        if let Err(err) = connection.send(&[message]) {
+            warn!("failed to send MIDI clock message {message:#x}: {err}");
+        }
+    }
+}
+
+/// Beat on which the earliest of the played blocks started, [None] when nothing is playing
+fn playing_since(app_state: &AppState) -> Option<f64> {
+    app_state
+        .currently_playing
+        .read()
+        .unwrap()
+        .values()
+        .map(|progress| progress.origin)
+        .reduce(f64::min)
+}
+
+/// Send MIDI clock to enabled ports whenever blocks are playing, never returns
+pub fn run(app_state: Arc<AppState>) {
+    let mut session_state = SessionState::new();
+    let quantum = app_state.quantum;
+
+    loop {
+        let Some(origin) = playing_since(&app_state) else {
+            std::thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        let mut connections = connect(&app_state);
+        if connections.is_empty() {
+            while playing_since(&app_state).is_some() {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            continue;
+        }
+
+        let mut started = false;
+        for pulse in 0_u64.. {
+            let beat = origin + pulse as f64 / PULSES_PER_QUARTER_NOTE;
+
+            // Wait for the pulse, stopping as soon as nothing plays
+            let playing = loop {
+                if playing_since(&app_state).is_none() {
+                    break false;
+                }
+                app_state.link.capture_app_session_state(&mut session_state);
+                let current_beat = session_state.beat_at_time(app_state.clock.micros(), quantum);
+                if current_beat >= beat {
+                    break true;
+                }
+                let sleep_time = (beat - current_beat) * 60.0 / session_state.tempo();
+                std::thread::sleep(Duration::from_secs_f64(sleep_time).min(POLL_INTERVAL));
+            };
+            if !playing {
+                break;
+            }
+
+            if !started {
+                info!("starting MIDI clock on beat {beat}");
+                send(&mut connections, START);
+                started = true;
+            }
+            send(&mut connections, TIMING_CLOCK);
+        }
+
+        if started {
+            info!("stopping MIDI clock");
+            send(&mut connections, STOP);
+        }
+    }
+}
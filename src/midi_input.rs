@@ -0,0 +1,204 @@
+//! Triggering blocks from MIDI controllers
+//!
+//! Harmonia listens on all MIDI input ports, so pad controllers and foot switches can start and
+//! stop blocks like keybinds do. Trigger of the block is assigned with MIDI learn: after learn is
+//! requested for the block, the next pressed note or controller becomes it's trigger. Pressing
+//! the trigger starts the block, or stops it when it's already playing.
+//!
+//! Notes trigger on Note On with non-zero velocity, controllers on any non-zero value, so foot
+//! switches trigger once per press. Ports connected later are picked up within
+//! [RESCAN_INTERVAL].
+
+use crate::{audio_engine, AppState};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use midly::{live::LiveEvent, MidiMessage};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::mpsc, sync::Arc, time::Duration};
+use tracing::{error, info, warn};
+
+/// How often list of MIDI input ports is checked for new or removed ports
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Incoming MIDI message that triggers the block
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Note pressed on given channel
+    Note {
+        /// Channel counted from 0
+        channel: u8,
+
+        /// Key of the note
+        note: u8,
+    },
+
+    /// Controller set to non-zero value on given channel
+    Control {
+        /// Channel counted from 0
+        channel: u8,
+
+        /// Number of the controller
+        controller: u8,
+    },
+}
+
+impl Trigger {
+    /// Trigger matching given MIDI message, [None] for releases and messages that can't trigger
+    pub fn from_message(bytes: &[u8]) -> Option<Trigger> {
+        let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(bytes) else {
+            return None;
+        };
+        let channel = channel.as_int();
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => Some(Trigger::Note {
+                channel,
+                note: key.as_int(),
+            }),
+            MidiMessage::Controller { controller, value } if value > 0 => Some(Trigger::Control {
+                channel,
+                controller: controller.as_int(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Trigger {
+    /// Format as shown in UI, with channels counted from 1
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::Note { channel, note } => write!(f, "Note {note} ch{}", channel + 1),
+            Trigger::Control {
+                channel,
+                controller,
+            } => write!(f, "CC {controller} ch{}", channel + 1),
+        }
+    }
+}
+
+/// Listen on all MIDI input ports and act on received triggers, never returns
+///
+/// Runs on it's own thread, triggered blocks are played on given runtime.
+pub fn run(app_state: Arc<AppState>, runtime: tokio::runtime::Handle) {
+    let (sender, receiver) = mpsc::channel();
+    let mut connections: HashMap<String, MidiInputConnection<()>> = HashMap::new();
+
+    loop {
+        rescan(&mut connections, &sender);
+
+        let deadline = std::time::Instant::now() + RESCAN_INTERVAL;
+        while let Some(timeout) = deadline.checked_duration_since(std::time::Instant::now()) {
+            match receiver.recv_timeout(timeout) {
+                Ok(trigger) => {
+                    runtime.spawn(handle(app_state.clone(), trigger));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    unreachable!("sender is owned by this loop")
+                }
+            }
+        }
+    }
+}
+
+/// Connect to new input ports and forget ports that disappeared
+fn rescan(
+    connections: &mut HashMap<String, MidiInputConnection<()>>,
+    sender: &mpsc::Sender<Trigger>,
+) {
+    let Ok(input) = MidiInput::new("harmonia-input") else {
+        warn!("failed to create MIDI input");
+        return;
+    };
+    let port_names: Vec<_> = input
+        .ports()
+        .iter()
+        .filter_map(|port| input.port_name(port).ok().map(|name| (port.clone(), name)))
+        .collect();
+
+    connections.retain(|connected, _| {
+        let present = port_names.iter().any(|(_, name)| name == connected);
+        if !present {
+            info!("MIDI input port {connected:?} disconnected");
+        }
+        present
+    });
+
+    for (port, port_name) in port_names {
+        if connections.contains_key(&port_name) {
+            continue;
+        }
+
+        let Ok(mut input) = MidiInput::new("harmonia-input") else {
+            continue;
+        };
+        input.ignore(Ignore::All);
+        let sender = sender.clone();
+        match input.connect(
+            &port,
+            "harmonia-input",
+            move |_, bytes, _| {
+                if let Some(trigger) = Trigger::from_message(bytes) {
+                    // Receiver lives as long as the connections, so send cannot fail
+                    let _ = sender.send(trigger);
+                }
+            },
+            (),
+        ) {
+            Ok(connection) => {
+                info!("listening for triggers on MIDI input port {port_name:?}");
+                connections.insert(port_name, connection);
+            }
+            Err(err) => warn!("failed to connect to MIDI input port {port_name:?}: {err}"),
+        }
+    }
+}
+
+/// Assign trigger to the block waiting for MIDI learn, or start and stop blocks it triggers
+async fn handle(app_state: Arc<AppState>, trigger: Trigger) {
+    let learning = app_state.midi_learn.lock().unwrap().take();
+    if let Some(uuid) = learning {
+        {
+            let mut blocks = app_state.blocks.write().unwrap();
+            let Some(block) = blocks.get_mut(&uuid) else {
+                warn!("block#{uuid} waiting for MIDI learn disappeared");
+                return;
+            };
+            info!("learned MIDI trigger {trigger} for block#{uuid}");
+            block.midi_trigger = Some(trigger);
+        }
+        if let Err(err) = app_state.remember_current_blocks() {
+            error!("MIDI learn failed to remember current sources: {err:#}")
+        }
+        return;
+    }
+
+    let triggered: Vec<String> = app_state
+        .blocks
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, block)| block.midi_trigger == Some(trigger))
+        .map(|(uuid, _)| uuid.clone())
+        .collect();
+
+    for uuid in triggered {
+        let playing = app_state
+            .currently_playing
+            .read()
+            .unwrap()
+            .contains_key(&uuid);
+        info!(
+            "MIDI trigger {trigger} {} block#{uuid}",
+            if playing { "stops" } else { "starts" }
+        );
+        let result = if playing {
+            audio_engine::interrupt_block(app_state.clone(), &uuid).await
+        } else {
+            audio_engine::play(app_state.clone(), &uuid).await
+        };
+        if let Err(err) = result {
+            error!("MIDI trigger {trigger} of block#{uuid} failed: {err}");
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Muting and soloing MIDI channels of blocks during playback
+//!
+//! During rehearsal a player may want to drop a part (or hear only one) without editing the file.
+//! Channels of a block can be muted or soloed from the UI while it plays, and
+//! [audio_engine][crate::audio_engine] drops note ons of channels that are not heard, see
+//! [allows]. When any channel is soloed, only soloed channels are heard.
+//!
+//! Only note ons are dropped, so notes already sounding when channel is muted end with their own
+//! note offs and controllers keep their values for when channel is heard again.
+//!
+//! Mix is kept in memory only: it's not a part of the block, so it's not stored, backed up nor
+//! pulled by other instances, and it's cleared when Harmonia restarts.
+
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Form,
+};
+use maud::{html, Markup};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::info;
+
+/// Muted and soloed channels of a block
+///
+/// Bit `n` is set when channel `n` (counted from 0) is muted or soloed, like in
+/// [ChannelFilter][crate::channels::ChannelFilter].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mix {
+    /// Muted channels
+    muted: u16,
+
+    /// Soloed channels
+    soloed: u16,
+}
+
+impl Mix {
+    /// Whether nothing is muted or soloed
+    pub fn is_empty(&self) -> bool {
+        self.muted == 0 && self.soloed == 0
+    }
+
+    /// Whether given channel (counted from 0) is muted
+    pub fn is_muted(&self, channel: u8) -> bool {
+        self.muted & (1 << channel) != 0
+    }
+
+    /// Whether given channel (counted from 0) is soloed
+    pub fn is_soloed(&self, channel: u8) -> bool {
+        self.soloed & (1 << channel) != 0
+    }
+
+    /// Whether given channel (counted from 0) is heard
+    pub fn allows(&self, channel: u8) -> bool {
+        if self.soloed != 0 {
+            self.is_soloed(channel)
+        } else {
+            !self.is_muted(channel)
+        }
+    }
+}
+
+/// Whether given channel (counted from 0) of the block is heard
+pub fn allows(app_state: &AppState, uuid: &str, channel: u8) -> bool {
+    app_state
+        .mixes
+        .read()
+        .unwrap()
+        .get(uuid)
+        .map_or(true, |mix| mix.allows(channel))
+}
+
+/// Render mute and solo toggles of all channels of the block, expanded when `open`
+pub fn render(uuid: &str, mix: Mix, open: bool) -> Markup {
+    let toggle = |kind: Kind, channel: u8, on: bool| {
+        let number = channel + 1;
+        html! {
+            button
+                class=(if on { "on" } else { "" })
+                hx-post=(format!("/blocks/mix/{uuid}"))
+                hx-vals=(format!("{{\"channel\": {number}, \"kind\": \"{}\"}}", kind.name()))
+            {
+                (number)
+            }
+        }
+    };
+
+    html! {
+        details class="mix" hx-target="this" hx-swap="outerHTML" open[open] {
+            summary {
+                "Mute and solo"
+                @if !mix.is_empty() {
+                    " (active)"
+                }
+            }
+            div {
+                span { "M" }
+                @for channel in 0..16 {
+                    (toggle(Kind::Mute, channel, mix.is_muted(channel)))
+                }
+            }
+            div {
+                span { "S" }
+                @for channel in 0..16 {
+                    (toggle(Kind::Solo, channel, mix.is_soloed(channel)))
+                }
+            }
+            button hx-delete=(format!("/blocks/mix/{uuid}")) disabled[mix.is_empty()] {
+                "Hear all channels"
+            }
+        }
+    }
+}
+
+/// Flag toggled by [toggle]
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Channel is not heard
+    Mute,
+
+    /// Only soloed channels are heard
+    Solo,
+}
+
+impl Kind {
+    /// Name of the flag, the same as in the form
+    fn name(self) -> &'static str {
+        match self {
+            Self::Mute => "mute",
+            Self::Solo => "solo",
+        }
+    }
+}
+
+/// Payload toggling mute or solo of the channel
+#[derive(Deserialize)]
+pub struct Toggle {
+    /// Channel, counted from 1
+    channel: u8,
+
+    /// Toggled flag
+    kind: Kind,
+}
+
+/// Toggle mute or solo of the channel of given block, heard on the next note
+pub async fn toggle(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+    Form(Toggle { channel, kind }): Form<Toggle>,
+) -> Result<Markup, (StatusCode, String)> {
+    if !app_state.blocks.read().unwrap().contains_key(&uuid) {
+        return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+    }
+    if !(1..=16).contains(&channel) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid channel {channel}, expected number from 1 to 16"),
+        ));
+    }
+
+    let mix = {
+        let mut mixes = app_state.mixes.write().unwrap();
+        let mix = mixes.entry(uuid.clone()).or_default();
+        match kind {
+            Kind::Mute => mix.muted ^= 1 << (channel - 1),
+            Kind::Solo => mix.soloed ^= 1 << (channel - 1),
+        }
+        let mix = *mix;
+        if mix.is_empty() {
+            mixes.remove(&uuid);
+        }
+        mix
+    };
+    info!(
+        "toggled {} of channel {channel} in block#{uuid}",
+        kind.name()
+    );
+    Ok(render(&uuid, mix, true))
+}
+
+/// Unmute and unsolo all channels of given block
+pub async fn clear(State(app_state): State<Arc<AppState>>, Path(uuid): Path<String>) -> Markup {
+    app_state.mixes.write().unwrap().remove(&uuid);
+    info!("cleared mute and solo of block#{uuid}");
+    render(&uuid, Mix::default(), true)
+}
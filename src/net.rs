@@ -4,59 +4,150 @@
 //! Group of sockets represent all of the IPv4 interfaces that can be binded to
 //! and listened on.
 // TODO: Support IPv6?
+use crate::interfaces::{self, InterfaceFilter};
+use hmac::Mac;
+use std::collections::{HashSet, VecDeque};
 use std::net::{IpAddr, Ipv4Addr, SocketAddrV4};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// HMAC used to authenticate packets, see [Sockets::set_key]
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+/// Length of the tag appended to packets when session key is set
+const TAG_LENGTH: usize = 32;
+
+/// Length of the stamp (time of sending and counter) signed together with the packet
+const STAMP_LENGTH: usize = 16;
+
+/// How far time of sending of signed packet may be from the time of receiving it
+///
+/// Older (or newer) packets are dropped, so recorded packets can't be replayed later. Clocks of
+/// computers sharing session key must be synchronized within this margin, which NTP easily does.
+const MAX_PACKET_AGE: Duration = Duration::from_secs(10);
+
+/// Shared session key, absent when packets are not authenticated
+type SessionKey = Arc<RwLock<Option<Vec<u8>>>>;
 
 /// Collection of references to sockets on all IPv4 interfaces
 pub struct Sockets {
     /// Sockets bound for each network interface
     pub sockets: Vec<Arc<tokio::net::UdpSocket>>,
+
+    /// Names of interfaces to which [Sockets::sockets] are bound
+    pub interfaces: Vec<String>,
+
+    /// Multicast address to which packets are sent
+    target: std::net::SocketAddr,
+
+    /// Key with which sent packets are signed and received verified, see [Sockets::set_key]
+    key: SessionKey,
+
+    /// Counter of sent packets, signed so packets sent at the same time differ
+    sent: AtomicU64,
+
+    /// Signed packets received recently, shared by all sockets
+    replays: Arc<Mutex<Replays>>,
 }
 
 impl Sockets {
-    /// Bind multicast to all interfaces
+    /// Bind given multicast address to all interfaces allowed by the filter
     ///
     /// Why bind to all interfaces? From testing binding to 0.0.0.0 will make OS bind to the
     /// gateway interface. For this reason connection from for example host to vm will not work
-    pub fn bind(enabled: bool) -> Self {
+    pub fn bind_to(enabled: bool, target: std::net::SocketAddr, filter: &InterfaceFilter) -> Self {
         if !enabled {
             return Self {
                 sockets: Default::default(),
+                interfaces: Default::default(),
+                target,
+                key: Default::default(),
+                sent: Default::default(),
+                replays: Default::default(),
             };
         }
-        let sockets: Vec<_> = get_current_ipv4_addresses()
+        let (sockets, interfaces): (Vec<_>, Vec<_>) = interfaces::list()
             .into_iter()
-            .filter_map(|addr| match open_multicast(addr) {
-                Ok(socket) => Some(Arc::new(socket)),
+            .filter(|(name, addr)| {
+                let allowed = filter.allows(name, *addr);
+                if !allowed {
+                    tracing::info!("skipping interface {name} ({addr}) excluded by filter");
+                }
+                allowed
+            })
+            .filter_map(|(name, addr)| match open_multicast(addr, target) {
+                Ok(socket) => Some((Arc::new(socket), name)),
                 Err(error) => {
                     tracing::error!(
-                        "failed to open multicast socket for interface {addr}: {error}"
+                        "failed to open multicast socket for interface {name} ({addr}): {error}"
                     );
                     None
                 }
             })
-            .collect();
-        assert!(!sockets.is_empty());
-        Self { sockets }
+            .unzip();
+        if sockets.is_empty() {
+            tracing::error!("no network interface could be bound to {target}, synchronization with others is disabled");
+        }
+        Self {
+            sockets,
+            interfaces,
+            target,
+            key: Default::default(),
+            sent: Default::default(),
+            replays: Default::default(),
+        }
+    }
+
+    /// Set pre-shared key authenticating packets, or stop authenticating them with `None`
+    ///
+    /// With the key every sent packet is followed by the time of sending, a counter and HMAC-SHA256
+    /// of all of them. Received packets without matching HMAC are dropped, so only instances knowing
+    /// the key can affect this one, and so are packets older than [MAX_PACKET_AGE] or received
+    /// already, so recorded packets can't be replayed.
+    pub fn set_key(&self, key: Option<&str>) {
+        *self.key.write().unwrap() = key.map(|key| key.as_bytes().to_vec());
+    }
+
+    /// Whether packets are authenticated with the session key
+    pub fn is_authenticated(&self) -> bool {
+        self.key.read().unwrap().is_some()
     }
 
     /// Send group frame via all sockets (= all interfaces)
-    pub async fn send(&self, frame: crate::GroupFrame) {
+    ///
+    /// Frame is followed by ghost time `sent_at` used to measure [clocks][crate::clocks] of peers.
+    /// Older versions ignore it, since bincode allows trailing bytes.
+    pub async fn send(&self, frame: crate::GroupFrame, sent_at: i64) {
         tracing::debug!("sending packet: {frame}");
-        let packet = bincode::serialize(&frame).unwrap();
-
-        let target = multicast();
+        let mut packet = bincode::serialize(&frame).unwrap();
+        packet.extend(bincode::serialize(&sent_at).unwrap());
+        self.send_bytes(&packet).await;
+    }
 
+    /// Send already encoded packet via all sockets (= all interfaces)
+    ///
+    /// Packet is signed when session key is set, see [Sockets::set_key].
+    pub async fn send_bytes(&self, packet: &[u8]) {
+        let counter = self.sent.fetch_add(1, Ordering::Relaxed);
+        let packet = sign(&self.key, packet, counter);
+        let packet = &packet[..];
         for socket in &self.sockets {
             // TODO: Don't ignore but ignore socket when it continously fails.
-            let _ = socket.send_to(&packet, target).await;
+            let _ = socket.send_to(packet, self.target).await;
         }
     }
 
     /// Listen on all interfaces and send incoming packets to negotiator.
+    ///
+    /// [PanicFrame][crate::PanicFrame]s are passed to `panics` instead, once per panic even
+    /// though each is received several times.
     pub async fn listen(
         &self,
         state: tokio::sync::mpsc::Sender<crate::Action>,
+        panics: tokio::sync::broadcast::Sender<()>,
+        clocks: Arc<crate::clocks::Measurements>,
+        link: Arc<rusty_link::AblLink>,
         mut wait_for_cancel: tokio::sync::mpsc::Receiver<()>,
     ) {
         tracing::info!("Started linky_groups");
@@ -65,22 +156,81 @@ impl Sockets {
 
         let mut workers = tokio::task::JoinSet::new();
 
+        let seen_panics = Arc::new(std::sync::Mutex::new(std::collections::VecDeque::new()));
+
         for socket in &self.sockets {
             let socket = socket.clone();
             let frames_out = frames_out.clone();
+            let panics = panics.clone();
+            let seen_panics = seen_panics.clone();
+            let key = self.key.clone();
+            let replays = self.replays.clone();
+            let clocks = clocks.clone();
+            let link = link.clone();
 
             workers.spawn(async move {
-                let mut buf = [0u8; std::mem::size_of::<crate::GroupFrame>()];
+                let mut buf = [0u8; std::mem::size_of::<crate::GroupFrame>()
+                    + std::mem::size_of::<i64>()
+                    + STAMP_LENGTH
+                    + TAG_LENGTH];
                 loop {
                     // TODO: This may fail for legitimate reasons, so don't just unwrap it.
                     let (len, remote) = socket.recv_from(&mut buf).await.unwrap();
-                    let frame: crate::GroupFrame = match bincode::deserialize(&buf[..len]) {
+                    let packet = match verify(&key, &replays, &buf[..len]) {
+                        Ok(packet) => packet,
+                        // Packets are received once on each interface
+                        Err(Rejection::Repeated) => {
+                            tracing::debug!("dropping repeated packet from {remote}");
+                            continue;
+                        }
+                        Err(rejection) => {
+                            tracing::warn!("dropping {rejection} packet from {remote}");
+                            continue;
+                        }
+                    };
+                    if packet.starts_with(&crate::PanicFrame::MAGIC) {
+                        let frame: crate::PanicFrame = match bincode::deserialize(packet) {
+                            Ok(v) => v,
+                            Err(err) => {
+                                tracing::error!("Failed to decode bincoded PanicFrame: {err}");
+                                continue;
+                            }
+                        };
+                        if !frame.is_supported() {
+                            tracing::error!("Frame {frame:?} is not supported");
+                            continue;
+                        }
+
+                        // Panic is repeated and received on each interface, act on it only once
+                        let mut seen_panics = seen_panics.lock().unwrap();
+                        if seen_panics.contains(&frame.id) {
+                            continue;
+                        }
+                        if seen_panics.len() >= SEEN_PANICS_CAPACITY {
+                            seen_panics.pop_front();
+                        }
+                        seen_panics.push_back(frame.id);
+                        drop(seen_panics);
+
+                        tracing::warn!("received panic from {remote}");
+                        // No receivers only means that nobody listens for panics
+                        let _ = panics.send(());
+                        continue;
+                    }
+
+                    let received_at = link.host_to_ghost(link.clock_micros());
+                    let mut packet = packet;
+                    let frame: crate::GroupFrame = match bincode::deserialize_from(&mut packet) {
                         Ok(v) => v,
                         Err(err) => {
                             tracing::error!("Failed to decode bincoded GroupFrame: {err}");
                             continue;
                         }
                     };
+                    // Frames from older versions are not followed by the time of sending
+                    if let Ok(sent_at) = bincode::deserialize_from::<_, i64>(&mut packet) {
+                        clocks.record(remote.ip(), sent_at, received_at);
+                    }
                     // TODO: Gracefully handle this unwrap
                     frames_out.send((frame, remote)).await.unwrap();
                 }
@@ -107,30 +257,140 @@ impl Sockets {
     }
 }
 
-/// Get all IPv4 interface addresses on local machine
-fn get_current_ipv4_addresses() -> Vec<Ipv4Addr> {
-    local_ip_address::list_afinet_netifas()
+/// How many recently received panics are remembered to ignore their repetitions
+const SEEN_PANICS_CAPACITY: usize = 16;
+
+/// Microseconds since Unix epoch, the time of sending in stamps of signed packets
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_micros() as u64)
+}
+
+/// Append time of sending, `counter` and HMAC of all of them when session key is set
+fn sign(key: &SessionKey, packet: &[u8], counter: u64) -> Vec<u8> {
+    let mut signed = packet.to_vec();
+    if let Some(key) = key.read().unwrap().as_deref() {
+        signed.extend_from_slice(&now_micros().to_le_bytes());
+        signed.extend_from_slice(&counter.to_le_bytes());
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&signed);
+        signed.extend_from_slice(&mac.finalize().into_bytes());
+    }
+    signed
+}
+
+/// Why received packet was dropped, see [verify]
+#[derive(Debug)]
+enum Rejection {
+    /// HMAC is missing or doesn't match
+    Unauthenticated,
+
+    /// Sent more than [MAX_PACKET_AGE] from now
+    Stale,
+
+    /// The same packet was already received
+    Repeated,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthenticated => write!(f, "unauthenticated"),
+            Self::Stale => write!(f, "stale"),
+            Self::Repeated => write!(f, "repeated"),
+        }
+    }
+}
+
+/// Tags of signed packets received within [MAX_PACKET_AGE], to drop their replays
+#[derive(Default)]
+struct Replays {
+    /// Tags of received packets
+    seen: HashSet<[u8; TAG_LENGTH]>,
+
+    /// Time of sending and tag of received packets, in the order of receiving
+    received: VecDeque<(u64, [u8; TAG_LENGTH])>,
+}
+
+impl Replays {
+    /// Remember packet sent at given time, returns false when it was already received
+    ///
+    /// Packets sent before `oldest` are forgotten, they are rejected as stale anyway.
+    fn remember(&mut self, sent_at: u64, tag: [u8; TAG_LENGTH], oldest: u64) -> bool {
+        while let Some((_, forgotten)) = self
+            .received
+            .front()
+            .filter(|(sent_at, _)| *sent_at < oldest)
+        {
+            self.seen.remove(forgotten);
+            self.received.pop_front();
+        }
+        if !self.seen.insert(tag) {
+            return false;
+        }
+        self.received.push_back((sent_at, tag));
+        true
+    }
+}
+
+/// Strip and check stamp and HMAC of the packet when session key is set
+///
+/// Without session key packets are accepted as they are, trailing stamps and tags are ignored by
+/// decoding.
+fn verify<'packet>(
+    key: &SessionKey,
+    replays: &Mutex<Replays>,
+    packet: &'packet [u8],
+) -> Result<&'packet [u8], Rejection> {
+    let Some(key) = key.read().unwrap().clone() else {
+        return Ok(packet);
+    };
+    let signed_length = packet
+        .len()
+        .checked_sub(TAG_LENGTH)
+        .filter(|length| *length >= STAMP_LENGTH)
+        .ok_or(Rejection::Unauthenticated)?;
+    let (signed, tag) = packet.split_at(signed_length);
+    let mut mac = HmacSha256::new_from_slice(&key).expect("HMAC accepts keys of any length");
+    mac.update(signed);
+    mac.verify_slice(tag)
+        .map_err(|_| Rejection::Unauthenticated)?;
+
+    let (payload, stamp) = signed.split_at(signed.len() - STAMP_LENGTH);
+    let sent_at = u64::from_le_bytes(stamp[..8].try_into().unwrap());
+    let now = now_micros();
+    let max_age = MAX_PACKET_AGE.as_micros() as u64;
+    if sent_at.abs_diff(now) > max_age {
+        return Err(Rejection::Stale);
+    }
+
+    let tag = tag.try_into().unwrap();
+    if !replays
+        .lock()
         .unwrap()
-        .iter()
-        .map(|(_, address)| *address)
-        .filter(|address| address.is_ipv4())
-        .map(|address| match address {
-            IpAddr::V4(v4) => v4,
-            IpAddr::V6(_) => unreachable!(),
-        })
-        .collect()
+        .remember(sent_at, tag, now.saturating_sub(max_age))
+    {
+        return Err(Rejection::Repeated);
+    }
+    Ok(payload)
 }
 
-/// Get multicast address for linky_groups communication
-fn multicast() -> std::net::SocketAddr {
-    std::net::SocketAddr::new(Ipv4Addr::new(224, 76, 78, 75).into(), 20810)
+/// Get multicast address for [discovery][crate::discovery] announcements, given address used by
+/// [GroupFrame][crate::GroupFrame]s
+///
+/// Uses the next port, so instances that don't know about discovery don't receive announcements.
+/// Port of the group multicast is never the last one, see [parse_multicast][crate::parse_multicast].
+pub fn discovery_multicast(multicast: std::net::SocketAddr) -> std::net::SocketAddr {
+    std::net::SocketAddr::new(multicast.ip(), multicast.port() + 1)
 }
 
 // TODO: Support IPv6
 /// Create UDP multicast capable socket for given IPv4 interface.
-fn open_multicast(interface: Ipv4Addr) -> std::io::Result<tokio::net::UdpSocket> {
-    let multicast = multicast();
-
+fn open_multicast(
+    interface: Ipv4Addr,
+    multicast: std::net::SocketAddr,
+) -> std::io::Result<tokio::net::UdpSocket> {
     let socket = socket2::Socket::new(
         socket2::Domain::IPV4,
         socket2::Type::DGRAM,
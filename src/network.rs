@@ -0,0 +1,202 @@
+//! Choosing network interfaces used for synchronization
+//!
+//! Interfaces used by [linky_groups] are chosen with `--interface` and `--exclude-interface`
+//! flags (see [InterfaceFilter]) and can additionally be turned off in the UI. Interfaces turned
+//! off are stored in [DISABLED_INTERFACES_PATH]. Sockets are bound when Harmonia starts, so like
+//! the flags changes take effect after restart.
+//!
+//! Synchronization frames can be authenticated with a pre-shared session key, given with
+//! `--session-key` or set in the UI (see [set_session_key]), so others in the network can't
+//! restart the beat grid of the orchestra. Key set in the UI takes effect immediately and is stored
+//! in [SESSION_KEY_PATH].
+
+use crate::{cache_path, AppState};
+use axum::{extract::State, Form};
+use linky_groups::interfaces::{self, InterfaceFilter, InterfaceRule};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc};
+use tracing::{info, warn};
+
+/// Filename under which Harmonia stores interfaces turned off in the UI
+pub const DISABLED_INTERFACES_PATH: &str = "harmonia_disabled_interfaces.bson";
+
+/// Filename under which Harmonia stores session key set in the UI
+pub const SESSION_KEY_PATH: &str = "harmonia_session_key.txt";
+
+/// Interfaces turned off in the UI, as stored in [DISABLED_INTERFACES_PATH]
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    /// Names of interfaces turned off
+    disabled: BTreeSet<String>,
+}
+
+/// Load interfaces stored in [DISABLED_INTERFACES_PATH], missing or invalid file means none
+pub fn load() -> BTreeSet<String> {
+    std::fs::read(cache_path().join(DISABLED_INTERFACES_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice::<Stored>(&bytes).ok())
+        .map(|stored| stored.disabled)
+        .unwrap_or_default()
+}
+
+/// Load session key stored in [SESSION_KEY_PATH]
+pub fn load_session_key() -> Option<String> {
+    std::fs::read_to_string(cache_path().join(SESSION_KEY_PATH))
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+/// Combine filter from the command line with interfaces turned off in the UI
+pub fn filter(flags: &InterfaceFilter, disabled: &BTreeSet<String>) -> InterfaceFilter {
+    let mut filter = flags.clone();
+    filter
+        .deny
+        .extend(disabled.iter().cloned().map(InterfaceRule::Name));
+    filter
+}
+
+/// Render list of interfaces with toggles turning them on and off
+pub async fn render(State(app_state): State<Arc<AppState>>) -> Markup {
+    let disabled = app_state.disabled_interfaces.read().unwrap().clone();
+    let used = app_state
+        .groups
+        .as_ref()
+        .map(|groups| groups.interfaces().to_vec())
+        .unwrap_or_default();
+
+    html! {
+        ul {
+            @for (name, address) in interfaces::list() {
+                li {
+                    form hx-post="/network/interfaces" hx-trigger="change" hx-target="#interfaces" hx-swap="innerHTML" {
+                        input type="hidden" name="name" value=(name);
+                        label {
+                            input type="checkbox" name="enabled" value="on" checked[!disabled.contains(&name)];
+                            " " (name) " (" (address) ")";
+                        }
+                        @if !app_state.interface_filter.allows(&name, address) {
+                            " - excluded by command line"
+                        } @else if used.contains(&name) {
+                            " - used"
+                        }
+                    }
+                }
+            }
+        }
+        p { "Changes take effect after restart" }
+    }
+}
+
+/// Payload turning interface on or off
+#[derive(Deserialize)]
+pub struct SetInterface {
+    /// Name of the interface
+    name: String,
+
+    /// Present when interface is turned on
+    enabled: Option<String>,
+}
+
+/// Turn interface on or off, taking effect after restart
+pub async fn set_interface(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetInterface { name, enabled }): Form<SetInterface>,
+) -> Markup {
+    let disabled = {
+        let mut disabled = app_state.disabled_interfaces.write().unwrap();
+        if enabled.is_some() {
+            info!("interface {name} turned on for synchronization");
+            disabled.remove(&name);
+        } else {
+            info!("interface {name} turned off for synchronization");
+            disabled.insert(name);
+        }
+        disabled.clone()
+    };
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(DISABLED_INTERFACES_PATH);
+        match bson::to_vec(&Stored { disabled }) {
+            Ok(bytes) => {
+                if let Err(err) = std::fs::write(&path, bytes) {
+                    warn!("failed to write disabled interfaces to {path:?}: {err}");
+                }
+            }
+            Err(err) => warn!("failed to serialize disabled interfaces: {err}"),
+        }
+    }
+
+    render(State(app_state)).await
+}
+
+/// Render form setting session key, without revealing the current one
+pub async fn session_key(State(app_state): State<Arc<AppState>>) -> Markup {
+    let authenticated = app_state
+        .groups
+        .as_ref()
+        .map_or(false, linky_groups::Groups::is_authenticated);
+
+    html! {
+        p {
+            @if authenticated {
+                "Synchronization is authenticated, frames from instances without the same key are ignored"
+            } @else {
+                "Synchronization is not authenticated, anyone in the network can affect groups"
+            }
+        }
+        form hx-post="/network/session-key" hx-target="#session-key" hx-swap="innerHTML" {
+            input type="password" name="key" placeholder="Session key" autocomplete="off";
+            " ";
+            button { "Set session key" }
+        }
+        @if authenticated {
+            form hx-post="/network/session-key" hx-target="#session-key" hx-swap="innerHTML" {
+                input type="hidden" name="key" value="";
+                button { "Remove session key" }
+            }
+        }
+    }
+}
+
+/// Payload setting session key
+#[derive(Deserialize)]
+pub struct SetSessionKey {
+    /// New session key, empty turns authentication off
+    key: String,
+}
+
+/// Set session key authenticating synchronization frames, taking effect immediately
+pub async fn set_session_key(
+    State(app_state): State<Arc<AppState>>,
+    Form(SetSessionKey { key }): Form<SetSessionKey>,
+) -> Markup {
+    let key = key.trim();
+    let key = (!key.is_empty()).then_some(key);
+    if let Some(groups) = &app_state.groups {
+        groups.set_session_key(key);
+    }
+    info!(
+        "session key {}",
+        if key.is_some() { "set" } else { "removed" }
+    );
+
+    if !app_state.ephemeral {
+        let path = cache_path().join(SESSION_KEY_PATH);
+        let result = match key {
+            Some(key) => std::fs::write(&path, key),
+            None => std::fs::remove_file(&path).or_else(|err| {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    Ok(())
+                } else {
+                    Err(err)
+                }
+            }),
+        };
+        if let Err(err) = result {
+            warn!("failed to store session key in {path:?}: {err}");
+        }
+    }
+
+    session_key(State(app_state)).await
+}
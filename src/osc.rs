@@ -0,0 +1,124 @@
+//! OSC control server for triggering blocks
+//!
+//! When Harmonia is started with `--osc-port`, it listens for [OSC] messages on this UDP port, so
+//! hardware controllers and other software can start and stop blocks without HTTP:
+//!
+//! - `/harmonia/play <uuid>` starts block with given identifier, see [audio_engine::play]; blocks
+//!   running programs only from this computer, see [handlers::ensure_local_programs]
+//! - `/harmonia/interrupt` stops everything, see [audio_engine::interrupt]
+//! - `/harmonia/interrupt <uuid>` stops only given block, see [audio_engine::interrupt_block]
+//!
+//! Messages of bundles are handled one after another, time tags are ignored. Handled messages are
+//! recorded in [audit] log like HTTP requests, with `OSC` as their method.
+//!
+//! OSC messages cannot carry the token, so when Harmonia runs with `--token` or
+//! `--read-only-remote` messages are accepted only from this computer, see [access].
+//!
+//! [OSC]: https://opensoundcontrol.stanford.edu/spec-1_0.html
+
+use crate::{access, audio_engine, audit, handlers, AppState};
+use axum::http::HeaderMap;
+use rosc::{OscMessage, OscPacket, OscType};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::net::UdpSocket;
+use tracing::{error, info, warn};
+
+/// Listen for OSC messages on given address until Harmonia stops
+pub async fn listen(app_state: Arc<AppState>, ip: String, port: u16) {
+    let socket = match UdpSocket::bind((ip.as_str(), port)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("failed to listen for OSC messages on {ip}:{port}: {err}");
+            return;
+        }
+    };
+    info!("listening for OSC messages on {ip}:{port}");
+
+    let mut buf = vec![0; rosc::decoder::MTU];
+    loop {
+        let (size, address) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("failed to receive OSC packet: {err}");
+                continue;
+            }
+        };
+
+        match rosc::decoder::decode_udp(&buf[..size]) {
+            Ok((_, packet)) => {
+                for message in messages(packet) {
+                    handle(&app_state, address, message).await;
+                }
+            }
+            Err(err) => warn!("invalid OSC packet from {address}: {err:?}"),
+        }
+    }
+}
+
+/// Flatten bundles into their messages, in order
+fn messages(packet: OscPacket) -> Vec<OscMessage> {
+    match packet {
+        OscPacket::Message(message) => vec![message],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(messages).collect(),
+    }
+}
+
+/// Execute single OSC message and record it in the [audit] log
+async fn handle(app_state: &Arc<AppState>, address: SocketAddr, message: OscMessage) {
+    let time = chrono::Local::now().to_rfc3339();
+
+    let result = match (message.addr.as_str(), message.args.as_slice()) {
+        _ if !access::can_change(app_state, &address, &HeaderMap::new()) => Err((
+            403,
+            "OSC messages are accepted only from this computer".to_string(),
+        )),
+        ("/harmonia/play", [OscType::String(uuid)]) => {
+            if !app_state.blocks.read().unwrap().contains_key(uuid) {
+                Err((404, format!("block#{uuid} not found")))
+            } else if let Err((status, err)) =
+                handlers::ensure_local_programs(app_state, address, [uuid.as_str()])
+            {
+                Err((status.as_u16(), err))
+            } else {
+                audio_engine::play(app_state.clone(), uuid)
+                    .await
+                    .map_err(|err| (500, err))
+            }
+        }
+        ("/harmonia/interrupt", []) => audio_engine::interrupt(app_state.clone())
+            .await
+            .map_err(|err| (500, err)),
+        ("/harmonia/interrupt", [OscType::String(uuid)]) => {
+            audio_engine::interrupt_block(app_state.clone(), uuid)
+                .await
+                .map_err(|err| (500, err))
+        }
+        (addr, args) => Err((400, format!("unknown OSC message {addr} {args:?}"))),
+    };
+
+    let status = match result {
+        Ok(()) => 200,
+        Err((status, err)) => {
+            warn!("OSC message from {address} failed: {err}");
+            status
+        }
+    };
+
+    let path = std::iter::once(message.addr.clone())
+        .chain(message.args.iter().map(|arg| match arg {
+            OscType::String(s) => s.clone(),
+            arg => format!("{arg:?}"),
+        }))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    info!("audit: OSC {path} from {address} responded with {status}");
+    app_state.audit.lock().unwrap().push(audit::Entry {
+        time,
+        method: "OSC".to_string(),
+        path,
+        address,
+        user_agent: None,
+        status,
+    });
+}
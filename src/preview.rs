@@ -0,0 +1,209 @@
+//! Audition of MIDI blocks through SoundFont, without any synthesizer connected
+//!
+//! Available with `soundfont` feature. When Harmonia is started with `--soundfont` pointing to
+//! SoundFont file, MIDI, chord and alias blocks can be rendered with [rustysynth] into WAV file
+//! that browser plays with `GET /blocks/preview/:uuid`. Rendering happens on request and the
+//! result is never stored: it's rendered in chunks of [CHUNK_FRAMES] and streamed to the browser,
+//! so only a chunk is kept in memory. Since any viewer may request previews, at most
+//! [MAX_CONCURRENT_PREVIEWS] are rendered at once and they are cut after [MAX_PREVIEW_SECONDS].
+
+use crate::{block, AppState};
+use anyhow::anyhow;
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::{Path, State},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::IntoResponse,
+};
+use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::info;
+
+/// Sample rate of the rendered audio
+const SAMPLE_RATE: i32 = 44100;
+
+/// Longest rendered preview in seconds, longer blocks are cut
+const MAX_PREVIEW_SECONDS: f64 = 3.0 * 60.0;
+
+/// Number of frames rendered and sent at once, a tenth of a second
+const CHUNK_FRAMES: usize = SAMPLE_RATE as usize / 10;
+
+/// How many previews may be rendered at the same time, further requests are refused
+const MAX_CONCURRENT_PREVIEWS: usize = 2;
+
+/// Permits for previews rendered at the moment, see [MAX_CONCURRENT_PREVIEWS]
+static PREVIEWS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_PREVIEWS);
+
+/// Number of audio channels of the rendered WAV file
+const CHANNELS: u16 = 2;
+
+/// Bits per sample of the rendered WAV file
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Bytes per frame of the rendered WAV file
+const BLOCK_ALIGN: u16 = CHANNELS * BITS_PER_SAMPLE / 8;
+
+/// Load SoundFont from given file
+pub fn load(path: &std::path::Path) -> anyhow::Result<Arc<SoundFont>> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|err| anyhow!("failed to open SoundFont {path:?}: {err}"))?;
+    let sound_font =
+        SoundFont::new(&mut file).map_err(|err| anyhow!("failed to load SoundFont: {err}"))?;
+    Ok(Arc::new(sound_font))
+}
+
+/// Sequencer playing MIDI file with given SoundFont and number of frames to render
+///
+/// Blocks are cut after [MAX_PREVIEW_SECONDS].
+fn sequence(
+    sound_font: &Arc<SoundFont>,
+    midi: &[u8],
+) -> anyhow::Result<(MidiFileSequencer, usize)> {
+    let midi_file = Arc::new(
+        MidiFile::new(&mut std::io::Cursor::new(midi))
+            .map_err(|err| anyhow!("failed to parse MIDI file: {err}"))?,
+    );
+
+    let settings = SynthesizerSettings::new(SAMPLE_RATE);
+    let synthesizer = Synthesizer::new(sound_font, &settings)
+        .map_err(|err| anyhow!("failed to create synthesizer: {err}"))?;
+    let mut sequencer = MidiFileSequencer::new(synthesizer);
+    sequencer.play(&midi_file, false);
+
+    let seconds = midi_file.get_length().min(MAX_PREVIEW_SECONDS);
+    let frames = (seconds * SAMPLE_RATE as f64).ceil() as usize;
+    Ok((sequencer, frames))
+}
+
+/// Render given number of frames into 16-bit stereo WAV file, sent in chunks
+///
+/// Header goes first and each chunk is sent as soon as it's rendered. Rendering stops when the
+/// receiver is dropped, for example when browser closed the connection.
+fn render(mut sequencer: MidiFileSequencer, frames: usize, chunks: mpsc::Sender<Bytes>) {
+    if chunks.blocking_send(wav_header(frames).into()).is_err() {
+        return;
+    }
+
+    let mut left = vec![0.0; CHUNK_FRAMES];
+    let mut right = vec![0.0; CHUNK_FRAMES];
+    let mut rendered = 0;
+    while rendered < frames {
+        let length = CHUNK_FRAMES.min(frames - rendered);
+        sequencer.render(&mut left[..length], &mut right[..length]);
+        rendered += length;
+        if chunks
+            .blocking_send(wav_samples(&left[..length], &right[..length]).into())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Size in bytes of the WAV file with given number of frames
+fn wav_size(frames: usize) -> usize {
+    44 + frames * BLOCK_ALIGN as usize
+}
+
+/// Header of 16-bit stereo PCM WAV file with given number of frames
+fn wav_header(frames: usize) -> Vec<u8> {
+    let data_size = (frames * BLOCK_ALIGN as usize) as u32;
+
+    let mut wav = Vec::with_capacity(44);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE as u32).to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE as u32 * BLOCK_ALIGN as u32).to_le_bytes());
+    wav.extend_from_slice(&BLOCK_ALIGN.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    wav
+}
+
+/// Encode stereo samples as interleaved 16-bit PCM, the data of WAV file
+fn wav_samples(left: &[f32], right: &[f32]) -> Vec<u8> {
+    let mut wav = Vec::with_capacity(left.len() * BLOCK_ALIGN as usize);
+    for (l, r) in left.iter().zip(right) {
+        for sample in [l, r] {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+    }
+    wav
+}
+
+/// Responds with given block rendered through SoundFont as WAV file
+pub async fn preview(
+    State(app_state): State<Arc<AppState>>,
+    Path(uuid): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let Some(sound_font) = app_state.soundfont.clone() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "preview requires starting Harmonia with --soundfont".to_string(),
+        ));
+    };
+
+    let midi = {
+        let blocks = app_state.blocks.read().unwrap();
+        let Some(block) = blocks.get(&uuid) else {
+            return Err((StatusCode::NOT_FOUND, format!("block#{uuid} not found")));
+        };
+        match &block.content {
+            block::Content::Midi(midi_source) => Ok(midi_source.clone()),
+            block::Content::Chord(chord_source) => chord_source.to_midi_source(),
+            block::Content::Alias(alias) => match blocks.get(&alias.target) {
+                Some(target) => alias.resolve(&target.content),
+                None => Err(format!(
+                    "block#{} referenced by alias not found",
+                    alias.target
+                )),
+            },
+            other => Err(format!(
+                "blocks of kind {} cannot be previewed",
+                other.kind()
+            )),
+        }
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?
+    };
+
+    let Ok(permit) = PREVIEWS.try_acquire() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many previews are rendered at the moment, try again later".to_string(),
+        ));
+    };
+
+    // Prepared upfront, so the size of the file is known and broken files are reported
+    let (sequencer, frames) = sequence(&sound_font, &midi.bytes)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))?;
+
+    info!("rendering preview of block#{uuid}");
+    let (sender, receiver) = mpsc::channel(1);
+    tokio::task::spawn_blocking(move || {
+        // Permit is released when rendering ends
+        let _permit = permit;
+        render(sequencer, frames, sender);
+    });
+
+    let chunks = futures::stream::unfold(receiver, |mut receiver| async move {
+        let chunk = receiver.recv().await?;
+        Some((Ok::<_, std::io::Error>(chunk), receiver))
+    });
+    Ok((
+        [
+            (CONTENT_TYPE, "audio/wav".to_string()),
+            (CONTENT_LENGTH, wav_size(frames).to_string()),
+        ],
+        StreamBody::new(chunks),
+    ))
+}
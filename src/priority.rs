@@ -0,0 +1,86 @@
+//! Real-time priority of the MIDI playback thread
+//!
+//! Projection, streaming or a browser full of tabs competing for the CPU delay the playback thread
+//! enough to hear it. Raising it's priority lets the operating system run it first:
+//!
+//! * Linux and macOS - `SCHED_FIFO` scheduling, which on Linux requires `CAP_SYS_NICE` or
+//!   `rtprio` limit (for example granted to `audio` group in `/etc/security/limits.d`)
+//! * Windows - Multimedia Class Scheduler Service "Pro Audio" task, falling back to time critical
+//!   thread priority
+//!
+//! Failure is logged and playback continues with normal priority. Can be turned off with
+//! `--disable-realtime-priority`.
+//!
+//! Playback thread busy-waits shortly before each event (see
+//! [SPIN_THRESHOLD][crate::audio_engine::SPIN_THRESHOLD]), so on Linux and macOS it runs with the
+//! lowest `SCHED_FIFO` priority: it's still ahead of every normal thread, but doesn't preempt
+//! audio servers and other real-time threads, which usually run with higher priorities.
+
+/// Keeps raised priority of the current thread, on Windows MMCSS registration is reverted on drop
+pub struct Guard {
+    /// MMCSS task handle, reverted when thread stops playing
+    #[cfg(windows)]
+    task: Option<winapi::um::winnt::HANDLE>,
+}
+
+/// Raise priority of the current thread to real-time
+#[cfg(unix)]
+pub fn raise() -> Guard {
+    // SAFETY: Only current thread is modified, param is fully initialized before use
+    unsafe {
+        let policy = libc::SCHED_FIFO;
+        let mut param: libc::sched_param = std::mem::zeroed();
+        // Lowest real-time priority, see the module documentation
+        param.sched_priority = libc::sched_get_priority_min(policy);
+        let result = libc::pthread_setschedparam(libc::pthread_self(), policy, &param);
+        if result != 0 {
+            tracing::warn!(
+                "failed to raise priority of playback thread: {}",
+                std::io::Error::from_raw_os_error(result)
+            );
+        } else {
+            tracing::debug!("playback thread runs with real-time priority");
+        }
+    }
+    Guard {}
+}
+
+/// Raise priority of the current thread to real-time
+#[cfg(windows)]
+pub fn raise() -> Guard {
+    use winapi::um::{
+        avrt::AvSetMmThreadCharacteristicsW,
+        processthreadsapi::{GetCurrentThread, SetThreadPriority},
+        winbase::THREAD_PRIORITY_TIME_CRITICAL,
+    };
+
+    let task_name: Vec<u16> = "Pro Audio\0".encode_utf16().collect();
+    let mut task_index = 0;
+    // SAFETY: Task name is null terminated and only current thread is modified
+    unsafe {
+        let task = AvSetMmThreadCharacteristicsW(task_name.as_ptr(), &mut task_index);
+        if !task.is_null() {
+            tracing::debug!("playback thread registered in MMCSS as Pro Audio");
+            return Guard { task: Some(task) };
+        }
+        if SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL as i32) == 0 {
+            tracing::warn!(
+                "failed to raise priority of playback thread: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Guard { task: None }
+}
+
+#[cfg(windows)]
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            // SAFETY: Handle was returned by AvSetMmThreadCharacteristicsW on this thread
+            unsafe {
+                winapi::um::avrt::AvRevertMmThreadCharacteristics(task);
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Pulling blocks from other instances
+//!
+//! Members arriving late to the rehearsal can catch up with the rest of the orchestra by copying
+//! blocks from a colleague's Harmonia. [list] fetches block list of the peer (entered by hand or
+//! picked from [discovery][linky_groups::discovery]) and [pull] copies selected blocks over HTTP,
+//! using the same routes that scripts use. Both are `POST` requests, since they make Harmonia
+//! send requests to other computers, which viewers shouldn't be able to do, see [crate::access].
+//! Responses of the peer are limited in size, so it cannot exhaust memory of this computer.
+//!
+//! Identifiers of file based blocks are derived from hash of their content, so blocks that are
+//! already present are skipped, and downloaded files are checked against their identifier. Only
+//! blocks with content that can be downloaded are offered: MIDI files, samples, audio, patterns
+//! and scenes. Ports are not copied, since they depend on devices of the computer.
+
+use crate::{
+    api::Format,
+    block::{self, Content},
+    handlers, AppState,
+};
+use anyhow::{anyhow, bail, Context};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Form, Json};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+/// How long to wait for the peer before giving up
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest accepted block list of the peer in bytes
+const MAX_LIST_SIZE: usize = 4 * handlers::MIB;
+
+/// Kinds of blocks which content can be downloaded from the peer
+const PULLABLE_KINDS: &[&str] = &["midi", "sample", "audio", "pattern", "scene"];
+
+/// Block as described by `GET /api/blocks` of the peer, see [crate::api::BlockSummary]
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RemoteBlock {
+    /// Identifier of the block on the peer
+    pub uuid: String,
+
+    /// Human readable name of the block
+    pub name: String,
+
+    /// Kind of the block content
+    pub kind: String,
+
+    /// Group in which block is played
+    pub group: String,
+
+    /// Associated keybind
+    pub keybind: String,
+
+    /// Free-text notes of the block, missing on older peers
+    #[serde(default)]
+    pub description: String,
+
+    /// Labels of the block, missing on older peers
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Color label of the block, missing on older peers
+    #[serde(default)]
+    pub color: Option<block::Color>,
+
+    /// Tick from which MIDI block starts
+    #[serde(default)]
+    pub start_tick: Option<u64>,
+
+    /// Tick on which MIDI block ends
+    #[serde(default)]
+    pub end_tick: Option<u64>,
+
+    /// Velocity scaling of MIDI block
+    #[serde(default)]
+    pub velocity: Option<block::Velocity>,
+
+    /// Whether block is already present in this instance, not sent by the peer
+    #[serde(default)]
+    pub present: bool,
+}
+
+/// Address of the peer, as entered by the user
+#[derive(Deserialize)]
+pub struct Peer {
+    /// URL of the peer, `http://` is assumed when scheme is missing
+    address: String,
+}
+
+/// Base URL of the peer, without trailing slash
+fn base_url(address: &str) -> Result<String, String> {
+    let address = address.trim().trim_end_matches('/');
+    if address.is_empty() {
+        return Err("missing address of the peer".to_string());
+    }
+    Ok(if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("http://{address}")
+    })
+}
+
+/// Client used to talk with peers
+fn client() -> anyhow::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .context("creating HTTP client")
+}
+
+/// Read body of the response, failing when it's bigger than `limit` bytes
+async fn read_body(mut response: reqwest::Response, limit: usize) -> anyhow::Result<Vec<u8>> {
+    if response
+        .content_length()
+        .map_or(false, |length| length > limit as u64)
+    {
+        bail!("response is bigger than {} MiB", limit / handlers::MIB);
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > limit {
+            bail!("response is bigger than {} MiB", limit / handlers::MIB);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Fetch blocks of the peer that can be pulled, marking ones already present
+async fn remote_blocks(app_state: &AppState, base_url: &str) -> anyhow::Result<Vec<RemoteBlock>> {
+    let response = client()?
+        .get(format!("{base_url}/api/blocks"))
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("fetching blocks of {base_url}"))?;
+    let bytes = read_body(response, MAX_LIST_SIZE)
+        .await
+        .with_context(|| format!("fetching blocks of {base_url}"))?;
+    let mut blocks: Vec<RemoteBlock> =
+        serde_json::from_slice(&bytes).with_context(|| format!("decoding blocks of {base_url}"))?;
+
+    let present = app_state.blocks.read().unwrap();
+    blocks.retain(|block| PULLABLE_KINDS.contains(&block.kind.as_str()));
+    for block in &mut blocks {
+        block.present = present.contains_key(&block.uuid);
+    }
+    Ok(blocks)
+}
+
+/// Lists blocks of the peer with checkboxes selecting which to pull
+///
+/// For JSON clients responds with list of [RemoteBlock]s.
+pub async fn list(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(Peer { address }): Form<Peer>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let base_url = base_url(&address).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    let blocks = remote_blocks(&app_state, &base_url)
+        .await
+        .map_err(|err| (StatusCode::BAD_GATEWAY, format!("{err:#}")))?;
+
+    if format == Format::Json {
+        return Ok(Json(blocks).into_response());
+    }
+
+    Ok(render(&base_url, &blocks).into_response())
+}
+
+/// Render list of blocks of the peer, with already present ones disabled
+fn render(base_url: &str, blocks: &[RemoteBlock]) -> Markup {
+    html! {
+        @if blocks.is_empty() {
+            p { (base_url) " has no blocks that can be pulled" }
+        } @else {
+            form hx-post="/pull" hx-target="#blocks" hx-swap="innerHTML" {
+                input type="hidden" name="address" value=(base_url);
+                ul {
+                    @for block in blocks {
+                        li {
+                            label {
+                                input
+                                    type="checkbox"
+                                    name="uuid"
+                                    value=(block.uuid)
+                                    checked[!block.present]
+                                    disabled[block.present];
+                                " " (block.name) " (" (block.kind) ")";
+                                @if !block.group.is_empty() {
+                                    " in group " (block.group)
+                                }
+                                @if block.present {
+                                    " - already present"
+                                }
+                            }
+                        }
+                    }
+                }
+                button { "Pull selected blocks" }
+            }
+        }
+    }
+}
+
+/// Sha1 of the content as hex, the way identifiers of file based blocks are derived
+fn content_hash(bytes: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(bytes);
+    hex::encode(hasher.finalize())
+}
+
+/// Download content of the block from the peer and create local copy of it
+///
+/// Content bigger than `max_size` bytes is rejected, like uploads of such files.
+async fn pull_block(
+    client: &reqwest::Client,
+    base_url: &str,
+    remote: &RemoteBlock,
+    max_size: usize,
+) -> anyhow::Result<Content> {
+    let response = client
+        .get(format!("{base_url}/blocks/{}", remote.uuid))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("downloading block#{}", remote.uuid))?;
+    let bytes = read_body(response, max_size)
+        .await
+        .with_context(|| format!("downloading block#{}", remote.uuid))?;
+
+    let content = match remote.kind.as_str() {
+        "pattern" => Content::Pattern(
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("decoding pattern block#{}", remote.uuid))?,
+        ),
+        "scene" => Content::Scene(
+            serde_json::from_slice(&bytes)
+                .with_context(|| format!("decoding scene block#{}", remote.uuid))?,
+        ),
+        "midi" | "sample" | "audio" => {
+            let expected = format!("{}-{}", remote.kind, content_hash(&bytes));
+            if remote.uuid != expected {
+                return Err(anyhow!(
+                    "content of block#{} doesn't match it's identifier",
+                    remote.uuid
+                ));
+            }
+            let file_name = remote.name.clone();
+            match remote.kind.as_str() {
+                "midi" => {
+                    midly::Smf::parse(&bytes).with_context(|| {
+                        format!("block#{} is not a valid MIDI file", remote.uuid)
+                    })?;
+                    Content::Midi(block::MidiSource {
+                        start_tick: remote.start_tick,
+                        end_tick: remote.end_tick,
+                        velocity: remote.velocity.unwrap_or_default(),
+                        ..block::MidiSource::new(bytes, file_name, handlers::MIN_PORT_NUMBER)
+                    })
+                }
+                "sample" => Content::Sample(block::AudioSource {
+                    bytes: bytes.into(),
+                    file_name,
+                }),
+                _ => Content::Audio(block::AudioSource {
+                    bytes: bytes.into(),
+                    file_name,
+                }),
+            }
+        }
+        kind => return Err(anyhow!("blocks of kind {kind:?} cannot be pulled")),
+    };
+    Ok(content)
+}
+
+/// Pulls blocks selected with `uuid` fields from the peer with given `address`
+///
+/// Blocks that are already present are skipped. Responds with current blocks, with rows
+/// describing blocks that failed to pull.
+pub async fn pull(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let address = fields
+        .iter()
+        .find(|(name, _)| name == "address")
+        .map(|(_, value)| value.as_str())
+        .unwrap_or_default();
+    let base_url = base_url(address).map_err(|err| (StatusCode::BAD_REQUEST, err))?;
+    let selected: Vec<&str> = fields
+        .iter()
+        .filter(|(name, _)| name == "uuid")
+        .map(|(_, value)| value.as_str())
+        .collect();
+
+    let remote_blocks = remote_blocks(&app_state, &base_url)
+        .await
+        .map_err(|err| (StatusCode::BAD_GATEWAY, format!("{err:#}")))?;
+    let client = client().map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")))?;
+
+    let mut errors = Vec::new();
+    for remote in remote_blocks
+        .iter()
+        .filter(|remote| !remote.present && selected.contains(&remote.uuid.as_str()))
+    {
+        match pull_block(&client, &base_url, remote, app_state.max_file_size).await {
+            Ok(content) => {
+                let mut block = app_state.defaults.read().unwrap().block(content);
+                block.group = handlers::limit_group_length(&remote.group);
+                block.keybind = remote.keybind.clone();
+                block.description = handlers::limit_description_length(&remote.description);
+                block.tags = handlers::normalize_tags(remote.tags.iter().map(String::as_str));
+                block.color = remote.color;
+                app_state
+                    .blocks
+                    .write()
+                    .unwrap()
+                    .insert(remote.uuid.clone(), block);
+                info!("pulled block#{} from {base_url}", remote.uuid);
+            }
+            Err(err) => {
+                error!(
+                    "failed to pull block#{} from {base_url}: {err:#}",
+                    remote.uuid
+                );
+                errors.push(format!("{}: {err:#}", remote.name));
+            }
+        }
+    }
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("pull failed to remember current sources: {err:#}")
+    }
+
+    Ok(handlers::uploaded_blocks(app_state, format, errors).await)
+}
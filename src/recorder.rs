@@ -0,0 +1,407 @@
+//! Safety recording of all MIDI messages sent by Harmonia
+//!
+//! When enabled (with `--record` flag), [audio_engine] passes every message that was sent to the
+//! MIDI output to the [Recorder]. Messages are timestamped in beats following the tempo of the
+//! Link session, so the recording can be opened in any DAW and aligned with the rest of the
+//! performance. The recording of the current session is stored in [cache][crate::cache_path]
+//! after each played block and can be downloaded from the UI. Sessions longer than [MAX_EVENTS]
+//! messages are split into several files.
+//!
+//! Recordings of all sessions are kept in [RECORDINGS_DIR] and are available with:
+//!
+//! - `GET /recordings` - stored recordings, newest first, as HTML list or JSON
+//! - `GET /recordings/:name` - MIDI file of given recording
+//!
+//! [audio_engine]: crate::audio_engine
+
+use crate::api;
+use anyhow::Context;
+use axum::{
+    body::Full,
+    extract::Path,
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use maud::{html, Markup};
+use midly::{
+    live::LiveEvent, Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Resolution of the produced MIDI file
+const TICKS_PER_BEAT: u16 = 480;
+
+/// Directory inside cache where recordings are stored
+pub const RECORDINGS_DIR: &str = "recordings";
+
+/// Most messages stored in a single recording file, longer sessions continue in the next one
+///
+/// Bounds both the memory held by the [Writer] and the time it takes to save the recording.
+const MAX_EVENTS: usize = 200_000;
+
+/// How many messages may wait for the [Writer] before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Single recorded MIDI message
+struct Event {
+    /// Beat since the start of the recording
+    beat: f64,
+
+    /// Tempo of Link session when message was sent
+    tempo: f64,
+
+    /// Raw MIDI message
+    bytes: Vec<u8>,
+}
+
+/// Request passed from [Recorder] to the [Writer]
+enum Message {
+    /// Message sent at `micros` host time with Link session at given `tempo`
+    Sent {
+        /// Host time of sending in microseconds
+        micros: i64,
+
+        /// Tempo of Link session when message was sent
+        tempo: f64,
+
+        /// Raw MIDI message
+        bytes: Vec<u8>,
+    },
+
+    /// Store recording in the cache, see [Writer::save]
+    Save,
+
+    /// Respond with file name and Standard MIDI File of the current recording
+    Render(tokio::sync::oneshot::Sender<(String, Vec<u8>)>),
+}
+
+/// Recording of the current session
+///
+/// Messages are passed to the [Writer] thread without locking, so recording never delays the
+/// real-time thread that sends them.
+pub struct Recorder {
+    /// Messages for the [Writer], `None` when recording is disabled
+    messages: Option<std::sync::mpsc::SyncSender<Message>>,
+}
+
+impl Recorder {
+    /// Create new recorder for the current session
+    ///
+    /// Recording is stored in the cache only when `persist` is set.
+    pub fn new(enabled: bool, persist: bool) -> Self {
+        let messages = enabled.then(|| {
+            let (messages, received) = std::sync::mpsc::sync_channel(QUEUE_CAPACITY);
+            std::thread::spawn(move || Writer::new(persist).run(received));
+            messages
+        });
+        Self { messages }
+    }
+
+    /// Is recording enabled for this session
+    pub fn is_enabled(&self) -> bool {
+        self.messages.is_some()
+    }
+
+    /// Record message sent at `micros` host time with Link session at given `tempo`
+    pub fn record(&self, micros: i64, tempo: f64, bytes: &[u8]) {
+        let Some(messages) = &self.messages else {
+            return;
+        };
+
+        // Losing the message in the recording is better than delaying the sent ones
+        let sent = Message::Sent {
+            micros,
+            tempo,
+            bytes: bytes.to_vec(),
+        };
+        if let Err(std::sync::mpsc::TrySendError::Full(_)) = messages.try_send(sent) {
+            tracing::warn!("recorder is falling behind, message was not recorded");
+        }
+    }
+
+    /// Store recording of the session so far in the cache, if anything new was recorded
+    pub fn save(&self) {
+        if let Some(messages) = &self.messages {
+            let _ = messages.send(Message::Save);
+        }
+    }
+
+    /// File name and Standard MIDI File of the current recording, `None` when disabled
+    pub async fn render(&self) -> Option<(String, Vec<u8>)> {
+        let messages = self.messages.as_ref()?;
+        let (reply, rendered) = tokio::sync::oneshot::channel();
+        messages.send(Message::Render(reply)).ok()?;
+        rendered.await.ok()
+    }
+}
+
+/// Thread owning the recorded messages, see [Recorder]
+struct Writer {
+    /// Should recording be stored in the cache
+    persist: bool,
+
+    /// Start of the session, recording files are named after it
+    started: String,
+
+    /// Number of the current recording file, counted from 1
+    part: usize,
+
+    /// Recorded messages, in the order in which they were sent
+    events: Vec<Event>,
+
+    /// Are there messages that were not saved yet
+    unsaved: bool,
+
+    /// Host time of the last recorded message in microseconds
+    last_micros: Option<i64>,
+
+    /// Beats passed from the first recorded message to the last one
+    beats: f64,
+}
+
+impl Writer {
+    /// Create writer of the recording started now
+    fn new(persist: bool) -> Self {
+        Self {
+            persist,
+            started: chrono::Local::now()
+                .format("harmonia-%Y-%m-%d_%H-%M-%S")
+                .to_string(),
+            part: 1,
+            events: Vec::new(),
+            unsaved: false,
+            last_micros: None,
+            beats: 0.0,
+        }
+    }
+
+    /// Handle messages until the [Recorder] is dropped
+    fn run(mut self, received: std::sync::mpsc::Receiver<Message>) {
+        for message in received {
+            match message {
+                Message::Sent {
+                    micros,
+                    tempo,
+                    bytes,
+                } => self.record(micros, tempo, bytes),
+                Message::Save => self.save(),
+                Message::Render(reply) => {
+                    let _ = reply.send((self.file_name(), self.to_midi()));
+                }
+            }
+        }
+        self.save();
+    }
+
+    /// Record message, continuing in the next file when the current one is full
+    fn record(&mut self, micros: i64, tempo: f64, bytes: Vec<u8>) {
+        if self.events.len() >= MAX_EVENTS {
+            self.save();
+            self.part += 1;
+            self.events.clear();
+            self.last_micros = None;
+            self.beats = 0.0;
+        }
+
+        if let Some(last_micros) = self.last_micros {
+            self.beats += (micros - last_micros).max(0) as f64 / 60_000_000.0 * tempo;
+        }
+        self.last_micros = Some(micros);
+
+        self.events.push(Event {
+            beat: self.beats,
+            tempo,
+            bytes,
+        });
+        self.unsaved = true;
+    }
+
+    /// Render recording as Standard MIDI File
+    fn to_midi(&self) -> Vec<u8> {
+        // System exclusive messages are copied into the arena, since track events only borrow them
+        let arena = midly::Arena::new();
+        let mut track = Vec::with_capacity(self.events.len() + 1);
+        let mut last_tick = 0_u32;
+        let mut last_tempo = None;
+
+        for event in &self.events {
+            let Ok(live_event) = LiveEvent::parse(&event.bytes) else {
+                tracing::warn!("skipping unparsable recorded message {:?}", event.bytes);
+                continue;
+            };
+
+            let tick = (event.beat * TICKS_PER_BEAT as f64).round() as u32;
+            let mut delta = tick.saturating_sub(last_tick);
+            last_tick = tick;
+
+            if last_tempo != Some(event.tempo) {
+                last_tempo = Some(event.tempo);
+                let micros_per_beat = (60_000_000.0 / event.tempo).round() as u32;
+                track.push(TrackEvent {
+                    delta: delta.into(),
+                    kind: TrackEventKind::Meta(MetaMessage::Tempo(micros_per_beat.into())),
+                });
+                delta = 0;
+            }
+
+            track.push(TrackEvent {
+                delta: delta.into(),
+                kind: live_event.as_track_event(&arena),
+            });
+        }
+
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(TICKS_PER_BEAT.into())),
+            tracks: vec![track],
+        };
+
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes)
+            .expect("writing to memory should never fail");
+        bytes
+    }
+
+    /// Name of the file under which current part of the recording is stored
+    fn file_name(&self) -> String {
+        match self.part {
+            1 => format!("{}.mid", self.started),
+            part => format!("{}-part{part}.mid", self.started),
+        }
+    }
+
+    /// Store recording in the cache, if anything new was recorded
+    fn save(&mut self) {
+        if !self.persist || !self.unsaved {
+            return;
+        }
+
+        match self.write() {
+            Ok(path) => {
+                self.unsaved = false;
+                tracing::info!("saved recording to {path:?}");
+            }
+            Err(err) => tracing::error!("failed to save recording: {err:#}"),
+        }
+    }
+
+    /// Write current part of the recording to [RECORDINGS_DIR]
+    fn write(&self) -> anyhow::Result<PathBuf> {
+        let dir = crate::cache_path().join(RECORDINGS_DIR);
+        std::fs::create_dir_all(&dir).context("creating recordings directory")?;
+
+        let path = dir.join(self.file_name());
+        std::fs::write(&path, self.to_midi()).context("saving recording")?;
+        Ok(path)
+    }
+}
+
+/// Recording stored in [RECORDINGS_DIR]
+#[derive(Serialize)]
+pub struct StoredRecording {
+    /// Name of the file
+    pub name: String,
+
+    /// Size of the file in bytes
+    pub size: u64,
+
+    /// Last modification time as RFC 3339 string
+    pub modified: Option<String>,
+}
+
+/// Recordings stored in [RECORDINGS_DIR], newest first
+pub fn stored() -> Vec<StoredRecording> {
+    let Ok(entries) = std::fs::read_dir(crate::cache_path().join(RECORDINGS_DIR)) else {
+        return Vec::new();
+    };
+
+    let mut recordings: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = entry.metadata().ok()?;
+            (metadata.is_file() && name.ends_with(".mid")).then(|| StoredRecording {
+                name,
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .map(|modified| chrono::DateTime::<chrono::Local>::from(modified).to_rfc3339()),
+            })
+        })
+        .collect();
+
+    // File names start with the date of the session, so they sort chronologically
+    recordings.sort_by(|lhs, rhs| rhs.name.cmp(&lhs.name));
+    recordings
+}
+
+/// Responds with recordings stored in [RECORDINGS_DIR], newest first
+pub async fn recordings(format: api::Format) -> Response {
+    let recordings = stored();
+    match format {
+        api::Format::Json => Json(recordings).into_response(),
+        api::Format::Html => render(&recordings).into_response(),
+    }
+}
+
+/// Render list of links to stored recordings
+pub fn render(recordings: &[StoredRecording]) -> Markup {
+    html! {
+        @if recordings.is_empty() {
+            p { "No recordings stored" }
+        } @else {
+            ul class="recordings" {
+                @for recording in recordings {
+                    li {
+                        a href=(format!("/recordings/{}", recording.name)) { (recording.name) }
+                        (format!(" ({} bytes)", recording.size))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Responds with MIDI file of stored recording with given name
+pub async fn download(Path(name): Path<String>) -> Result<Response, (StatusCode, String)> {
+    // Only plain file names are accepted, so requests cannot reach outside of recordings
+    if name.contains(['/', '\\', '"']) || name.starts_with('.') || !name.ends_with(".mid") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("invalid recording name {name:?}"),
+        ));
+    }
+
+    // Name is sent back in the header, so it has to be a valid header value
+    let disposition =
+        HeaderValue::from_str(&format!("attachment; filename=\"{name}\"")).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("invalid recording name {name:?}"),
+            )
+        })?;
+
+    let path = crate::cache_path().join(RECORDINGS_DIR).join(&name);
+    let bytes = std::fs::read(&path).map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("recording {name:?} not found"),
+        )
+    })?;
+
+    let mut response = Response::new(Full::from(bytes));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_DISPOSITION, disposition);
+    headers.insert(CONTENT_TYPE, "audio/midi".parse().unwrap());
+    Ok(response.into_response())
+}
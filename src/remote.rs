@@ -0,0 +1,271 @@
+//! Subcommands controlling already running Harmonia instance
+//!
+//! `harmonia list`, `harmonia play`, `harmonia stop` and `harmonia status` talk to the JSON API
+//! (see [api][crate::api]) of the instance on this computer, so playback can be scripted from
+//! cron, QLab hooks or shell scripts without a browser:
+//!
+//! ```sh
+//! harmonia play intro && sleep 30 && harmonia stop
+//! ```
+//!
+//! Instance is found on `--port` (and `--tls`) of the configuration, another one can be chosen with
+//! `--url`. Token from `--token` is sent along, so instances on other computers can be controlled
+//! too.
+
+use crate::Cli;
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::process::ExitCode;
+
+/// Commands sent to running instance
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// List blocks of running instance
+    List {
+        /// Address of Harmonia instance, local instance by default
+        #[arg(long)]
+        url: Option<String>,
+    },
+
+    /// Play block given by identifier or keybind
+    Play {
+        /// Identifier or keybind of the block
+        block: String,
+
+        /// Address of Harmonia instance, local instance by default
+        #[arg(long)]
+        url: Option<String>,
+    },
+
+    /// Stop all played blocks, or only the given one
+    Stop {
+        /// Identifier or keybind of the block
+        block: Option<String>,
+
+        /// Address of Harmonia instance, local instance by default
+        #[arg(long)]
+        url: Option<String>,
+    },
+
+    /// Print synchronization and playback state
+    Status {
+        /// Address of Harmonia instance, local instance by default
+        #[arg(long)]
+        url: Option<String>,
+    },
+}
+
+/// Block as described by [crate::api::BlockSummary]
+#[derive(Deserialize)]
+struct BlockSummary {
+    /// Unique identifier of the block
+    uuid: String,
+
+    /// Human readable name of the block
+    name: String,
+
+    /// Kind of the block content
+    kind: String,
+
+    /// Group in which block is played
+    group: String,
+
+    /// Associated keybind
+    keybind: String,
+}
+
+/// Played block as described by [crate::api::Playing]
+#[derive(Deserialize)]
+struct Playing {
+    /// Unique identifier of the block
+    uuid: String,
+
+    /// Human readable name of the block
+    name: String,
+
+    /// Bar (counted from 1) that is currently played
+    bar: usize,
+
+    /// Number of bars in the block, [None] for infinite blocks
+    bar_count: Option<usize>,
+}
+
+/// Synchronization and playback state as described by [crate::api::Status]
+#[derive(Deserialize)]
+struct Status {
+    /// Is Link session enabled
+    synchronized: bool,
+
+    /// Tempo of Link session in beats per minute
+    tempo: f64,
+
+    /// Current beat of Link session
+    beat: f64,
+
+    /// Number of peers in Link session
+    peers: u64,
+
+    /// Currently played blocks
+    playing: Vec<Playing>,
+}
+
+/// Connection to running Harmonia instance
+struct Harmonia {
+    /// HTTP client reused between requests
+    client: reqwest::Client,
+
+    /// Address of Harmonia instance, without trailing slash
+    url: String,
+
+    /// Access token sent with every request, see [access][crate::access]
+    token: Option<String>,
+}
+
+impl Harmonia {
+    /// Send request to given path, failing on error status
+    async fn send(&self, method: reqwest::Method, path: &str) -> anyhow::Result<reqwest::Response> {
+        let mut request = self
+            .client
+            .request(method, format!("{}{path}", self.url))
+            .header(reqwest::header::ACCEPT, "application/json");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("connecting to Harmonia at {}", self.url))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow!("{path} failed with {status}: {body}"));
+        }
+        Ok(response)
+    }
+
+    /// Fetch all blocks
+    async fn blocks(&self) -> anyhow::Result<Vec<BlockSummary>> {
+        self.send(reqwest::Method::GET, "/api/blocks")
+            .await?
+            .json()
+            .await
+            .context("parsing blocks")
+    }
+
+    /// Find identifier of the block given by identifier or keybind
+    async fn find(&self, block: &str) -> anyhow::Result<String> {
+        let blocks = self.blocks().await?;
+        blocks
+            .iter()
+            .find(|summary| summary.uuid == block)
+            .or_else(|| blocks.iter().find(|summary| summary.keybind == block))
+            .map(|summary| summary.uuid.clone())
+            .ok_or_else(|| anyhow!("no block with identifier or keybind {block:?}"))
+    }
+}
+
+/// Print blocks, one per line
+async fn list(harmonia: &Harmonia) -> anyhow::Result<()> {
+    for block in harmonia.blocks().await? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            block.uuid, block.kind, block.group, block.keybind, block.name
+        );
+    }
+    Ok(())
+}
+
+/// Print synchronization state and played blocks
+async fn status(harmonia: &Harmonia) -> anyhow::Result<()> {
+    let status: Status = harmonia
+        .send(reqwest::Method::GET, "/api/status")
+        .await?
+        .json()
+        .await
+        .context("parsing status")?;
+
+    println!(
+        "{} {:.1} BPM, beat {:.1}, {} peers",
+        if status.synchronized {
+            "synchronized"
+        } else {
+            "not synchronized"
+        },
+        status.tempo,
+        status.beat,
+        status.peers
+    );
+    for playing in &status.playing {
+        let bars = playing
+            .bar_count
+            .map_or_else(|| "-".to_string(), |count| count.to_string());
+        println!(
+            "playing {} ({}), bar {}/{bars}",
+            playing.name, playing.uuid, playing.bar
+        );
+    }
+    Ok(())
+}
+
+/// Run subcommand against running instance
+pub async fn run(command: Command, cli: &Cli) -> ExitCode {
+    let (Command::List { url }
+    | Command::Play { url, .. }
+    | Command::Stop { url, .. }
+    | Command::Status { url }) = &command;
+    let local = url.is_none();
+    let url = url.clone().unwrap_or_else(|| {
+        let scheme = if cli.https() { "https" } else { "http" };
+        format!("{scheme}://localhost:{}", cli.port)
+    });
+
+    // Certificate of local instance is usually self-signed, see `tls` module
+    let client = match reqwest::Client::builder()
+        .danger_accept_invalid_certs(local)
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("failed to create HTTP client: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let harmonia = Harmonia {
+        client,
+        url: url.trim_end_matches('/').to_string(),
+        token: cli.token.clone(),
+    };
+
+    let result = match &command {
+        Command::List { .. } => list(&harmonia).await,
+        Command::Status { .. } => status(&harmonia).await,
+        Command::Play { block, .. } => match harmonia.find(block).await {
+            Ok(uuid) => harmonia
+                .send(reqwest::Method::POST, &format!("/blocks/play/{uuid}"))
+                .await
+                .map(drop),
+            Err(err) => Err(err),
+        },
+        Command::Stop { block: None, .. } => harmonia
+            .send(reqwest::Method::POST, "/interrupt")
+            .await
+            .map(drop),
+        Command::Stop {
+            block: Some(block), ..
+        } => match harmonia.find(block).await {
+            Ok(uuid) => harmonia
+                .send(reqwest::Method::POST, &format!("/interrupt/{uuid}"))
+                .await
+                .map(drop),
+            Err(err) => Err(err),
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Running Harmonia as a background service
+//!
+//! On Linux performance machines Harmonia can be started by systemd, for example with
+//! `~/.config/systemd/user/harmonia.service`:
+//!
+//! ```ini
+//! [Unit]
+//! Description=Harmonia
+//! After=network-online.target sound.target
+//!
+//! [Service]
+//! Type=notify
+//! ExecStart=%h/.cargo/bin/harmonia --state-dir %h/harmonia
+//! Restart=on-failure
+//! TimeoutStopSec=15
+//!
+//! [Install]
+//! WantedBy=default.target
+//! ```
+//!
+//! With `Type=notify` systemd considers service started only when Harmonia reports [ready] (UI is
+//! listening), and is told when Harmonia is [stopping]. Stopping (`SIGTERM`) is bounded by
+//! [SHUTDOWN_TIMEOUT][crate::SHUTDOWN_TIMEOUT], so it doesn't hang on open connections.
+//!
+//! Without systemd (no `NOTIFY_SOCKET` environment variable) and on other systems notifications
+//! are not sent.
+
+/// Send notification to the service manager, failures are only logged
+#[cfg(unix)]
+fn notify(states: &[sd_notify::NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, states) {
+        tracing::warn!("failed to notify service manager: {err}");
+    }
+}
+
+/// Tell service manager that Harmonia is ready, serving UI at given address
+pub fn ready(address: &str) {
+    #[cfg(unix)]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(&format!("Serving UI at {address}")),
+    ]);
+    #[cfg(not(unix))]
+    let _ = address;
+}
+
+/// Tell service manager that Harmonia is shutting down
+pub fn stopping() {
+    #[cfg(unix)]
+    notify(&[
+        sd_notify::NotifyState::Stopping,
+        sd_notify::NotifyState::Status("Shutting down"),
+    ]);
+}
@@ -0,0 +1,153 @@
+//! Setlists: named, ordered lists of blocks played one after another
+//!
+//! Whole concert can be loaded as a setlist and started with a single request. When a block of the
+//! setlist finishes, the next one starts on the following bar, see [audio_engine::play_setlist].
+//! Interrupting playback stops the setlist as well.
+//!
+//! Setlists are managed with:
+//!
+//! - `GET /api/setlists` - all setlists with their blocks
+//! - `PUT /api/setlists/:name` - create or replace setlist with JSON list of block identifiers
+//! - `DELETE /api/setlists/:name` - remove setlist
+//! - `POST /api/setlists/:name/play?from=N` - play setlist, optionally from N-th block (counted
+//!   from 0)
+
+use crate::{audio_engine, cache_path, handlers, timeline, AppState};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use tracing::{info, warn};
+
+/// Filename under which Harmonia stores setlists
+pub const SETLISTS_PATH: &str = "harmonia_setlists.bson";
+
+/// Ordered list of blocks played one after another
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Setlist {
+    /// Identifiers of blocks in the order in which they are played
+    pub blocks: Vec<String>,
+}
+
+/// Load setlists stored in [SETLISTS_PATH], missing or invalid file means no setlists
+pub fn load() -> BTreeMap<String, Setlist> {
+    std::fs::read(cache_path().join(SETLISTS_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Store setlists in [SETLISTS_PATH], unless Harmonia is ephemeral
+fn remember(app_state: &AppState) {
+    if app_state.ephemeral {
+        return;
+    }
+
+    let path = cache_path().join(SETLISTS_PATH);
+    let setlists = app_state.setlists.read().unwrap().clone();
+    match bson::to_vec(&setlists) {
+        Ok(bytes) => {
+            if let Err(err) = std::fs::write(&path, bytes) {
+                warn!("failed to write setlists to {path:?}: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize setlists: {err}"),
+    }
+}
+
+/// Responds with all setlists, indexed by their names
+pub async fn setlists(State(app_state): State<Arc<AppState>>) -> Json<BTreeMap<String, Setlist>> {
+    Json(app_state.setlists.read().unwrap().clone())
+}
+
+/// Creates or replaces setlist with given name
+pub async fn set_setlist(
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(blocks): Json<Vec<String>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if let Some(missing) = {
+        let known = app_state.blocks.read().unwrap();
+        blocks
+            .iter()
+            .find(|uuid| !known.contains_key(*uuid))
+            .cloned()
+    } {
+        return Err((StatusCode::NOT_FOUND, format!("block#{missing} not found")));
+    }
+
+    info!("setting setlist {name:?} to {} blocks", blocks.len());
+    app_state
+        .setlists
+        .write()
+        .unwrap()
+        .insert(name, Setlist { blocks });
+    remember(&app_state);
+    Ok(StatusCode::OK)
+}
+
+/// Removes setlist with given name
+pub async fn remove_setlist(
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if app_state.setlists.write().unwrap().remove(&name).is_none() {
+        return Err((StatusCode::NOT_FOUND, format!("setlist {name:?} not found")));
+    }
+
+    info!("removed setlist {name:?}");
+    remember(&app_state);
+    Ok(StatusCode::OK)
+}
+
+/// Query parameters of the request to play setlist
+#[derive(Deserialize)]
+pub struct PlaySetlist {
+    /// Index of the first played block, counted from 0
+    #[serde(default)]
+    from: usize,
+}
+
+/// Starts playing setlist with given name
+///
+/// Responds immediately, the setlist is played by the [audio_engine] until finished or
+/// interrupted.
+pub async fn play_setlist(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(app_state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(PlaySetlist { from }): Query<PlaySetlist>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let Some(setlist) = app_state.setlists.read().unwrap().get(&name).cloned() else {
+        return Err((StatusCode::NOT_FOUND, format!("setlist {name:?} not found")));
+    };
+
+    if from >= setlist.blocks.len() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "setlist {name:?} has only {} blocks, cannot start from {from}",
+                setlist.blocks.len()
+            ),
+        ));
+    }
+
+    handlers::ensure_local_programs(
+        &app_state,
+        addr,
+        setlist.blocks[from..].iter().map(String::as_str),
+    )?;
+
+    info!("starting setlist {name:?} from block {from}");
+    timeline::record(
+        &app_state,
+        timeline::Kind::SetlistStarted,
+        None,
+        format!("{name} from block {from}"),
+    );
+    audio_engine::play_setlist(app_state, setlist.blocks[from..].to_vec());
+    Ok(StatusCode::ACCEPTED)
+}
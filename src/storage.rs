@@ -0,0 +1,237 @@
+//! Content of file based blocks stored on disk, addressed by it's hash
+//!
+//! State file used to contain bytes of every MIDI and audio file, so each change of any block
+//! serialized the whole library again. Now the state contains only sha1 of the content, and the
+//! content itself is stored once in [CONTENT_PATH] under the name of it's hash. Content is read
+//! from disk when it's first needed (played, downloaded or verified), so starting Harmonia with a
+//! large library doesn't read all of it into memory.
+//!
+//! State files from older versions, with content stored inline, are still read; their content is
+//! moved to [CONTENT_PATH] when state is saved.
+
+use crate::{block, cache_path};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha1::{Digest, Sha1};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
+use tracing::{error, warn};
+
+/// Directory under cache in which content of blocks is stored
+pub const CONTENT_PATH: &str = "harmonia_content";
+
+/// Shared state of [StoredBytes]
+#[derive(Default)]
+struct Inner {
+    /// Sha1 of the content as hex, computed when first needed
+    hash: OnceLock<String>,
+
+    /// Content itself, read from disk when first needed
+    bytes: OnceLock<Vec<u8>>,
+
+    /// Whether content is known to be present in [CONTENT_PATH]
+    stored: AtomicBool,
+}
+
+/// Content of the file, stored on disk and read lazily
+///
+/// Dereferences to the content, reading it from disk on the first access. Content that can't be
+/// read is logged and seen as empty, which [integrity verification][crate::integrity] reports.
+/// Clones share the content.
+#[derive(Clone, Default)]
+pub struct StoredBytes {
+    /// Shared state, so clones of blocks don't copy the content
+    inner: Arc<Inner>,
+}
+
+impl StoredBytes {
+    /// Content identified by it's hash, read from [CONTENT_PATH] when needed
+    fn from_hash(hash: String) -> Self {
+        let stored = Self::default();
+        stored.inner.hash.set(hash).unwrap();
+        stored.inner.stored.store(true, Ordering::Relaxed);
+        stored
+    }
+
+    /// Content of the file with given hash, moved into [CONTENT_PATH] without reading it
+    ///
+    /// Hash isn't verified, so it must be computed from the file by the caller. File is left in
+    /// place when the same content is already stored.
+    pub fn adopt(path: &Path, hash: String) -> std::io::Result<Self> {
+        let stored = Self::from_hash(hash);
+        let target = stored.path();
+        if !target.exists() {
+            std::fs::create_dir_all(cache_path().join(CONTENT_PATH))?;
+            std::fs::rename(path, &target)?;
+        }
+        Ok(stored)
+    }
+
+    /// Sha1 of the content as hex
+    pub fn hash(&self) -> &str {
+        self.inner.hash.get_or_init(|| {
+            let bytes = self.inner.bytes.get().map_or(&[][..], Vec::as_slice);
+            hex::encode(Sha1::digest(bytes))
+        })
+    }
+
+    /// Path under which content is stored
+    fn path(&self) -> PathBuf {
+        cache_path().join(CONTENT_PATH).join(self.hash())
+    }
+
+    /// Content, read from disk without keeping it in memory when it wasn't loaded yet
+    pub fn read(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        match self.inner.bytes.get() {
+            Some(bytes) => Ok(Cow::Borrowed(bytes)),
+            None => std::fs::read(self.path()).map(Cow::Owned),
+        }
+    }
+
+    /// Provide content for the hash read from the state, for example from exported archive
+    ///
+    /// Content that doesn't match the hash is rejected.
+    pub fn fill(&self, bytes: Vec<u8>) -> Result<(), String> {
+        if hex::encode(Sha1::digest(&bytes)) != self.hash() {
+            return Err(format!("content doesn't match hash {}", self.hash()));
+        }
+        let _ = self.inner.bytes.set(bytes);
+        // Content may come from elsewhere, so it's written again unless it's already on disk
+        self.inner.stored.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Whether content is kept in memory
+    pub fn is_loaded(&self) -> bool {
+        self.inner.bytes.get().is_some()
+    }
+
+    /// Whether content is kept in memory or stored on disk
+    pub fn is_available(&self) -> bool {
+        self.is_loaded() || self.path().exists()
+    }
+
+    /// Write content to [CONTENT_PATH] unless it's already there
+    fn store(&self) -> std::io::Result<()> {
+        if self.inner.stored.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let path = self.path();
+        if !path.exists() {
+            let Some(bytes) = self.inner.bytes.get() else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("content {} is neither in memory nor on disk", self.hash()),
+                ));
+            };
+            std::fs::create_dir_all(cache_path().join(CONTENT_PATH))?;
+            // Written under temporary name, so interrupted write never leaves partial content
+            let temporary = path.with_extension("partial");
+            std::fs::write(&temporary, bytes)?;
+            std::fs::rename(&temporary, &path)?;
+        }
+        self.inner.stored.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl std::ops::Deref for StoredBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if let Some(bytes) = self.inner.bytes.get() {
+            return bytes;
+        }
+        match std::fs::read(self.path()) {
+            Ok(bytes) => {
+                let _ = self.inner.bytes.set(bytes);
+                self.inner.bytes.get().unwrap()
+            }
+            Err(err) => {
+                error!("failed to read content {}: {err}", self.hash());
+                &[]
+            }
+        }
+    }
+}
+
+impl AsRef<[u8]> for StoredBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for StoredBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        let stored = Self::default();
+        stored.inner.bytes.set(bytes).unwrap();
+        stored
+    }
+}
+
+impl Serialize for StoredBytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.hash())
+    }
+}
+
+impl<'de> Deserialize<'de> for StoredBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// Content as stored in the state file
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Serialized {
+            /// Hash of the content stored in [CONTENT_PATH]
+            Hash(String),
+
+            /// Content itself, stored by older versions
+            Inline(Vec<u8>),
+        }
+
+        Ok(match Serialized::deserialize(deserializer)? {
+            Serialized::Hash(hash) => Self::from_hash(hash),
+            Serialized::Inline(bytes) => Self::from(bytes),
+        })
+    }
+}
+
+/// Content of the block stored on disk, if it has any
+pub fn of(content: &block::Content) -> Option<&StoredBytes> {
+    match content {
+        block::Content::Midi(source) => Some(&source.bytes),
+        block::Content::Sample(source) | block::Content::Audio(source) => Some(&source.bytes),
+        _ => None,
+    }
+}
+
+/// Write content of given blocks to disk and remove content that none of them uses
+///
+/// Failures are only logged, so content that went missing doesn't prevent saving the state.
+pub fn persist<'a>(blocks: impl Iterator<Item = &'a block::Block>) {
+    let mut used = HashSet::new();
+    for content in blocks.filter_map(|block| of(&block.content)) {
+        if let Err(err) = content.store() {
+            error!("failed to store content {}: {err}", content.hash());
+        }
+        used.insert(content.hash().to_string());
+    }
+
+    let Ok(entries) = std::fs::read_dir(cache_path().join(CONTENT_PATH)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if used.contains(name.to_string_lossy().as_ref()) {
+            continue;
+        }
+        if let Err(err) = std::fs::remove_file(entry.path()) {
+            warn!("failed to remove unused content {:?}: {err}", entry.path());
+        }
+    }
+}
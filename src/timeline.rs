@@ -0,0 +1,155 @@
+//! Timeline of transport events of the current session
+//!
+//! [audio_engine][crate::audio_engine] records when blocks start and stop, when tempo changes,
+//! when playback is interrupted and when group is joined. [Setlists][crate::setlist] and
+//! [cues][crate::cues] record when they start and fire, so it's visible why block started. Each
+//! event is timestamped both with
+//! local time and with the beat and tempo of the Link session, so the course of the performance
+//! can be reconstructed afterwards. Timeline can be exported as JSON with `GET /api/timeline` or
+//! as CSV with `GET /api/timeline/csv`.
+
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::IntoResponse,
+    Json,
+};
+use rusty_link::SessionState;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Kind of the transport event
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// Block started playing
+    BlockStarted,
+
+    /// Block stopped playing, either finished or interrupted
+    BlockStopped,
+
+    /// Tempo of Link session was changed by Harmonia
+    TempoChanged,
+
+    /// User requested to stop current playback
+    Interrupted,
+
+    /// Started or joined playing in the group
+    GroupJoined,
+
+    /// Setlist started playing it's blocks
+    SetlistStarted,
+
+    /// Scheduled cue fired
+    CueFired,
+}
+
+impl Kind {
+    /// Name of the kind, the same as in JSON
+    fn name(self) -> &'static str {
+        match self {
+            Self::BlockStarted => "block_started",
+            Self::BlockStopped => "block_stopped",
+            Self::TempoChanged => "tempo_changed",
+            Self::Interrupted => "interrupted",
+            Self::GroupJoined => "group_joined",
+            Self::SetlistStarted => "setlist_started",
+            Self::CueFired => "cue_fired",
+        }
+    }
+}
+
+/// Single transport event
+#[derive(Serialize, Clone)]
+pub struct Event {
+    /// Local time of the event in RFC 3339 format
+    pub time: String,
+
+    /// Beat of Link session at the time of the event
+    pub beat: f64,
+
+    /// Tempo of Link session at the time of the event
+    pub tempo: f64,
+
+    /// What happened
+    pub kind: Kind,
+
+    /// Identifier of the block that the event is about, if any
+    pub block: Option<String>,
+
+    /// Additional information like name of the block, group or reason for stopping
+    pub detail: String,
+}
+
+/// All transport events of the current session
+#[derive(Default)]
+pub struct Timeline {
+    /// Recorded events, oldest first
+    events: Vec<Event>,
+}
+
+impl Timeline {
+    /// All recorded events, oldest first
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+}
+
+/// Record event that happened now in the timeline of [AppState]
+pub fn record(app_state: &AppState, kind: Kind, block: Option<&str>, detail: impl Into<String>) {
+    let mut session_state = SessionState::new();
+    app_state.link.capture_app_session_state(&mut session_state);
+
+    let event = Event {
+        time: chrono::Local::now().to_rfc3339(),
+        beat: session_state.beat_at_time(app_state.clock.micros(), app_state.quantum),
+        tempo: session_state.tempo(),
+        kind,
+        block: block.map(str::to_owned),
+        detail: detail.into(),
+    };
+    app_state.timeline.lock().unwrap().events.push(event);
+}
+
+/// Quote CSV field when it contains characters with special meaning
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Responds with recorded events as JSON, oldest first
+pub async fn timeline(State(app_state): State<Arc<AppState>>) -> Json<Vec<Event>> {
+    Json(app_state.timeline.lock().unwrap().events().to_vec())
+}
+
+/// Responds with recorded events as downloadable CSV file, oldest first
+pub async fn timeline_csv(State(app_state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut csv = String::from("time,beat,tempo,kind,block,detail\n");
+    for event in app_state.timeline.lock().unwrap().events() {
+        csv += &format!(
+            "{},{:.3},{:.3},{},{},{}\n",
+            event.time,
+            event.beat,
+            event.tempo,
+            event.kind.name(),
+            csv_field(event.block.as_deref().unwrap_or_default()),
+            csv_field(&event.detail),
+        );
+    }
+
+    let file_name = chrono::Local::now().format("harmonia-timeline-%Y-%m-%d_%H-%M-%S.csv");
+    (
+        [
+            (CONTENT_TYPE, "text/csv".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        csv,
+    )
+}
@@ -0,0 +1,69 @@
+//! HTTPS for the UI
+//!
+//! On shared venue networks plain HTTP exposes control traffic (including access token, see
+//! [access][crate::access]) to everyone and browsers warn about insecure forms. With `--tls-cert`
+//! and `--tls-key` Harmonia serves UI over HTTPS with given certificate. With `--tls` alone it
+//! generates self-signed certificate on the first run and keeps it in cache, so browsers ask to
+//! trust it only once.
+
+use crate::cache_path;
+use anyhow::Context;
+use axum_server::tls_rustls::RustlsConfig;
+use std::path::Path;
+use tracing::info;
+
+/// Filename under which Harmonia stores generated self-signed certificate
+pub const SELF_SIGNED_CERT_PATH: &str = "harmonia_tls_cert.pem";
+
+/// Filename under which Harmonia stores private key of generated self-signed certificate
+pub const SELF_SIGNED_KEY_PATH: &str = "harmonia_tls_key.pem";
+
+/// Load certificate and key given on command line
+pub async fn load(cert: &Path, key: &Path) -> anyhow::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .with_context(|| format!("loading TLS certificate {cert:?} with key {key:?}"))
+}
+
+/// Load self-signed certificate from cache, or generate it when it's missing
+///
+/// In ephemeral mode certificate is generated on every run and never stored.
+pub async fn self_signed(ephemeral: bool) -> anyhow::Result<RustlsConfig> {
+    let cert_path = cache_path().join(SELF_SIGNED_CERT_PATH);
+    let key_path = cache_path().join(SELF_SIGNED_KEY_PATH);
+
+    if !ephemeral && cert_path.exists() && key_path.exists() {
+        return load(&cert_path, &key_path).await;
+    }
+
+    let (cert, key) = generate()?;
+    if !ephemeral {
+        std::fs::write(&cert_path, &cert).with_context(|| format!("writing {cert_path:?}"))?;
+        std::fs::write(&key_path, &key).with_context(|| format!("writing {key_path:?}"))?;
+        info!("generated self-signed TLS certificate {cert_path:?}");
+    }
+
+    RustlsConfig::from_pem(cert.into_bytes(), key.into_bytes())
+        .await
+        .context("loading generated TLS certificate")
+}
+
+/// Generate self-signed certificate and it's private key, both PEM encoded
+///
+/// Certificate is valid for names under which colleagues can reach this computer: localhost,
+/// hostname and local IP addresses.
+fn generate() -> anyhow::Result<(String, String)> {
+    let mut names = vec!["localhost".to_string(), whoami::devicename()];
+    if let Ok(interfaces) = local_ip_address::list_afinet_netifas() {
+        names.extend(interfaces.into_iter().map(|(_, ip)| ip.to_string()));
+    }
+    names.sort();
+    names.dedup();
+
+    let certificate =
+        rcgen::generate_simple_self_signed(names).context("generating TLS certificate")?;
+    let cert = certificate
+        .serialize_pem()
+        .context("serializing TLS certificate")?;
+    Ok((cert, certificate.serialize_private_key_pem()))
+}
@@ -0,0 +1,215 @@
+//! Trash for removed blocks
+//!
+//! Removing a block moves it to the [Trash], from which it can be restored for [RETENTION_DAYS]
+//! or until the trash is purged. Trash is stored next to the blocks in [cache][crate::cache_path],
+//! so blocks removed by accident can be recovered even after restart.
+
+use crate::{api::Format, block, handlers, AppState};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tracing::{error, info};
+
+/// Number of days after which removed blocks are forgotten
+pub const RETENTION_DAYS: i64 = 7;
+
+/// Path (relative to cache) where trash is stored
+pub const TRASH_PATH: &str = "harmonia_trash.bson";
+
+/// Block removed by the user
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Trashed {
+    /// Removed block itself
+    pub block: block::Block,
+
+    /// Unix timestamp (in seconds) of the removal
+    pub deleted_at: i64,
+}
+
+/// Collection of removed blocks, indexed by their former identifiers
+#[derive(Serialize, Deserialize, Default)]
+pub struct Trash {
+    /// Removed blocks
+    entries: HashMap<String, Trashed>,
+}
+
+impl Trash {
+    /// Move removed block to the trash, forgetting the blocks older then [RETENTION_DAYS]
+    pub fn put(&mut self, uuid: String, block: block::Block) {
+        self.purge_expired();
+        self.entries.insert(
+            uuid,
+            Trashed {
+                block,
+                deleted_at: chrono::Utc::now().timestamp(),
+            },
+        );
+    }
+
+    /// Take block out of the trash
+    pub fn take(&mut self, uuid: &str) -> Option<block::Block> {
+        self.entries.remove(uuid).map(|trashed| trashed.block)
+    }
+
+    /// Forget blocks removed more then [RETENTION_DAYS] ago
+    pub fn purge_expired(&mut self) {
+        let oldest = chrono::Utc::now().timestamp() - RETENTION_DAYS * 24 * 60 * 60;
+        self.entries
+            .retain(|_, trashed| trashed.deleted_at >= oldest);
+    }
+
+    /// Forget all removed blocks
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Removed blocks in no particular order
+    pub fn blocks(&self) -> impl Iterator<Item = &block::Block> {
+        self.entries.values().map(|trashed| &trashed.block)
+    }
+
+    /// Removed blocks, most recently removed first
+    pub fn entries(&self) -> Vec<(&String, &Trashed)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, trashed)| std::cmp::Reverse(trashed.deleted_at));
+        entries
+    }
+}
+
+/// JSON representation of the removed block
+#[derive(Serialize)]
+pub struct TrashedSummary {
+    /// Identifier of the block before removal
+    pub uuid: String,
+
+    /// Human readable name of the block
+    pub name: String,
+
+    /// Kind of the block content, see [block::Content::kind]
+    pub kind: &'static str,
+
+    /// Unix timestamp (in seconds) of the removal
+    pub deleted_at: i64,
+}
+
+/// Render list of removed blocks, to be put inside element with `trash` id
+pub fn render(app_state: &AppState) -> Markup {
+    let trash = app_state.trash.lock().unwrap();
+    let entries = trash.entries();
+
+    html! {
+        @if entries.is_empty() {
+            p { "Trash is empty" }
+        } @else {
+            ul {
+                @for (uuid, trashed) in entries {
+                    li {
+                        (trashed.block.content.name())
+                        " (removed "
+                        (chrono::DateTime::from_timestamp(trashed.deleted_at, 0)
+                            .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                            .unwrap_or_default())
+                        ") "
+                        button
+                            hx-post=(format!("/trash/restore/{uuid}"))
+                            hx-target="#blocks"
+                            hx-swap="innerHTML"
+                        {
+                            "Restore"
+                        }
+                    }
+                }
+            }
+            button
+                hx-delete="/trash"
+                hx-target="#trash"
+                hx-swap="innerHTML"
+                hx-confirm="Are you sure that you want to permanently remove all blocks from trash?"
+            {
+                "Empty trash"
+            }
+        }
+    }
+}
+
+/// Respond with removed blocks in requested format
+fn trash_in_format(app_state: &AppState, format: Format) -> Response {
+    match format {
+        Format::Json => {
+            let trash = app_state.trash.lock().unwrap();
+            let summaries: Vec<_> = trash
+                .entries()
+                .into_iter()
+                .map(|(uuid, trashed)| TrashedSummary {
+                    uuid: uuid.clone(),
+                    name: trashed.block.content.name(),
+                    kind: trashed.block.content.kind(),
+                    deleted_at: trashed.deleted_at,
+                })
+                .collect();
+            Json(summaries).into_response()
+        }
+        Format::Html => render(app_state).into_response(),
+    }
+}
+
+/// Respond with current blocks, updating the trash list out of band for HTMX
+pub async fn blocks_with_trash(app_state: Arc<AppState>, format: Format) -> Response {
+    match format {
+        Format::Json => Json(crate::api::block_summaries(&app_state)).into_response(),
+        Format::Html => {
+            let blocks = handlers::blocks(State(app_state.clone())).await;
+            let markup = html! {
+                (blocks)
+                div id="trash" hx-swap-oob="true" {
+                    (render(&app_state))
+                }
+            };
+            markup.into_response()
+        }
+    }
+}
+
+/// Responds with removed blocks
+pub async fn trash(State(app_state): State<Arc<AppState>>, format: Format) -> Response {
+    trash_in_format(&app_state, format)
+}
+
+/// Moves removed block back to the list of blocks
+pub async fn restore(
+    State(app_state): State<Arc<AppState>>,
+    format: Format,
+    Path(uuid): Path<String>,
+) -> Response {
+    let Some(block) = app_state.trash.lock().unwrap().take(&uuid) else {
+        error!("block#{uuid} not found in trash");
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    info!("restoring block#{uuid} from trash");
+    app_state.blocks.write().unwrap().insert(uuid, block);
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("trash::restore failed to remember current sources: {err:#}")
+    }
+
+    blocks_with_trash(app_state, format).await
+}
+
+/// Permanently forgets all removed blocks
+pub async fn purge(State(app_state): State<Arc<AppState>>, format: Format) -> Response {
+    info!("emptying trash");
+    app_state.trash.lock().unwrap().clear();
+
+    if let Err(err) = app_state.remember_current_blocks() {
+        error!("trash::purge failed to remember current sources: {err:#}")
+    }
+
+    trash_in_format(&app_state, format)
+}
@@ -0,0 +1,172 @@
+//! System tray (menu bar on macOS) icon with basic controls
+//!
+//! Performers that don't use the terminal can open the UI, interrupt playback and quit Harmonia
+//! from the icon. Enabled with `--tray`, requires `tray` feature.
+//!
+//! Operating systems expect the tray to live on the main thread with it's own event loop, so with
+//! `--tray` the main thread runs [run] and Harmonia itself is served from another thread. Chosen
+//! entries are sent to it as [Command]s and handled by [handle].
+
+use crate::{audio_engine, AppState};
+use std::{process::ExitCode, sync::Arc, time::Duration};
+use tao::{
+    event::{Event, StartCause},
+    event_loop::{ControlFlow, EventLoopBuilder},
+};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+use tray_icon::{
+    menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+    Icon, TrayIcon, TrayIconBuilder,
+};
+
+/// How often menu is checked for chosen entries
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Size of the generated icon in pixels
+const ICON_SIZE: u32 = 32;
+
+/// Entry of the tray menu chosen by the user
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    /// Open UI in default browser
+    Open,
+
+    /// Interrupt all played blocks
+    Interrupt,
+
+    /// Shut Harmonia down
+    Quit,
+}
+
+/// Receiving end of the [Command]s chosen in the tray
+pub type Commands = mpsc::UnboundedReceiver<Command>;
+
+/// Entries of the menu, compared with chosen ones
+struct Entries {
+    /// Opens UI
+    open: MenuItem,
+
+    /// Interrupts playback
+    interrupt: MenuItem,
+
+    /// Quits Harmonia
+    quit: MenuItem,
+}
+
+/// Circle in the middle of transparent square, so icon doesn't depend on image files
+fn icon() -> Icon {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0 - 2.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f32 - center).hypot(y as f32 - center);
+            let alpha = (radius - distance + 0.5).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&[255, 255, 255, (alpha * 255.0) as u8]);
+        }
+    }
+    Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE).expect("icon has size matching it's pixels")
+}
+
+/// Create tray icon with it's menu
+fn create() -> Result<(TrayIcon, Entries), String> {
+    let entries = Entries {
+        open: MenuItem::new("Open UI", true, None),
+        interrupt: MenuItem::new("Interrupt", true, None),
+        quit: MenuItem::new("Quit", true, None),
+    };
+    let menu = Menu::new();
+    menu.append_items(&[
+        &entries.open,
+        &entries.interrupt,
+        &PredefinedMenuItem::separator(),
+        &entries.quit,
+    ])
+    .map_err(|err| format!("failed to create tray menu: {err}"))?;
+
+    let tray = TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip("Harmonia")
+        .with_icon(icon())
+        .build()
+        .map_err(|err| format!("failed to create tray icon: {err}"))?;
+    Ok((tray, entries))
+}
+
+/// Run tray on the current (main) thread, serving Harmonia with `serve` on another thread
+///
+/// Exits the process with exit code of `serve` when it returns.
+pub fn run(serve: impl FnOnce(Commands) -> ExitCode + Send + 'static) -> ExitCode {
+    // Exit code of the process, as returned by `serve`
+    let event_loop = EventLoopBuilder::<i32>::with_user_event().build();
+    let proxy = event_loop.create_proxy();
+    let (commands, received) = mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        let exit_code = if serve(received) == ExitCode::SUCCESS {
+            0
+        } else {
+            1
+        };
+        // Event loop is gone only when process is already exiting
+        let _ = proxy.send_event(exit_code);
+    });
+
+    let mut tray = None;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::WaitUntil(std::time::Instant::now() + POLL_INTERVAL);
+
+        match event {
+            // macOS requires the icon to be created after event loop started
+            Event::NewEvents(StartCause::Init) => match create() {
+                Ok(created) => tray = Some(created),
+                // Harmonia still works without the icon, through the browser
+                Err(err) => error!("{err}"),
+            },
+            Event::UserEvent(exit_code) => {
+                tray = None;
+                *control_flow = ControlFlow::ExitWithCode(exit_code);
+                return;
+            }
+            _ => {}
+        }
+
+        let Some((_, entries)) = &tray else {
+            return;
+        };
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            let command = if &event.id == entries.open.id() {
+                Command::Open
+            } else if &event.id == entries.interrupt.id() {
+                Command::Interrupt
+            } else if &event.id == entries.quit.id() {
+                Command::Quit
+            } else {
+                continue;
+            };
+            // Harmonia stopped already, so exit code is on the way
+            let _ = commands.send(command);
+        }
+    })
+}
+
+/// Execute commands chosen in the tray until Harmonia shuts down
+pub async fn handle(app_state: Arc<AppState>, mut commands: Commands, url: String) {
+    while let Some(command) = commands.recv().await {
+        info!("{command:?} chosen in tray");
+        match command {
+            Command::Open => {
+                if let Err(err) = open::that_detached(&url) {
+                    error!("failed to open {url} in browser: {err}");
+                }
+            }
+            Command::Interrupt => {
+                if let Err(err) = audio_engine::interrupt(app_state.clone()).await {
+                    error!("failed to interrupt: {err}");
+                }
+            }
+            Command::Quit => app_state.abort.notify_one(),
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Terminal client for Harmonia
+//!
+//! Connects to running Harmonia instance over its JSON API (see `api` module of Harmonia) and
+//! allows to browse, play and stop blocks, watch beat of Link session and change groups of blocks
+//! without web browser, for example over SSH.
+//!
+//! Keys:
+//!
+//! - up / down (or `k` / `j`) - select block
+//! - enter - play selected block
+//! - space - interrupt all currently played blocks
+//! - `s` - interrupt selected block only
+//! - `g` - edit group of selected block, enter confirms and escape cancels
+//! - `q` - quit
+
+use anyhow::Context;
+use clap::Parser;
+use crossterm::{
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::StreamExt;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+};
+use serde::Deserialize;
+use std::{io::Stdout, time::Duration};
+
+/// How often state of Harmonia instance is refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Parser, Debug)]
+#[command(version)]
+/// Terminal client for Harmonia
+struct Cli {
+    /// Address of Harmonia instance
+    #[arg(default_value_t = String::from("http://localhost:8080"))]
+    url: String,
+}
+
+/// Block as described by Harmonia JSON API
+#[derive(Deserialize, Clone)]
+struct BlockSummary {
+    /// Unique identifier of the block
+    uuid: String,
+
+    /// Human readable name of the block
+    name: String,
+
+    /// Kind of the block content
+    kind: String,
+
+    /// Group in which block is played
+    group: String,
+
+    /// MIDI port for blocks played through MIDI
+    port: Option<usize>,
+
+    /// Reason why block failed integrity verification
+    #[serde(default)]
+    integrity_error: Option<String>,
+}
+
+/// Currently played block as described by Harmonia JSON API
+#[derive(Deserialize)]
+struct Playing {
+    /// Human readable name of the block
+    name: String,
+
+    /// Bar (counted from 1) that is currently played
+    bar: usize,
+
+    /// Number of bars in the block, [None] for infinite blocks
+    bar_count: Option<usize>,
+
+    /// Seconds until the end of the block, [None] for infinite blocks
+    remaining_seconds: Option<f64>,
+
+    /// Beats left until the block starts, [None] when it already started
+    #[serde(default)]
+    countdown_beats: Option<f64>,
+}
+
+/// Synchronization and playback state as described by Harmonia JSON API
+#[derive(Deserialize)]
+struct Status {
+    /// Is Link session enabled
+    synchronized: bool,
+
+    /// Tempo of Link session in beats per minute
+    tempo: f64,
+
+    /// Current beat of Link session
+    beat: f64,
+
+    /// Quantum used for synchronization
+    quantum: f64,
+
+    /// Number of peers in Link session
+    peers: u64,
+
+    /// Currently played blocks
+    playing: Vec<Playing>,
+}
+
+/// Main page of Harmonia as described by Harmonia JSON API
+#[derive(Deserialize)]
+struct Overview {
+    /// Current state of synchronization and playback
+    status: Status,
+
+    /// All blocks in the UI order
+    blocks: Vec<BlockSummary>,
+}
+
+/// Connection to Harmonia instance
+struct Harmonia {
+    /// HTTP client reused between requests
+    client: reqwest::Client,
+
+    /// Address of Harmonia instance, without trailing slash
+    url: String,
+}
+
+impl Harmonia {
+    /// Fetch current state of Harmonia instance
+    async fn overview(&self) -> anyhow::Result<Overview> {
+        self.client
+            .get(format!("{}/", self.url))
+            .query(&[("format", "json")])
+            .send()
+            .await
+            .context("requesting overview")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("parsing overview")
+    }
+
+    /// Send POST request to given path with optional form
+    async fn post(&self, path: &str, form: &[(&str, &str)]) -> anyhow::Result<()> {
+        self.client
+            .post(format!("{}{path}", self.url))
+            .query(&[("format", "json")])
+            .form(form)
+            .send()
+            .await
+            .with_context(|| format!("requesting {path}"))?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// State of the terminal interface
+#[derive(Default)]
+struct App {
+    /// Last known state of Harmonia instance
+    overview: Option<Overview>,
+
+    /// Selected block in the list of blocks
+    list: ListState,
+
+    /// Group being edited for the selected block, if in editing mode
+    group: Option<String>,
+
+    /// Last error or information for the user
+    message: String,
+}
+
+impl App {
+    /// Currently selected block, if any
+    fn selected(&self) -> Option<&BlockSummary> {
+        let blocks = &self.overview.as_ref()?.blocks;
+        blocks.get(self.list.selected()?)
+    }
+
+    /// Move selection by given offset, staying within the list of blocks
+    fn select(&mut self, offset: isize) {
+        let count = self
+            .overview
+            .as_ref()
+            .map_or(0, |overview| overview.blocks.len());
+        if count == 0 {
+            self.list.select(None);
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        self.list.select(Some(
+            (current + offset).clamp(0, count as isize - 1) as usize
+        ));
+    }
+}
+
+/// Action requested by the user with a key press
+enum Action {
+    /// Nothing to send to Harmonia
+    None,
+
+    /// Play given block
+    Play(String),
+
+    /// Interrupt all currently played blocks
+    Interrupt,
+
+    /// Interrupt given block only
+    InterruptBlock(String),
+
+    /// Set group of given block
+    SetGroup(String, String),
+
+    /// Close terminal client
+    Quit,
+}
+
+/// Update state of the interface based on key press and return action to perform
+fn on_key_press(app: &mut App, key: KeyEvent) -> Action {
+    if key.kind == KeyEventKind::Release {
+        return Action::None;
+    }
+
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        return Action::Quit;
+    }
+
+    if let Some(group) = &mut app.group {
+        match key.code {
+            KeyCode::Enter => {
+                let group = app.group.take().unwrap_or_default();
+                if let Some(block) = app.selected() {
+                    return Action::SetGroup(block.uuid.clone(), group);
+                }
+            }
+            KeyCode::Esc => app.group = None,
+            KeyCode::Backspace => {
+                group.pop();
+            }
+            KeyCode::Char(c) if c.is_ascii_alphanumeric() || c == '_' || c == ' ' => group.push(c),
+            _ => {}
+        }
+        return Action::None;
+    }
+
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => Action::Quit,
+        KeyCode::Up | KeyCode::Char('k') => {
+            app.select(-1);
+            Action::None
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            app.select(1);
+            Action::None
+        }
+        KeyCode::Enter => app
+            .selected()
+            .map_or(Action::None, |block| Action::Play(block.uuid.clone())),
+        KeyCode::Char(' ') => Action::Interrupt,
+        KeyCode::Char('s') => app.selected().map_or(Action::None, |block| {
+            Action::InterruptBlock(block.uuid.clone())
+        }),
+        KeyCode::Char('g') => {
+            app.group = app.selected().map(|block| block.group.clone());
+            Action::None
+        }
+        _ => Action::None,
+    }
+}
+
+/// Render beats of the current bar, highlighting the current one
+fn beat_indicator(beat: f64, quantum: f64) -> String {
+    let beats = quantum.max(1.0).round() as usize;
+    let current = beat.rem_euclid(beats as f64).floor() as usize;
+    (0..beats)
+        .map(|i| if i == current { "●" } else { "○" })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Render whole interface
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [header, blocks, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(4),
+    ])
+    .areas(frame.size());
+
+    let status = match &app.overview {
+        Some(Overview { status, .. }) => format!(
+            "{}  peers: {}  beat: {:.1}  {}  BPM: {:.1}",
+            if status.synchronized {
+                "Synchronized"
+            } else {
+                "ERROR"
+            },
+            status.peers,
+            status.beat,
+            beat_indicator(status.beat, status.quantum),
+            status.tempo,
+        ),
+        None => "Connecting...".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(status).block(Block::default().borders(Borders::ALL).title("Harmonia")),
+        header,
+    );
+
+    let items: Vec<ListItem> = app
+        .overview
+        .iter()
+        .flat_map(|overview| overview.blocks.iter())
+        .map(|block| {
+            let mut line = format!("{:<8} {}", block.kind, block.name);
+            if let Some(port) = block.port {
+                line += &format!("  [port {port}]");
+            }
+            if !block.group.is_empty() {
+                line += &format!("  [group {}]", block.group);
+            }
+            if let Some(err) = &block.integrity_error {
+                line += &format!("  ⚠ {err}");
+            }
+            ListItem::new(line)
+        })
+        .collect();
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title("Blocks"))
+            .highlight_symbol("> ")
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+        blocks,
+        &mut app.list,
+    );
+
+    let playing: Vec<_> = app
+        .overview
+        .iter()
+        .flat_map(|overview| &overview.status.playing)
+        .map(|playing| match playing {
+            Playing {
+                name,
+                countdown_beats: Some(countdown),
+                ..
+            } => format!("{name} starts in {}…", countdown.ceil()),
+            playing => {
+                let mut line = format!("Playing {} (bar {}", playing.name, playing.bar);
+                if let Some(bar_count) = playing.bar_count {
+                    line += &format!(" of {bar_count}");
+                }
+                line += ")";
+                if let Some(remaining) = playing.remaining_seconds {
+                    let remaining = remaining.max(0.0).round() as u64;
+                    line += &format!(" {}:{:02} remaining", remaining / 60, remaining % 60);
+                }
+                line
+            }
+        })
+        .collect();
+    let playing = if playing.is_empty() {
+        "Nothing is playing".to_string()
+    } else {
+        playing.join("; ")
+    };
+    let help = match &app.group {
+        Some(group) => format!("Group: {group}_  (enter - save, esc - cancel)"),
+        None if !app.message.is_empty() => app.message.clone(),
+        None => "enter - play, space - stop all, s - stop, g - set group, q - quit".to_string(),
+    };
+    frame.render_widget(
+        Paragraph::new(vec![Line::from(playing), Line::from(help)])
+            .block(Block::default().borders(Borders::ALL)),
+        footer,
+    );
+}
+
+/// Restores terminal to it's normal state when dropped
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// Run interface until user quits
+async fn run(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    harmonia: Harmonia,
+) -> anyhow::Result<()> {
+    let mut app = App::default();
+    let mut keys = EventStream::new().fuse();
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        terminal.draw(|frame| draw(frame, &mut app))?;
+
+        let action = tokio::select! {
+            event = keys.next() => match event {
+                Some(Ok(Event::Key(key))) => on_key_press(&mut app, key),
+                Some(Ok(_)) => Action::None,
+                Some(Err(err)) => return Err(err).context("reading terminal events"),
+                None => Action::Quit,
+            },
+            _ = refresh.tick() => {
+                match harmonia.overview().await {
+                    Ok(overview) => {
+                        app.overview = Some(overview);
+                        if app.list.selected().is_none() {
+                            app.select(0);
+                        }
+                    }
+                    Err(err) => {
+                        app.overview = None;
+                        app.message = format!("{err:#}");
+                    }
+                }
+                Action::None
+            }
+        };
+
+        let result = match action {
+            Action::None => continue,
+            Action::Quit => return Ok(()),
+            Action::Play(uuid) => harmonia.post(&format!("/blocks/play/{uuid}"), &[]).await,
+            Action::Interrupt => harmonia.post("/interrupt", &[]).await,
+            Action::InterruptBlock(uuid) => harmonia.post(&format!("/interrupt/{uuid}"), &[]).await,
+            Action::SetGroup(uuid, group) => {
+                harmonia
+                    .post(&format!("/blocks/set-group/{uuid}"), &[("group", &group)])
+                    .await
+            }
+        };
+
+        app.message = match result {
+            Ok(()) => String::new(),
+            Err(err) => format!("{err:#}"),
+        };
+    }
+}
+
+/// Connect to Harmonia instance and run terminal interface
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let harmonia = Harmonia {
+        client: reqwest::Client::new(),
+        url: cli.url.trim_end_matches('/').to_string(),
+    };
+
+    terminal::enable_raw_mode().context("enabling raw mode")?;
+    let _guard = TerminalGuard;
+    execute!(std::io::stdout(), EnterAlternateScreen).context("entering alternate screen")?;
+
+    let mut terminal =
+        Terminal::new(CrosstermBackend::new(std::io::stdout())).context("creating terminal")?;
+    run(&mut terminal, harmonia).await
+}
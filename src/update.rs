@@ -0,0 +1,183 @@
+//! Checking whether newer version of Harmonia was released
+//!
+//! Once a day latest release is fetched from GitHub and compared with version of this build. When
+//! it's newer, [banner] is shown in the header of the UI. Result of the check is cached in
+//! [UPDATE_CHECK_PATH], so restarting Harmonia during rehearsal doesn't ask GitHub again.
+//!
+//! Nothing is ever downloaded or installed. Check is off by default, since it makes requests to
+//! GitHub without being asked, and is turned on with `--update-check` flag or `update-check = true`
+//! in the configuration file.
+
+use crate::{cache_path, version::Version, AppState};
+use anyhow::Context;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+/// Path (relative to cache) where result of the last check is stored
+pub const UPDATE_CHECK_PATH: &str = "harmonia_update_check.bson";
+
+/// GitHub API route describing the latest release
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/RobertBendun/harmonia/releases/latest";
+
+/// How often GitHub is asked for the latest release
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long to wait for GitHub
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Release of Harmonia published on GitHub
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Release {
+    /// Version of the release, like `0.5.0`
+    pub version: String,
+
+    /// Page of the release with it's notes and binaries
+    pub url: String,
+}
+
+/// Result of the last check, see [UPDATE_CHECK_PATH]
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    /// Unix timestamp (in seconds) of the check
+    checked_at: i64,
+
+    /// Latest release at the time of the check
+    release: Release,
+}
+
+/// Latest release as described by GitHub API
+#[derive(Deserialize)]
+struct GitHubRelease {
+    /// Tag of the release, like `v0.5.0`
+    tag_name: String,
+
+    /// Page of the release
+    html_url: String,
+}
+
+/// Parse version like `v0.5.0` or `0.5.0-rc1` into comparable numbers, ignoring pre-release part
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    Some((
+        parts.next()??,
+        parts.next()??,
+        parts.next().unwrap_or(Some(0))?,
+    ))
+}
+
+/// Whether `release` is newer than this build
+fn is_newer(release: &Release) -> bool {
+    match (
+        parse_version(&release.version),
+        parse_version(Version::default().pkg_version()),
+    ) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+/// Ask GitHub for the latest release
+async fn fetch() -> anyhow::Result<Release> {
+    let release: GitHubRelease = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        // GitHub rejects requests without user agent
+        .user_agent(concat!("harmonia/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .context("creating HTTP client")?
+        .get(LATEST_RELEASE_URL)
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .context("requesting latest release")?
+        .json()
+        .await
+        .context("decoding latest release")?;
+
+    Ok(Release {
+        version: release.tag_name.trim_start_matches('v').to_string(),
+        url: release.html_url,
+    })
+}
+
+/// Result of the previous check, if it's recent enough
+fn load_cached() -> Option<Cached> {
+    let cached: Cached = std::fs::read(cache_path().join(UPDATE_CHECK_PATH))
+        .ok()
+        .and_then(|bytes| bson::from_slice(&bytes).ok())?;
+    let age = chrono::Utc::now().timestamp() - cached.checked_at;
+    (0..CHECK_INTERVAL.as_secs() as i64)
+        .contains(&age)
+        .then_some(cached)
+}
+
+/// Remember result of the check, so it's not repeated after restart
+fn store(release: &Release) {
+    let cached = Cached {
+        checked_at: chrono::Utc::now().timestamp(),
+        release: release.clone(),
+    };
+    let path = cache_path().join(UPDATE_CHECK_PATH);
+    match bson::to_vec(&cached) {
+        Ok(bytes) => {
+            if let Err(err) = std::fs::write(&path, bytes) {
+                warn!("failed to write update check to {path:?}: {err}");
+            }
+        }
+        Err(err) => warn!("failed to serialize update check: {err}"),
+    }
+}
+
+/// Check for the latest release every [CHECK_INTERVAL], starting immediately
+pub async fn check_periodically(app_state: Arc<AppState>) {
+    if !app_state.ephemeral {
+        if let Some(cached) = load_cached() {
+            debug!(
+                "using latest release {} checked before",
+                cached.release.version
+            );
+            *app_state.latest_release.write().unwrap() = Some(cached.release);
+            let age = chrono::Utc::now().timestamp() - cached.checked_at;
+            let until_next = CHECK_INTERVAL.as_secs().saturating_sub(age as u64);
+            tokio::time::sleep(Duration::from_secs(until_next)).await;
+        }
+    }
+
+    loop {
+        match fetch().await {
+            Ok(release) => {
+                if is_newer(&release) {
+                    info!(
+                        "newer version of Harmonia is available: {}",
+                        release.version
+                    );
+                }
+                if !app_state.ephemeral {
+                    store(&release);
+                }
+                *app_state.latest_release.write().unwrap() = Some(release);
+            }
+            // No internet access is common on stage, so it's not worth a warning
+            Err(err) => debug!("failed to check for updates: {err:#}"),
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Notice about newer release shown in the header, empty when this build is up to date
+pub fn banner(app_state: &AppState) -> Markup {
+    let latest_release = app_state.latest_release.read().unwrap();
+    html! {
+        @if let Some(release) = latest_release.as_ref().filter(|release| is_newer(release)) {
+            p class="update-available" {
+                "Harmonia " (release.version) " is available: "
+                a href=(release.url) target="_blank" { "see what's new" }
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Receiving uploaded files without buffering them in memory
+//!
+//! Fields of multipart uploads are streamed to temporary files in [UPLOADS_PATH], hashed on the
+//! way, so dozens of large files can be uploaded on low-memory machines. Blocks are identified by
+//! the hash, so uploads of files that already have a block are dropped without being read, and
+//! new files are moved into [storage] with [UploadedFile::into_stored] instead of being copied.
+//! Temporary file is removed when [UploadedFile] is dropped. In ephemeral mode nothing is written
+//! to disk, so files are kept in memory instead.
+
+use crate::{cache_path, handlers::MIB, storage, AppState};
+use axum::extract::multipart::Field;
+use sha1::{Digest, Sha1};
+use std::{path::PathBuf, sync::Arc};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+/// Directory under cache in which uploads in progress are stored
+pub const UPLOADS_PATH: &str = "harmonia_uploads";
+
+/// Where content of the uploaded file is kept
+enum Storage {
+    /// Temporary file removed on drop
+    Disk(PathBuf),
+
+    /// Content itself, used in ephemeral mode
+    Memory(Vec<u8>),
+}
+
+/// File received from multipart upload, see [receive]
+pub struct UploadedFile {
+    /// Where content of the file is kept
+    storage: Storage,
+
+    /// Sha1 of the content as hex, from which identifiers of file based blocks are derived
+    pub hash: String,
+}
+
+impl UploadedFile {
+    /// Move content of the file into [storage] under it's hash, without reading it
+    pub fn into_stored(mut self) -> std::io::Result<storage::StoredBytes> {
+        match &mut self.storage {
+            Storage::Disk(path) => storage::StoredBytes::adopt(path, self.hash.clone()),
+            Storage::Memory(bytes) => Ok(std::mem::take(bytes).into()),
+        }
+    }
+
+    /// Read content of the file into memory
+    pub fn into_bytes(mut self) -> std::io::Result<Vec<u8>> {
+        match &mut self.storage {
+            Storage::Disk(path) => std::fs::read(path),
+            Storage::Memory(bytes) => Ok(std::mem::take(bytes)),
+        }
+    }
+
+    /// Insert MIDI block for each MIDI file inside uploaded zip archive, reading it from disk
+    ///
+    /// See [import_zip_archive][crate::handlers::import_zip_archive].
+    pub async fn import_zip_archive(
+        mut self,
+        app_state: Arc<AppState>,
+        group: Option<String>,
+    ) -> anyhow::Result<crate::handlers::ZipImport> {
+        use crate::handlers::import_zip_archive;
+        use anyhow::Context;
+        // Temporary file is removed when upload is dropped, after the import
+        match &mut self.storage {
+            Storage::Disk(path) => {
+                let file = std::fs::File::open(path).context("opening uploaded zip archive")?;
+                import_zip_archive(app_state, std::io::BufReader::new(file), group).await
+            }
+            Storage::Memory(bytes) => {
+                let bytes = std::mem::take(bytes);
+                import_zip_archive(app_state, std::io::Cursor::new(bytes), group).await
+            }
+        }
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Storage::Disk(path) = &self.storage {
+            match std::fs::remove_file(path) {
+                // File was moved into storage, see [UploadedFile::into_stored]
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => warn!("failed to remove uploaded file {path:?}: {err}"),
+                Ok(()) => {}
+            }
+        }
+    }
+}
+
+/// Stream uploaded file, rejecting it as soon as it exceeds `--max-file-size`
+pub async fn receive(app_state: &AppState, field: &mut Field<'_>) -> Result<UploadedFile, String> {
+    let mut upload = UploadedFile {
+        storage: if app_state.ephemeral {
+            Storage::Memory(Vec::new())
+        } else {
+            let directory = cache_path().join(UPLOADS_PATH);
+            std::fs::create_dir_all(&directory)
+                .map_err(|err| format!("failed to create {directory:?}: {err}"))?;
+            Storage::Disk(directory.join(uuid::Uuid::new_v4().to_string()))
+        },
+        hash: String::new(),
+    };
+
+    let mut file = match &upload.storage {
+        Storage::Disk(path) => Some(
+            tokio::fs::File::create(path)
+                .await
+                .map_err(|err| format!("failed to create {path:?}: {err}"))?,
+        ),
+        Storage::Memory(_) => None,
+    };
+
+    let mut hasher = Sha1::new();
+    let mut size = 0;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| format!("failed to read upload: {err}"))?
+    {
+        size += chunk.len();
+        if size > app_state.max_file_size {
+            return Err(format!(
+                "file is bigger than {} MiB",
+                app_state.max_file_size / MIB
+            ));
+        }
+        hasher.update(&chunk);
+        match (&mut file, &mut upload.storage) {
+            (Some(file), _) => file
+                .write_all(&chunk)
+                .await
+                .map_err(|err| format!("failed to store upload: {err}"))?,
+            (None, Storage::Memory(bytes)) => bytes.extend_from_slice(&chunk),
+            (None, Storage::Disk(_)) => unreachable!("file is opened for uploads stored on disk"),
+        }
+    }
+
+    if let Some(mut file) = file {
+        file.flush()
+            .await
+            .map_err(|err| format!("failed to store upload: {err}"))?;
+    }
+    upload.hash = hex::encode(hasher.finalize());
+    Ok(upload)
+}
+
+/// Remove uploads left by previous run that was interrupted while receiving them
+pub fn remove_leftovers() {
+    let directory = cache_path().join(UPLOADS_PATH);
+    if directory.exists() {
+        if let Err(err) = std::fs::remove_dir_all(&directory) {
+            warn!("failed to remove leftover uploads in {directory:?}: {err}");
+        }
+    }
+}
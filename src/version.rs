@@ -45,6 +45,13 @@ impl Default for Version {
     }
 }
 
+impl Version {
+    /// Version of package, reported in Cargo.toml
+    pub fn pkg_version(&self) -> &'static str {
+        self.pkg_version
+    }
+}
+
 /// Pretty print Version information in terminal
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -0,0 +1,164 @@
+//! Builtin Harmonia MIDI virtual port
+//!
+//! On unix platforms the port is created with midir. Windows has no system API for virtual MIDI
+//! ports, so with `windows-virtual-port` feature the port is created through [teVirtualMIDI]
+//! driver by Tobias Erichsen, installed for example with loopMIDI. The driver library is loaded
+//! when Harmonia starts, so the same executable fails with a clear message when the driver is
+//! missing. Without the feature there is no virtual port on Windows, see
+//! [MIN_PORT_NUMBER][crate::handlers::MIN_PORT_NUMBER].
+//!
+//! [teVirtualMIDI]: https://www.tobias-erichsen.de/software/virtualmidi.html
+
+/// Destination of serialized MIDI messages
+pub trait MidiSink {
+    /// Send single MIDI message
+    fn send(&mut self, message: &[u8]) -> Result<(), midir::SendError>;
+}
+
+impl MidiSink for midir::MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), midir::SendError> {
+        midir::MidiOutputConnection::send(self, message)
+    }
+}
+
+/// Shared port is locked only for each message, so blocks played at the same time and port tests
+/// interleave their messages instead of waiting for each other
+impl<T: MidiSink> MidiSink for std::sync::Arc<std::sync::Mutex<T>> {
+    fn send(&mut self, message: &[u8]) -> Result<(), midir::SendError> {
+        self.lock().unwrap().send(message)
+    }
+}
+
+/// Virtual port created by Harmonia, on unix platforms it's simply midir connection
+#[cfg(unix)]
+pub type VirtualPort = midir::MidiOutputConnection;
+
+/// Create virtual port named [crate::channels::VIRTUAL_PORT_NAME]
+#[cfg(unix)]
+pub fn create() -> anyhow::Result<VirtualPort> {
+    use midir::os::unix::VirtualOutput;
+    midir::MidiOutput::new("HarmoniaVirt")?
+        .create_virtual(crate::channels::VIRTUAL_PORT_NAME)
+        .map_err(|err| anyhow::anyhow!("creating virtual midi port: {err}"))
+}
+
+#[cfg(all(windows, feature = "windows-virtual-port"))]
+pub use te_virtual_midi::{create, VirtualPort};
+
+/// Bindings to teVirtualMIDI driver library, loaded at runtime
+#[cfg(all(windows, feature = "windows-virtual-port"))]
+mod te_virtual_midi {
+    use super::MidiSink;
+    use anyhow::{anyhow, Context};
+    use std::ffi::c_void;
+
+    /// Name of the driver library matching architecture of Harmonia
+    #[cfg(target_pointer_width = "64")]
+    const LIBRARY: &str = "teVirtualMIDI64.dll";
+
+    /// Name of the driver library matching architecture of Harmonia
+    #[cfg(target_pointer_width = "32")]
+    const LIBRARY: &str = "teVirtualMIDI32.dll";
+
+    /// Driver parses data sent by Harmonia, so only complete messages reach other applications
+    const TE_VM_FLAGS_PARSE_TX: u32 = 2;
+
+    /// Port is only a source of messages for other applications, it doesn't receive any
+    const TE_VM_FLAGS_INSTANTIATE_TX_ONLY: u32 = 8;
+
+    /// Largest SysEx message accepted by the port
+    const MAX_SYSEX_LENGTH: u32 = 0xffff;
+
+    /// `virtualMIDICreatePortEx2` from `teVirtualMIDI.h`
+    type CreatePort = unsafe extern "system" fn(
+        port_name: *const u16,
+        callback: *const c_void,
+        callback_instance: usize,
+        max_sysex_length: u32,
+        flags: u32,
+    ) -> *mut c_void;
+
+    /// `virtualMIDISendData` from `teVirtualMIDI.h`
+    type SendData =
+        unsafe extern "system" fn(port: *mut c_void, data: *const u8, length: u32) -> i32;
+
+    /// `virtualMIDIClosePort` from `teVirtualMIDI.h`
+    type ClosePort = unsafe extern "system" fn(port: *mut c_void);
+
+    /// Port created through teVirtualMIDI, closed when dropped
+    pub struct VirtualPort {
+        /// Handle of the port returned by the driver
+        port: *mut c_void,
+
+        /// `virtualMIDISendData` of the loaded library
+        send_data: SendData,
+
+        /// `virtualMIDIClosePort` of the loaded library
+        close_port: ClosePort,
+
+        /// Loaded library, kept so function pointers above stay valid
+        _library: libloading::Library,
+    }
+
+    // Driver serializes access to the port itself, and Harmonia keeps it behind mutex anyway
+    unsafe impl Send for VirtualPort {}
+
+    /// Create virtual port named [crate::channels::VIRTUAL_PORT_NAME]
+    pub fn create() -> anyhow::Result<VirtualPort> {
+        // SAFETY: Library is trusted driver component and symbols have signatures from it's header
+        unsafe {
+            let library = libloading::Library::new(LIBRARY).with_context(|| {
+                format!("loading {LIBRARY}, is teVirtualMIDI driver (for example from loopMIDI) installed?")
+            })?;
+            let create_port = *library.get::<CreatePort>(b"virtualMIDICreatePortEx2\0")?;
+            let send_data = *library.get::<SendData>(b"virtualMIDISendData\0")?;
+            let close_port = *library.get::<ClosePort>(b"virtualMIDIClosePort\0")?;
+
+            let name: Vec<u16> = crate::channels::VIRTUAL_PORT_NAME
+                .encode_utf16()
+                .chain(std::iter::once(0))
+                .collect();
+            let port = create_port(
+                name.as_ptr(),
+                std::ptr::null(),
+                0,
+                MAX_SYSEX_LENGTH,
+                TE_VM_FLAGS_PARSE_TX | TE_VM_FLAGS_INSTANTIATE_TX_ONLY,
+            );
+            if port.is_null() {
+                return Err(anyhow!(
+                    "creating virtual midi port: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+
+            Ok(VirtualPort {
+                port,
+                send_data,
+                close_port,
+                _library: library,
+            })
+        }
+    }
+
+    impl MidiSink for VirtualPort {
+        fn send(&mut self, message: &[u8]) -> Result<(), midir::SendError> {
+            // SAFETY: Port is open until drop and driver only reads given bytes
+            let sent =
+                unsafe { (self.send_data)(self.port, message.as_ptr(), message.len() as u32) };
+            if sent == 0 {
+                return Err(midir::SendError::Other(
+                    "teVirtualMIDI failed to send message",
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for VirtualPort {
+        fn drop(&mut self) {
+            // SAFETY: Port was created by this library and is closed only once
+            unsafe { (self.close_port)(self.port) }
+        }
+    }
+}